@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    {
+        // Builds do not depend on a protoc installed on the machine
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        let out = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+        tonic_prost_build::configure()
+            .build_client(false)
+            .file_descriptor_set_path(out.join("autoupdate_descriptor.bin"))
+            .compile_protos(&["proto/autoupdate.proto"], &["proto"])?;
+    }
+    Ok(())
+}
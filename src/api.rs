@@ -0,0 +1,120 @@
+use crate::control::{Control, Refusal, DEFAULT_HISTORY_LIMIT};
+use crate::Command;
+use autoupdate::server::{Request, Response};
+use serde_json::json;
+use std::sync::Arc;
+
+/// The control operations as a REST API below `/api`.
+pub(crate) struct Api {
+    token: Option<String>,
+    control: Arc<Control>,
+}
+
+fn error(refusal: Refusal) -> Response {
+    Response::json(refusal.status, &json!({ "error": refusal.message }))
+}
+
+/// Whether `given`, the value of an `Authorization` header, carries `token`.
+pub(crate) fn authorized(token: Option<&str>, given: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let expected = format!("Bearer {}", token);
+    match given {
+        Some(given) => {
+            given.len() == expected.len()
+                && given
+                    .bytes()
+                    .zip(expected.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        None => false,
+    }
+}
+
+impl Api {
+    pub(crate) fn new(token: Option<String>, control: Arc<Control>) -> Api {
+        Api { token, control }
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, Refusal> {
+        let query: Vec<(String, String)> = form_urlencoded::parse(request.query.as_bytes())
+            .into_owned()
+            .collect();
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let number = |name: &str| match param(name).map(str::parse::<usize>) {
+            Some(Err(_)) => Err(Refusal::new(400, &format!("{} must be a number", name))),
+            value => Ok(value.and_then(Result::ok)),
+        };
+        let path = param("path");
+        let control = &self.control;
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/api/repositories") => Ok(Response::json(200, &control.repositories())),
+            ("POST", "/api/sync") => {
+                let indexes = control.selected(path)?;
+                let queued = indexes.len();
+                control.queue(indexes.into_iter().map(Command::Sync))?;
+                Ok(Response::json(202, &json!({ "queued": queued })))
+            }
+            ("POST", action @ ("/api/pause" | "/api/resume")) => {
+                let pause = action == "/api/pause";
+                let indexes = control.selected(path)?;
+                let queued = indexes.len();
+                control.queue(
+                    indexes
+                        .into_iter()
+                        .map(|index| Command::Pause(index, Some(pause))),
+                )?;
+                Ok(Response::json(202, &json!({ "queued": queued })))
+            }
+            ("GET", "/api/history") => {
+                let limit = number("limit")?.unwrap_or(DEFAULT_HISTORY_LIMIT);
+                let indexes = control.selected(path)?;
+                Ok(Response::json(200, &control.histories(&indexes, limit)))
+            }
+            ("POST", "/api/approve") => {
+                let approved = control.approve(path, param("commit"))?;
+                Ok(Response::json(
+                    202,
+                    &json!({ "approved": approved.to_string() }),
+                ))
+            }
+            ("POST", "/api/rollback") => {
+                let (from, to) = control.rollback(path, number("steps")?.unwrap_or(1))?;
+                Ok(Response::json(
+                    200,
+                    &json!({ "from": from.to_string(), "to": to.to_string() }),
+                ))
+            }
+            (
+                _,
+                "/api/repositories" | "/api/sync" | "/api/pause" | "/api/resume" | "/api/history"
+                | "/api/approve" | "/api/rollback",
+            ) => Err(Refusal::new(405, "method not allowed")),
+            _ => Err(Refusal::new(404, "not found")),
+        }
+    }
+
+    pub(crate) fn handle(&self, request: &Request) -> Response {
+        if !authorized(self.token.as_deref(), request.header("Authorization")) {
+            return error(Refusal::new(401, "missing or invalid API token"));
+        }
+        self.route(request).unwrap_or_else(error)
+    }
+}
+
+/// Answers a request below `/api`, with 404 when the API is not served on
+/// the address it came in on.
+pub(crate) fn handle(api: Option<&Api>, request: &Request) -> Response {
+    match api {
+        Some(api) => api.handle(request),
+        None => error(Refusal::new(404, "not found")),
+    }
+}
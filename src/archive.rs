@@ -0,0 +1,188 @@
+use crate::config::{Archive, ArchiveFormat, Repo};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::s3;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const SYMLINK_MODE: i32 = 0o120000;
+const EXECUTABLE_MODE: i32 = 0o100755;
+
+fn zip_error(e: zip::result::ZipError) -> Error {
+    Error::Io(std::io::Error::other(e.to_string()))
+}
+
+/// Files of the commit as path, git file mode and blob, leaving out submodules.
+/// The blobs are read one at a time as they are written to the archive.
+fn files(repository: &Repository, commit: Oid) -> Result<Vec<(String, i32, Oid)>> {
+    let tree = repository.find_commit(commit)?.tree()?;
+    let mut files = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let name = entry.name().unwrap_or_default();
+            files.push((format!("{}{}", dir, name), entry.filemode(), entry.id()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+fn tar_gz<W: Write>(
+    out: W,
+    repository: &Repository,
+    files: &[(String, i32, Oid)],
+    time: u64,
+) -> Result<W> {
+    let mut builder = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    for (path, mode, oid) in files {
+        let blob = repository.find_blob(*oid)?;
+        let content = blob.content();
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(time);
+        if *mode == SYMLINK_MODE {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            let target = String::from_utf8_lossy(content).into_owned();
+            builder.append_link(&mut header, path, target)?;
+        } else {
+            header.set_mode(if *mode == EXECUTABLE_MODE {
+                0o755
+            } else {
+                0o644
+            });
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content)?;
+        }
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+fn zip<W: Write + Seek>(
+    out: W,
+    repository: &Repository,
+    files: &[(String, i32, Oid)],
+) -> Result<W> {
+    let mut writer = ZipWriter::new(out);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (path, mode, oid) in files {
+        let blob = repository.find_blob(*oid)?;
+        let content = blob.content();
+        if *mode == SYMLINK_MODE {
+            let target = String::from_utf8_lossy(content).into_owned();
+            writer
+                .add_symlink(path, target, options)
+                .map_err(zip_error)?;
+        } else {
+            let permissions = if *mode == EXECUTABLE_MODE {
+                0o755
+            } else {
+                0o644
+            };
+            writer
+                .start_file(path, options.unix_permissions(permissions))
+                .map_err(zip_error)?;
+            writer.write_all(content)?;
+        }
+    }
+    writer.finish().map_err(zip_error)
+}
+
+/// Writes the tree of `commit` as `<commit>.tar.gz` or `<commit>.zip` to the
+/// configured directory or S3 location. A directory also gets the archive of
+/// a commit that was already checked out when it does not have it yet.
+pub fn export(
+    repo: &Repo,
+    archive: &Archive,
+    repository: &Repository,
+    commit: Oid,
+    updated: bool,
+    output: &Output,
+) -> Result<()> {
+    let name = format!(
+        "{}.{}",
+        commit,
+        match archive.format {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    );
+    let s3 = archive.destination.strip_prefix("s3://");
+    let local = Path::new(&archive.destination).join(&name);
+    let exported = match s3 {
+        Some(_) => !updated,
+        None => local.exists(),
+    };
+    if exported {
+        return Ok(());
+    }
+
+    // Written to a staged file first, so consumers never pick up half an archive
+    // and the archive never has to fit in memory
+    let staged = match s3 {
+        Some(_) => std::env::temp_dir().join(format!("autoupdate-{}.partial", name)),
+        None => {
+            fs::create_dir_all(&archive.destination)?;
+            local.with_extension("partial")
+        }
+    };
+    let written = write(archive, repository, commit, &staged);
+    if let Err(error) = written {
+        let _ = fs::remove_file(&staged);
+        return Err(error);
+    }
+
+    let destination = match s3 {
+        Some(location) => {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            let key = match prefix.trim_matches('/') {
+                "" => name,
+                prefix => format!("{}/{}", prefix, name),
+            };
+            let uploaded = s3::put(
+                &s3::Object {
+                    bucket,
+                    key: key.clone(),
+                    region: archive.region.as_deref(),
+                    endpoint: archive.endpoint.as_deref(),
+                },
+                &staged,
+            );
+            let _ = fs::remove_file(&staged);
+            uploaded?;
+            format!("s3://{}/{}", bucket, key)
+        }
+        None => {
+            fs::rename(&staged, &local)?;
+            local.display().to_string()
+        }
+    };
+
+    output.emit(Event::Archived {
+        path: &repo.path,
+        commit: commit.to_string(),
+        destination,
+    });
+    Ok(())
+}
+
+/// Streams the archive of `commit` into `path`.
+fn write(archive: &Archive, repository: &Repository, commit: Oid, path: &Path) -> Result<()> {
+    let files = files(repository, commit)?;
+    let out = BufWriter::new(File::create(path)?);
+    let out = match archive.format {
+        ArchiveFormat::TarGz => {
+            let time = repository.find_commit(commit)?.time().seconds().max(0) as u64;
+            tar_gz(out, repository, &files, time)?
+        }
+        ArchiveFormat::Zip => zip(out, repository, &files)?,
+    };
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
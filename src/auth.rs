@@ -0,0 +1,125 @@
+use crate::config::Repo;
+use crate::credential::{self, Credential};
+use crate::error::Error;
+use crate::secret;
+use git2::{Cred, CredentialType, ErrorClass, ErrorCode, RemoteCallbacks};
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const MAX_ATTEMPTS: usize = 3;
+
+fn failure(message: &str) -> git2::Error {
+    git2::Error::new(ErrorCode::Auth, ErrorClass::Callback, message)
+}
+
+fn unresolved(e: Error) -> git2::Error {
+    match e {
+        Error::Auth(message) | Error::Config(message) => failure(&message),
+        e => failure(&e.to_string()),
+    }
+}
+
+pub(crate) fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The credentials the credential helpers handed out for a connection.
+#[derive(Default)]
+pub struct Supplied(Rc<RefCell<Option<(String, Credential)>>>);
+
+impl Supplied {
+    /// Tells the credential helpers the remote accepted what they gave, if
+    /// they were asked at all.
+    pub fn approve(&self) {
+        if let Some((url, credential)) = self.0.borrow_mut().take() {
+            credential::approve(&url, &credential);
+        }
+    }
+}
+
+pub fn attach<'a>(cb: &mut RemoteCallbacks<'a>, repo: &'a Repo) -> Supplied {
+    let mut attempts = 0;
+    let mut agent_tried = false;
+    let handed = Supplied::default();
+    let supplied = Rc::clone(&handed.0);
+    cb.credentials(move |url, url_username, allowed| {
+        attempts += 1;
+        if attempts > MAX_ATTEMPTS {
+            return Err(failure("authentication failed"));
+        }
+
+        let username = url_username.unwrap_or("git");
+
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some(auth) = &repo.auth {
+                let token = auth.token().map_err(unresolved)?;
+                if let Some(token) = token {
+                    let username = auth.username.as_deref().unwrap_or(username);
+                    return Cred::userpass_plaintext(username, &token);
+                }
+                if !auth.credential_helper {
+                    return Err(git2::Error::from_str(
+                        "auth token is not set or its environment variable is empty",
+                    ));
+                }
+
+                // Being asked again means the remote refused what the helper gave
+                if let Some((_, refused)) = supplied.borrow_mut().take() {
+                    credential::reject(url, &refused);
+                    return Err(failure(
+                        "the remote refused the credential helper's credentials",
+                    ));
+                }
+                let username = auth.username.as_deref().or(url_username);
+                let credential = credential::fill(url, username)
+                    .map_err(|e| failure(&format!("could not run 'git credential': {}", e)))?
+                    .ok_or_else(|| {
+                        failure("the credential helper has no credentials for the remote")
+                    })?;
+                let cred = Cred::userpass_plaintext(&credential.username, &credential.password);
+                *supplied.borrow_mut() = Some((url.to_string(), credential));
+                return cred;
+            }
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if let Some(ssh) = &repo.ssh {
+                // Try the agent first, then fall back to the configured key
+                if ssh.agent && !agent_tried {
+                    agent_tried = true;
+                    return Cred::ssh_key_from_agent(username);
+                }
+                if let Some(key) = &ssh.key {
+                    let public_key = ssh.public_key.as_deref().map(expand);
+                    let passphrase = ssh
+                        .passphrase
+                        .as_deref()
+                        .map(|passphrase| secret::resolve("ssh passphrase", passphrase))
+                        .transpose()
+                        .map_err(unresolved)?;
+                    return Cred::ssh_key(
+                        username,
+                        public_key.as_deref(),
+                        &expand(key),
+                        passphrase.as_deref(),
+                    );
+                }
+                if ssh.agent {
+                    return Err(failure("ssh-agent authentication failed"));
+                }
+            }
+        }
+
+        Cred::default()
+    });
+    handed
+}
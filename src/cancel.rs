@@ -0,0 +1,41 @@
+use crate::error::{Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// How often a cancellable wait looks at the flag.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Asks the transfers and checkouts in progress to stop at their next
+/// callback, and the syncs that have not started yet to fail right away.
+/// Returns whether cancellation had been asked for already.
+pub fn request() -> bool {
+    REQUESTED.swap(true, Ordering::Relaxed)
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Sleeps for `duration`, waking up early once cancellation is requested.
+pub fn sleep(duration: Duration) {
+    let until = Instant::now() + duration;
+    while !requested() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        thread::sleep(left.min(POLL));
+    }
+}
+
+/// Fails once cancellation is requested; libgit2 reports a checkout stopped
+/// by its callbacks as a success.
+pub(crate) fn ensure(what: &str) -> Result<()> {
+    match requested() {
+        true => Err(Error::Interrupted(what.to_string())),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::Result;
+use git2::{Delta, Oid, Repository};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The files `new` adds, modifies and deletes compared to `old`.
+#[derive(Serialize, Default)]
+pub(crate) struct Changes {
+    pub(crate) added: Vec<String>,
+    pub(crate) modified: Vec<String>,
+    pub(crate) deleted: Vec<String>,
+}
+
+impl Changes {
+    pub(crate) fn between(repository: &Repository, old: Oid, new: Oid) -> Result<Changes> {
+        let old = repository.find_commit(old)?.tree()?;
+        let new = repository.find_commit(new)?.tree()?;
+        let diff = repository.diff_tree_to_tree(Some(&old), Some(&new), None)?;
+        let mut changes = Changes::default();
+        for delta in diff.deltas() {
+            let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+                continue;
+            };
+            let path = path.to_string_lossy().into_owned();
+            match delta.status() {
+                Delta::Added => changes.added.push(path),
+                Delta::Deleted => changes.deleted.push(path),
+                _ => changes.modified.push(path),
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Every changed path, sorted.
+    pub(crate) fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self
+            .added
+            .iter()
+            .chain(&self.modified)
+            .chain(&self.deleted)
+            .cloned()
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+/// Paths of the files `new` adds, modifies or deletes compared to `old`.
+pub(crate) fn between(repository: &Repository, old: Oid, new: Oid) -> Result<Vec<String>> {
+    Ok(Changes::between(repository, old, new)?.paths())
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    path: &'a str,
+    branch: &'a str,
+    old: String,
+    new: String,
+    timestamp: u64,
+    #[serde(flatten)]
+    changes: &'a Changes,
+}
+
+/// Replaces `file` with the manifest of an update of the checkout at `path`.
+/// It is written next to its final name first, so readers never see half of it.
+pub(crate) fn write_manifest(
+    file: &Path,
+    path: &str,
+    branch: &str,
+    old: Oid,
+    new: Oid,
+    changes: &Changes,
+) -> Result<()> {
+    let manifest = Manifest {
+        path,
+        branch,
+        old: old.to_string(),
+        new: new.to_string(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+        changes,
+    };
+    let json = serde_json::to_string_pretty(&manifest).unwrap();
+    if let Some(parent) = file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let staged = file.with_extension("partial");
+    fs::write(&staged, json + "\n")?;
+    fs::rename(&staged, file)?;
+    Ok(())
+}
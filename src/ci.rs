@@ -0,0 +1,162 @@
+use crate::cancel;
+use crate::config::{Ci, Provider, Repo};
+use crate::error::{Error, Result};
+use crate::forge::Api;
+use crate::output::{Event, Output};
+use crate::retry;
+use git2::Oid;
+use serde::Deserialize;
+use std::time::Instant;
+use tracing::{debug, instrument};
+
+enum Checks {
+    Passed,
+    Pending,
+    /// Names of the checks that failed.
+    Failed(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct CombinedStatus {
+    state: String,
+    total_count: usize,
+    statuses: Vec<Status>,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    context: String,
+    state: String,
+}
+
+#[derive(Deserialize)]
+struct CheckRuns {
+    total_count: usize,
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Deserialize)]
+struct CheckRun {
+    name: String,
+    status: String,
+    conclusion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabCommit {
+    last_pipeline: Option<Pipeline>,
+}
+
+#[derive(Deserialize)]
+struct Pipeline {
+    id: u64,
+    status: String,
+}
+
+/// GitHub has commit statuses and check runs side by side; both count.
+/// Checks that could not all be read may still fail, so they count as pending.
+fn github(api: &Api, commit: Oid) -> Result<Checks> {
+    let url = format!("{}/commits/{}", api.project_url(), commit);
+    let (combined, all_statuses) =
+        api.pages::<CombinedStatus>(&format!("{}/status?per_page=100", url))?;
+    let (runs, all_runs) = api.pages::<CheckRuns>(&format!("{}/check-runs?per_page=100", url))?;
+
+    // Every page repeats the combined state and the totals
+    let state = combined
+        .first()
+        .map_or("pending", |page| page.state.as_str());
+    let total_statuses = combined.first().map_or(0, |page| page.total_count);
+    let total_runs = runs.first().map_or(0, |page| page.total_count);
+    let statuses: Vec<&Status> = combined.iter().flat_map(|page| &page.statuses).collect();
+    let check_runs: Vec<&CheckRun> = runs.iter().flat_map(|page| &page.check_runs).collect();
+    let truncated = !all_statuses
+        || !all_runs
+        || statuses.len() < total_statuses
+        || check_runs.len() < total_runs;
+
+    let mut failed: Vec<String> = statuses
+        .iter()
+        .filter(|status| matches!(status.state.as_str(), "failure" | "error"))
+        .map(|status| status.context.clone())
+        .collect();
+    let mut pending = truncated || (total_statuses > 0 && state == "pending");
+    for run in &check_runs {
+        match (run.status.as_str(), run.conclusion.as_deref()) {
+            ("completed", Some("success" | "neutral" | "skipped")) => {}
+            ("completed", _) => failed.push(run.name.clone()),
+            _ => pending = true,
+        }
+    }
+    Ok(match (failed.is_empty(), pending) {
+        (false, _) => Checks::Failed(failed),
+        // A commit gets its checks some time after it was pushed
+        (true, _) if statuses.is_empty() && check_runs.is_empty() => Checks::Pending,
+        (true, true) => Checks::Pending,
+        (true, false) => Checks::Passed,
+    })
+}
+
+fn gitlab(api: &Api, commit: Oid) -> Result<Checks> {
+    let url = format!("{}/repository/commits/{}", api.project_url(), commit);
+    let commit: GitlabCommit = api.json(&url)?;
+    Ok(match commit.last_pipeline {
+        Some(pipeline) if pipeline.status == "success" => Checks::Passed,
+        Some(pipeline) if matches!(pipeline.status.as_str(), "failed" | "canceled") => {
+            Checks::Failed(vec![format!("pipeline {}", pipeline.id)])
+        }
+        _ => Checks::Pending,
+    })
+}
+
+/// Polls the checks of `commit` until they have all passed. Fails once one
+/// of them failed or they are still pending after the configured timeout;
+/// network failures in between only cost a poll.
+#[instrument(skip_all, fields(%commit))]
+pub(crate) fn wait(repo: &Repo, ci: &Ci, commit: Oid, output: &Output) -> Result<()> {
+    let api = Api::new(repo, ci.provider, ci.api_url.as_deref())?;
+    let (timeout, interval) = (ci.timeout()?, ci.poll_interval()?);
+    let started = Instant::now();
+    let mut told = false;
+    loop {
+        let checks = match api.provider() {
+            Provider::Github => github(&api, commit),
+            Provider::Gitlab => gitlab(&api, commit),
+        };
+        match checks {
+            Ok(Checks::Passed) => break,
+            Ok(Checks::Failed(names)) => {
+                return Err(Error::Refused(format!(
+                    "checks of {} failed: {}",
+                    commit,
+                    names.join(", ")
+                )))
+            }
+            Ok(Checks::Pending) => {}
+            Err(Error::Git(e)) if retry::is_transient(&e) => {
+                debug!(error = %e, "could not poll the checks")
+            }
+            Err(e) => return Err(e),
+        }
+        if !told {
+            output.emit(Event::ChecksPending {
+                path: &repo.path,
+                commit: commit.to_string(),
+            });
+            told = true;
+        }
+        if started.elapsed() + interval > timeout {
+            return Err(Error::Timeout(format!(
+                "checks of {} still pending after {}",
+                commit,
+                humantime::format_duration(timeout)
+            )));
+        }
+        cancel::sleep(interval);
+        cancel::ensure(&format!("waiting for the checks of {}", commit))?;
+    }
+    output.emit(Event::ChecksPassed {
+        path: &repo.path,
+        commit: commit.to_string(),
+    });
+    Ok(())
+}
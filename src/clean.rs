@@ -0,0 +1,85 @@
+use crate::config::{CleanPolicy, Repo};
+use crate::error::Result;
+use crate::output::{Event, Output};
+use git2::build::CheckoutBuilder;
+use git2::{Repository, StatusOptions};
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::path::Path;
+
+/// Keeps a checkout from overwriting or deleting protected paths. Goes before
+/// `sparse::restrict`, as the first pathspec matching a path decides.
+pub fn protect(repo: &Repo, cb: &mut CheckoutBuilder) {
+    if repo.protected.is_empty() {
+        return;
+    }
+    for pattern in &repo.protected {
+        cb.path(format!("!{}", pattern));
+    }
+    if repo.sparse.is_empty() {
+        cb.path("*");
+    }
+}
+
+/// Whether a protected pattern matches `path` or one of the directories it is in.
+pub fn protected(patterns: &[Pattern], path: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches_path_with(ancestor, options))
+        })
+}
+
+/// Deletes untracked files, and with `untracked+ignored` ignored ones too, so
+/// the working tree holds nothing but the checked out commit.
+pub fn run(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    if repo.clean == CleanPolicy::None {
+        return Ok(());
+    }
+    let ignored = repo.clean == CleanPolicy::UntrackedIgnored;
+    let patterns = repo.protected()?;
+
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(ignored)
+        .recurse_ignored_dirs(ignored)
+        .exclude_submodules(true);
+    let root = Path::new(&repo.path);
+    let mut removed = 0;
+    for entry in repository.statuses(Some(&mut opts))?.iter() {
+        let status = entry.status();
+        if !(status.is_wt_new() || status.is_ignored()) {
+            continue;
+        }
+        let Some(path) = entry.path().map(Path::new) else {
+            continue;
+        };
+        if protected(&patterns, path) {
+            continue;
+        }
+        fs::remove_file(root.join(path))?;
+        removed += 1;
+
+        // Directories the removed files leave empty go as well
+        for parent in path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() || fs::remove_dir(root.join(parent)).is_err() {
+                break;
+            }
+        }
+    }
+
+    if removed > 0 {
+        output.emit(Event::Cleaned {
+            path: &repo.path,
+            files: removed,
+        });
+    }
+    Ok(())
+}
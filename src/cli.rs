@@ -0,0 +1,351 @@
+use crate::init::SettingsFormat;
+use crate::logging::LogFormat;
+use crate::selfupdate;
+use autoupdate::config::{self, Settings};
+use autoupdate::output::Format;
+#[cfg(windows)]
+use clap::ValueEnum;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Keep local git checkouts in sync with their remotes",
+    after_help = "Exit codes:
+  0   every repository is up to date (or updated, without --detailed-exit-codes)
+  2   invalid settings
+  3   git error
+  4   i/o error
+  5   authentication failed
+  6   a hook failed
+  7   an update was refused
+  8   a notification could not be sent
+  9   another run holds the lock
+  10  at least one repository was updated (--detailed-exit-codes)
+  11  at least one update is available in a dry run or held back (--detailed-exit-codes)
+  12  a clone, fetch or checkout took longer than its timeout
+  13  not enough free disk space for a clone or update
+  130 interrupted by Ctrl-C"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    /// Path to the settings file (defaults to ./settings.toml or ./settings.xml)
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Override the branch of every configured repository
+    #[arg(short, long)]
+    pub branch: Option<String>,
+
+    /// Override the polling interval used in watch mode (e.g. 30s, 5m)
+    #[arg(short, long)]
+    pub interval: Option<String>,
+
+    /// Keep running and re-check the remotes periodically
+    #[arg(long, conflicts_with = "once")]
+    pub watch: bool,
+
+    /// Run a single update cycle even if watch mode is enabled in the settings
+    #[arg(long)]
+    pub once: bool,
+
+    /// Show a dashboard of the repositories instead of log lines while watching
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Listen for GitHub/GitLab push webhooks on this address (e.g. 0.0.0.0:8080)
+    #[arg(long, value_name = "ADDR")]
+    pub serve: Option<String>,
+
+    /// Serve Prometheus metrics on /metrics at this address (e.g. 0.0.0.0:9100)
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+
+    /// Wait for another run that is updating the same repository instead of failing
+    #[arg(long, overrides_with = "no_wait")]
+    pub wait: bool,
+
+    /// Fail right away when another run is updating the same repository (default)
+    #[arg(long, overrides_with = "wait")]
+    pub no_wait: bool,
+
+    /// Fetch this many more commits of history before updating
+    #[arg(long, value_name = "N", conflicts_with = "unshallow")]
+    pub deepen: Option<u32>,
+
+    /// Fetch the complete history before updating
+    #[arg(long)]
+    pub unshallow: bool,
+
+    /// Number of repositories to update at the same time
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Fetch and report pending commits without touching the working tree
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Use what was fetched before instead of contacting any remote
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Exit with 10 when a repository was updated and 11 when updates were found but not applied
+    #[arg(long)]
+    pub detailed_exit_codes: bool,
+
+    /// Print a systemd unit that runs the watch mode with this settings file and exit
+    #[arg(long)]
+    pub generate_systemd_unit: bool,
+
+    /// Output format; `json` emits one machine-readable event per line
+    #[arg(short, long, value_enum, default_value = "human", global = true)]
+    pub output: Format,
+
+    /// Log more details, repeat for even more (-vv)
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet", global = true)]
+    pub verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Never draw progress bars; they are only drawn on a terminal anyway
+    #[arg(long, global = true)]
+    pub no_progress: bool,
+
+    /// Print without colors, like setting NO_COLOR
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Format of the human-readable log lines
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Subcommand)]
+pub enum Action {
+    /// Reset a repository back to the commit it was at before an earlier update
+    Rollback {
+        /// Number of updates to undo
+        #[arg(default_value_t = 1)]
+        steps: usize,
+
+        /// Repository to roll back, required when more than one is configured
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Show the recorded syncs of the configured repositories
+    History {
+        /// Only show the history of this repository
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Show at most this many of the most recent entries per repository
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Let an update that waits for approval through, or list the waiting ones
+    Approve {
+        /// Commit the update has to go to, checked against the waiting one
+        commit: Option<String>,
+
+        /// Repository to approve the update of, required when more than one is configured
+        #[arg(long)]
+        path: Option<String>,
+
+        /// List the updates waiting for approval with their commits instead
+        #[arg(long, conflicts_with = "commit")]
+        list: bool,
+    },
+    /// Repack the object databases and prune their unreachable objects now
+    Maintain {
+        /// Only maintain this repository
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Ask the running daemon over its control socket to sync, pause or resume
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Check the settings file and report every problem found in it
+    Validate,
+    /// Write a new settings file, asking for any value not given as a flag
+    Init {
+        /// URL of the repository to keep in sync
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Directory to check the repository out into
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Branch to follow
+        #[arg(long)]
+        branch: Option<String>,
+
+        /// Format of the settings file (defaults to the extension of --config, or xml)
+        #[arg(long, value_enum)]
+        format: Option<SettingsFormat>,
+
+        /// Overwrite the settings file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Manage the Windows service that runs the watch mode
+    #[cfg(windows)]
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    /// Replace this executable with the latest release
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+
+        /// Reinstall the latest release even if it is not newer
+        #[arg(long)]
+        force: bool,
+
+        /// GitHub repository the releases are published in
+        #[arg(long, value_name = "OWNER/NAME", default_value = selfupdate::DEFAULT_REPOSITORY)]
+        repository: String,
+
+        /// Require a detached signature by this gpg key (fingerprint or long key id)
+        #[arg(long = "key", value_name = "KEY")]
+        keys: Vec<String>,
+
+        /// Look up gpg keys in this keyring instead of the default one
+        #[arg(long, value_name = "FILE")]
+        keyring: Option<String>,
+
+        /// Require a detached ssh signature by a key listed in this allowed signers file
+        #[arg(long, value_name = "FILE")]
+        allowed_signers: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CtlCommand {
+    /// Sync now instead of waiting for the next check
+    Sync {
+        /// Only sync this repository
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Show the repositories the daemon keeps in sync
+    Status,
+    /// Stop the scheduled checks
+    Pause {
+        /// Only pause this repository
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Resume the scheduled checks
+    Resume {
+        /// Only resume this repository
+        #[arg(long)]
+        path: Option<String>,
+    },
+}
+
+#[cfg(windows)]
+#[derive(Subcommand)]
+pub enum ServiceCommand {
+    /// Register the service, using the current settings file
+    Install {
+        /// Name of the service
+        #[arg(long, default_value = "autoupdate")]
+        name: String,
+
+        /// Name shown in the services console (defaults to the name)
+        #[arg(long)]
+        display_name: Option<String>,
+
+        /// When the service starts
+        #[arg(long, value_enum, default_value = "auto")]
+        start: StartType,
+
+        /// How long to wait before restarting the service after it fails
+        #[arg(long, value_name = "DURATION", default_value = "60s")]
+        restart_delay: String,
+
+        /// Leave the service stopped after it fails
+        #[arg(long)]
+        no_restart: bool,
+    },
+    /// Stop and remove the service
+    Uninstall {
+        /// Name of the service
+        #[arg(long, default_value = "autoupdate")]
+        name: String,
+    },
+    /// Entry point used by the service control manager
+    #[command(hide = true)]
+    Run {
+        #[arg(long, default_value = "autoupdate")]
+        name: String,
+    },
+}
+
+#[cfg(windows)]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StartType {
+    /// Start with Windows
+    Auto,
+    /// Start shortly after Windows has booted
+    Delayed,
+    /// Only start when asked to
+    Manual,
+}
+
+impl Cli {
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(branch) = &self.branch {
+            for repo in &mut settings.repositories {
+                repo.branch = branch.clone();
+            }
+        }
+        if self.dry_run {
+            for repo in &mut settings.repositories {
+                repo.dry_run = true;
+            }
+        }
+        if self.offline {
+            for repo in &mut settings.repositories {
+                repo.offline = true;
+            }
+        }
+        if let Some(interval) = &self.interval {
+            settings.interval = Some(interval.clone());
+        }
+        if let Some(listen) = &self.serve {
+            settings.webhook.get_or_insert_with(Default::default).listen = Some(listen.clone());
+        }
+        if let Some(listen) = &self.metrics {
+            settings.metrics = Some(config::Metrics {
+                listen: listen.clone(),
+            });
+        }
+        if self.wait {
+            settings.wait = true;
+        }
+        if self.no_wait {
+            settings.wait = false;
+        }
+        if let Some(jobs) = self.jobs {
+            settings.concurrency = Some(jobs);
+        }
+        if self.watch {
+            settings.watch = true;
+        }
+        if self.once {
+            settings.watch = false;
+        }
+    }
+}
@@ -0,0 +1,2548 @@
+use crate::error::{Error, Result};
+use crate::schedule::Cron;
+use crate::secret;
+use quick_xml::de;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CANDIDATES: [&str; 2] = ["./settings.toml", "./settings.xml"];
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+const DEFAULT_DEPTH: u32 = 1;
+const DEFAULT_REMOTE: &str = "origin";
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_KEEP: usize = 5;
+const DEFAULT_KEEP_RELEASES: u32 = 5;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+const DEFAULT_MAINTENANCE_EVERY: u32 = 50;
+const DEFAULT_CI_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_CI_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_ROLLOUT_CANARY: f64 = 0.1;
+const DEFAULT_ROLLOUT_SOAK: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_LEADER_LEASE: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_MQTT_COMMAND_TOPIC: &str = "autoupdate/{host}/command";
+const DEFAULT_MQTT_STATUS_TOPIC: &str = "autoupdate/{host}/status";
+/// Shortest wait between polls of a remote or forge.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Shortest wait between redraws of the progress bars.
+const MIN_REFRESH: Duration = Duration::from_millis(1);
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(rename = "repository", default)]
+    pub repositories: Vec<Repo>,
+    /// Files and directories of files adding more repositories, read in order.
+    #[serde(rename = "include", default)]
+    pub includes: Vec<String>,
+    pub interval: Option<String>,
+    /// Random delay of up to this long before the first check, spreading hosts started together.
+    pub splay: Option<String>,
+    /// Random delay of up to this long added to every scheduled check.
+    pub jitter: Option<String>,
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(default)]
+    pub webhook: Option<Webhook>,
+    #[serde(default)]
+    pub notify: Notify,
+    pub metrics: Option<Metrics>,
+    pub api: Option<Api>,
+    pub grpc: Option<Grpc>,
+    /// Unix socket `autoupdate ctl` reaches the running daemon over.
+    pub socket: Option<String>,
+    pub mqtt: Option<Mqtt>,
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub wait: bool,
+    pub log: Option<LogFile>,
+    pub syslog: Option<Syslog>,
+    pub event_log: Option<EventLog>,
+    pub telemetry: Option<Telemetry>,
+    pub sentry: Option<Sentry>,
+    pub progress: Option<ProgressBars>,
+    /// User, or `user:group`, the process switches to when started as root.
+    pub run_as: Option<String>,
+    /// Deletes the checkouts of repositories removed from the settings while watching.
+    #[serde(default)]
+    pub delete_removed: bool,
+    /// The settings file and every file and directory it includes, watched for changes.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LogFile {
+    pub file: String,
+    #[serde(default)]
+    pub rotate: Rotation,
+    pub max_size: Option<String>,
+    pub keep: Option<usize>,
+}
+
+impl LogFile {
+    pub fn max_size(&self) -> Result<u64> {
+        match &self.max_size {
+            Some(max_size) => parse_size(max_size)
+                .ok_or_else(|| Error::Config(format!("invalid log max_size '{}'", max_size))),
+            None => Ok(DEFAULT_LOG_MAX_SIZE),
+        }
+    }
+
+    /// Number of rotated files kept besides the current one.
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_LOG_KEEP)
+    }
+}
+
+/// Look of the progress bars drawn on a terminal.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProgressBars {
+    /// indicatif template, e.g. `{bar:40} {bytes}/{total_bytes}`.
+    pub template: Option<String>,
+    /// Characters the spinner cycles through, the last one shown when done.
+    pub spinner: Option<String>,
+    /// How often the bars are redrawn, e.g. `100ms`.
+    pub refresh: Option<String>,
+}
+
+impl ProgressBars {
+    pub fn refresh(&self) -> Result<Option<Duration>> {
+        self.refresh
+            .as_deref()
+            .map(|value| fine_duration("progress refresh", value))
+            .transpose()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    #[default]
+    Size,
+    Daily,
+}
+
+/// Sends every log line to the local syslog daemon too.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Syslog {
+    pub ident: Option<String>,
+    #[serde(default)]
+    pub facility: Facility,
+}
+
+impl Syslog {
+    pub fn ident(&self) -> &str {
+        self.ident.as_deref().unwrap_or("autoupdate")
+    }
+}
+
+/// OTLP collector the spans of every sync, with the clones, fetches,
+/// checkouts and hooks in it, are exported to over HTTP.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Telemetry {
+    /// Base URL of the collector, such as `http://localhost:4318`.
+    pub endpoint: String,
+    pub service_name: Option<String>,
+    #[serde(rename = "header", default)]
+    pub headers: Vec<Header>,
+}
+
+impl Telemetry {
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or("autoupdate")
+    }
+
+    pub fn traces_url(&self) -> String {
+        format!("{}/v1/traces", self.endpoint.trim_end_matches('/'))
+    }
+}
+
+/// Sentry project failures are reported to, with the steps that led to them.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Sentry {
+    pub dsn: Option<String>,
+    pub dsn_env: Option<String>,
+    pub environment: Option<String>,
+}
+
+impl Sentry {
+    pub fn dsn(&self) -> Result<Option<String>> {
+        secret::lookup("sentry dsn", self.dsn.as_deref(), self.dsn_env.as_deref())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Facility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+/// Reports every log line to the Windows Event Log too, in the Application
+/// log under `source`.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EventLog {
+    pub source: Option<String>,
+}
+
+impl EventLog {
+    pub fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or("autoupdate")
+    }
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Notify {
+    #[serde(rename = "webhook", default)]
+    pub webhooks: Vec<NotifyWebhook>,
+    #[serde(rename = "email", default)]
+    pub emails: Vec<NotifyEmail>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyEmail {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+    pub from: String,
+    #[serde(rename = "to", default)]
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+impl NotifyEmail {
+    pub fn password(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "smtp password",
+            self.password.as_deref(),
+            self.password_env.as_deref(),
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    Tls,
+    None,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyWebhook {
+    pub url: String,
+    pub format: Option<NotifyFormat>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotifyFormat {
+    Slack,
+    Discord,
+    Json,
+}
+
+/// Address the Prometheus `/metrics` endpoint listens on; it shares the
+/// webhook server when both use the same address.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    pub listen: String,
+}
+
+/// Address the control API under `/api` listens on; like the metrics it
+/// shares the webhook server when both use the same address.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Api {
+    pub listen: String,
+    /// Required as a bearer token when set.
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+}
+
+impl Api {
+    pub fn token(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "api token",
+            self.token.as_deref(),
+            self.token_env.as_deref(),
+        )
+    }
+}
+
+/// Address the gRPC control service listens on, over TLS when a certificate
+/// is set and requiring client certificates signed by `client_ca` when that
+/// is set too.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Grpc {
+    pub listen: String,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub client_ca: Option<String>,
+    /// Serves the reflection service for tools such as grpcurl.
+    #[serde(default)]
+    pub reflection: bool,
+    /// Required as a bearer token in the `authorization` metadata when set.
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+}
+
+impl Grpc {
+    pub fn token(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "grpc token",
+            self.token.as_deref(),
+            self.token_env.as_deref(),
+        )
+    }
+}
+
+/// MQTT broker the daemon takes sync commands from and publishes the
+/// results of its syncs to. `{host}` in a topic stands for the host name.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Mqtt {
+    /// `mqtt://host[:port]`, or `mqtts://host[:port]` over TLS.
+    pub broker: String,
+    pub client_id: Option<String>,
+    pub command_topic: Option<String>,
+    pub status_topic: Option<String>,
+    /// CA certificate of a broker whose certificate is not publicly trusted.
+    pub ca: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_env: Option<String>,
+}
+
+impl Mqtt {
+    pub fn command_topic(&self) -> &str {
+        self.command_topic
+            .as_deref()
+            .unwrap_or(DEFAULT_MQTT_COMMAND_TOPIC)
+    }
+
+    pub fn status_topic(&self) -> &str {
+        self.status_topic
+            .as_deref()
+            .unwrap_or(DEFAULT_MQTT_STATUS_TOPIC)
+    }
+
+    pub fn password(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "mqtt password",
+            self.password.as_deref(),
+            self.password_env.as_deref(),
+        )
+    }
+
+    /// Host and port of the broker, and whether it is reached over TLS.
+    pub fn address(&self) -> Result<(String, u16, bool)> {
+        let invalid = || {
+            Error::Config(format!(
+                "invalid mqtt broker '{}', expected mqtt://host[:port] or mqtts://host[:port]",
+                self.broker
+            ))
+        };
+        let (tls, rest) = match self.broker.split_once("://") {
+            Some(("mqtt", rest)) => (false, rest),
+            Some(("mqtts", rest)) => (true, rest),
+            _ => return Err(invalid()),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (rest, if tls { 8883 } else { 1883 }),
+        };
+        if host.is_empty() || host.contains('/') {
+            return Err(invalid());
+        }
+        Ok((host.to_string(), port, tls))
+    }
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    pub listen: Option<String>,
+    pub secret: Option<String>,
+    pub secret_env: Option<String>,
+}
+
+impl Webhook {
+    pub fn secret(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "webhook secret",
+            self.secret.as_deref(),
+            self.secret_env.as_deref(),
+        )
+    }
+}
+
+impl Settings {
+    pub fn interval(&self) -> Result<Duration> {
+        match &self.interval {
+            Some(interval) => duration("interval", interval),
+            None => Ok(DEFAULT_INTERVAL),
+        }
+    }
+
+    pub fn splay(&self) -> Result<Option<Duration>> {
+        self.splay
+            .as_deref()
+            .map(|splay| duration("splay", splay))
+            .transpose()
+    }
+
+    pub fn jitter(&self) -> Result<Option<Duration>> {
+        self.jitter
+            .as_deref()
+            .map(|jitter| duration("jitter", jitter))
+            .transpose()
+    }
+
+    /// How many repositories are updated at the same time, one by one unless configured.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(1).max(1)
+    }
+
+    /// Checks the values serde cannot, so mistakes surface before any repository is touched.
+    pub fn validate(&self) -> Result<()> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(Error::Config(problem)),
+            None => Ok(()),
+        }
+    }
+
+    /// Describes everything that is wrong with the settings, in file order.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.repositories.is_empty() {
+            problems.push("at least one repository must be configured".to_string());
+        }
+        let mut check = |result: Result<()>| {
+            if let Err(Error::Config(message)) = result {
+                problems.push(message);
+            }
+        };
+        check(
+            self.interval()
+                .and_then(|interval| at_least("interval", interval, MIN_INTERVAL)),
+        );
+        check(self.splay().map(drop));
+        check(self.jitter().map(drop));
+        if let Some(log) = &self.log {
+            check(log.max_size().map(drop));
+        }
+        if let Some(progress) = &self.progress {
+            check(crate::output::progress_style(Some(progress)).map(drop));
+            check(progress.refresh().and_then(|refresh| match refresh {
+                Some(refresh) => at_least("progress refresh", refresh, MIN_REFRESH),
+                None => Ok(()),
+            }));
+        }
+        if let Some(run_as) = &self.run_as {
+            check(crate::privileges::check(run_as));
+        }
+        if self.syslog.is_some() && !cfg!(unix) {
+            check(Err(Error::Config(
+                "syslog is only supported on unix systems".to_string(),
+            )));
+        }
+        if self.event_log.is_some() && !cfg!(windows) {
+            check(Err(Error::Config(
+                "event_log is only supported on Windows".to_string(),
+            )));
+        }
+        if let Some(telemetry) = &self.telemetry {
+            if !cfg!(feature = "otel") {
+                check(Err(Error::Config(
+                    "telemetry needs autoupdate built with the otel feature".to_string(),
+                )));
+            }
+            if !telemetry.endpoint.starts_with("http://")
+                && !telemetry.endpoint.starts_with("https://")
+            {
+                check(Err(Error::Config(format!(
+                    "telemetry endpoint '{}' must be an http or https URL",
+                    telemetry.endpoint
+                ))));
+            }
+            for header in &telemetry.headers {
+                for problem in header.problems("telemetry") {
+                    check(Err(problem));
+                }
+            }
+        }
+        if let Some(sentry) = &self.sentry {
+            if !cfg!(feature = "sentry") {
+                check(Err(Error::Config(
+                    "sentry needs autoupdate built with the sentry feature".to_string(),
+                )));
+            }
+            if sentry.dsn.is_some() == sentry.dsn_env.is_some() {
+                check(Err(Error::Config(
+                    "sentry needs exactly one of dsn and dsn_env".to_string(),
+                )));
+            }
+        }
+        if self.socket.is_some() && !cfg!(unix) {
+            check(Err(Error::Config(
+                "socket is only supported on unix systems".to_string(),
+            )));
+        }
+        if let Some(mqtt) = &self.mqtt {
+            if !cfg!(feature = "mqtt") {
+                check(Err(Error::Config(
+                    "mqtt needs autoupdate built with the mqtt feature".to_string(),
+                )));
+            }
+            match mqtt.address() {
+                Ok((_, _, false)) if mqtt.ca.is_some() => check(Err(Error::Config(
+                    "mqtt ca needs TLS, use an mqtts:// broker".to_string(),
+                ))),
+                result => check(result.map(drop)),
+            }
+            if mqtt.username.is_none() && (mqtt.password.is_some() || mqtt.password_env.is_some()) {
+                check(Err(Error::Config(
+                    "mqtt password needs a username".to_string(),
+                )));
+            }
+        }
+        if let Some(grpc) = &self.grpc {
+            if !cfg!(feature = "grpc") {
+                check(Err(Error::Config(
+                    "grpc needs autoupdate built with the grpc feature".to_string(),
+                )));
+            }
+            if grpc.cert.is_some() != grpc.key.is_some() {
+                check(Err(Error::Config(
+                    "grpc needs both cert and key for TLS".to_string(),
+                )));
+            }
+            if grpc.client_ca.is_some() && grpc.cert.is_none() {
+                check(Err(Error::Config(
+                    "grpc client_ca needs TLS, set cert and key too".to_string(),
+                )));
+            }
+        }
+
+        let mut paths = Vec::new();
+        let mut worktrees = Vec::new();
+        let mut connect = None;
+        let mut transfer = None;
+        for (index, repo) in self.repositories.iter().enumerate() {
+            let name = match repo.path.is_empty() {
+                true => format!("repository #{}", index + 1),
+                false => format!("'{}'", repo.path),
+            };
+            for (field, value) in [
+                ("url", &repo.url),
+                ("path", &repo.path),
+                ("branch", &repo.branch),
+            ] {
+                if value.trim().is_empty() {
+                    check(Err(Error::Config(format!(
+                        "{} has an empty {}",
+                        name, field
+                    ))));
+                }
+            }
+            if !repo.url.is_empty() && !valid_url(&repo.url) {
+                check(Err(Error::Config(format!(
+                    "{} has a malformed url '{}'",
+                    name, repo.url
+                ))));
+            }
+            if Path::new(&repo.path).is_file() {
+                check(Err(Error::Config(format!(
+                    "{} points at a file, not a directory",
+                    name
+                ))));
+            }
+            if !repo.path.is_empty() {
+                let path = Path::new(&repo.path);
+                if paths.contains(&path) {
+                    check(Err(Error::Config(format!(
+                        "{} is configured more than once",
+                        name
+                    ))));
+                }
+                paths.push(path);
+            }
+            if repo.tag.is_some() && repo.rev.is_some() {
+                check(Err(Error::Config(format!(
+                    "{} could not set both tag and rev",
+                    name
+                ))));
+            }
+            if repo.mirror {
+                for (option, set) in [
+                    ("tag", repo.tag.is_some()),
+                    ("rev", repo.rev.is_some()),
+                    ("sparse", !repo.sparse.is_empty()),
+                    ("submodules", repo.submodules),
+                    ("lfs", repo.lfs),
+                    ("stash", repo.stash),
+                    ("releases", repo.releases.is_some()),
+                    ("manifest", repo.manifest.is_some()),
+                    ("propagate", repo.propagate.is_some()),
+                    ("filter", repo.filter.is_some()),
+                    ("ci", repo.ci.is_some()),
+                    ("approval", repo.approval),
+                    ("rollout", repo.rollout.is_some()),
+                    (
+                        "maintenance windows",
+                        !repo.windows.is_empty() || !repo.blackouts.is_empty(),
+                    ),
+                ] {
+                    if set {
+                        check(Err(Error::Config(format!(
+                            "{} is a mirror and could not use {}",
+                            name, option
+                        ))));
+                    }
+                }
+            }
+            let source = match (repo.vcs, &repo.tarball) {
+                (Vcs::Mercurial, _) => Some("is a Mercurial repository"),
+                (Vcs::Git, Some(_)) => Some("downloads tarballs"),
+                (Vcs::Git, None) => None,
+            };
+            if let Some(source) = source {
+                for (option, set) in [
+                    ("mirror", repo.mirror),
+                    ("worktree_of", repo.worktree_of.is_some()),
+                    ("checkouts", !repo.checkouts.is_empty()),
+                    ("tag", repo.tag.is_some()),
+                    ("atomic", repo.atomic),
+                    ("sparse", !repo.sparse.is_empty()),
+                    ("submodules", repo.submodules),
+                    ("lfs", repo.lfs),
+                    ("stash", repo.stash),
+                    ("releases", repo.releases.is_some()),
+                    ("archive", repo.archive.is_some()),
+                    ("manifest", repo.manifest.is_some()),
+                    ("propagate", repo.propagate.is_some()),
+                    ("verify", repo.verify.is_some()),
+                    ("integrity", repo.integrity.is_some()),
+                    ("maintenance", repo.maintenance.is_some()),
+                    ("filter", repo.filter.is_some()),
+                    ("ci", repo.ci.is_some()),
+                    ("approval", repo.approval),
+                    ("rollout", repo.rollout.is_some()),
+                    ("network", repo.network.is_some()),
+                    (
+                        "tls pins",
+                        repo.tls.as_ref().is_some_and(|tls| !tls.pins.is_empty()),
+                    ),
+                ] {
+                    if set {
+                        check(Err(Error::Config(format!(
+                            "{} {} and could not use {}",
+                            name, source, option
+                        ))));
+                    }
+                }
+            }
+            if repo.vcs == Vcs::Mercurial {
+                // hg takes these from its own configuration
+                for (option, set) in [
+                    ("tarball", repo.tarball.is_some()),
+                    ("auth", repo.auth.is_some()),
+                    ("tls", repo.tls.is_some()),
+                    ("proxy", repo.proxy.is_some()),
+                    ("header", !repo.headers.is_empty()),
+                    ("dirty backup", repo.dirty == DirtyPolicy::Backup),
+                ] {
+                    if set {
+                        check(Err(Error::Config(format!(
+                            "{} is a Mercurial repository and could not use {}",
+                            name, option
+                        ))));
+                    }
+                }
+            }
+            if let (Vcs::Git, Some(tarball)) = (repo.vcs, &repo.tarball) {
+                check(crate::tarball::Download::new(repo, tarball).map(drop));
+            }
+            if let Some(shared) = &repo.worktree_of {
+                for (option, set) in [
+                    ("mirror", repo.mirror),
+                    ("atomic", repo.atomic),
+                    ("sparse", !repo.sparse.is_empty()),
+                    ("a depth", repo.depth.is_some_and(|depth| depth > 0)),
+                    (
+                        "corruption repair",
+                        repo.corruption == CorruptionPolicy::Repair,
+                    ),
+                ] {
+                    if set {
+                        check(Err(Error::Config(format!(
+                            "{} is a worktree and could not use {}",
+                            name, option
+                        ))));
+                    }
+                }
+                if Path::new(shared) == Path::new(&repo.path) {
+                    check(Err(Error::Config(format!(
+                        "{} is a worktree of itself",
+                        name
+                    ))));
+                }
+                // A branch is checked out in one worktree at most
+                if worktrees.contains(&(Path::new(shared), &repo.branch)) {
+                    check(Err(Error::Config(format!(
+                        "{} checks out branch '{}', which another worktree of '{}' already has",
+                        name, repo.branch, shared
+                    ))));
+                }
+                worktrees.push((Path::new(shared), &repo.branch));
+            }
+            if repo.atomic {
+                for (option, set) in [
+                    ("mirror", repo.mirror),
+                    ("the merge strategy", repo.strategy == Strategy::Merge),
+                    ("protected paths", !repo.protected.is_empty()),
+                    ("releases", repo.releases.is_some()),
+                ] {
+                    if set {
+                        check(Err(Error::Config(format!(
+                            "{} is updated atomically and could not use {}",
+                            name, option
+                        ))));
+                    }
+                }
+            }
+            if let Some(auth) = &repo.auth {
+                if let Some(Err(e)) = auth.token.as_deref().and_then(secret::keyring_entry) {
+                    check(Err(e));
+                }
+                if let Some(file) = auth
+                    .token_file
+                    .as_ref()
+                    .filter(|file| !Path::new(file).is_file())
+                {
+                    check(Err(Error::Config(format!(
+                        "{} has auth token_file '{}', which does not exist",
+                        name, file
+                    ))));
+                }
+            }
+            if let Some(ssh) = &repo.ssh {
+                if let Some(Err(e)) = ssh.passphrase.as_deref().and_then(secret::keyring_entry) {
+                    check(Err(e));
+                }
+                for fingerprint in &ssh.host_keys.fingerprints {
+                    if !fingerprint.starts_with("SHA256:") {
+                        check(Err(Error::Config(format!(
+                            "{} has host key fingerprint '{}', which does not start with 'SHA256:'",
+                            name, fingerprint
+                        ))));
+                    }
+                }
+            }
+            if let Some(tls) = &repo.tls {
+                if let Some(ca_file) = tls
+                    .ca_file
+                    .as_ref()
+                    .filter(|file| !Path::new(file).is_file())
+                {
+                    check(Err(Error::Config(format!(
+                        "{} has tls ca_file '{}', which does not exist",
+                        name, ca_file
+                    ))));
+                }
+                for pin in &tls.pins {
+                    let normalized = crate::tls::normalize(pin);
+                    if normalized.len() != 64 || !normalized.bytes().all(|b| b.is_ascii_hexdigit())
+                    {
+                        check(Err(Error::Config(format!(
+                            "{} has tls pin '{}', which is not a sha256 fingerprint",
+                            name, pin
+                        ))));
+                    }
+                }
+                if tls.skip_verify && (tls.ca_file.is_some() || !tls.pins.is_empty()) {
+                    check(Err(Error::Config(format!(
+                        "{} sets tls skip_verify together with a ca_file or pins",
+                        name
+                    ))));
+                }
+            }
+            if let Some(ownership) = &repo.ownership {
+                if cfg!(not(unix)) {
+                    check(Err(Error::Config(format!(
+                        "{} sets ownership, which is only supported on Unix",
+                        name
+                    ))));
+                }
+                check(crate::ownership::ids(ownership).map(drop));
+                check(ownership.file_mode().map(drop));
+                check(ownership.dir_mode().map(drop));
+            }
+            if let Some(network) = &repo.network {
+                if repo.proxy.is_some() {
+                    check(Err(Error::Config(format!(
+                        "{} sets network together with a proxy, which resolves the host itself",
+                        name
+                    ))));
+                }
+                if crate::resolve::endpoint(&repo.url).is_none() {
+                    check(Err(Error::Config(format!(
+                        "{} sets network, which only applies to https remotes",
+                        name
+                    ))));
+                }
+                for host in &network.hosts {
+                    check(host.addresses().map(drop));
+                }
+            }
+            for header in &repo.headers {
+                for problem in header.problems(&name) {
+                    check(Err(problem));
+                }
+                if RESERVED_HEADERS
+                    .iter()
+                    .any(|reserved| reserved.eq_ignore_ascii_case(&header.name))
+                {
+                    check(Err(Error::Config(format!(
+                        "{} sets header {}, which libgit2 sets itself",
+                        name, header.name
+                    ))));
+                }
+            }
+            check(repo.protected().map(drop));
+            if let Some(propagate) = &repo.propagate {
+                check(propagate.excludes().map(drop));
+                let destination = Path::new(&propagate.destination);
+                if propagate.destination.is_empty() {
+                    check(Err(Error::Config(format!(
+                        "{} has an empty propagate destination",
+                        name
+                    ))));
+                } else if !propagate.remote()
+                    && (destination.starts_with(&repo.path)
+                        || Path::new(&repo.path).starts_with(destination))
+                {
+                    check(Err(Error::Config(format!(
+                        "{} propagates to '{}', which overlaps the checkout",
+                        name, propagate.destination
+                    ))));
+                }
+            }
+            if let Some(filter) = &repo.filter {
+                check(filter.paths().map(drop));
+            }
+            match crate::window::Windows::of(repo) {
+                Ok(Some(windows)) if windows.opens_after(0).is_none() => {
+                    check(Err(Error::Config(format!(
+                        "{} has maintenance windows that never allow an update",
+                        name
+                    ))))
+                }
+                result => check(result.map(drop)),
+            }
+            if let Some(rollout) = &repo.rollout {
+                if !(0.0..=1.0).contains(&rollout.canary()) {
+                    check(Err(Error::Config(format!(
+                        "{} has rollout canary {}, expected a fraction from 0 to 1",
+                        name,
+                        rollout.canary()
+                    ))));
+                }
+                check(rollout.soak().map(drop));
+                if let Some(Err(e)) = rollout.token.as_deref().and_then(secret::keyring_entry) {
+                    check(Err(e));
+                }
+                if !rollout.url.starts_with("http://") && !rollout.url.starts_with("https://") {
+                    check(Err(Error::Config(format!(
+                        "{} has rollout url '{}', expected an http or https URL",
+                        name, rollout.url
+                    ))));
+                }
+            }
+            if let Some(leader) = &repo.leader {
+                match (&leader.url, &leader.file) {
+                    (Some(_), Some(_)) | (None, None) => check(Err(Error::Config(format!(
+                        "{} has a leader election, which needs exactly one of url and file",
+                        name
+                    )))),
+                    (Some(url), None)
+                        if !url.starts_with("http://") && !url.starts_with("https://") =>
+                    {
+                        check(Err(Error::Config(format!(
+                            "{} has leader url '{}', expected an http or https URL",
+                            name, url
+                        ))))
+                    }
+                    _ => {}
+                }
+                check(leader.lease().map(drop));
+                if let Some(Err(e)) = leader.token.as_deref().and_then(secret::keyring_entry) {
+                    check(Err(e));
+                }
+            }
+            if let Some(ci) = &repo.ci {
+                check(ci.timeout().map(drop));
+                check(
+                    ci.poll_interval()
+                        .and_then(|poll| at_least("ci poll interval", poll, MIN_INTERVAL)),
+                );
+                check(crate::forge::Api::new(repo, ci.provider, ci.api_url.as_deref()).map(drop));
+            }
+            match repo.schedule() {
+                Ok(Some(cron)) if cron.next_after(0).is_none() => {
+                    check(Err(Error::Config(format!(
+                        "{} has schedule '{}', which never fires",
+                        name,
+                        repo.schedule.as_deref().unwrap_or_default()
+                    ))))
+                }
+                result => check(result.map(drop)),
+            }
+            check(repo.hooks.timeout().map(drop));
+            for variable in &repo.hooks.env {
+                if variable.name.is_empty() || variable.name.contains(['=', '\0']) {
+                    check(Err(Error::Config(format!(
+                        "{} has hook variable name '{}', which is not valid",
+                        name, variable.name
+                    ))));
+                }
+                if RESERVED_VARIABLES.contains(&variable.name.as_str()) {
+                    check(Err(Error::Config(format!(
+                        "{} sets hook variable {}, which the updater sets itself",
+                        name, variable.name
+                    ))));
+                }
+            }
+            check(repo.retry.delay().map(drop));
+            check(repo.retry.max_delay().map(drop));
+            check(repo.max_bandwidth().map(drop));
+            check(repo.min_free_space().map(drop));
+            check(repo.quota().map(drop));
+            check(repo.timeouts.connect().map(drop));
+            check(repo.timeouts.transfer().map(drop));
+            check(repo.timeouts.checkout().map(drop));
+            for (field, value, first) in [
+                ("connect", repo.timeouts.connect(), &mut connect),
+                ("transfer", repo.timeouts.transfer(), &mut transfer),
+            ] {
+                let Ok(Some(value)) = value else {
+                    continue;
+                };
+                match first {
+                    Some(first) if *first != value => check(Err(Error::Config(format!(
+                        "{} has a {} timeout of {}, but libgit2 only has one for the whole process and an earlier repository sets {}",
+                        name,
+                        field,
+                        humantime::format_duration(value),
+                        humantime::format_duration(*first)
+                    )))),
+                    Some(_) => {}
+                    None => *first = Some(value),
+                }
+            }
+            for file in repo.integrity.iter().flat_map(|integrity| &integrity.files) {
+                if file.sha256.len() != 64 || !file.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                    check(Err(Error::Config(format!(
+                        "{} has an invalid sha256 '{}' for '{}'",
+                        name, file.sha256, file.path
+                    ))));
+                }
+            }
+        }
+
+        for email in &self.notify.emails {
+            if email.to.is_empty() {
+                check(Err(Error::Config(format!(
+                    "email notification from '{}' has no recipients",
+                    email.from
+                ))));
+            }
+            if email.username.is_some() && email.password.is_none() && email.password_env.is_none()
+            {
+                check(Err(Error::Config(format!(
+                    "email notification from '{}' has a username but no password",
+                    email.from
+                ))));
+            }
+        }
+        problems
+    }
+}
+
+/// Accepts URLs with a scheme, scp-like `user@host:path` remotes and local paths.
+fn valid_url(url: &str) -> bool {
+    if url.chars().any(char::is_whitespace) {
+        return false;
+    }
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            matches!(
+                scheme,
+                "http" | "https" | "ssh" | "git" | "file" | "git+ssh"
+            ) && !rest.trim_start_matches('/').is_empty()
+        }
+        None => match url.split_once(':') {
+            Some((host, path)) if !host.contains('/') && host.len() > 1 => !path.is_empty(),
+            _ => Path::new(url).exists(),
+        },
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Repo {
+    pub url: String,
+    pub path: String,
+    /// Empty when the repository has checkouts, they name their own branches.
+    #[serde(default)]
+    pub branch: String,
+    #[serde(default)]
+    pub ssh: Option<Ssh>,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub submodules: bool,
+    #[serde(default)]
+    pub lfs: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Works from what was fetched before, without any network access.
+    #[serde(default)]
+    pub offline: bool,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub stash: bool,
+    #[serde(default)]
+    pub dirty: DirtyPolicy,
+    /// Checks updates out into a new directory and flips a symlink at `path` to it.
+    #[serde(default)]
+    pub atomic: bool,
+    pub releases: Option<Releases>,
+    pub archive: Option<Archive>,
+    pub propagate: Option<Propagate>,
+    /// Downloads the tree as an archive from the forge's API instead of fetching with git.
+    pub tarball: Option<Tarball>,
+    #[serde(default)]
+    pub vcs: Vcs,
+    pub ownership: Option<Ownership>,
+    #[serde(default)]
+    pub clean: CleanPolicy,
+    /// Glob patterns of paths that updates and cleaning never overwrite or delete.
+    #[serde(rename = "protect", default)]
+    pub protected: Vec<String>,
+    /// Glob or semver range of the tags to follow; a webhook push of a
+    /// matching tag syncs the repository.
+    pub tag: Option<String>,
+    /// Commit to stay at. No push moves it, so webhooks leave it alone.
+    pub rev: Option<String>,
+    pub depth: Option<u32>,
+    pub remote: Option<String>,
+    #[serde(default)]
+    pub url_drift: DriftPolicy,
+    #[serde(default)]
+    pub corruption: CorruptionPolicy,
+    /// Keeps a bare mirror of every ref instead of a checkout; `branch` only names its HEAD.
+    #[serde(default)]
+    pub mirror: bool,
+    /// Bare repository the checkout is a worktree of, shared with the other
+    /// checkouts of the same repository on the host.
+    pub worktree_of: Option<String>,
+    /// Branches checked out to their own paths as worktrees of the bare
+    /// repository at `path`; each becomes a repository of its own on loading.
+    #[serde(rename = "checkout", default)]
+    pub checkouts: Vec<Checkout>,
+    /// Deletes remote-tracking branches and tags that are gone from the remote.
+    #[serde(default)]
+    pub prune: bool,
+    /// With `prune`, also deletes local branches whose upstream branch is gone.
+    #[serde(default)]
+    pub prune_local: bool,
+    /// Fetched in addition to the tracked branch.
+    #[serde(rename = "refspec", default)]
+    pub refspecs: Vec<String>,
+    /// Only these paths are checked out when set.
+    #[serde(default)]
+    pub sparse: Vec<String>,
+    pub changelog: Option<String>,
+    /// JSON file rewritten after each update with the paths it added, modified and deleted.
+    pub manifest: Option<String>,
+    pub filter: Option<Filter>,
+    pub ci: Option<Ci>,
+    /// Updates wait until `autoupdate approve` lets them through.
+    #[serde(default)]
+    pub approval: bool,
+    pub rollout: Option<Rollout>,
+    /// Elects the one host that runs the leader hooks among those sharing it.
+    pub leader: Option<Leader>,
+    /// Updates are only applied inside one of these when any are set.
+    #[serde(rename = "window", default)]
+    pub windows: Vec<Window>,
+    /// Updates found during one of these wait until it is over.
+    #[serde(rename = "blackout", default)]
+    pub blackouts: Vec<Window>,
+    /// `UTC`, `local` or an offset such as `+02:00` the windows are in, UTC by default.
+    pub timezone: Option<String>,
+    /// Cron expression saying when to check while watching, instead of the interval.
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+    #[serde(default)]
+    pub retry: Retry,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    pub maintenance: Option<Maintenance>,
+    pub verify: Option<Verify>,
+    /// Checks the fetched objects before they are checked out when set.
+    pub integrity: Option<Integrity>,
+    /// Proxy URL for clone and fetch, or `auto` to detect it from git config and the environment.
+    pub proxy: Option<String>,
+    pub network: Option<Network>,
+    /// Extra HTTP headers sent with clones and fetches, e.g. for an auth gateway.
+    #[serde(rename = "header", default)]
+    pub headers: Vec<Header>,
+    /// Download rate limit such as `5MB/s`.
+    pub max_bandwidth: Option<String>,
+    /// Space such as `1G` to leave free on the filesystem of the checkout;
+    /// clones and updates that would eat into it are refused up front.
+    pub min_free_space: Option<String>,
+    /// Space such as `500M` the checkout may take up, git directory included.
+    pub quota: Option<String>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Checkout {
+    pub branch: String,
+    pub path: String,
+}
+
+/// Trusted signers: gpg fingerprints or key ids, optionally from a dedicated
+/// keyring, and an ssh allowed signers file.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Verify {
+    #[serde(rename = "key", default)]
+    pub keys: Vec<String>,
+    pub keyring: Option<String>,
+    pub allowed_signers: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Integrity {
+    /// Files whose content must match a known digest.
+    #[serde(rename = "file", default)]
+    pub files: Vec<ExpectedFile>,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedFile {
+    pub path: String,
+    pub sha256: String,
+}
+
+/// How clones and fetches over https reach the remote, for hosts
+/// whose DNS records the system resolver gets wrong.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Network {
+    #[serde(default)]
+    pub ip: IpFamily,
+    /// Addresses to connect to instead of looking the names up, like `/etc/hosts`.
+    #[serde(rename = "host", default)]
+    pub hosts: Vec<HostAddresses>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn describe(self) -> &'static str {
+        match self {
+            IpFamily::Any => "IP",
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HostAddresses {
+    pub name: String,
+    #[serde(rename = "address")]
+    pub addresses: Vec<String>,
+}
+
+impl HostAddresses {
+    pub fn addresses(&self) -> Result<Vec<IpAddr>> {
+        self.addresses
+            .iter()
+            .map(|address| {
+                address.trim().parse().map_err(|_| {
+                    Error::Config(format!(
+                        "invalid address '{}' for host '{}'",
+                        address, self.name
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Headers libgit2 sets itself and refuses to take from the caller.
+const RESERVED_HEADERS: &[&str] = &[
+    "User-Agent",
+    "Host",
+    "Accept",
+    "Content-Type",
+    "Transfer-Encoding",
+    "Content-Length",
+];
+
+/// Set for the hooks by the updater itself.
+const RESERVED_VARIABLES: &[&str] = &[
+    "HOOK_STAGE",
+    "REPO_PATH",
+    "BRANCH",
+    "OLD_COMMIT",
+    "NEW_COMMIT",
+    "CHANGED_FILES",
+    "CHANGED_FILES_PATH",
+    "CHANGED_FILES_TRUNCATED",
+];
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Header {
+    pub name: String,
+    /// The value itself or a `keyring://service/account` reference.
+    pub value: Option<String>,
+    pub value_env: Option<String>,
+}
+
+impl Header {
+    pub fn value(&self) -> Result<String> {
+        let value = match (&self.value, &self.value_env) {
+            (Some(value), _) => secret::resolve(&format!("header {}", self.name), value)?,
+            (None, Some(name)) => std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "header {} has value_env '{}', which is not set",
+                        self.name, name
+                    ))
+                })?,
+            (None, None) => String::new(),
+        };
+        if value.contains(['\r', '\n']) {
+            return Err(Error::Config(format!(
+                "header {} has a value with a line break",
+                self.name
+            )));
+        }
+        Ok(value)
+    }
+
+    /// The header as sent, `Name: value`.
+    pub fn line(&self) -> Result<String> {
+        Ok(format!("{}: {}", self.name, self.value()?))
+    }
+
+    /// What is wrong with the header `owner` sends.
+    fn problems(&self, owner: &str) -> Vec<Error> {
+        let mut problems = Vec::new();
+        let token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(token) {
+            problems.push(Error::Config(format!(
+                "{} has header name '{}', which is not valid",
+                owner, self.name
+            )));
+        }
+        if self.value.is_some() == self.value_env.is_some() {
+            problems.push(Error::Config(format!(
+                "{} needs exactly one of value and value_env for header {}",
+                owner, self.name
+            )));
+        }
+        if let Some(Err(e)) = self.value.as_deref().and_then(secret::keyring_entry) {
+            problems.push(e);
+        }
+        problems
+    }
+}
+
+/// Limits on how long the network and checkout steps of a sync may take.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Timeouts {
+    /// Applies to every repository, libgit2 only has a process-wide setting,
+    /// so the repositories setting it have to agree on it.
+    pub connect: Option<String>,
+    /// Limits a clone or fetch. It is also the read timeout of every
+    /// connection, which is process-wide like `connect`.
+    pub transfer: Option<String>,
+    /// Limits updating the working tree after a fetch.
+    pub checkout: Option<String>,
+}
+
+/// Conditions new commits have to meet for an update to be applied; the
+/// checkout stays where it is until one of them does.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    /// Glob patterns such as `services/api/**` of paths a commit has to change.
+    #[serde(rename = "path", default)]
+    pub paths: Vec<String>,
+    /// Commits whose message contains this, e.g. `[skip deploy]`, never count.
+    pub skip_marker: Option<String>,
+}
+
+impl Filter {
+    pub fn paths(&self) -> Result<Vec<glob::Pattern>> {
+        self.paths
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .map_err(|e| Error::Config(format!("invalid filter path '{}': {}", pattern, e)))
+            })
+            .collect()
+    }
+}
+
+/// Rolls updates out to a fraction of the hosts that share a key first, and
+/// to the others once they ran the update for the soak period without any
+/// host failing to apply it.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rollout {
+    /// Base URL the state of the rollouts is kept below, at `{key}/{commit}`,
+    /// read with GET and written with conditional PUT requests.
+    pub url: String,
+    /// Shared by the hosts updating the same checkout; derived from the URL
+    /// and branch when unset.
+    pub key: Option<String>,
+    /// Fraction of the hosts that update first, 0.1 by default.
+    pub canary: Option<f64>,
+    /// 1 hour by default.
+    pub soak: Option<String>,
+    /// Name of this host among the others, the system's host name by default.
+    pub host: Option<String>,
+    /// Sent as a bearer token; the token itself or a `keyring://service/account` reference.
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+}
+
+impl Rollout {
+    pub fn canary(&self) -> f64 {
+        self.canary.unwrap_or(DEFAULT_ROLLOUT_CANARY)
+    }
+
+    pub fn soak(&self) -> Result<Duration> {
+        self.soak
+            .as_deref()
+            .map_or(Ok(DEFAULT_ROLLOUT_SOAK), |value| {
+                duration("rollout soak", value)
+            })
+    }
+
+    pub fn token(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "rollout token",
+            self.token.as_deref(),
+            self.token_env.as_deref(),
+        )
+    }
+}
+
+/// Where the hosts updating the same checkout elect their leader: a lease in
+/// a document at `url`, kept with the same conditional requests as rollouts,
+/// or a lock on a `file` on storage they all mount.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Leader {
+    pub url: Option<String>,
+    pub file: Option<String>,
+    /// Shared by the hosts electing the same leader; derived from the URL
+    /// and branch when unset.
+    pub key: Option<String>,
+    /// How long the leadership lasts once taken, 5 minutes by default. It
+    /// should outlast the time the hosts take to apply the same update.
+    pub lease: Option<String>,
+    /// Name of this host among the others, the system's host name by default.
+    pub host: Option<String>,
+    /// Sent as a bearer token; the token itself or a `keyring://service/account` reference.
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+}
+
+impl Leader {
+    pub fn lease(&self) -> Result<Duration> {
+        self.lease
+            .as_deref()
+            .map_or(Ok(DEFAULT_LEADER_LEASE), |value| {
+                duration("leader lease", value)
+            })
+    }
+
+    pub fn token(&self) -> Result<Option<String>> {
+        secret::lookup(
+            "leader token",
+            self.token.as_deref(),
+            self.token_env.as_deref(),
+        )
+    }
+}
+
+/// Times of day, on the given days, at which updates may or may not be
+/// applied. One that ends before it starts runs past midnight.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    /// Days of the week it starts on such as `mon-fri`, every day when unset.
+    pub days: Option<String>,
+    /// Such as `22:00`.
+    pub start: String,
+    pub end: String,
+}
+
+/// Waits for the checks the forge reports on a new commit to pass before
+/// applying it.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Ci {
+    /// Told apart by the host of the URL when unset, as for tarballs.
+    pub provider: Option<Provider>,
+    pub api_url: Option<String>,
+    /// How long checks may stay pending, 30 minutes by default.
+    pub timeout: Option<String>,
+    pub poll_interval: Option<String>,
+}
+
+impl Ci {
+    pub fn timeout(&self) -> Result<Duration> {
+        self.timeout
+            .as_deref()
+            .map_or(Ok(DEFAULT_CI_TIMEOUT), |value| {
+                duration("ci timeout", value)
+            })
+    }
+
+    pub fn poll_interval(&self) -> Result<Duration> {
+        self.poll_interval
+            .as_deref()
+            .map_or(Ok(DEFAULT_CI_POLL_INTERVAL), |value| {
+                duration("ci poll interval", value)
+            })
+    }
+}
+
+/// Repacking the object database and pruning unreachable objects with
+/// `git gc` after every so many updates.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Maintenance {
+    pub every: Option<u32>,
+}
+
+impl Maintenance {
+    /// Number of updates between two runs.
+    pub fn every(&self) -> u32 {
+        self.every.unwrap_or(DEFAULT_MAINTENANCE_EVERY).max(1)
+    }
+}
+
+impl Timeouts {
+    pub fn connect(&self) -> Result<Option<Duration>> {
+        self.connect
+            .as_deref()
+            .map(|value| duration("connect timeout", value))
+            .transpose()
+    }
+
+    pub fn transfer(&self) -> Result<Option<Duration>> {
+        self.transfer
+            .as_deref()
+            .map(|value| duration("transfer timeout", value))
+            .transpose()
+    }
+
+    pub fn checkout(&self) -> Result<Option<Duration>> {
+        self.checkout
+            .as_deref()
+            .map(|value| duration("checkout timeout", value))
+            .transpose()
+    }
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Retry {
+    pub attempts: Option<u32>,
+    pub delay: Option<String>,
+    pub max_delay: Option<String>,
+    pub jitter: Option<bool>,
+}
+
+impl Retry {
+    /// Total number of tries, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS).max(1)
+    }
+
+    pub fn delay(&self) -> Result<Duration> {
+        match &self.delay {
+            Some(delay) => duration("retry delay", delay),
+            None => Ok(DEFAULT_RETRY_DELAY),
+        }
+    }
+
+    pub fn max_delay(&self) -> Result<Duration> {
+        match &self.max_delay {
+            Some(max_delay) => duration("retry max_delay", max_delay),
+            None => Ok(DEFAULT_RETRY_MAX_DELAY),
+        }
+    }
+
+    pub fn jitter(&self) -> bool {
+        self.jitter.unwrap_or(true)
+    }
+}
+
+impl Repo {
+    /// Name of the remote updates are fetched from.
+    pub fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or(DEFAULT_REMOTE)
+    }
+
+    /// Remote-tracking branch the local branch is updated to, e.g. `origin/main`.
+    pub fn upstream(&self) -> String {
+        format!("{}/{}", self.remote(), self.branch)
+    }
+
+    pub fn schedule(&self) -> Result<Option<Cron>> {
+        self.schedule.as_deref().map(Cron::parse).transpose()
+    }
+
+    /// Download rate limit of clones and fetches in bytes per second.
+    pub fn max_bandwidth(&self) -> Result<Option<u64>> {
+        let Some(value) = &self.max_bandwidth else {
+            return Ok(None);
+        };
+        let rate = value.trim();
+        let rate = rate.strip_suffix("/s").unwrap_or(rate);
+        match parse_size(rate) {
+            Some(rate) if rate > 0 => Ok(Some(rate)),
+            _ => Err(Error::Config(format!("invalid max_bandwidth '{}'", value))),
+        }
+    }
+
+    /// Bytes a clone or update has to leave free, 0 when unset.
+    pub fn min_free_space(&self) -> Result<u64> {
+        let Some(value) = &self.min_free_space else {
+            return Ok(0);
+        };
+        parse_size(value)
+            .ok_or_else(|| Error::Config(format!("invalid min_free_space '{}'", value)))
+    }
+
+    pub fn quota(&self) -> Result<Option<u64>> {
+        self.quota
+            .as_deref()
+            .map(|value| {
+                parse_size(value).ok_or_else(|| Error::Config(format!("invalid quota '{}'", value)))
+            })
+            .transpose()
+    }
+
+    pub fn protected(&self) -> Result<Vec<glob::Pattern>> {
+        self.protected
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern).map_err(|e| {
+                    Error::Config(format!("invalid protected path '{}': {}", pattern, e))
+                })
+            })
+            .collect()
+    }
+
+    /// Number of commits to clone and fetch, 0 for the full history.
+    pub fn depth(&self) -> u32 {
+        // libgit2 replaces the shallow boundaries of a repository on every
+        // shallow fetch, which would cut off the branches of the other worktrees
+        if self.worktree_of.is_some() {
+            return 0;
+        }
+        self.depth.unwrap_or(DEFAULT_DEPTH)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirtyPolicy {
+    Abort,
+    #[default]
+    Force,
+    Backup,
+}
+
+/// Exports every synced commit into `releases/<timestamp>` below `path` and
+/// points the `current` symlink there.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Releases {
+    pub path: String,
+    /// Number of releases kept for rolling back, 5 by default.
+    pub keep: Option<u32>,
+}
+
+impl Releases {
+    pub fn keep(&self) -> u32 {
+        self.keep.unwrap_or(DEFAULT_KEEP_RELEASES).max(1)
+    }
+}
+
+/// For networks that block the git protocol but let HTTPS API requests
+/// through; the checkout has no git directory then.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tarball {
+    /// Told apart by the host of the URL when unset.
+    pub provider: Option<Provider>,
+    /// Root of the API such as `https://git.example.com/api/v4`; derived from the host when unset.
+    pub api_url: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    Github,
+    Gitlab,
+}
+
+/// Version control system the remote is checked out with. Mercurial needs
+/// the `hg` command; the branch names a bookmark, branch or tag there.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Vcs {
+    #[default]
+    Git,
+    Mercurial,
+}
+
+/// Exports the tree of every updated commit, named by its id, to a directory
+/// or an `s3://bucket/prefix` location.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Archive {
+    pub destination: String,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    pub region: Option<String>,
+    /// S3 compatible endpoint used instead of AWS.
+    pub endpoint: Option<String>,
+}
+
+/// Copies the working tree after each sync to another directory, or with
+/// rsync to a `[user@]host:path` target.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Propagate {
+    pub destination: String,
+    /// Copies the files an update changed and deletes the ones it deleted,
+    /// instead of mirroring the whole tree.
+    #[serde(default)]
+    pub changed_only: bool,
+    /// Glob patterns of paths that are neither copied nor deleted at the destination.
+    #[serde(rename = "exclude", default)]
+    pub excludes: Vec<String>,
+}
+
+impl Propagate {
+    /// Whether the destination is an rsync target rather than a local path.
+    pub fn remote(&self) -> bool {
+        if self.destination.starts_with("rsync://") {
+            return true;
+        }
+        // `C:\www` is a drive, not a host
+        match self.destination.split_once(':') {
+            Some((host, _)) => host.len() > 1 && !host.contains(['/', '\\']),
+            None => false,
+        }
+    }
+
+    pub fn excludes(&self) -> Result<Vec<glob::Pattern>> {
+        self.excludes
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern).map_err(|e| {
+                    Error::Config(format!("invalid propagate exclude '{}': {}", pattern, e))
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    Zip,
+}
+
+/// Owner, group and octal modes given to the checked out files after every
+/// update, on Unix only.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Ownership {
+    pub user: Option<String>,
+    pub group: Option<String>,
+    /// Executable files also get execute permission wherever this allows reading.
+    pub file_mode: Option<String>,
+    pub dir_mode: Option<String>,
+}
+
+impl Ownership {
+    pub fn file_mode(&self) -> Result<Option<u32>> {
+        self.file_mode
+            .as_deref()
+            .map(|value| mode("file_mode", value))
+            .transpose()
+    }
+
+    pub fn dir_mode(&self) -> Result<Option<u32>> {
+        self.dir_mode
+            .as_deref()
+            .map(|value| mode("dir_mode", value))
+            .transpose()
+    }
+}
+
+fn mode(name: &str, value: &str) -> Result<u32> {
+    u32::from_str_radix(value.trim().trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| Error::Config(format!("invalid {} '{}'", name, value)))
+}
+
+/// What happens to scheduled checks missed while the machine slept or the tool
+/// was not running.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CatchUp {
+    /// Checks once right away, however many were missed.
+    #[default]
+    RunOnce,
+    /// Waits for the next scheduled time.
+    Skip,
+}
+
+/// Which files not in the checked out commit are deleted after an update.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CleanPolicy {
+    #[default]
+    None,
+    Untracked,
+    #[serde(rename = "untracked+ignored")]
+    UntrackedIgnored,
+}
+
+/// What to do when an existing checkout fetches from a different URL than configured.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum DriftPolicy {
+    #[default]
+    Warn,
+    Fail,
+    Rewrite,
+}
+
+/// What to do when the object database of a checkout turns out to be corrupt.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CorruptionPolicy {
+    #[default]
+    Fail,
+    /// Moves the broken repository aside and clones it again.
+    Repair,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    HardReset,
+    FastForwardOnly,
+    Merge,
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_update: Vec<String>,
+    #[serde(default)]
+    pub post_update: Vec<String>,
+    /// Run after the post-update hooks, on the elected leader only.
+    #[serde(default)]
+    pub leader_update: Vec<String>,
+    pub timeout: Option<String>,
+    /// Set for every hook next to the variables describing the update.
+    #[serde(rename = "env", default)]
+    pub env: Vec<Variable>,
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Variable {
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+impl Hooks {
+    pub fn timeout(&self) -> Result<Duration> {
+        match &self.timeout {
+            Some(timeout) => duration("hook timeout", timeout),
+            None => Ok(DEFAULT_HOOK_TIMEOUT),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    pub username: Option<String>,
+    /// The token itself or a `keyring://service/account` reference.
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    pub token_env: Option<String>,
+    /// Asks the helpers configured for `git credential` when no token is set.
+    #[serde(default)]
+    pub credential_helper: bool,
+}
+
+impl Auth {
+    pub fn token(&self) -> Result<Option<String>> {
+        match (&self.token, &self.token_file, &self.token_env) {
+            (Some(token), _, _) => secret::resolve("auth token", token).map(Some),
+            (None, Some(file), _) => secret::read_file("auth token_file", file).map(Some),
+            (None, None, Some(name)) => {
+                Ok(std::env::var(name).ok().filter(|token| !token.is_empty()))
+            }
+            (None, None, None) => Ok(None),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Ssh {
+    pub key: Option<String>,
+    pub public_key: Option<String>,
+    /// The passphrase itself or a `keyring://service/account` reference.
+    pub passphrase: Option<String>,
+    #[serde(default)]
+    pub agent: bool,
+    /// Defaults to `~/.ssh/known_hosts`.
+    pub known_hosts: Option<String>,
+    /// Fingerprints as `ssh-keygen -l` prints them; when set, only these keys are accepted.
+    #[serde(default)]
+    pub host_keys: HostKeys,
+    #[serde(default)]
+    pub host_key_check: HostKeyCheck,
+}
+
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HostKeys {
+    #[serde(rename = "fingerprint", default)]
+    pub fingerprints: Vec<String>,
+}
+
+/// What happens when known_hosts has no key for the host of an SSH remote.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyCheck {
+    #[default]
+    Strict,
+    /// Adds the key the host presents to known_hosts and accepts it.
+    TrustOnFirstUse,
+}
+
+/// Server certificate checks for HTTPS remotes: an extra CA bundle, SHA-256
+/// fingerprints of certificates accepted as they are, or no checks at all.
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub ca_file: Option<String>,
+    #[serde(rename = "pin", default)]
+    pub pins: Vec<String>,
+    /// Accepts any certificate, which lets anyone on the network impersonate the remote.
+    #[serde(default)]
+    pub skip_verify: bool,
+}
+
+pub fn load(path: Option<&Path>) -> Result<Settings> {
+    from_file(&locate_file(path)?)
+}
+
+/// The settings file that `load` reads: `path` itself, or the first default
+/// candidate that exists.
+pub fn locate_file(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+
+    match CANDIDATES.iter().map(Path::new).find(|path| path.exists()) {
+        Some(path) => Ok(path.to_path_buf()),
+        None => Err(Error::Config(
+            "could not find settings.toml or settings.xml".to_string(),
+        )),
+    }
+}
+
+/// Whether a path given on the command line or to the control API names the
+/// configured repository path, however either of them is spelled.
+pub fn same_path(configured: &str, given: &str) -> bool {
+    match (fs::canonicalize(configured), fs::canonicalize(given)) {
+        (Ok(configured), Ok(given)) => configured == given,
+        _ => configured.trim_end_matches('/') == given.trim_end_matches('/'),
+    }
+}
+
+pub fn from_file(path: &Path) -> Result<Settings> {
+    let content = read(path)?;
+    let mut settings: Settings = parse(path, &content)?;
+
+    let base = base(path);
+    for repo in &mut settings.repositories {
+        expand(repo)?;
+        resolve(repo, base);
+    }
+    if let (Some(base), Some(log)) = (base, settings.log.as_mut()) {
+        if Path::new(&log.file).is_relative() {
+            log.file = base.join(&log.file).to_string_lossy().into_owned();
+        }
+    }
+
+    let canonical = fs::canonicalize(path)?;
+    let mut includes = Includes {
+        loading: vec![canonical.clone()],
+        loaded: vec![canonical],
+        directories: Vec::new(),
+        repositories: Vec::new(),
+    };
+    for include in &settings.includes {
+        includes.include(base, include)?;
+    }
+    settings.repositories.extend(includes.repositories);
+    settings.repositories = split(settings.repositories)?;
+    settings.sources = includes.loaded;
+    settings.sources.extend(includes.directories);
+    Ok(settings)
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| Error::Config(format!("could not read '{}': {}", path.display(), e)))
+}
+
+fn parse<T: serde::de::DeserializeOwned>(path: &Path, content: &str) -> Result<T> {
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(content).map_err(|e| e.to_string()),
+        _ => de::from_str(content).map_err(|e| e.to_string()),
+    };
+    parsed.map_err(|e| {
+        let position = match locate(content, &e) {
+            Some((line, column)) => format!(" at line {}, column {}", line, column),
+            None => String::new(),
+        };
+        Error::Config(format!(
+            "could not parse '{}'{}: {}",
+            path.display(),
+            position,
+            e
+        ))
+    })
+}
+
+/// Replaces every repository with checkouts by one worktree repository per checkout.
+fn split(repositories: Vec<Repo>) -> Result<Vec<Repo>> {
+    let mut split = Vec::new();
+    for mut repo in repositories {
+        if repo.checkouts.is_empty() {
+            split.push(repo);
+            continue;
+        }
+        for (field, set) in [
+            ("a branch", !repo.branch.is_empty()),
+            ("worktree_of", repo.worktree_of.is_some()),
+        ] {
+            if set {
+                return Err(Error::Config(format!(
+                    "'{}' has checkouts and could not set {}",
+                    repo.path, field
+                )));
+            }
+        }
+        for checkout in std::mem::take(&mut repo.checkouts) {
+            let mut worktree = repo.clone();
+            worktree.worktree_of = Some(repo.path.clone());
+            worktree.path = checkout.path;
+            worktree.branch = checkout.branch;
+            split.push(worktree);
+        }
+    }
+    Ok(split)
+}
+
+/// Directory relative paths in the file at `path` are resolved against.
+fn base(path: &Path) -> Option<&Path> {
+    path.parent()
+        .filter(|base| !base.as_os_str().is_empty() && *base != Path::new("."))
+}
+
+/// Resolves the relative paths of `repo` against the location of the file defining it.
+fn resolve(repo: &mut Repo, base: Option<&Path>) {
+    let Some(base) = base else {
+        return;
+    };
+    if Path::new(&repo.path).is_relative() {
+        repo.path = base.join(&repo.path).to_string_lossy().into_owned();
+    }
+    for checkout in &mut repo.checkouts {
+        if Path::new(&checkout.path).is_relative() {
+            checkout.path = base.join(&checkout.path).to_string_lossy().into_owned();
+        }
+    }
+    if let Some(shared) = repo
+        .worktree_of
+        .as_mut()
+        .filter(|shared| Path::new(shared).is_relative())
+    {
+        *shared = base.join(&*shared).to_string_lossy().into_owned();
+    }
+    if let Some(releases) = repo
+        .releases
+        .as_mut()
+        .filter(|releases| Path::new(&releases.path).is_relative())
+    {
+        releases.path = base.join(&releases.path).to_string_lossy().into_owned();
+    }
+    if let Some(archive) = repo.archive.as_mut().filter(|archive| {
+        !archive.destination.starts_with("s3://") && Path::new(&archive.destination).is_relative()
+    }) {
+        archive.destination = base
+            .join(&archive.destination)
+            .to_string_lossy()
+            .into_owned();
+    }
+    if let Some(token_file) = repo
+        .auth
+        .as_mut()
+        .and_then(|auth| auth.token_file.as_mut())
+        .filter(|file| Path::new(file).is_relative())
+    {
+        *token_file = base.join(&*token_file).to_string_lossy().into_owned();
+    }
+    if let Some(ca_file) = repo
+        .tls
+        .as_mut()
+        .and_then(|tls| tls.ca_file.as_mut())
+        .filter(|file| Path::new(file).is_relative())
+    {
+        *ca_file = base.join(&*ca_file).to_string_lossy().into_owned();
+    }
+    if let Some(changelog) = repo
+        .changelog
+        .as_mut()
+        .filter(|file| Path::new(file).is_relative())
+    {
+        *changelog = base.join(&*changelog).to_string_lossy().into_owned();
+    }
+    if let Some(propagate) = repo
+        .propagate
+        .as_mut()
+        .filter(|propagate| !propagate.remote() && Path::new(&propagate.destination).is_relative())
+    {
+        propagate.destination = base
+            .join(&propagate.destination)
+            .to_string_lossy()
+            .into_owned();
+    }
+    if let Some(manifest) = repo
+        .manifest
+        .as_mut()
+        .filter(|file| Path::new(file).is_relative())
+    {
+        *manifest = base.join(&*manifest).to_string_lossy().into_owned();
+    }
+}
+
+/// An included file: either a single repository, or more repositories and
+/// includes in the layout of the settings file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(rename = "repository", default)]
+    repositories: Vec<Repo>,
+    #[serde(rename = "include", default)]
+    includes: Vec<String>,
+}
+
+struct Includes {
+    /// Files whose includes are being read, to detect cycles.
+    loading: Vec<PathBuf>,
+    /// Files already read; a file reached twice adds its repositories once.
+    loaded: Vec<PathBuf>,
+    directories: Vec<PathBuf>,
+    repositories: Vec<Repo>,
+}
+
+impl Includes {
+    /// Reads the file `include` names, or every settings file in the directory
+    /// it names in the order of their names.
+    fn include(&mut self, base: Option<&Path>, include: &str) -> Result<()> {
+        let include = interpolate("include", include)?;
+        let target = match base {
+            Some(base) => base.join(&include),
+            None => PathBuf::from(&include),
+        };
+        let unreadable = |e: std::io::Error| {
+            Error::Config(format!("could not read include '{}': {}", include, e))
+        };
+        if !fs::metadata(&target).map_err(unreadable)?.is_dir() {
+            return self.load(&target);
+        }
+
+        if let Ok(directory) = fs::canonicalize(&target) {
+            self.directories.push(directory);
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&target).map_err(unreadable)? {
+            let path = entry.map_err(unreadable)?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.'));
+            let settings = matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("xml" | "toml")
+            );
+            if settings && !hidden && path.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+        for file in files {
+            self.load(&file)?;
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(path)
+            .map_err(|e| Error::Config(format!("could not read '{}': {}", path.display(), e)))?;
+        if let Some(start) = self.loading.iter().position(|file| *file == canonical) {
+            let chain: Vec<String> = self.loading[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|file| format!("'{}'", file.display()))
+                .collect();
+            return Err(Error::Config(format!(
+                "include cycle: {}",
+                chain.join(" includes ")
+            )));
+        }
+        if self.loaded.contains(&canonical) {
+            return Ok(());
+        }
+        self.loaded.push(canonical.clone());
+
+        let content = read(path)?;
+        let fragment = match single_repository(path, &content) {
+            true => Fragment {
+                repositories: vec![parse(path, &content)?],
+                includes: Vec::new(),
+            },
+            false => parse(path, &content)?,
+        };
+        let base = base(path);
+        for mut repo in fragment.repositories {
+            expand(&mut repo)?;
+            resolve(&mut repo, base);
+            self.repositories.push(repo);
+        }
+
+        self.loading.push(canonical);
+        for include in &fragment.includes {
+            self.include(base, include)?;
+        }
+        self.loading.pop();
+        Ok(())
+    }
+}
+
+/// Whether an included file holds one repository at its top level, a
+/// `<repository>` root element or a TOML file without repository tables.
+fn single_repository(path: &Path, content: &str) -> bool {
+    if path.extension().and_then(|ext| ext.to_str()) == Some("toml") {
+        return content.parse::<toml::Table>().is_ok_and(|table| {
+            !table.contains_key("repository") && !table.contains_key("include")
+        });
+    }
+
+    let mut rest = content;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.split_once("-->").map_or("", |(_, after)| after);
+            continue;
+        }
+        if rest.starts_with(['?', '!']) {
+            continue;
+        }
+        let name = rest
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next();
+        return name == Some("repository");
+    }
+    false
+}
+
+/// Finds the line and column of the element or value a quick-xml error names.
+///
+/// toml errors already carry their position, so only messages quoting a
+/// name in backticks, like unknown fields and variants, can be located.
+fn locate(content: &str, message: &str) -> Option<(usize, usize)> {
+    if message.contains(" at line ") || message.starts_with("TOML parse error") {
+        return None;
+    }
+    let name = message.split('`').nth(1)?;
+    let offset = [
+        format!("<{}>", name),
+        format!("<{} ", name),
+        format!("<{}/", name),
+        format!(">{}<", name),
+    ]
+    .iter()
+    .filter_map(|needle| content.find(needle.as_str()).map(|index| index + 1))
+    .min()?;
+
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+    Some((line, column))
+}
+
+/// Expands `${VAR}` placeholders in the fields that commonly differ per host.
+fn expand(repo: &mut Repo) -> Result<()> {
+    repo.url = interpolate("url", &repo.url)?;
+    repo.path = interpolate("path", &repo.path)?;
+    repo.branch = interpolate("branch", &repo.branch)?;
+    for checkout in &mut repo.checkouts {
+        checkout.path = interpolate("path", &checkout.path)?;
+        checkout.branch = interpolate("branch", &checkout.branch)?;
+    }
+    if let Some(auth) = &mut repo.auth {
+        for (name, value) in [
+            ("auth username", &mut auth.username),
+            ("auth token", &mut auth.token),
+            ("auth token_file", &mut auth.token_file),
+        ] {
+            if let Some(value) = value {
+                *value = interpolate(name, value)?;
+            }
+        }
+    }
+    if let Some(ssh) = &mut repo.ssh {
+        for (name, value) in [
+            ("ssh key", &mut ssh.key),
+            ("ssh public_key", &mut ssh.public_key),
+            ("ssh passphrase", &mut ssh.passphrase),
+        ] {
+            if let Some(value) = value {
+                *value = interpolate(name, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces every `${VAR}` in `value` with the environment variable `VAR`;
+/// `$${` stands for a literal `${`.
+pub fn interpolate(field: &str, value: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::Config(format!("unterminated '${{' in {}", field)))?;
+        let name = &rest[start + 2..start + end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::Config(format!(
+                "invalid environment variable name '{}' in {}",
+                name, field
+            )));
+        }
+        let var = std::env::var(name).map_err(|_| {
+            Error::Config(format!(
+                "environment variable '{}' used in {} is not set",
+                name, field
+            ))
+        })?;
+        expanded.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Fails for a period shorter than `min`, which would keep a polling loop
+/// spinning without pause.
+fn at_least(name: &str, value: Duration, min: Duration) -> Result<()> {
+    match value < min {
+        true => Err(Error::Config(format!(
+            "{} must be at least {}, not {}",
+            name,
+            humantime::format_duration(min),
+            humantime::format_duration(value)
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Every duration but the progress refresh is used in whole seconds, so a
+/// finer one is refused rather than cut short.
+fn duration(name: &str, value: &str) -> Result<Duration> {
+    let parsed = fine_duration(name, value)?;
+    match parsed.subsec_nanos() {
+        0 => Ok(parsed),
+        _ => Err(Error::Config(format!(
+            "invalid {} '{}', it is counted in whole seconds",
+            name, value
+        ))),
+    }
+}
+
+fn fine_duration(name: &str, value: &str) -> Result<Duration> {
+    parse_duration(value).ok_or_else(|| Error::Config(format!("invalid {} '{}'", name, value)))
+}
+
+/// Parses durations such as `90`, `250ms`, `30s`, `5m` or `1h`; bare numbers are seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().ok()?;
+    let multiplier = match unit.trim() {
+        "ms" => return Some(Duration::from_millis(number)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+    number.checked_mul(multiplier).map(Duration::from_secs)
+}
+
+/// Parses sizes such as `512`, `64K`, `10M` or `1G`; bare numbers are bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, ""),
+    };
+    let number: u64 = number.parse().ok()?;
+    let multiplier = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_variables() {
+        std::env::set_var("AUTOUPDATE_TEST_HOST", "git.example.com");
+        std::env::set_var("AUTOUPDATE_TEST_ORG", "acme");
+        assert_eq!(
+            interpolate(
+                "url",
+                "https://${AUTOUPDATE_TEST_HOST}/${AUTOUPDATE_TEST_ORG}/app.git"
+            )
+            .unwrap(),
+            "https://git.example.com/acme/app.git"
+        );
+        assert_eq!(
+            interpolate("url", "no placeholders").unwrap(),
+            "no placeholders"
+        );
+        assert_eq!(
+            interpolate("url", "$HOME and {braces}").unwrap(),
+            "$HOME and {braces}"
+        );
+    }
+
+    #[test]
+    fn refuses_unset_variables() {
+        std::env::remove_var("AUTOUPDATE_TEST_UNSET");
+        assert!(matches!(
+            interpolate("path", "/srv/${AUTOUPDATE_TEST_UNSET}"),
+            Err(Error::Config(message))
+                if message == "environment variable 'AUTOUPDATE_TEST_UNSET' used in path is not set"
+        ));
+    }
+
+    #[test]
+    fn refuses_unterminated_placeholders() {
+        assert!(matches!(
+            interpolate("url", "https://${AUTOUPDATE_TEST_HOST/app.git"),
+            Err(Error::Config(message)) if message == "unterminated '${' in url"
+        ));
+    }
+
+    #[test]
+    fn refuses_nested_and_empty_names() {
+        std::env::set_var("AUTOUPDATE_TEST_INNER", "X");
+        for value in ["${A${AUTOUPDATE_TEST_INNER}}", "${}", "${NOT-A-NAME}"] {
+            assert!(
+                matches!(interpolate("branch", value), Err(Error::Config(message)) if message.starts_with("invalid environment variable name")),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_placeholders() {
+        std::env::set_var("AUTOUPDATE_TEST_NAME", "main");
+        assert_eq!(interpolate("branch", "$${NAME}").unwrap(), "${NAME}");
+        assert_eq!(
+            interpolate("branch", "a$${NAME}-${AUTOUPDATE_TEST_NAME}").unwrap(),
+            "a${NAME}-main"
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        for (value, expected) in [
+            ("90", Duration::from_secs(90)),
+            ("250ms", Duration::from_millis(250)),
+            ("30s", Duration::from_secs(30)),
+            ("5m", Duration::from_secs(300)),
+            ("1h", Duration::from_secs(3600)),
+            (" 2 m ", Duration::from_secs(120)),
+            ("0", Duration::ZERO),
+        ] {
+            assert_eq!(parse_duration(value), Some(expected), "{}", value);
+        }
+        for value in ["", "s", "1.5s", "-1", "10d", "5M", "99999999999999999h"] {
+            assert_eq!(parse_duration(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn only_the_progress_refresh_takes_fractions_of_a_second() {
+        assert!(matches!(
+            duration("interval", "250ms"),
+            Err(Error::Config(message)) if message == "invalid interval '250ms', it is counted in whole seconds"
+        ));
+        assert_eq!(
+            duration("interval", "2000ms").unwrap(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            fine_duration("progress refresh", "250ms").unwrap(),
+            Duration::from_millis(250)
+        );
+    }
+
+    #[test]
+    fn parses_sizes() {
+        for (value, expected) in [
+            ("512", 512),
+            ("64K", 64 * 1024),
+            ("64k", 64 * 1024),
+            ("1kb", 1024),
+            ("10M", 10 * 1024 * 1024),
+            ("2 GB", 2 * 1024 * 1024 * 1024),
+            ("0", 0),
+        ] {
+            assert_eq!(parse_size(value), Some(expected), "{}", value);
+        }
+        for value in [
+            "",
+            "K",
+            "1.5M",
+            "-1",
+            "1T",
+            "99999999999999999999",
+            "17179869184G",
+        ] {
+            assert_eq!(parse_size(value), None, "{}", value);
+        }
+    }
+}
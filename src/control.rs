@@ -0,0 +1,199 @@
+use crate::Command;
+use autoupdate::config::{self, Repo};
+use autoupdate::health::{self, Health};
+use autoupdate::metrics::Registry;
+use autoupdate::output::Output;
+use autoupdate::{Error, HistoryEntry, Updater};
+use git2::Oid;
+use serde::Serialize;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+
+pub(crate) const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Serialize)]
+pub(crate) struct Repository {
+    pub url: String,
+    pub branch: String,
+    pub paused: bool,
+    #[serde(flatten)]
+    pub health: Health,
+}
+
+#[derive(Serialize)]
+pub(crate) struct History {
+    pub path: String,
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Why a request could not be carried out, with the HTTP status it is
+/// answered with.
+pub(crate) struct Refusal {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Refusal {
+    pub(crate) fn new(status: u16, message: &str) -> Refusal {
+        Refusal {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<Error> for Refusal {
+    fn from(e: Error) -> Refusal {
+        let status = match e {
+            Error::Config(_) => 400,
+            Error::Refused(_) | Error::Locked(_) => 409,
+            _ => 500,
+        };
+        Refusal::new(status, &e.to_string())
+    }
+}
+
+/// What the control interfaces do to the running daemon: list the
+/// repositories, sync, pause and resume them, read their history, and
+/// approve or roll back their updates.
+pub(crate) struct Control {
+    wait: bool,
+    repos: Arc<RwLock<Vec<Repo>>>,
+    registry: Arc<Registry>,
+    paused: Arc<RwLock<Vec<String>>>,
+    commands: Mutex<Sender<Command>>,
+    output: Output,
+}
+
+impl Control {
+    pub(crate) fn new(
+        wait: bool,
+        repos: Arc<RwLock<Vec<Repo>>>,
+        registry: Arc<Registry>,
+        paused: Arc<RwLock<Vec<String>>>,
+        commands: Sender<Command>,
+        output: Output,
+    ) -> Control {
+        Control {
+            wait,
+            repos,
+            registry,
+            paused,
+            commands: Mutex::new(commands),
+            output,
+        }
+    }
+
+    /// Indexes of the repositories `path` selects, all of them without one.
+    pub(crate) fn selected(&self, path: Option<&str>) -> Result<Vec<usize>, Refusal> {
+        let repos = self.repos.read().unwrap_or_else(|e| e.into_inner());
+        match path {
+            Some(path) => match repos
+                .iter()
+                .position(|repo| config::same_path(&repo.path, path))
+            {
+                Some(index) => Ok(vec![index]),
+                None => Err(Refusal::new(
+                    404,
+                    &format!("no repository is configured at '{}'", path),
+                )),
+            },
+            None => Ok((0..repos.len()).collect()),
+        }
+    }
+
+    /// The one repository `path` selects, or the only one among those
+    /// `eligible` without it.
+    fn single(
+        &self,
+        path: Option<&str>,
+        eligible: impl Fn(&Repo) -> bool,
+        what: &str,
+    ) -> Result<(usize, Repo), Refusal> {
+        let selected = self.selected(path)?;
+        let repos = self.repos.read().unwrap_or_else(|e| e.into_inner());
+        let candidates: Vec<usize> = match path {
+            Some(_) => selected,
+            None => selected
+                .into_iter()
+                .filter(|index| eligible(&repos[*index]))
+                .collect(),
+        };
+        match candidates[..] {
+            [index] => Ok((index, repos[index].clone())),
+            [] => Err(Refusal::new(
+                400,
+                &format!("no configured repository {}", what),
+            )),
+            _ => Err(Refusal::new(
+                400,
+                &format!("more than one repository {}, select one with path", what),
+            )),
+        }
+    }
+
+    pub(crate) fn queue(&self, commands: impl IntoIterator<Item = Command>) -> Result<(), Refusal> {
+        let sender = self.commands.lock().unwrap_or_else(|e| e.into_inner());
+        for command in commands {
+            if sender.send(command).is_err() {
+                return Err(Refusal::new(503, "updater is shutting down"));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn repositories(&self) -> Vec<Repository> {
+        let repos = self.repos.read().unwrap_or_else(|e| e.into_inner());
+        let paused = self.paused.read().unwrap_or_else(|e| e.into_inner());
+        repos
+            .iter()
+            .enumerate()
+            .map(|(index, repo)| Repository {
+                url: repo.url.clone(),
+                branch: repo.branch.clone(),
+                paused: paused.contains(&repo.path),
+                health: health::check(repo, &self.registry.repo(index)),
+            })
+            .collect()
+    }
+
+    /// The last `limit` syncs of each repository at `indexes`.
+    pub(crate) fn histories(&self, indexes: &[usize], limit: usize) -> Vec<History> {
+        let repos: Vec<Repo> = {
+            let repos = self.repos.read().unwrap_or_else(|e| e.into_inner());
+            indexes.iter().map(|index| repos[*index].clone()).collect()
+        };
+        repos
+            .into_iter()
+            .map(|repo| {
+                // A repository that was never synced has no history yet
+                let mut entries = Updater::new(repo.clone()).history().unwrap_or_default();
+                entries.drain(..entries.len().saturating_sub(limit));
+                History {
+                    path: repo.path,
+                    entries,
+                }
+            })
+            .collect()
+    }
+
+    fn updater(&self, repo: Repo) -> Updater {
+        Updater::new(repo)
+            .with_output(self.output.clone())
+            .with_lock_wait(self.wait)
+    }
+
+    /// Approves the waiting update and syncs right away to apply it.
+    pub(crate) fn approve(&self, path: Option<&str>, commit: Option<&str>) -> Result<Oid, Refusal> {
+        let (index, repo) = self.single(path, |repo| repo.approval, "needs approval")?;
+        let approved = self.updater(repo).approve(commit)?;
+        self.queue([Command::Sync(index)])?;
+        Ok(approved)
+    }
+
+    /// Rolls back `steps` updates, returning the commits it went from and to.
+    pub(crate) fn rollback(&self, path: Option<&str>, steps: usize) -> Result<(Oid, Oid), Refusal> {
+        let (_, repo) = self.single(path, |_| true, "is configured")?;
+        Ok(self.updater(repo).rollback(steps)?)
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// A username and password from `git credential`.
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+/// Runs `git credential <action>`, which calls the helpers configured in git,
+/// with the attributes in `input`. Prompts are turned off since nobody is there
+/// to answer them.
+fn run(action: &str, input: &str) -> io::Result<Option<String>> {
+    let mut child = Command::new("git")
+        .args(["credential", action])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+fn attributes(url: &str, username: Option<&str>) -> String {
+    let mut input = format!("url={}\n", url);
+    if let Some(username) = username {
+        input.push_str(&format!("username={}\n", username));
+    }
+    input
+}
+
+/// Asks the credential helpers for the credentials of `url`. Gives `None`
+/// when none of them has any.
+pub fn fill(url: &str, username: Option<&str>) -> io::Result<Option<Credential>> {
+    let Some(output) = run("fill", &format!("{}\n", attributes(url, username)))? else {
+        return Ok(None);
+    };
+    let mut credential = Credential {
+        username: username.unwrap_or_default().to_string(),
+        password: String::new(),
+    };
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => credential.username = value.to_string(),
+            Some(("password", value)) => credential.password = value.to_string(),
+            _ => {}
+        }
+    }
+    Ok(Some(credential).filter(|credential| !credential.password.is_empty()))
+}
+
+/// Tells the credential helpers that `credential` was refused, so they can
+/// forget it instead of handing it out again.
+pub fn reject(url: &str, credential: &Credential) {
+    let input = format!(
+        "{}password={}\n\n",
+        attributes(url, Some(&credential.username)),
+        credential.password
+    );
+    let _ = run("reject", &input);
+}
+
+/// Tells the credential helpers that `credential` was accepted, so the ones
+/// that store credentials keep it.
+pub fn approve(url: &str, credential: &Credential) {
+    let input = format!(
+        "{}password={}\n\n",
+        attributes(url, Some(&credential.username)),
+        credential.password
+    );
+    let _ = run("approve", &input);
+}
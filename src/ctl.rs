@@ -0,0 +1,148 @@
+use crate::cli::CtlCommand;
+use autoupdate::config::{self, Settings};
+use autoupdate::output::{Event, Output};
+use autoupdate::Error;
+use serde::Deserialize;
+use std::process::ExitCode;
+
+#[derive(Deserialize)]
+struct Queued {
+    queued: usize,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    path: String,
+    branch: String,
+    commit: Option<String>,
+    paused: bool,
+    healthy: bool,
+}
+
+#[derive(Deserialize)]
+struct Failure {
+    error: String,
+}
+
+/// Sends a request without a body to the daemon listening on `socket` and
+/// returns the status and body of its response.
+#[cfg(unix)]
+fn request(socket: &str, method: &str, target: &str) -> Result<(u16, Vec<u8>), Error> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let unreachable = |e: std::io::Error| {
+        Error::Io(std::io::Error::new(
+            e.kind(),
+            format!("could not reach the daemon at '{}': {}", socket, e),
+        ))
+    };
+    let mut stream = UnixStream::connect(socket).map_err(unreachable)?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, target
+    )
+    .map_err(unreachable)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).map_err(unreachable)?;
+
+    let malformed = || Error::Io(std::io::Error::other("malformed response from the daemon"));
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let status = std::str::from_utf8(&response[..split])
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1)?.parse().ok())
+        .ok_or_else(malformed)?;
+    Ok((status, response[split + 4..].to_vec()))
+}
+
+#[cfg(not(unix))]
+fn request(_socket: &str, _method: &str, _target: &str) -> Result<(u16, Vec<u8>), Error> {
+    Err(Error::Config(
+        "the control socket is only supported on unix systems".to_string(),
+    ))
+}
+
+/// Asks the daemon for `target` and parses its answer.
+fn call<T: for<'de> Deserialize<'de>>(
+    socket: &str,
+    method: &str,
+    target: &str,
+) -> Result<T, Error> {
+    let (status, body) = request(socket, method, target)?;
+    if !(200..300).contains(&status) {
+        let message = serde_json::from_slice::<Failure>(&body)
+            .map(|failure| failure.error)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+        return Err(match status {
+            400 | 404 => Error::Config(message),
+            _ => Error::Refused(message),
+        });
+    }
+    serde_json::from_slice(&body).map_err(|e| {
+        Error::Io(std::io::Error::other(format!(
+            "unexpected answer from the daemon: {}",
+            e
+        )))
+    })
+}
+
+fn target(endpoint: &str, path: Option<&str>) -> String {
+    match path {
+        Some(path) => format!(
+            "{}?{}",
+            endpoint,
+            form_urlencoded::Serializer::new(String::new())
+                .append_pair("path", path)
+                .finish()
+        ),
+        None => endpoint.to_string(),
+    }
+}
+
+pub(crate) fn run(
+    settings: &Settings,
+    command: &CtlCommand,
+    output: &Output,
+) -> Result<ExitCode, Error> {
+    let Some(socket) = &settings.socket else {
+        return Err(Error::Config(
+            "no control socket is configured, set socket in the settings".to_string(),
+        ));
+    };
+    let (action, endpoint, path) = match command {
+        CtlCommand::Status => {
+            let repositories: Vec<Repository> = call(socket, "GET", "/api/repositories")?;
+            for repository in &repositories {
+                output.emit(Event::DaemonStatus {
+                    path: &repository.path,
+                    branch: &repository.branch,
+                    commit: repository.commit.as_deref(),
+                    paused: repository.paused,
+                    healthy: repository.healthy,
+                });
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        CtlCommand::Sync { path } => ("sync", "/api/sync", path),
+        CtlCommand::Pause { path } => ("pause", "/api/pause", path),
+        CtlCommand::Resume { path } => ("resume", "/api/resume", path),
+    };
+    // The daemon may run elsewhere, so a relative path is sent the way it is configured
+    let path = path.as_deref().map(|path| {
+        settings
+            .repositories
+            .iter()
+            .find(|repo| config::same_path(&repo.path, path))
+            .map_or(path, |repo| repo.path.as_str())
+    });
+    let queued: Queued = call(socket, "POST", &target(endpoint, path))?;
+    output.emit(Event::DaemonQueued {
+        action,
+        repositories: queued.queued,
+    });
+    Ok(ExitCode::SUCCESS)
+}
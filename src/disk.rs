@@ -0,0 +1,222 @@
+use crate::config::Repo;
+use crate::error::{Error, Result};
+use git2::{
+    ObjectType, Odb, Oid, Pathspec, PathspecFlags, Repository, TreeWalkMode, TreeWalkResult,
+};
+use indicatif::HumanBytes;
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Received objects after which the size of the whole pack is extrapolated,
+/// before that a handful of commits says little about the blobs to come.
+const ESTIMATE_AFTER: usize = 100;
+
+/// Free bytes on the filesystem holding `path`, or its closest existing
+/// parent when it is not created yet.
+#[cfg(unix)]
+fn available(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = path
+        .ancestors()
+        .map(|path| match path.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => path,
+        })
+        .find(|path| path.exists())?;
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    // The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn available(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Bytes taken up by the files below `path`, not following symlinks inside it.
+pub(crate) fn usage(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => usage(&entry.path()),
+            _ => entry.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+        })
+        .sum()
+}
+
+fn short(path: &Path, what: &str, needed: u64, free: u64) -> Error {
+    Error::DiskSpace(format!(
+        "{} needs about {} but only {} is free on the filesystem of '{}'",
+        what,
+        HumanBytes(needed),
+        HumanBytes(free),
+        path.display()
+    ))
+}
+
+/// Watches a download against the space that was free on its filesystem
+/// when it started, and stops it once the pack it is estimated to end up at
+/// would leave less than `min_free_space`.
+pub(crate) struct Space {
+    path: PathBuf,
+    reserve: u64,
+    free: Cell<Option<Option<u64>>>,
+    needed: Cell<Option<u64>>,
+}
+
+impl Space {
+    pub(crate) fn new(repo: &Repo, path: &Path) -> Result<Space> {
+        Ok(Space {
+            path: path.to_path_buf(),
+            reserve: repo.min_free_space()?,
+            free: Cell::new(None),
+            needed: Cell::new(None),
+        })
+    }
+
+    /// Measures the free space again for another attempt.
+    pub(crate) fn restart(&self) {
+        self.free.set(None);
+        self.needed.set(None);
+    }
+
+    /// Whether the transfer may go on; the callbacks return this to libgit2.
+    pub(crate) fn check(&self, stats: &git2::Progress) -> bool {
+        if self.needed.get().is_some() {
+            return false;
+        }
+        let free = match self.free.get() {
+            Some(free) => free,
+            None => {
+                let free = available(&self.path);
+                self.free.set(Some(free));
+                free
+            }
+        };
+        let Some(free) = free else {
+            return true;
+        };
+
+        let received = stats.received_bytes() as u64;
+        let estimate = match stats.received_objects() {
+            objects if objects >= ESTIMATE_AFTER => {
+                let total = stats.total_objects() as u64;
+                (received.saturating_mul(total) / objects as u64).max(received)
+            }
+            _ => received,
+        };
+        if estimate.saturating_add(self.reserve) > free {
+            self.needed.set(Some(estimate));
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn exhausted(&self) -> bool {
+        self.needed.get().is_some()
+    }
+
+    pub(crate) fn error(&self, what: &str) -> Error {
+        let free = self.free.get().flatten().unwrap_or_default();
+        let needed = self.needed.get().unwrap_or_default();
+        short(&self.path, what, needed + self.reserve, free)
+    }
+}
+
+/// Bytes of the blob `id`, if it is a sparse path that gets checked out.
+fn blob_size(odb: &Odb, pathspec: Option<&Pathspec>, path: &Path, id: Oid) -> Result<u64> {
+    if pathspec.is_some_and(|pathspec| !pathspec.matches_path(path, PathspecFlags::DEFAULT)) {
+        return Ok(0);
+    }
+    Ok(odb.read_header(id)?.0 as u64)
+}
+
+/// Bytes the work tree grows by when it goes from `from`, or from nothing,
+/// to the tree of `to`, counting the size of the files rather than the blocks
+/// they take up.
+fn growth(repo: &Repo, repository: &Repository, from: Option<Oid>, to: Oid) -> Result<u64> {
+    let odb = repository.odb()?;
+    let pathspec = match repo.sparse.is_empty() {
+        true => None,
+        false => Some(Pathspec::new(repo.sparse.iter())?),
+    };
+    let to = repository.find_commit(to)?.tree()?;
+
+    let Some(from) = from else {
+        let mut size = 0;
+        let mut failed = None;
+        to.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(ObjectType::Blob) {
+                return TreeWalkResult::Ok;
+            }
+            let path = PathBuf::from(format!("{}{}", dir, entry.name().unwrap_or_default()));
+            match blob_size(&odb, pathspec.as_ref(), &path, entry.id()) {
+                Ok(bytes) => size += bytes,
+                Err(e) => {
+                    failed = Some(e);
+                    return TreeWalkResult::Abort;
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+        return match failed {
+            Some(e) => Err(e),
+            None => Ok(size),
+        };
+    };
+
+    let from = repository.find_commit(from)?.tree()?;
+    let diff = repository.diff_tree_to_tree(Some(&from), Some(&to), None)?;
+    let mut grown: i64 = 0;
+    for delta in diff.deltas() {
+        for (file, sign) in [(delta.new_file(), 1), (delta.old_file(), -1)] {
+            let (Some(path), true) = (file.path(), file.exists()) else {
+                continue;
+            };
+            if file.mode() == git2::FileMode::Commit {
+                continue;
+            }
+            grown += sign * blob_size(&odb, pathspec.as_ref(), path, file.id())? as i64;
+        }
+    }
+    Ok(grown.max(0) as u64)
+}
+
+/// Refuses to check out `to` when the files it adds over `from` would leave
+/// less than `min_free_space` on the filesystem of the checkout. Without
+/// `from` the whole tree is written, as in a clone or atomic update.
+pub(crate) fn ensure_checkout(
+    repo: &Repo,
+    repository: &Repository,
+    from: Option<Oid>,
+    to: Oid,
+) -> Result<()> {
+    let path = Path::new(&repo.path);
+    let Some(free) = available(path) else {
+        return Ok(());
+    };
+    let needed = growth(repo, repository, from, to)?;
+    let reserve = repo.min_free_space()?;
+    debug!(needed, free, reserve, "checking free disk space");
+    match needed.saturating_add(reserve) > free {
+        true => Err(short(
+            path,
+            &format!("checking out {}", to),
+            needed + reserve,
+            free,
+        )),
+        false => Ok(()),
+    }
+}
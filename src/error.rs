@@ -0,0 +1,87 @@
+use git2::ErrorCode;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Config(String),
+    Git(git2::Error),
+    Io(io::Error),
+    Auth(String),
+    Hook(String),
+    Refused(String),
+    Notify(String),
+    Locked(String),
+    Timeout(String),
+    DiskSpace(String),
+    Interrupted(String),
+}
+
+impl Error {
+    /// Whether the remote could not be reached, as when the network is down.
+    pub fn is_network(&self) -> bool {
+        matches!(self, Error::Git(e) if crate::retry::is_transient(e))
+    }
+
+    /// Process exit code used by the binary for this class of failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Git(_) => 3,
+            Error::Io(_) => 4,
+            Error::Auth(_) => 5,
+            Error::Hook(_) => 6,
+            Error::Refused(_) => 7,
+            Error::Notify(_) => 8,
+            Error::Locked(_) => 9,
+            Error::Timeout(_) => 12,
+            Error::DiskSpace(_) => 13,
+            Error::Interrupted(_) => 130,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "configuration error: {}", message),
+            Error::Git(e) => write!(f, "git error: {}", e.message()),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Auth(message) => write!(f, "authentication error: {}", message),
+            Error::Hook(message) => write!(f, "hook error: {}", message),
+            Error::Refused(message) => write!(f, "update refused: {}", message),
+            Error::Notify(message) => write!(f, "notification error: {}", message),
+            Error::Locked(message) => write!(f, "already running: {}", message),
+            Error::Timeout(message) => write!(f, "timed out: {}", message),
+            Error::DiskSpace(message) => write!(f, "not enough disk space: {}", message),
+            Error::Interrupted(message) => write!(f, "interrupted: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Git(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Error {
+        match e.code() {
+            ErrorCode::Auth => Error::Auth(e.message().to_string()),
+            _ => Error::Git(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
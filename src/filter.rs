@@ -0,0 +1,51 @@
+use crate::config::Filter;
+use crate::error::Result;
+use git2::{Commit, Oid, Repository};
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+use tracing::debug;
+
+/// Whether `commit` changes a path matching one of `patterns`, compared to
+/// its first parent. A commit whose parent was not fetched changes every path.
+fn touches(repository: &Repository, commit: &Commit, patterns: &[Pattern]) -> Result<bool> {
+    let parent = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repository.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    Ok(diff.deltas().any(|delta| {
+        [delta.old_file().path(), delta.new_file().path()]
+            .into_iter()
+            .flatten()
+            .any(|path: &Path| {
+                patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(path, options))
+            })
+    }))
+}
+
+/// Whether one of the new `commits` calls for an update: its message lacks
+/// the skip marker and it changes one of the filtered paths, if any are set.
+pub(crate) fn applies(filter: &Filter, repository: &Repository, commits: &[Oid]) -> Result<bool> {
+    let patterns = filter.paths()?;
+    for id in commits {
+        let commit = repository.find_commit(*id)?;
+        let message = commit.message().unwrap_or_default();
+        if let Some(marker) = &filter.skip_marker {
+            if message.contains(marker.as_str()) {
+                debug!(%id, "commit is marked to be skipped");
+                continue;
+            }
+        }
+        if patterns.is_empty() || touches(repository, &commit, &patterns)? {
+            debug!(%id, "commit passes the filter");
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,287 @@
+use crate::config::{Provider, Repo};
+use crate::error::{Error, Result};
+use crate::source::Revision;
+use git2::{ErrorClass, ErrorCode, Oid};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fs;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Most pages of a listing that are read; the rest is left unread.
+const MAX_PAGES: usize = 10;
+
+/// Host and project path of a remote URL such as `owner/name`, for
+/// `https://host/owner/name.git` and `git@host:owner/name.git` alike.
+fn project(url: &str) -> Option<(&str, &str)> {
+    let (authority, path) = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?,
+        None => url.split_once(':')?,
+    };
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    match host.is_empty() || !path.contains('/') {
+        true => None,
+        false => Some((host, path)),
+    }
+}
+
+/// The target of the `rel="next"` link in a `Link` header.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|link| {
+        let (target, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| matches!(param.trim(), "rel=\"next\"" | "rel=next"))
+            .then(|| target.trim().trim_matches(['<', '>']).to_string())
+    })
+}
+
+/// Keeps GitLab's project path usable as a single path segment.
+fn encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Network failures are reported like libgit2's, so retries and the
+/// unreachable backoff treat them the same.
+pub(crate) fn failure(url: &str, e: ureq::Error) -> Error {
+    let (code, class) = match &e {
+        ureq::Error::StatusCode(401 | 403) => (ErrorCode::Auth, ErrorClass::Http),
+        ureq::Error::StatusCode(404) => (ErrorCode::NotFound, ErrorClass::Reference),
+        ureq::Error::StatusCode(status) if *status < 500 && *status != 429 => {
+            (ErrorCode::GenericError, ErrorClass::Callback)
+        }
+        _ => (ErrorCode::GenericError, ErrorClass::Net),
+    };
+    git2::Error::new(code, class, format!("{}: {}", url, e)).into()
+}
+
+#[derive(Deserialize)]
+struct GithubCommit {
+    sha: String,
+    commit: GithubMessage,
+}
+
+#[derive(Deserialize)]
+struct GithubMessage {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabCommit {
+    id: String,
+    message: String,
+}
+
+/// The GitHub or GitLab REST API of the project the remote URL names.
+pub(crate) struct Api<'a> {
+    repo: &'a Repo,
+    provider: Provider,
+    base: String,
+    project: String,
+}
+
+impl<'a> Api<'a> {
+    /// `provider` and `api_url` are derived from the host of the URL when unset.
+    pub(crate) fn new(
+        repo: &'a Repo,
+        provider: Option<Provider>,
+        api_url: Option<&str>,
+    ) -> Result<Api<'a>> {
+        let (host, project) = project(&repo.url).ok_or_else(|| {
+            Error::Config(format!(
+                "'{}' has a url that names no project for the forge API",
+                repo.path
+            ))
+        })?;
+        let provider = match provider {
+            Some(provider) => provider,
+            None if host == "github.com" => Provider::Github,
+            None if host.contains("gitlab") => Provider::Gitlab,
+            None => {
+                return Err(Error::Config(format!(
+                    "'{}' needs a forge provider, '{}' is not known to be GitHub or GitLab",
+                    repo.path, host
+                )))
+            }
+        };
+        let base = match (api_url, provider) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Provider::Github) if host == "github.com" => {
+                "https://api.github.com".to_string()
+            }
+            (None, Provider::Github) => format!("https://{}/api/v3", host),
+            (None, Provider::Gitlab) => format!("https://{}/api/v4", host),
+        };
+        Ok(Api {
+            repo,
+            provider,
+            base,
+            project: project.to_string(),
+        })
+    }
+
+    pub(crate) fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    /// `{base}/repos/owner/name` or `{base}/projects/owner%2Fname`, which the
+    /// endpoints of the project are below.
+    pub(crate) fn project_url(&self) -> String {
+        match self.provider {
+            Provider::Github => format!("{}/repos/{}", self.base, self.project),
+            Provider::Gitlab => format!("{}/projects/{}", self.base, encode(&self.project)),
+        }
+    }
+
+    pub(crate) fn get(&self, url: &str) -> Result<ureq::http::Response<ureq::Body>> {
+        let mut request = ureq::get(url);
+        if let Some(token) = self
+            .repo
+            .auth
+            .as_ref()
+            .map(|auth| auth.token())
+            .transpose()?
+        {
+            request = match (self.provider, token) {
+                (_, None) => request,
+                (Provider::Github, Some(token)) => {
+                    request.header("Authorization", format!("Bearer {}", token))
+                }
+                (Provider::Gitlab, Some(token)) => request.header("PRIVATE-TOKEN", token),
+            };
+        }
+        for header in &self.repo.headers {
+            let line = header.line()?;
+            let value = line.split_once(':').map_or("", |(_, value)| value.trim());
+            request = request.header(&header.name, value);
+        }
+
+        let mut tls = ureq::tls::TlsConfig::builder();
+        if let Some(settings) = &self.repo.tls {
+            tls = tls.disable_verification(settings.skip_verify);
+            if let Some(ca_file) = &settings.ca_file {
+                let pem = fs::read(ca_file)?;
+                let certs = ureq::tls::parse_pem(&pem)
+                    .filter_map(|item| match item {
+                        Ok(ureq::tls::PemItem::Certificate(cert)) => Some(Ok(cert)),
+                        Ok(_) => None,
+                        Err(e) => Some(Err(e)),
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| Error::Config(format!("could not read '{}': {}", ca_file, e)))?;
+                tls = tls.root_certs(ureq::tls::RootCerts::Specific(Arc::new(certs)));
+            }
+        }
+        let proxy = match self.repo.proxy.as_deref() {
+            None | Some("auto") => ureq::Proxy::try_from_env(),
+            Some(url) => Some(
+                ureq::Proxy::new(url)
+                    .map_err(|e| Error::Config(format!("invalid proxy '{}': {}", url, e)))?,
+            ),
+        };
+
+        request
+            .config()
+            .tls_config(tls.build())
+            .proxy(proxy)
+            .timeout_connect(self.repo.timeouts.connect()?)
+            .timeout_global(self.repo.timeouts.transfer()?)
+            .build()
+            .call()
+            .map_err(|e| failure(url, e))
+    }
+
+    pub(crate) fn json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.get(url)?
+            .body_mut()
+            .read_json()
+            .map_err(|e| failure(url, e))
+    }
+
+    /// Every page of a listing, following the `Link` headers from `url` on.
+    /// The flag tells whether the last page was reached within [`MAX_PAGES`].
+    pub(crate) fn pages<T: DeserializeOwned>(&self, url: &str) -> Result<(Vec<T>, bool)> {
+        let mut pages = Vec::new();
+        let mut next = Some(url.to_string());
+        while let Some(url) = next.take() {
+            if pages.len() == MAX_PAGES {
+                return Ok((pages, false));
+            }
+            let mut response = self.get(&url)?;
+            next = response
+                .headers()
+                .get("link")
+                .and_then(|link| link.to_str().ok())
+                .and_then(next_page);
+            pages.push(
+                response
+                    .body_mut()
+                    .read_json()
+                    .map_err(|e| failure(&url, e))?,
+            );
+        }
+        Ok((pages, true))
+    }
+
+    /// The commit `reference` points to.
+    pub(crate) fn commit(&self, reference: &str) -> Result<Revision> {
+        let url = match self.provider {
+            Provider::Github => format!("{}/commits/{}", self.project_url(), reference),
+            Provider::Gitlab => format!(
+                "{}/repository/commits/{}",
+                self.project_url(),
+                encode(reference)
+            ),
+        };
+        let (id, message) = match self.provider {
+            Provider::Github => {
+                let commit: GithubCommit = self.json(&url)?;
+                (commit.sha, commit.commit.message)
+            }
+            Provider::Gitlab => {
+                let commit: GitlabCommit = self.json(&url)?;
+                (commit.id, commit.message)
+            }
+        };
+        let id = Oid::from_str(&id).map_err(|_| {
+            Error::Git(git2::Error::from_str(&format!(
+                "{} answered with an invalid commit id '{}'",
+                url, id
+            )))
+        })?;
+        debug!(%id, reference, "resolved reference");
+        Ok(Revision { id, message })
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        format!("{}/{}", self.base, self.project)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_page;
+
+    #[test]
+    fn follows_the_next_link() {
+        let link = r#"<https://api.github.com/x?page=2>; rel="next", <https://api.github.com/x?page=5>; rel="last""#;
+        assert_eq!(
+            next_page(link).as_deref(),
+            Some("https://api.github.com/x?page=2")
+        );
+        let last = r#"<https://api.github.com/x?page=4>; rel="prev", <https://api.github.com/x?page=1>; rel="first""#;
+        assert_eq!(next_page(last), None);
+        assert_eq!(next_page(""), None);
+    }
+}
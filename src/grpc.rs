@@ -0,0 +1,222 @@
+use crate::api;
+use crate::control::{Control, Refusal, DEFAULT_HISTORY_LIMIT};
+use crate::Command;
+use autoupdate::config;
+use autoupdate::Error;
+use std::fs;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Code, Request, Response, Status};
+
+mod proto {
+    tonic::include_proto!("autoupdate.v1");
+
+    pub const DESCRIPTOR: &[u8] = tonic::include_file_descriptor_set!("autoupdate_descriptor");
+}
+
+use proto::updater_server::{Updater, UpdaterServer};
+
+impl From<Refusal> for Status {
+    fn from(refusal: Refusal) -> Status {
+        let code = match refusal.status {
+            400 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            404 => Code::NotFound,
+            409 => Code::FailedPrecondition,
+            503 => Code::Unavailable,
+            _ => Code::Internal,
+        };
+        Status::new(code, refusal.message)
+    }
+}
+
+/// The control operations as the `autoupdate.v1.Updater` service.
+struct Service {
+    control: Arc<Control>,
+}
+
+impl Service {
+    /// Runs `operation` off the runtime's threads, since the control
+    /// operations read and write repositories.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&Control) -> Result<T, Refusal> + Send + 'static,
+    ) -> Result<Response<T>, Status> {
+        let control = self.control.clone();
+        tokio::task::spawn_blocking(move || operation(&control))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map(Response::new)
+            .map_err(Status::from)
+    }
+}
+
+#[tonic::async_trait]
+impl Updater for Service {
+    async fn sync(
+        &self,
+        request: Request<proto::SyncRequest>,
+    ) -> Result<Response<proto::SyncReply>, Status> {
+        let path = request.into_inner().path;
+        self.blocking(move |control| {
+            let indexes = control.selected(path.as_deref())?;
+            let queued = indexes.len() as u32;
+            control.queue(indexes.into_iter().map(Command::Sync))?;
+            Ok(proto::SyncReply { queued })
+        })
+        .await
+    }
+
+    async fn status(
+        &self,
+        request: Request<proto::StatusRequest>,
+    ) -> Result<Response<proto::StatusReply>, Status> {
+        let path = request.into_inner().path;
+        self.blocking(move |control| {
+            let indexes = control.selected(path.as_deref())?;
+            let repositories = control
+                .repositories()
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| indexes.contains(index))
+                .map(|(_, repository)| proto::Repository {
+                    path: repository.health.path,
+                    url: repository.url,
+                    branch: repository.branch,
+                    paused: repository.paused,
+                    commit: repository.health.commit,
+                    clean: repository.health.clean,
+                    last_sync: repository.health.last_sync,
+                    last_success: repository.health.last_success,
+                    disk_usage: repository.health.disk_usage,
+                    healthy: repository.health.healthy,
+                })
+                .collect();
+            Ok(proto::StatusReply { repositories })
+        })
+        .await
+    }
+
+    async fn history(
+        &self,
+        request: Request<proto::HistoryRequest>,
+    ) -> Result<Response<proto::HistoryReply>, Status> {
+        let request = request.into_inner();
+        let limit = request
+            .limit
+            .map_or(DEFAULT_HISTORY_LIMIT, |limit| limit as usize);
+        self.blocking(move |control| {
+            let indexes = control.selected(request.path.as_deref())?;
+            let repositories = control
+                .histories(&indexes, limit)
+                .into_iter()
+                .map(|history| proto::RepositoryHistory {
+                    path: history.path,
+                    entries: history
+                        .entries
+                        .into_iter()
+                        .map(|entry| proto::HistoryEntry {
+                            timestamp: entry.timestamp,
+                            old: entry.old,
+                            new: entry.new,
+                            duration_ms: entry.duration_ms,
+                            outcome: serde_json::to_value(entry.outcome)
+                                .ok()
+                                .and_then(|outcome| outcome.as_str().map(str::to_string))
+                                .unwrap_or_default(),
+                            error: entry.error,
+                        })
+                        .collect(),
+                })
+                .collect();
+            Ok(proto::HistoryReply { repositories })
+        })
+        .await
+    }
+
+    async fn rollback(
+        &self,
+        request: Request<proto::RollbackRequest>,
+    ) -> Result<Response<proto::RollbackReply>, Status> {
+        let request = request.into_inner();
+        let steps = request.steps.map_or(1, |steps| steps as usize);
+        self.blocking(move |control| {
+            let (from, to) = control.rollback(request.path.as_deref(), steps)?;
+            Ok(proto::RollbackReply {
+                from: from.to_string(),
+                to: to.to_string(),
+            })
+        })
+        .await
+    }
+}
+
+fn read(name: &str, file: &str) -> Result<Vec<u8>, Error> {
+    fs::read(file)
+        .map_err(|e| Error::Config(format!("could not read grpc {} '{}': {}", name, file, e)))
+}
+
+/// Serves the control service on its own thread and runtime. The address is
+/// bound and the certificates are read before it returns, so that their
+/// failures stop the daemon from starting.
+pub(crate) fn spawn(settings: &config::Grpc, control: Arc<Control>) -> Result<(), Error> {
+    let mut server = Server::builder();
+    if let (Some(cert), Some(key)) = (&settings.cert, &settings.key) {
+        let mut tls = ServerTlsConfig::new()
+            .identity(Identity::from_pem(read("cert", cert)?, read("key", key)?));
+        if let Some(client_ca) = &settings.client_ca {
+            tls = tls.client_ca_root(Certificate::from_pem(read("client_ca", client_ca)?));
+        }
+        server = server
+            .tls_config(tls)
+            .map_err(|e| Error::Config(format!("invalid grpc TLS settings: {}", e)))?;
+    }
+
+    let token = settings.token()?;
+    let service =
+        UpdaterServer::with_interceptor(Service { control }, move |request: Request<()>| {
+            let given = request
+                .metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+            match api::authorized(token.as_deref(), given) {
+                true => Ok(request),
+                false => Err(Status::unauthenticated("missing or invalid token")),
+            }
+        });
+    let reflection = match settings.reflection {
+        true => Some(
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(proto::DESCRIPTOR)
+                .build_v1()
+                .map_err(|e| Error::Io(std::io::Error::other(e)))?,
+        ),
+        false => None,
+    };
+
+    let listener = TcpListener::bind(&settings.listen)?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let incoming = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => TcpIncoming::from(listener),
+                Err(e) => return tracing::error!(error = %e, "could not serve gRPC"),
+            };
+            let result = server
+                .add_service(service)
+                .add_optional_service(reflection)
+                .serve_with_incoming(incoming)
+                .await;
+            if let Err(e) = result {
+                tracing::error!(error = %e, "the gRPC server stopped");
+            }
+        })
+    });
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::config::Repo;
+use crate::metrics::RepoMetrics;
+use crate::source;
+use crate::updater;
+use git2::Repository;
+use serde::Serialize;
+
+/// State of one checkout as reported by `/healthz`.
+#[derive(Serialize)]
+pub struct Health {
+    pub path: String,
+    pub commit: Option<String>,
+    /// Whether the working tree has no local modifications, unknown when it could not be read.
+    pub clean: Option<bool>,
+    pub last_sync: Option<u64>,
+    pub last_success: Option<u64>,
+    /// Bytes the checkout took up after the last sync.
+    pub disk_usage: Option<u64>,
+    /// Checked out and the last sync, if any, succeeded.
+    pub healthy: bool,
+}
+
+pub fn check(repo: &Repo, metrics: &RepoMetrics) -> Health {
+    // Checkouts from other sources say which commit they hold, not whether they are clean
+    if let Ok(Some(source)) = source::of(repo) {
+        let commit = source.current().ok().flatten().map(|oid| oid.to_string());
+        return Health {
+            path: repo.path.clone(),
+            healthy: commit.is_some() && !metrics.failing(),
+            commit,
+            clean: None,
+            last_sync: metrics.last_sync(),
+            last_success: metrics.last_success(),
+            disk_usage: metrics.disk_usage(),
+        };
+    }
+
+    let repository = Repository::open(&repo.path).ok();
+    let commit = repository
+        .as_ref()
+        .and_then(|repository| repository.head().ok()?.target())
+        .map(|oid| oid.to_string());
+    let clean = repository
+        .as_ref()
+        .and_then(|repository| match repository.is_bare() {
+            true => Some(false),
+            false => updater::is_dirty(repository).ok(),
+        })
+        .map(|dirty| !dirty);
+
+    Health {
+        path: repo.path.clone(),
+        healthy: commit.is_some() && !metrics.failing(),
+        commit,
+        clean,
+        last_sync: metrics.last_sync(),
+        last_success: metrics.last_success(),
+        disk_usage: metrics.disk_usage(),
+    }
+}
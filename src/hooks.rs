@@ -0,0 +1,221 @@
+use crate::config::{Hooks, Variable};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use git2::Oid;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Most of the changed paths put in `CHANGED_FILES` itself, well below the
+/// 128 KiB Linux allows a single variable.
+const MAX_CHANGED_FILES: usize = 64 * 1024;
+
+static LISTS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    PreUpdate,
+    PostUpdate,
+    Leader,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::PreUpdate => "pre-update",
+            Stage::PostUpdate => "post-update",
+            Stage::Leader => "leader-update",
+        }
+    }
+}
+
+pub struct Context<'a> {
+    pub dir: &'a Path,
+    pub branch: &'a str,
+    pub old_commit: Oid,
+    pub new_commit: Oid,
+    /// Paths the update adds, modifies or deletes, passed to the hooks one
+    /// per line in the file `CHANGED_FILES_PATH` names and, as far as they
+    /// fit, in `CHANGED_FILES`; empty where the source cannot tell.
+    pub changed: Vec<String>,
+}
+
+/// The changed paths, one per line, in a temporary file of their own that
+/// is deleted when this is dropped.
+struct ChangedFiles(PathBuf);
+
+impl ChangedFiles {
+    fn write(paths: &[String]) -> io::Result<ChangedFiles> {
+        let path = std::env::temp_dir().join(format!(
+            "autoupdate-changed-{}-{}",
+            std::process::id(),
+            LISTS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_file(&path);
+        let mut file = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?,
+        );
+        for changed in paths {
+            writeln!(file, "{}", changed)?;
+        }
+        file.flush()?;
+        Ok(ChangedFiles(path))
+    }
+}
+
+impl Drop for ChangedFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// `list` cut after its last whole line within `MAX_CHANGED_FILES`, and
+/// whether anything was cut.
+fn truncated(list: &str) -> (&str, bool) {
+    if list.len() <= MAX_CHANGED_FILES {
+        return (list, false);
+    }
+    let mut end = MAX_CHANGED_FILES;
+    while !list.is_char_boundary(end) {
+        end -= 1;
+    }
+    (
+        list[..end].rsplit_once('\n').map_or("", |(head, _)| head),
+        true,
+    )
+}
+
+pub struct Completion {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.args(["/C", command]);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+    cmd
+}
+
+fn capture(mut pipe: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+fn collect(handle: Option<JoinHandle<String>>) -> String {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
+#[tracing::instrument(name = "hook", skip(context, env, timeout), fields(stage = stage.name()))]
+pub fn execute(
+    command: &str,
+    stage: Stage,
+    context: &Context,
+    env: &[Variable],
+    timeout: Duration,
+) -> io::Result<Completion> {
+    let list = context.changed.join("\n");
+    let file = ChangedFiles::write(&context.changed)?;
+    let (changed, cut) = truncated(&list);
+    let mut child = shell(command)
+        .current_dir(context.dir)
+        .envs(env.iter().map(|variable| (&variable.name, &variable.value)))
+        .env("HOOK_STAGE", stage.name())
+        .env("REPO_PATH", context.dir)
+        .env("BRANCH", context.branch)
+        .env("OLD_COMMIT", context.old_commit.to_string())
+        .env("NEW_COMMIT", context.new_commit.to_string())
+        .env("CHANGED_FILES", changed)
+        .env("CHANGED_FILES_PATH", &file.0)
+        .env("CHANGED_FILES_TRUNCATED", if cut { "1" } else { "0" })
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().map(capture);
+    let stderr = child.stderr.take().map(capture);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("'{}' timed out after {}s", command, timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    drop(file);
+    Ok(Completion {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    })
+}
+
+/// Runs the commands of a stage one after another in the order they are
+/// configured, stopping at the first command that fails.
+pub fn run(stage: Stage, hooks: &Hooks, context: &Context, output: &Output) -> Result<()> {
+    let commands = match stage {
+        Stage::PreUpdate => &hooks.pre_update,
+        Stage::PostUpdate => &hooks.post_update,
+        Stage::Leader => &hooks.leader_update,
+    };
+
+    for command in commands {
+        output.emit(Event::HookStarted {
+            stage: stage.name(),
+            command,
+        });
+
+        let result =
+            execute(command, stage, context, &hooks.env, hooks.timeout()?).map_err(|e| {
+                Error::Hook(format!("{} hook '{}' failed: {}", stage.name(), command, e))
+            })?;
+
+        output.emit(Event::HookFinished {
+            stage: stage.name(),
+            command,
+            code: result.status.code(),
+            stdout: &result.stdout,
+            stderr: &result.stderr,
+        });
+
+        if !result.status.success() {
+            return Err(Error::Hook(format!(
+                "{} hook '{}' exited with {}",
+                stage.name(),
+                command,
+                result.status
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use autoupdate::{Error, Result};
+use clap::ValueEnum;
+use quick_xml::escape::escape;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SettingsFormat {
+    Xml,
+    Toml,
+}
+
+pub struct Answers {
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub branch: Option<String>,
+    pub format: Option<SettingsFormat>,
+}
+
+/// Asks for whatever was not given on the command line and writes the
+/// settings file, returning where it was written.
+pub fn run(answers: Answers, target: Option<&Path>, force: bool) -> Result<PathBuf> {
+    let format = answers.format.unwrap_or_else(|| {
+        match target
+            .and_then(|path| path.extension())
+            .and_then(|ext| ext.to_str())
+        {
+            Some("toml") => SettingsFormat::Toml,
+            _ => SettingsFormat::Xml,
+        }
+    });
+    let target = target.map(Path::to_path_buf).unwrap_or_else(|| {
+        PathBuf::from(match format {
+            SettingsFormat::Xml => "settings.xml",
+            SettingsFormat::Toml => "settings.toml",
+        })
+    });
+    if target.exists() && !force {
+        return Err(Error::Config(format!(
+            "'{}' already exists, pass --force to overwrite it",
+            target.display()
+        )));
+    }
+
+    let url = match answers.url {
+        Some(url) => url,
+        None => prompt("Repository URL", None)?,
+    };
+    let path = match answers.path {
+        Some(path) => path,
+        None => prompt("Checkout directory", default_path(&url).as_deref())?,
+    };
+    let branch = match answers.branch {
+        Some(branch) => branch,
+        None => prompt("Branch", Some("main"))?,
+    };
+
+    let content = match format {
+        SettingsFormat::Xml => xml(&url, &path, &branch),
+        SettingsFormat::Toml => toml(&url, &path, &branch),
+    };
+    fs::write(&target, content)?;
+    Ok(target)
+}
+
+/// Names the checkout after the last segment of the URL, like `git clone` does.
+fn default_path(url: &str) -> Option<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    loop {
+        if interactive {
+            match default {
+                Some(default) => eprint!("{} [{}]: ", question, default),
+                None => eprint!("{}: ", question),
+            }
+            io::stderr().flush()?;
+        }
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return default
+                .map(str::to_string)
+                .ok_or_else(|| Error::Config(format!("no answer given for '{}'", question)));
+        }
+        match (line.trim(), default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
+
+fn xml(url: &str, path: &str, branch: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<?xml-model href="settings.xsd"?>
+<settings>
+  <repository>
+    <url>{}</url>
+    <path>{}</path>
+    <branch>{}</branch>
+  </repository>
+</settings>
+"#,
+        escape(url),
+        escape(path),
+        escape(branch)
+    )
+}
+
+fn toml(url: &str, path: &str, branch: &str) -> String {
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+    format!(
+        "[[repository]]\nurl = {}\npath = {}\nbranch = {}\n",
+        quote(url),
+        quote(path),
+        quote(branch)
+    )
+}
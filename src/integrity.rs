@@ -0,0 +1,85 @@
+use crate::config::Integrity;
+use crate::error::{Error, Result};
+use git2::{ObjectType, Oid, Repository, Tree};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+fn corrupt(commit: Oid, what: &str, e: git2::Error) -> Error {
+    Error::Refused(format!(
+        "{} of {} is missing or corrupt: {}",
+        what,
+        commit,
+        e.message()
+    ))
+}
+
+/// Reads every commit between `local_oid` and `target` and every object of the
+/// target tree, which makes libgit2 check their hashes, then compares the
+/// configured files with their expected digests. Returns the number of objects read.
+pub fn check(
+    integrity: &Integrity,
+    repository: &Repository,
+    local_oid: Oid,
+    target: Oid,
+) -> Result<usize> {
+    let mut objects = 0;
+
+    let mut walk = repository.revwalk()?;
+    walk.push(target)?;
+    // The local commit is gone after a force-push that rewrote it away
+    let _ = walk.hide(local_oid);
+    for oid in walk {
+        let oid = oid.map_err(|e| corrupt(target, "history", e))?;
+        repository
+            .find_commit(oid)
+            .map_err(|e| corrupt(oid, "commit", e))?;
+        objects += 1;
+    }
+
+    let commit = repository
+        .find_commit(target)
+        .map_err(|e| corrupt(target, "commit", e))?;
+    let tree = commit.tree().map_err(|e| corrupt(target, "tree", e))?;
+    objects += read_tree(repository, &tree, target, "")?;
+
+    for file in &integrity.files {
+        let entry = tree
+            .get_path(Path::new(&file.path))
+            .map_err(|_| Error::Refused(format!("{} has no file '{}'", target, file.path)))?;
+        let blob = repository
+            .find_blob(entry.id())
+            .map_err(|e| corrupt(target, &file.path, e))?;
+        let actual = hex::encode(Sha256::digest(blob.content()));
+        if !actual.eq_ignore_ascii_case(&file.sha256) {
+            return Err(Error::Refused(format!(
+                "sha256 of '{}' in {} is {} but {} is expected",
+                file.path, target, actual, file.sha256
+            )));
+        }
+    }
+    Ok(objects)
+}
+
+fn read_tree(repository: &Repository, tree: &Tree, commit: Oid, dir: &str) -> Result<usize> {
+    let mut objects = 1;
+    for entry in tree.iter() {
+        let name = format!("{}{}", dir, entry.name().unwrap_or_default());
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let subtree = repository
+                    .find_tree(entry.id())
+                    .map_err(|e| corrupt(commit, &name, e))?;
+                objects += read_tree(repository, &subtree, commit, &format!("{}/", name))?;
+            }
+            Some(ObjectType::Blob) => {
+                repository
+                    .find_blob(entry.id())
+                    .map_err(|e| corrupt(commit, &name, e))?;
+                objects += 1;
+            }
+            // Submodule commits live in another repository
+            _ => {}
+        }
+    }
+    Ok(objects)
+}
@@ -0,0 +1,251 @@
+use crate::auth;
+use crate::config::{HostKeyCheck, Repo, Ssh};
+use crate::output::{Event, Output};
+use base64::prelude::{Engine as _, BASE64_STANDARD, BASE64_STANDARD_NO_PAD};
+use git2::cert::CertHostkey;
+use git2::{CertificateCheckStatus, ErrorClass, ErrorCode};
+use hmac::{Hmac, KeyInit, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+fn failure(message: String) -> git2::Error {
+    git2::Error::new(ErrorCode::Certificate, ErrorClass::Callback, message)
+}
+
+fn file(ssh: &Ssh) -> PathBuf {
+    auth::expand(ssh.known_hosts.as_deref().unwrap_or("~/.ssh/known_hosts"))
+}
+
+/// The name known_hosts uses for the remote: `host` on the default port and
+/// `[host]:port` on any other.
+fn host_name(url: &str, host: &str) -> String {
+    let port = url
+        .strip_prefix("ssh://")
+        .and_then(|rest| rest.split('/').next())
+        .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+        .and_then(|authority| authority.rsplit_once(':'))
+        .map(|(_, port)| port)
+        .filter(|port| !port.is_empty() && *port != "22" && !port.ends_with(']'));
+    match port {
+        Some(port) => format!("[{}]:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Matches `*` and `?` wildcards the way OpenSSH does in host patterns.
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard(&pattern[1..], name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p.eq_ignore_ascii_case(n) => wildcard(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Whether the host field of a known_hosts line names `name`, either as a
+/// list of patterns or hashed with `ssh-keygen -H`.
+fn matches(hosts: &str, name: &str) -> bool {
+    if let Some(hashed) = hosts.strip_prefix("|1|") {
+        let Some((salt, hash)) = hashed.split_once('|') else {
+            return false;
+        };
+        let (Ok(salt), Ok(hash)) = (BASE64_STANDARD.decode(salt), BASE64_STANDARD.decode(hash))
+        else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+            return false;
+        };
+        mac.update(name.as_bytes());
+        return mac.verify_slice(&hash).is_ok();
+    }
+
+    let mut matched = false;
+    for pattern in hosts.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard(negated.as_bytes(), name.as_bytes()) => return false,
+            Some(_) => {}
+            None => matched |= wildcard(pattern.as_bytes(), name.as_bytes()),
+        }
+    }
+    matched
+}
+
+/// Accepts the host key of an SSH remote when it is pinned or listed for the
+/// host in known_hosts. An unknown host is added to the file when trusting on
+/// first use, a changed key is always refused.
+pub fn verify(
+    repo: &Repo,
+    hostkey: &CertHostkey,
+    host: &str,
+    output: &Output,
+) -> Result<CertificateCheckStatus, git2::Error> {
+    let default = Ssh::default();
+    let ssh = repo.ssh.as_ref().unwrap_or(&default);
+    let (Some(key), Some(kind)) = (hostkey.hostkey(), hostkey.hostkey_type()) else {
+        return Err(failure(format!("{} did not send its host key", host)));
+    };
+    let fingerprint = format!(
+        "SHA256:{}",
+        BASE64_STANDARD_NO_PAD.encode(Sha256::digest(key))
+    );
+
+    if !ssh.host_keys.fingerprints.is_empty() {
+        return match ssh.host_keys.fingerprints.contains(&fingerprint) {
+            true => Ok(CertificateCheckStatus::CertificateOk),
+            false => Err(failure(format!(
+                "host key of {} with fingerprint {} is not pinned",
+                host, fingerprint
+            ))),
+        };
+    }
+
+    let path = file(ssh);
+    let name = host_name(&repo.url, host);
+    let key = BASE64_STANDARD.encode(key);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(failure(format!("could not read {}: {}", path.display(), e))),
+    };
+
+    let mut known = false;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (marker, hosts) = match fields.next() {
+            Some(marker) if marker.starts_with('@') => (Some(marker), fields.next()),
+            hosts => (None, hosts),
+        };
+        let (Some(hosts), Some(listed_kind), Some(listed)) = (hosts, fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // Like OpenSSH, only keys of the type the server sent are compared
+        if listed_kind != kind.name() || !matches(hosts, &name) {
+            continue;
+        }
+        match marker {
+            Some("@revoked") if listed == key => {
+                return Err(failure(format!(
+                    "host key of {} with fingerprint {} is revoked in {}",
+                    name,
+                    fingerprint,
+                    path.display()
+                )))
+            }
+            // Host certificates are not supported, their authorities say nothing about plain keys
+            Some(_) => {}
+            None if listed == key => return Ok(CertificateCheckStatus::CertificateOk),
+            None => known = true,
+        }
+    }
+
+    if known {
+        return Err(failure(format!(
+            "host key of {} changed to {}, which does not match {}; someone may be intercepting the connection",
+            name,
+            fingerprint,
+            path.display()
+        )));
+    }
+    if ssh.host_key_check != HostKeyCheck::TrustOnFirstUse {
+        return Err(failure(format!(
+            "host key of {} with fingerprint {} is not in {}",
+            name,
+            fingerprint,
+            path.display()
+        )));
+    }
+
+    let line = format!("{} {} {}\n", name, kind.name(), key);
+    let added = (|| -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(line.as_bytes())
+    })();
+    added.map_err(|e| {
+        failure(format!(
+            "could not add {} to {}: {}",
+            name,
+            path.display(),
+            e
+        ))
+    })?;
+    output.emit(Event::HostKeyTrusted {
+        path: &repo.path,
+        host: &name,
+        fingerprint: &fingerprint,
+    });
+    Ok(CertificateCheckStatus::CertificateOk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by `ssh-keygen -H` for `github.com` and `[git.example.com]:2222`.
+    const HASHED: &str = "|1|5jB29FoNbA5TdkKd9oXFf5a8eAY=|R/pktmoqKdOqQCfD1AUV7NMvuVM=";
+    const HASHED_PORT: &str = "|1|ZWT+IbpoI1X6wx7lukaWH2rMoeg=|58dwteiuRbPXF31bkQYgvgitS9k=";
+
+    #[test]
+    fn matches_hashed_hosts() {
+        assert!(matches(HASHED, "github.com"));
+        assert!(!matches(HASHED, "gitlab.com"));
+        assert!(!matches(HASHED, "GitHub.com"));
+        assert!(matches(HASHED_PORT, "[git.example.com]:2222"));
+        assert!(!matches(HASHED_PORT, "git.example.com"));
+    }
+
+    #[test]
+    fn ignores_malformed_hashes() {
+        assert!(!matches("|1|", "github.com"));
+        assert!(!matches(
+            "|1|not base64|R/pktmoqKdOqQCfD1AUV7NMvuVM=",
+            "github.com"
+        ));
+        assert!(!matches("|1|5jB29FoNbA5TdkKd9oXFf5a8eAY=", "github.com"));
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches("github.com", "github.com"));
+        assert!(matches("gitlab.com,GitHub.com", "github.com"));
+        assert!(matches("*.example.com", "git.example.com"));
+        assert!(!matches("*.example.com", "example.com"));
+        assert!(matches("git?.example.com", "git1.example.com"));
+        assert!(!matches(
+            "*.example.com,!bad.example.com",
+            "bad.example.com"
+        ));
+        assert!(matches(
+            "*.example.com,!bad.example.com",
+            "good.example.com"
+        ));
+        assert!(!matches("!bad.example.com", "good.example.com"));
+    }
+
+    #[test]
+    fn names_hosts_on_other_ports() {
+        assert_eq!(host_name("ssh://git@host/repo.git", "host"), "host");
+        assert_eq!(host_name("ssh://git@host:22/repo.git", "host"), "host");
+        assert_eq!(
+            host_name("ssh://git@host:2222/repo.git", "host"),
+            "[host]:2222"
+        );
+        assert_eq!(host_name("git@host:org/repo.git", "host"), "host");
+    }
+}
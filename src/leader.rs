@@ -0,0 +1,126 @@
+use crate::config::{Leader, Repo};
+use crate::error::{Error, Result};
+use crate::hooks::{self, Context, Stage};
+use crate::output::{Event, Output};
+use crate::store::{self, Document};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::debug;
+
+/// Lock files this process holds, and with them the leadership, until it
+/// exits.
+static HELD: Mutex<BTreeMap<PathBuf, File>> = Mutex::new(BTreeMap::new());
+
+enum Election {
+    Won,
+    /// Another host leads, named when it is known.
+    Lost(Option<String>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    /// Seconds since the unix epoch when another host may take over.
+    expires: u64,
+}
+
+/// Takes or renews the lease at `url` unless another host holds it.
+fn lease(repo: &Repo, leader: &Leader, url: &str, host: &str) -> Result<Election> {
+    let key = match &leader.key {
+        Some(key) => key.clone(),
+        None => {
+            let digest = Sha256::digest(format!("{}#{}", repo.url, repo.branch));
+            hex::encode(&digest[..8])
+        }
+    };
+    let document = Document::new(
+        format!("{}/{}", url.trim_end_matches('/'), key),
+        leader.token()?,
+    );
+    let expires = store::now().saturating_add(leader.lease()?.as_secs());
+    let lease = document.update(|stored: Option<Lease>| match stored {
+        Some(lease) if lease.holder != host && lease.expires > store::now() => (lease, false),
+        _ => (
+            Lease {
+                holder: host.to_string(),
+                expires,
+            },
+            true,
+        ),
+    })?;
+    Ok(match lease.holder == host {
+        true => Election::Won,
+        false => Election::Lost(Some(lease.holder)),
+    })
+}
+
+/// Takes the lock on `file` unless another process holds it.
+fn lock(file: &str, host: &str) -> Result<Election> {
+    let path = PathBuf::from(file);
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if held.contains_key(&path) {
+        return Ok(Election::Won);
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            lock.read_to_string(&mut holder)?;
+            let holder = Some(holder.trim().to_string()).filter(|holder| !holder.is_empty());
+            return Ok(Election::Lost(holder));
+        }
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+    lock.set_len(0)?;
+    lock.seek(SeekFrom::Start(0))?;
+    write!(lock, "{}", host)?;
+    lock.flush()?;
+    held.insert(path, lock);
+    Ok(Election::Won)
+}
+
+/// Runs the leader hooks when this host is the leader, which it always is
+/// without an election.
+pub(crate) fn run_hooks(repo: &Repo, context: &Context, output: &Output) -> Result<()> {
+    if repo.hooks.leader_update.is_empty() {
+        return Ok(());
+    }
+    if let Some(leader) = &repo.leader {
+        let host = store::host(leader.host.as_deref())?;
+        let election = match (&leader.url, &leader.file) {
+            (Some(url), _) => lease(repo, leader, url, &host)?,
+            (None, Some(file)) => lock(file, &host)?,
+            (None, None) => {
+                return Err(Error::Config(
+                    "leader election needs a url or a file".to_string(),
+                ))
+            }
+        };
+        if let Election::Lost(holder) = election {
+            output.emit(Event::LeaderHooksSkipped {
+                path: &repo.path,
+                leader: holder,
+            });
+            return Ok(());
+        }
+        debug!(%host, "elected leader");
+    }
+    hooks::run(Stage::Leader, &repo.hooks, context, output)
+}
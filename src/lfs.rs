@@ -0,0 +1,81 @@
+use crate::config::Repo;
+use crate::error::{Error, Result};
+use crate::output::{Output, ProgressKind};
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Whether the checkout tracks any paths with the LFS filter.
+fn uses_lfs(path: &Path) -> bool {
+    fs::read_to_string(path.join(".gitattributes"))
+        .map(|attributes| attributes.contains("filter=lfs"))
+        .unwrap_or(false)
+}
+
+/// Extracts `(done, total)` from git-lfs progress lines such as
+/// `Downloading LFS objects:  50% (1/2), 1.2 KB | 0 B/s`.
+fn parse_progress(line: &str) -> Option<(usize, usize)> {
+    let start = line.find('(')? + 1;
+    let end = start + line[start..].find(')')?;
+    let (done, total) = line[start..end].split_once('/')?;
+    Some((done.trim().parse().ok()?, total.trim().parse().ok()?))
+}
+
+/// Downloads and checks out the LFS objects of the working tree by running `git lfs pull`.
+pub fn pull(repo: &Repo, output: &Output) -> Result<()> {
+    let path = Path::new(&repo.path);
+    if !uses_lfs(path) {
+        return Ok(());
+    }
+
+    let mut command = Command::new("git");
+    // git-lfs reads the proxy from the environment, "auto" leaves that untouched
+    if let Some(proxy) = repo.proxy.as_deref().filter(|proxy| *proxy != "auto") {
+        command.env("HTTP_PROXY", proxy).env("HTTPS_PROXY", proxy);
+    }
+    let mut child = command
+        .args(["lfs", "pull"])
+        .current_dir(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::Config("git is not installed".to_string()),
+            _ => Error::Io(e),
+        })?;
+
+    let progress = output.progress(&repo.path, ProgressKind::Lfs);
+
+    // git-lfs redraws its progress line with carriage returns
+    let mut stderr = child.stderr.take().unwrap();
+    let mut last_line = String::new();
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while stderr.read(&mut byte)? == 1 {
+        if byte[0] != b'\r' && byte[0] != b'\n' {
+            line.push(byte[0]);
+            continue;
+        }
+        let text = String::from_utf8_lossy(&line).trim().to_string();
+        if let Some((done, total)) = parse_progress(&text) {
+            progress.set(done, total);
+        }
+        if !text.is_empty() {
+            last_line = text;
+        }
+        line.clear();
+    }
+
+    let status = child.wait()?;
+    progress.finish();
+
+    if !status.success() {
+        return Err(Error::Git(git2::Error::from_str(&format!(
+            "git lfs pull failed: {}",
+            last_line
+        ))));
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Clone and keep local git checkouts in sync with their remotes.
+
+mod archive;
+mod auth;
+pub mod cancel;
+mod changes;
+mod ci;
+mod clean;
+pub mod config;
+mod credential;
+mod disk;
+mod error;
+mod filter;
+mod forge;
+pub mod health;
+mod hooks;
+mod integrity;
+mod known_hosts;
+mod leader;
+mod lfs;
+mod lock;
+mod maintenance;
+mod mercurial;
+pub mod metrics;
+pub mod notify;
+pub mod output;
+mod ownership;
+pub mod privileges;
+mod propagate;
+mod releases;
+mod repair;
+mod resolve;
+mod retry;
+mod rollout;
+mod s3;
+pub mod schedule;
+mod secret;
+pub mod server;
+mod source;
+mod sparse;
+mod state;
+mod store;
+mod swap;
+mod tags;
+mod tarball;
+mod throttle;
+mod timeout;
+mod tls;
+mod updater;
+#[cfg(unix)]
+mod users;
+pub mod verify;
+pub mod webhook;
+pub mod window;
+mod worktree;
+
+pub use error::{Error, Result};
+pub use state::HistoryEntry;
+pub use updater::{SyncOutcome, Updater};
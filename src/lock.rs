@@ -0,0 +1,88 @@
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Exclusive lock on a repository path, held until dropped.
+///
+/// The lock lives next to the checkout rather than inside it so it can be
+/// taken before the first clone. The operating system releases it when the
+/// process dies, so the pid written into it only matters for diagnostics.
+pub struct Lock {
+    file: File,
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.autoupdate.lock", name))
+}
+
+fn holder(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+pub fn acquire(path: &str, wait: bool, output: &Output) -> Result<Lock> {
+    let lock_path = lock_path(Path::new(path));
+    if let Some(parent) = lock_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            output.emit(Event::WaitingForLock {
+                path,
+                pid: holder(&mut file),
+            });
+            file.lock()?;
+        }
+        Err(TryLockError::WouldBlock) => {
+            let by = holder(&mut file)
+                .map(|pid| format!(" by pid {}", pid))
+                .unwrap_or_default();
+            return Err(Error::Locked(format!(
+                "'{}' is locked{} ({})",
+                path,
+                by,
+                lock_path.display()
+            )));
+        }
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    // A pid left behind means the previous holder died without cleaning up
+    if let Some(pid) = holder(&mut file) {
+        output.emit(Event::StaleLock { path, pid });
+    }
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", process::id())?;
+    file.flush()?;
+    Ok(Lock { file })
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Leave the file in place, removing it would race with a waiting run
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,411 @@
+use autoupdate::config::{LogFile, Rotation, Settings};
+use autoupdate::{output, Result};
+use clap::ValueEnum;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Prints info and above as bare lines like the tool always has, and
+/// prefixes the more verbose levels with their level and span. On a color
+/// terminal errors are red, warnings yellow and status lines in their tone.
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let level = *event.metadata().level();
+        let color = match level {
+            Level::ERROR => Some(31),
+            Level::WARN => Some(33),
+            _ => output::tone(),
+        }
+        .filter(|_| writer.has_ansi_escapes());
+        if let Some(color) = color {
+            write!(writer, "\x1b[{}m", color)?;
+        }
+        if level > Level::INFO {
+            write!(writer, "{} ", level)?;
+            if let Some(scope) = ctx.event_scope() {
+                for span in scope.from_root() {
+                    write!(writer, "{}", span.name())?;
+                    let extensions = span.extensions();
+                    if let Some(fields) = extensions
+                        .get::<tracing_subscriber::fmt::FormattedFields<N>>()
+                        .filter(|fields| !fields.is_empty())
+                    {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                    write!(writer, ": ")?;
+                }
+            }
+        }
+        ctx.format_fields(writer.by_ref(), event)?;
+        if color.is_some() {
+            write!(writer, "\x1b[0m")?;
+        }
+        writeln!(writer)
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86400)
+        .unwrap_or_default()
+}
+
+/// Log file that is moved aside to `<file>.1`, `<file>.2`, ... once it grows
+/// past the size limit or the day changes, keeping `keep` old files.
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+impl RotatingFile {
+    fn open(config: &LogFile) -> Result<RotatingFile> {
+        let path = PathBuf::from(&config.file);
+        let file = append(&path)?;
+        Ok(RotatingFile {
+            size: file.metadata()?.len(),
+            day: today(),
+            rotation: config.rotate,
+            max_size: config.max_size()?,
+            keep: config.keep(),
+            path,
+            file,
+        })
+    }
+
+    fn due(&self, incoming: usize) -> bool {
+        match self.rotation {
+            Rotation::Size => self.size > 0 && self.size + incoming as u64 > self.max_size,
+            Rotation::Daily => self.day != today(),
+        }
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |index: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", index));
+            PathBuf::from(name)
+        };
+
+        let _ = fs::remove_file(rotated(self.keep));
+        for index in (1..self.keep).rev() {
+            let _ = fs::rename(rotated(index), rotated(index + 1));
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = append(&self.path)?;
+        self.size = 0;
+        self.day = today();
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Standard output, written with the progress bars out of the way.
+struct Console;
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        output::suspend(|| io::stdout().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// The host's own log: syslog on unix, the Event Log on Windows.
+struct SystemLog {
+    #[cfg(windows)]
+    handle: windows_sys::Win32::Foundation::HANDLE,
+}
+
+// The event log handle may be used from any thread
+#[cfg(windows)]
+unsafe impl Send for SystemLog {}
+#[cfg(windows)]
+unsafe impl Sync for SystemLog {}
+
+impl SystemLog {
+    #[cfg(unix)]
+    fn open(syslog: &autoupdate::config::Syslog) -> Result<SystemLog> {
+        use autoupdate::config::Facility;
+
+        let facility = match syslog.facility {
+            Facility::User => libc::LOG_USER,
+            Facility::Daemon => libc::LOG_DAEMON,
+            Facility::Local0 => libc::LOG_LOCAL0,
+            Facility::Local1 => libc::LOG_LOCAL1,
+            Facility::Local2 => libc::LOG_LOCAL2,
+            Facility::Local3 => libc::LOG_LOCAL3,
+            Facility::Local4 => libc::LOG_LOCAL4,
+            Facility::Local5 => libc::LOG_LOCAL5,
+            Facility::Local6 => libc::LOG_LOCAL6,
+            Facility::Local7 => libc::LOG_LOCAL7,
+        };
+        let ident = std::ffi::CString::new(syslog.ident()).map_err(|_| {
+            autoupdate::Error::Config("syslog ident must not contain NUL".to_string())
+        })?;
+        // openlog keeps the pointer, so the name has to outlive every message
+        let ident: &'static std::ffi::CStr = Box::leak(ident.into_boxed_c_str());
+        unsafe { libc::openlog(ident.as_ptr(), libc::LOG_PID, facility) };
+        Ok(SystemLog {})
+    }
+
+    #[cfg(windows)]
+    fn open(event_log: &autoupdate::config::EventLog) -> Result<SystemLog> {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::System::EventLog::RegisterEventSourceW;
+
+        let source: Vec<u16> = std::ffi::OsStr::new(event_log.source())
+            .encode_wide()
+            .chain([0])
+            .collect();
+        let handle = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(SystemLog { handle })
+    }
+
+    #[cfg(unix)]
+    fn write(&self, level: Level, message: &str) {
+        let priority = match level {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+        };
+        let Ok(message) = std::ffi::CString::new(message.replace('\0', "")) else {
+            return;
+        };
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+    }
+
+    #[cfg(windows)]
+    fn write(&self, level: Level, message: &str) {
+        use windows_sys::Win32::System::EventLog::{
+            ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+        };
+
+        let kind = match level {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        let message: Vec<u16> = message.encode_utf16().chain([0]).collect();
+        let strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                0,
+                0,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+    }
+}
+
+/// One formatted event, handed to the system log whole once it is written.
+struct SystemLine<'a> {
+    log: &'a SystemLog,
+    level: Level,
+    buffer: Vec<u8>,
+}
+
+impl Write for SystemLine<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SystemLine<'_> {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buffer);
+        let message = message.trim_end();
+        if !message.is_empty() {
+            self.log.write(self.level, message);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SystemLog {
+    type Writer = SystemLine<'a>;
+
+    fn make_writer(&'a self) -> SystemLine<'a> {
+        SystemLine {
+            log: self,
+            level: Level::INFO,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> SystemLine<'a> {
+        SystemLine {
+            log: self,
+            level: *meta.level(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Installs the global subscriber; `RUST_LOG` takes precedence over the flags.
+///
+/// With a log file configured every line also goes there, timestamped, so
+/// headless runs leave a record of what was updated when; the same goes for
+/// syslog and the Event Log, which keep their own time. Without `console`
+/// nothing is printed, the terminal belongs to the dashboard.
+pub fn init(
+    verbose: u8,
+    quiet: bool,
+    format: LogFormat,
+    settings: Option<&Settings>,
+    console: bool,
+) -> Result<()> {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::WARN,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+
+    let stdout = tracing_subscriber::fmt::layer()
+        .with_writer(|| Console)
+        .with_ansi(output::colors());
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    if console {
+        layers.push(match format {
+            LogFormat::Text => stdout.event_format(Plain).boxed(),
+            LogFormat::Json => stdout.json().with_current_span(true).boxed(),
+        });
+    }
+
+    if let Some(file) = settings.and_then(|settings| settings.log.as_ref()) {
+        let writer = Mutex::new(RotatingFile::open(file)?);
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false);
+        layers.push(match format {
+            LogFormat::Text => layer.with_target(false).boxed(),
+            LogFormat::Json => layer.json().with_current_span(true).boxed(),
+        });
+    }
+
+    #[cfg(feature = "otel")]
+    if let Some(telemetry) = settings.and_then(|settings| settings.telemetry.as_ref()) {
+        let tracer = crate::telemetry::tracer(telemetry)?;
+        layers.push(tracing_opentelemetry::layer().with_tracer(tracer).boxed());
+    }
+
+    #[cfg(feature = "sentry")]
+    if settings.is_some_and(|settings| settings.sentry.is_some()) {
+        use sentry::integrations::tracing::EventFilter;
+
+        // Failures are reported with their repository, the rest leads up to them
+        let breadcrumbs = sentry::integrations::tracing::layer()
+            .event_filter(|metadata| match *metadata.level() {
+                Level::TRACE => EventFilter::Ignore,
+                _ => EventFilter::Breadcrumb,
+            })
+            .span_filter(|_| false);
+        layers.push(breadcrumbs.boxed());
+        layers.push(crate::reporting::Phases.boxed());
+    }
+
+    #[cfg(unix)]
+    let system = settings.and_then(|settings| settings.syslog.as_ref());
+    #[cfg(windows)]
+    let system = settings.and_then(|settings| settings.event_log.as_ref());
+    #[cfg(any(unix, windows))]
+    if let Some(system) = system {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(SystemLog::open(system)?)
+            .with_ansi(false);
+        layers.push(match format {
+            LogFormat::Text => layer.event_format(Plain).boxed(),
+            LogFormat::Json => layer.json().with_current_span(true).boxed(),
+        });
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+    Ok(())
+}
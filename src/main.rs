@@ -1,142 +1,956 @@
-use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{ErrorCode, FetchOptions, RemoteCallbacks, Repository};
-use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use quick_xml::de;
-use serde::Deserialize;
-use std::fmt::Write;
+mod api;
+mod cli;
+mod control;
+mod ctl;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod init;
+mod logging;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod reload;
+#[cfg(feature = "sentry")]
+mod reporting;
+mod scheduler;
+mod selfupdate;
+#[cfg(windows)]
+mod service;
+mod systemd;
+#[cfg(feature = "otel")]
+mod telemetry;
+#[cfg(feature = "tui")]
+mod tui;
+
+use autoupdate::metrics::Registry;
+#[cfg(feature = "tui")]
+use autoupdate::output::Format;
+use autoupdate::output::{self, Event, Outcome, Output, SummaryEntry};
+use autoupdate::server::{self, Response};
+use autoupdate::webhook::{Target, Tracks, Webhook};
+use autoupdate::{cancel, config, health, notify, privileges};
+use autoupdate::{Error, SyncOutcome, Updater};
+use clap::Parser;
+use cli::{Action, Cli};
+use scheduler::Scheduler;
 use std::fs;
-use std::{cmp::min, path::Path};
+use std::io::IsTerminal;
+use std::process::{self, ExitCode};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+const EXIT_UPDATED: u8 = 10;
+const EXIT_UPDATE_AVAILABLE: u8 = 11;
+/// Same as `Error::Interrupted`, for quitting without cleaning up.
+const EXIT_INTERRUPTED: i32 = 130;
+
+enum Status {
+    UpToDate,
+    Updated,
+    UpdateAvailable,
+    Held,
+    Failed(Error),
+}
+
+#[derive(Default)]
+struct Summary {
+    entries: Vec<(String, Status)>,
+}
+
+impl Summary {
+    fn push(&mut self, path: &str, status: Status) {
+        self.entries.push((path.to_string(), status));
+    }
+
+    /// The first failure decides the exit code; with `detailed` codes a run
+    /// without failures tells pending updates and applied ones apart too.
+    fn exit_code(&self, detailed: bool) -> ExitCode {
+        let failure = self.entries.iter().find_map(|(_, status)| match status {
+            Status::Failed(e) => Some(e.exit_code()),
+            _ => None,
+        });
+        let has =
+            |wanted: fn(&Status) -> bool| self.entries.iter().any(|(_, status)| wanted(status));
+        let code = match failure {
+            Some(code) => code,
+            None if !detailed => 0,
+            None if has(|status| matches!(status, Status::UpdateAvailable | Status::Held)) => {
+                EXIT_UPDATE_AVAILABLE
+            }
+            None if has(|status| matches!(status, Status::Updated)) => EXIT_UPDATED,
+            None => 0,
+        };
+        ExitCode::from(code)
+    }
+
+    fn repositories(&self) -> Vec<SummaryEntry<'_>> {
+        self.entries
+            .iter()
+            .map(|(path, status)| match status {
+                Status::UpToDate => SummaryEntry {
+                    path,
+                    status: Outcome::UpToDate,
+                    error: None,
+                },
+                Status::Updated => SummaryEntry {
+                    path,
+                    status: Outcome::Updated,
+                    error: None,
+                },
+                Status::UpdateAvailable => SummaryEntry {
+                    path,
+                    status: Outcome::UpdateAvailable,
+                    error: None,
+                },
+                Status::Held => SummaryEntry {
+                    path,
+                    status: Outcome::Held,
+                    error: None,
+                },
+                Status::Failed(e) => SummaryEntry {
+                    path,
+                    status: Outcome::Failed,
+                    error: Some(e.to_string()),
+                },
+            })
+            .collect()
+    }
+
+    fn report(&self, output: &Output) {
+        output.emit(Event::Summary {
+            repositories: self.repositories(),
+        });
+    }
+}
+
+fn run(updater: &mut Updater, output: &Output) -> Status {
+    #[cfg(feature = "sentry")]
+    let result = reporting::sync(updater);
+    #[cfg(not(feature = "sentry"))]
+    let result = updater.sync();
+    match result {
+        Ok(SyncOutcome::UpToDate { .. }) => Status::UpToDate,
+        Ok(SyncOutcome::Updated { .. }) => Status::Updated,
+        Ok(SyncOutcome::UpdateAvailable { .. }) => Status::UpdateAvailable,
+        Ok(SyncOutcome::Held { .. }) => Status::Held,
+        Err(e) => {
+            // An unreachable remote was reported when it went away
+            if updater.unreachable() <= 1 {
+                output.emit(Event::Error {
+                    path: &updater.repo().path,
+                    message: e.to_string(),
+                });
+            }
+            Status::Failed(e)
+        }
+    }
+}
+
+/// Syncs the selected updaters on up to `jobs` threads, keeping the summary in settings order.
+fn run_all(updaters: &mut [Updater], selected: &[usize], jobs: usize, output: &Output) -> Summary {
+    let queue = Mutex::new(
+        updaters
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| selected.contains(index))
+            .collect::<Vec<_>>()
+            .into_iter(),
+    );
+    let results = Mutex::new(Vec::new());
+    // The syncs belong to the span of the run, wherever they happen
+    let parent = tracing::Span::current();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, selected.len().max(1)) {
+            scope.spawn(|| loop {
+                let Some((index, updater)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let status = parent.in_scope(|| run(updater, output));
+                let path = updater.repo().path.clone();
+                results.lock().unwrap().push((index, path, status));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _, _)| *index);
+    let mut summary = Summary::default();
+    for (_, path, status) in results {
+        summary.push(&path, status);
+    }
+    summary
+}
+
+fn find_repo<'a>(settings: &'a config::Settings, path: &str) -> Result<&'a config::Repo, Error> {
+    settings
+        .repositories
+        .iter()
+        .find(|repo| config::same_path(&repo.path, path))
+        .ok_or_else(|| Error::Config(format!("'{}' is not a configured repository", path)))
+}
+
+/// Rolls back the repository selected by `path`, or the only one configured.
+fn rollback(
+    settings: &config::Settings,
+    steps: usize,
+    path: Option<&str>,
+    output: &Output,
+) -> Result<ExitCode, Error> {
+    let repo = match path {
+        Some(path) => find_repo(settings, path)?,
+        None if settings.repositories.len() == 1 => &settings.repositories[0],
+        None => {
+            return Err(Error::Config(
+                "more than one repository is configured, select one with --path".to_string(),
+            ))
+        }
+    };
 
-#[derive(Deserialize, Clone)]
-struct Settings {
-    url: String,
-    path: String,
-    branch: String,
+    Updater::new(repo.clone())
+        .with_output(output.clone())
+        .with_lock_wait(settings.wait)
+        .rollback(steps)?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn spawn_progress_bar() -> ProgressBar {
-    let pb = ProgressBar::new(0);
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-"));
-    pb
+fn history(
+    settings: &config::Settings,
+    path: Option<&str>,
+    limit: Option<usize>,
+    output: &Output,
+) -> Result<ExitCode, Error> {
+    let repos = match path {
+        Some(path) => vec![find_repo(settings, path)?],
+        None => settings.repositories.iter().collect(),
+    };
+
+    for repo in repos {
+        let entries = Updater::new(repo.clone()).history()?;
+        let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+        output.emit(Event::History {
+            path: &repo.path,
+            entries: &entries[skip..],
+        });
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Approves the waiting update of the repository selected by `path`, or the
+/// only one configured, or lists the waiting updates.
+fn approve(
+    settings: &config::Settings,
+    commit: Option<&str>,
+    path: Option<&str>,
+    list: bool,
+    output: &Output,
+) -> Result<ExitCode, Error> {
+    if list {
+        let repos = match path {
+            Some(path) => vec![find_repo(settings, path)?],
+            None => settings
+                .repositories
+                .iter()
+                .filter(|repo| repo.approval)
+                .collect(),
+        };
+        let mut waiting = false;
+        for repo in repos {
+            let updater = Updater::new(repo.clone()).with_output(output.clone());
+            waiting |= updater.awaiting_approval()?;
+        }
+        if !waiting {
+            output.emit(Event::NothingAwaitingApproval);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let repo = match path {
+        Some(path) => find_repo(settings, path)?,
+        None => match &settings
+            .repositories
+            .iter()
+            .filter(|repo| repo.approval)
+            .collect::<Vec<_>>()[..]
+        {
+            [repo] => *repo,
+            [] => {
+                return Err(Error::Config(
+                    "no configured repository needs approval".to_string(),
+                ))
+            }
+            _ => {
+                return Err(Error::Config(
+                    "more than one repository needs approval, select one with --path".to_string(),
+                ))
+            }
+        },
+    };
+    Updater::new(repo.clone())
+        .with_output(output.clone())
+        .with_lock_wait(settings.wait)
+        .approve(commit)?;
+    Ok(ExitCode::SUCCESS)
 }
 
-fn run(settings: Settings) -> Result<Repository, git2::Error> {
-    let mut repo = match Repository::open(&settings.path) {
-        Ok(repo) => Some(repo),
-        Err(_) => None,
+fn maintain(
+    settings: &config::Settings,
+    path: Option<&str>,
+    output: &Output,
+) -> Result<ExitCode, Error> {
+    let repos = match path {
+        Some(path) => vec![find_repo(settings, path)?],
+        None => settings.repositories.iter().collect(),
     };
 
-    if repo.is_none() == false {
-        return Ok(repo.unwrap());
+    for repo in repos {
+        Updater::new(repo.clone())
+            .with_output(output.clone())
+            .with_lock_wait(settings.wait)
+            .maintain()?;
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    let pb = spawn_progress_bar();
+/// Reports the state of every checkout, failing when any of them is unhealthy.
+fn healthz(repos: &RwLock<Vec<config::Repo>>, registry: &Registry) -> Response {
+    let repos = repos.read().unwrap_or_else(|e| e.into_inner());
+    let report: Vec<_> = repos
+        .iter()
+        .enumerate()
+        .map(|(index, repo)| health::check(repo, &registry.repo(index)))
+        .collect();
+    let status = match report.iter().all(|health| health.healthy) {
+        true => 200,
+        false => 503,
+    };
+    Response::json(status, &report)
+}
+
+pub(crate) enum Command {
+    Stop,
+    Sync(usize),
+    Reload,
+    /// Stops or resumes scheduled checks of a repository, switching between
+    /// the two without a value.
+    Pause(usize, Option<bool>),
+}
 
-    let mut cb = RemoteCallbacks::new();
-    cb.transfer_progress(|stats| {
-        let stats_binding = Some(stats.to_owned());
-        let stats = stats_binding.as_ref().unwrap();
-        pb.set_length(stats.total_objects() as u64);
-        let position = min(stats.received_objects(), stats.total_objects());
-        pb.set_position(position as u64);
-        true
+/// What the servers read about the configured repositories, replaced on reload.
+struct Shared {
+    registry: Arc<Registry>,
+    repos: Arc<RwLock<Vec<config::Repo>>>,
+    webhook: Option<Arc<Webhook>>,
+}
+
+fn targets(settings: &config::Settings) -> Vec<Target> {
+    settings
+        .repositories
+        .iter()
+        .map(|repo| Target {
+            url: repo.url.clone(),
+            tracks: match (&repo.tag, &repo.rev) {
+                (_, Some(_)) => Tracks::Rev,
+                (Some(pattern), None) => Tracks::Tag(pattern.clone()),
+                (None, None) => Tracks::Branch(repo.branch.clone()),
+            },
+        })
+        .collect()
+}
+
+/// The updater of the repository configured at `index`.
+fn updater(
+    settings: &config::Settings,
+    index: usize,
+    notifiers: &[Arc<dyn notify::Notifier>],
+    registry: &Registry,
+    output: &Output,
+) -> Updater {
+    let repo = &settings.repositories[index];
+    // Lines from repositories updated side by side need to say which one they are about
+    let output = match settings.concurrency() {
+        1 => output.clone(),
+        _ => output.labelled(&repo.path),
+    };
+    notifiers.iter().fold(
+        Updater::new(repo.clone())
+            .with_output(output)
+            .with_lock_wait(settings.wait)
+            .with_metrics(registry.repo(index)),
+        |updater, notifier| updater.with_notifier(notifier.clone()),
+    )
+}
+
+/// Loads the settings again and brings the updaters in line with them: added
+/// repositories get an updater, removed ones lose theirs and changed ones get
+/// a new one. Returns the paths of added and changed repositories.
+fn reload(
+    cli: &Cli,
+    settings: &mut config::Settings,
+    updaters: &mut Vec<Updater>,
+    shared: &Shared,
+    output: &Output,
+) -> Result<Vec<String>, Error> {
+    let mut next = config::load(cli.config.as_deref())?;
+    cli.apply(&mut next);
+    next.validate()?;
+
+    let listen = |settings: &config::Settings| {
+        settings
+            .webhook
+            .as_ref()
+            .map(|webhook| (webhook.listen.clone(), webhook.secret().ok()))
+    };
+    let metrics = |settings: &config::Settings| {
+        settings
+            .metrics
+            .as_ref()
+            .map(|metrics| metrics.listen.clone())
+    };
+    for (setting, changed) in [
+        ("webhook", listen(settings) != listen(&next)),
+        ("metrics", metrics(settings) != metrics(&next)),
+        ("api", settings.api != next.api),
+        ("grpc", settings.grpc != next.grpc),
+        ("socket", settings.socket != next.socket),
+        ("mqtt", settings.mqtt != next.mqtt),
+        ("log", settings.log != next.log),
+        ("syslog", settings.syslog != next.syslog),
+        ("event_log", settings.event_log != next.event_log),
+        ("telemetry", settings.telemetry != next.telemetry),
+        ("sentry", settings.sentry != next.sentry),
+        ("progress", settings.progress != next.progress),
+        ("run_as", settings.run_as != next.run_as),
+    ] {
+        if changed {
+            output.emit(Event::RestartRequired { setting });
+        }
+    }
+
+    let notifiers = notify::notifiers(&next.notify)?;
+    // Updaters carry the notifiers and lock settings, all of them are rebuilt when those change
+    let rebuild = settings.notify != next.notify
+        || settings.wait != next.wait
+        || settings.concurrency() != next.concurrency();
+    shared
+        .registry
+        .set_paths(next.repositories.iter().map(|repo| repo.path.as_str()));
+
+    let mut previous = std::mem::take(updaters);
+    let mut changed = Vec::new();
+    for (index, repo) in next.repositories.iter().enumerate() {
+        let kept = previous
+            .iter()
+            .position(|updater| updater.repo().path == repo.path)
+            .map(|position| previous.remove(position));
+        match kept {
+            Some(updater) if updater.repo() == repo && !rebuild => {
+                updaters.push(updater);
+                continue;
+            }
+            Some(updater) if updater.repo() == repo => {}
+            Some(_) => {
+                output.emit(Event::RepositoryChanged { path: &repo.path });
+                changed.push(repo.path.clone());
+            }
+            None => {
+                output.emit(Event::RepositoryAdded { path: &repo.path });
+                changed.push(repo.path.clone());
+            }
+        }
+        updaters.push(updater(&next, index, &notifiers, &shared.registry, output));
+    }
+    for updater in previous {
+        let path = &updater.repo().path;
+        let deleted = next.delete_removed && fs::remove_dir_all(path).is_ok();
+        output.emit(Event::RepositoryRemoved { path, deleted });
+    }
+
+    if let Some(webhook) = &shared.webhook {
+        webhook.set_targets(targets(&next));
+    }
+    *shared.repos.write().unwrap_or_else(|e| e.into_inner()) = next.repositories.clone();
+    output.emit(Event::SettingsReloaded {
+        repositories: next.repositories.len(),
     });
+    *settings = next;
+    Ok(changed)
+}
 
-    println!(
-        "Cloning repository from '{}' into directory '{}'. Please wait...",
-        settings.url, settings.path
-    );
+fn validate(settings: &config::Settings, output: &Output) -> Result<ExitCode, Error> {
+    let problems = settings.problems();
+    for problem in &problems {
+        output.emit(Event::InvalidSetting { message: problem });
+    }
+    if !problems.is_empty() {
+        return Err(Error::Config(format!(
+            "settings have {} problem(s)",
+            problems.len()
+        )));
+    }
+
+    output.emit(Event::SettingsValid {
+        repositories: settings.repositories.len(),
+    });
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs the command line; `channel` carries the requests to stop or sync
+/// while watching.
+pub(crate) fn start(
+    cli: Cli,
+    (tx, rx): (Sender<Command>, Receiver<Command>),
+) -> Result<ExitCode, Error> {
+    // Also reaches the progress bars and the hooks
+    if cli.no_color {
+        std::env::set_var("NO_COLOR", "1");
+    }
+
+    #[cfg(windows)]
+    if let Some(Action::Service { command }) = &cli.action {
+        service::run(command, &cli)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Init {
+        url,
+        path,
+        branch,
+        format,
+        force,
+    }) = &cli.action
+    {
+        let answers = init::Answers {
+            url: url.clone(),
+            path: path.clone(),
+            branch: branch.clone(),
+            format: *format,
+        };
+        let written = init::run(answers, cli.config.as_deref(), *force)?;
+        logging::init(cli.verbose, cli.quiet, cli.log_format, None, true)?;
+        Output::new(cli.output).emit(Event::SettingsWritten {
+            file: &written.display().to_string(),
+        });
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let mut fo = FetchOptions::new();
-    fo.depth(1);
-    fo.remote_callbacks(cb);
-    repo = match RepoBuilder::new()
-        .branch(&settings.branch)
-        .fetch_options(fo)
-        .clone(&settings.url, Path::new(&settings.path))
+    if let Some(Action::SelfUpdate {
+        check,
+        force,
+        repository,
+        keys,
+        keyring,
+        allowed_signers,
+    }) = &cli.action
     {
-        Ok(repo) => Some(repo),
-        Err(ref e) if e.code() == ErrorCode::NotFound => {
-            panic!(
-                "Could not clone repository from '{}' branch '{}' does not existed.",
-                settings.url, settings.branch
+        logging::init(cli.verbose, cli.quiet, cli.log_format, None, true)?;
+        let trusted = !keys.is_empty() || keyring.is_some() || allowed_signers.is_some();
+        let options = selfupdate::Options {
+            repository,
+            check: *check,
+            force: *force,
+            verify: trusted.then(|| config::Verify {
+                keys: keys.clone(),
+                keyring: keyring.clone(),
+                allowed_signers: allowed_signers.clone(),
+            }),
+        };
+        selfupdate::run(options, &Output::new(cli.output))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if cli.generate_systemd_unit {
+        print!("{}", systemd::unit(cli.config.as_deref())?);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut settings = config::load(cli.config.as_deref())?;
+    cli.apply(&mut settings);
+    if let Some(Action::Validate) = &cli.action {
+        logging::init(cli.verbose, cli.quiet, cli.log_format, None, true)?;
+        return validate(&settings, &Output::new(cli.output));
+    }
+    settings.validate()?;
+    // Before anything is written, so log files and checkouts get the right owner
+    if let Some(run_as) = &settings.run_as {
+        privileges::drop_to(run_as)?;
+    }
+    #[cfg(feature = "tui")]
+    let dashboard = cli.tui;
+    #[cfg(not(feature = "tui"))]
+    let dashboard = false;
+    logging::init(
+        cli.verbose,
+        cli.quiet,
+        cli.log_format,
+        Some(&settings),
+        !dashboard,
+    )?;
+    #[cfg(feature = "otel")]
+    let _root = telemetry::root().map(tracing::Span::entered);
+    #[cfg(feature = "sentry")]
+    let _sentry = match &settings.sentry {
+        Some(sentry) => Some(reporting::init(sentry)?),
+        None => None,
+    };
+    // Bars written into a log file or pipe are only noise
+    let progress = !cli.no_progress && !cli.quiet && std::io::stderr().is_terminal();
+    output::configure_progress(settings.progress.as_ref(), progress)?;
+
+    let watch = settings.watch;
+    if dashboard && !watch {
+        return Err(Error::Config("--tui needs watch mode".to_string()));
+    }
+    let repos = Arc::new(RwLock::new(settings.repositories.clone()));
+    #[cfg(feature = "tui")]
+    let _dashboard = dashboard.then(|| tui::Dashboard::start(repos.clone(), tx.clone()));
+    let output = Output::new(cli.output);
+    #[cfg(feature = "tui")]
+    let output = match &_dashboard {
+        Some(dashboard) => Output::new(Format::Human).observed(dashboard.observer()),
+        None => output,
+    };
+
+    match &cli.action {
+        Some(Action::Rollback { steps, path }) => {
+            return rollback(&settings, *steps, path.as_deref(), &output)
+        }
+        Some(Action::History { path, limit }) => {
+            return history(&settings, path.as_deref(), *limit, &output)
+        }
+        Some(Action::Maintain { path }) => return maintain(&settings, path.as_deref(), &output),
+        Some(Action::Ctl { command }) => return ctl::run(&settings, command, &output),
+        Some(Action::Approve { commit, path, list }) => {
+            return approve(
+                &settings,
+                commit.as_deref(),
+                path.as_deref(),
+                *list,
+                &output,
             )
         }
-        Err(e) => return Err(e),
+        _ => {}
+    }
+
+    let listen = settings
+        .webhook
+        .as_ref()
+        .and_then(|webhook| webhook.listen.clone());
+    // A single cycle ends the run even when something listens for triggers
+    let serving = !cli.once
+        && (listen.is_some()
+            || settings.api.is_some()
+            || settings.grpc.is_some()
+            || settings.socket.is_some()
+            || settings.mqtt.is_some());
+
+    {
+        let tx = tx.clone();
+        let output = output.clone();
+        ctrlc::set_handler(move || {
+            // The second Ctrl-C does not wait for the transfers to wind down
+            if cancel::request() {
+                process::exit(EXIT_INTERRUPTED);
+            }
+            output.emit(Event::Interrupted);
+            let _ = tx.send(Command::Stop);
+        })
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+    }
+
+    let registry = Arc::new(Registry::new(
+        settings.repositories.iter().map(|repo| repo.path.as_str()),
+    ));
+    let metrics = settings
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.listen.clone());
+    let same_listener = metrics.is_some() && metrics == listen;
+    let mut webhook_targets = None;
+    let paused: Arc<RwLock<Vec<String>>> = Arc::default();
+    let control = Arc::new(control::Control::new(
+        settings.wait,
+        repos.clone(),
+        registry.clone(),
+        paused.clone(),
+        tx.clone(),
+        output.clone(),
+    ));
+    let api = match &settings.api {
+        Some(settings_api) => {
+            let token = settings_api.token()?;
+            Some((
+                settings_api.listen.clone(),
+                token.is_some(),
+                Arc::new(api::Api::new(token, control.clone())),
+            ))
+        }
+        None => None,
+    };
+    // The API is served alongside the webhook or the metrics on a shared address
+    let api_on = |address: &str| {
+        api.as_ref()
+            .filter(|(listen, _, _)| listen == address)
+            .map(|(_, _, api)| api.clone())
     };
 
-    pb.finish();
+    if let Some(listen) = &listen {
+        let secret = match &settings.webhook {
+            Some(webhook) => webhook.secret()?,
+            None => None,
+        };
+        let (queue_tx, queue_rx) = mpsc::channel();
+        output.emit(Event::Listening {
+            address: listen,
+            secured: secret.is_some(),
+        });
+        let webhook = Arc::new(Webhook::new(secret, targets(&settings), queue_tx));
+        webhook_targets = Some(webhook.clone());
+        let registry = registry.clone();
+        let repos = repos.clone();
+        let api = api_on(listen);
+        server::spawn(listen, move |request| match request.path.as_str() {
+            "/webhook" => webhook.handle(request),
+            "/healthz" => healthz(&repos, &registry),
+            "/metrics" if same_listener => Response::text(200, &registry.render()),
+            path if path.starts_with("/api/") => api::handle(api.as_deref(), request),
+            _ => Response::text(404, "not found"),
+        })?;
 
-    Ok(repo.unwrap())
-}
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for index in queue_rx {
+                if tx.send(Command::Sync(index)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
-fn main() {
-    let xml = fs::read_to_string("./settings.xml").unwrap();
+    if let Some(address) = &metrics {
+        output.emit(Event::ServingMetrics { address });
+        if !same_listener {
+            let registry = registry.clone();
+            let repos = repos.clone();
+            let api = api_on(address);
+            server::spawn(address, move |request| match request.path.as_str() {
+                "/metrics" => Response::text(200, &registry.render()),
+                "/healthz" => healthz(&repos, &registry),
+                path if path.starts_with("/api/") => api::handle(api.as_deref(), request),
+                _ => Response::text(404, "not found"),
+            })?;
+        }
+    }
+
+    if let Some((address, secured, api)) = &api {
+        output.emit(Event::ServingApi {
+            address,
+            secured: *secured,
+        });
+        if listen.as_ref() != Some(address) && metrics.as_ref() != Some(address) {
+            let registry = registry.clone();
+            let repos = repos.clone();
+            let api = api.clone();
+            server::spawn(address, move |request| match request.path.as_str() {
+                "/healthz" => healthz(&repos, &registry),
+                path if path.starts_with("/api/") => api.handle(request),
+                _ => Response::text(404, "not found"),
+            })?;
+        }
+    }
 
-    let settings: Settings = de::from_str(&xml).unwrap();
+    #[cfg(unix)]
+    if let Some(socket) = &settings.socket {
+        let api = api::Api::new(None, control.clone());
+        server::spawn_unix(socket, move |request| match request.path.as_str() {
+            path if path.starts_with("/api/") => api.handle(request),
+            _ => Response::text(404, "not found"),
+        })?;
+        output.emit(Event::ServingSocket { path: socket });
+    }
 
-    if settings.url.is_empty() || settings.path.is_empty() {
-        panic!("url or path could not be empty");
+    #[cfg(feature = "grpc")]
+    if let Some(grpc) = &settings.grpc {
+        grpc::spawn(grpc, control.clone())?;
+        output.emit(Event::ServingGrpc {
+            address: &grpc.listen,
+            tls: grpc.cert.is_some(),
+            // Serving at all means a configured token was resolved
+            secured: grpc.token.is_some() || grpc.token_env.is_some() || grpc.client_ca.is_some(),
+        });
     }
 
-    match run(settings.clone()) {
-        Ok(repo) => {
-            let mut remote = repo.find_remote("origin").unwrap();
+    #[cfg(feature = "mqtt")]
+    let mqtt = match &settings.mqtt {
+        Some(mqtt) => Some(mqtt::spawn(mqtt, control.clone(), output.clone())?),
+        None => None,
+    };
 
-            remote
-                .fetch(&[format!("refs/head/{}", settings.branch)], None, None)
-                .unwrap();
+    let notifiers = notify::notifiers(&settings.notify)?;
+    let mut updaters: Vec<Updater> = (0..settings.repositories.len())
+        .map(|index| updater(&settings, index, &notifiers, &registry, &output))
+        .collect();
+    let shared = Shared {
+        registry,
+        repos,
+        webhook: webhook_targets,
+    };
 
-            let origin_head = repo.find_reference("refs/remotes/origin/HEAD").unwrap();
-            let origin_commit = repo.reference_to_annotated_commit(&origin_head).unwrap();
+    if cli.deepen.is_some() || cli.unshallow {
+        for updater in &mut updaters {
+            updater.deepen(cli.deepen)?;
+        }
+    }
 
-            // Find the local branch
-            let local_branch = repo
-                .find_branch(&settings.branch, git2::BranchType::Local)
-                .unwrap();
-            let local_oid = local_branch.get().target().unwrap();
+    if watch || serving {
+        systemd::notify("READY=1");
+        systemd::spawn_watchdog();
+    }
 
-            // Get the commit for 'origin/HEAD'
-            let origin_oid = origin_commit.id();
-            let origin_commit = repo.find_commit(origin_oid).unwrap();
+    // Changes to the settings apply while the process keeps running
+    let mut _watcher = match watch || serving {
+        true => Some(reload::watch(&settings.sources, tx.clone())?),
+        false => None,
+    };
 
-            if local_oid == origin_oid {
-                println!("Already up to date");
-            } else {
-                println!("Resetting local '{}' to 'origin/HEAD'...", settings.branch);
+    // The first cycle syncs everything that is due, later ones what the
+    // schedule or a webhook asks for
+    let mut scheduler = Scheduler::new(&settings, &updaters, &output)?;
+    let mut selected: Vec<usize> = match watch {
+        true => scheduler.due(&updaters, &output),
+        false => (0..updaters.len()).collect(),
+    };
+    if let Some(splay) = settings.splay()?.filter(|_| !watch) {
+        let delay = splay.mul_f64(fastrand::f64());
+        output.emit(Event::Splaying {
+            seconds: delay.as_secs(),
+        });
+        cancel::sleep(delay);
+    }
+    let mut announce = true;
 
-                let pb = spawn_progress_bar();
+    loop {
+        systemd::notify(&format!("STATUS=Syncing {} repositories", selected.len()));
+        let summary = run_all(&mut updaters, &selected, settings.concurrency(), &output);
+        // A reload that changed no repository leaves nothing to report
+        if !selected.is_empty() {
+            summary.report(&output);
+            #[cfg(feature = "mqtt")]
+            if let Some(mqtt) = &mqtt {
+                mqtt.publish(&summary.repositories());
+            }
+        }
 
-                let mut cb = CheckoutBuilder::new();
-                cb.progress(|_, cur, total| {
-                    pb.set_length(total as u64);
-                    let position = min(cur, total);
-                    pb.set_position(position as u64);
-                });
+        if !watch && !serving {
+            return Ok(summary.exit_code(cli.detailed_exit_codes));
+        }
+        scheduler.synced(&selected, &updaters);
 
-                repo.reset(
-                    origin_commit.as_object(),
-                    git2::ResetType::Hard,
-                    Some(&mut cb),
-                )
-                .unwrap();
+        let command = if watch {
+            // Waits end every minute to look at the clock, which is only worth telling after a sync
+            if announce || !selected.is_empty() {
+                let seconds = scheduler.next();
+                output.emit(Event::NextCheck { seconds });
+                systemd::notify(&format!("STATUS=Next check in {}s", seconds));
+            }
+            announce = false;
+            rx.recv_timeout(scheduler.wait())
+        } else {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
 
-                pb.finish();
+        selected = match command {
+            Err(RecvTimeoutError::Timeout) => {
+                // Paused repositories move on to their next slot without a check
+                let paused = paused.read().unwrap_or_else(|e| e.into_inner());
+                let (due, held): (Vec<usize>, Vec<usize>) = scheduler
+                    .due(&updaters, &output)
+                    .into_iter()
+                    .partition(|index| !paused.contains(&updaters[*index].repo().path));
+                scheduler.synced(&held, &updaters);
+                due
+            }
+            Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                systemd::notify("STOPPING=1");
+                output.emit(Event::ShuttingDown);
+                return Ok(ExitCode::SUCCESS);
+            }
+            Ok(command) => {
+                // Coalesce triggers that queued up while the previous cycle was running;
+                // they are kept by path since a reload moves repositories around
+                let mut paths: Vec<String> = Vec::new();
+                let mut reloading = false;
+                let mut stop = false;
+                for command in std::iter::once(command).chain(rx.try_iter()) {
+                    match command {
+                        Command::Sync(index) => {
+                            if let Some(updater) = updaters.get(index) {
+                                if !paths.contains(&updater.repo().path) {
+                                    paths.push(updater.repo().path.clone());
+                                }
+                            }
+                        }
+                        Command::Reload => reloading = true,
+                        Command::Stop => stop = true,
+                        Command::Pause(index, pause) => {
+                            let Some(updater) = updaters.get(index) else {
+                                continue;
+                            };
+                            let path = &updater.repo().path;
+                            let mut paused = paused.write().unwrap_or_else(|e| e.into_inner());
+                            let was = paused.contains(path);
+                            let now = pause.unwrap_or(!was);
+                            paused.retain(|kept| kept != path);
+                            if now {
+                                paused.push(path.clone());
+                            }
+                            if now != was {
+                                output.emit(Event::Paused { path, paused: now });
+                            }
+                        }
+                    }
+                }
+                if stop {
+                    systemd::notify("STOPPING=1");
+                    output.emit(Event::ShuttingDown);
+                    return Ok(ExitCode::SUCCESS);
+                }
+                for path in &paths {
+                    output.emit(Event::WebhookTriggered { path });
+                }
 
-                println!("Local branch reset to 'origin/HEAD'");
+                if reloading {
+                    match reload(&cli, &mut settings, &mut updaters, &shared, &output) {
+                        Ok(changed) => {
+                            scheduler.reconcile(&settings, &updaters, &output)?;
+                            paths.extend(changed);
+                            _watcher = Some(reload::watch(&settings.sources, tx.clone())?);
+                        }
+                        Err(e) => output.emit(Event::ReloadFailed {
+                            message: e.to_string(),
+                        }),
+                    }
+                }
+                updaters
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, updater)| paths.contains(&updater.repo().path))
+                    .map(|(index, _)| index)
+                    .collect()
             }
-            println!(
-                "Current HEAD at commit {}: {}",
-                origin_commit.id(),
-                origin_commit
-                    .message()
-                    .unwrap_or("No commit message")
-                    .trim()
-            );
+        };
+    }
+}
+
+fn main() -> ExitCode {
+    let result = start(Cli::parse(), mpsc::channel());
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(e.exit_code())
         }
-        Err(e) => println!("error: {}", e),
-    };
+    }
 }
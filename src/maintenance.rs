@@ -0,0 +1,76 @@
+use crate::config::Repo;
+use crate::disk;
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::state::State;
+use git2::Repository;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tracing::instrument;
+
+/// Repacks the object database and prunes unreachable objects by running
+/// `git gc`. The reflog entries of unreachable commits expire right away,
+/// otherwise a shallow clone keeps every history it was ever fetched to.
+#[instrument(skip_all, fields(path = %repo.path))]
+pub fn run(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    // Worktrees share the objects of the repository they belong to
+    let objects = match &repo.worktree_of {
+        Some(shared) => Path::new(shared).join("objects"),
+        None => repository.path().join("objects"),
+    };
+    let before = disk::usage(&objects);
+
+    let result = Command::new("git")
+        .args([
+            "-c",
+            "gc.reflogExpireUnreachable=now",
+            "gc",
+            "--prune=now",
+            "--quiet",
+        ])
+        .current_dir(&repo.path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::Config("git is not installed".to_string()),
+            _ => Error::Io(e),
+        })?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(Error::Git(git2::Error::from_str(&format!(
+            "git gc failed: {}",
+            stderr.lines().last().unwrap_or_default().trim()
+        ))));
+    }
+
+    output.emit(Event::Maintained {
+        path: &repo.path,
+        before,
+        after: disk::usage(&objects),
+    });
+    Ok(())
+}
+
+/// Counts an applied update and maintains the repository once the
+/// configured number of them has piled up.
+pub fn after_update(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    let Some(maintenance) = &repo.maintenance else {
+        return Ok(());
+    };
+    let mut state = State::load(repository)?;
+    state.updates_since_maintenance += 1;
+    let due = state.updates_since_maintenance >= maintenance.every();
+    let result = match due {
+        true => run(repo, repository, output),
+        false => Ok(()),
+    };
+    // A failed run is tried again after the next update
+    if due && result.is_ok() {
+        state.updates_since_maintenance = 0;
+    }
+    state.save(repository)?;
+    result
+}
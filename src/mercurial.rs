@@ -0,0 +1,148 @@
+use crate::config::{DirtyPolicy, Repo};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::source::{Revision, Source};
+use git2::{ErrorClass, ErrorCode, Oid};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tracing::{debug, instrument};
+
+/// Node of the null revision, which a clone without a working copy is at.
+const NULL: &str = "0000000000000000000000000000000000000000";
+
+/// A Mercurial checkout, worked on through the `hg` command. Its node ids
+/// are SHA-1 hashes like git's, so they pass for commit ids everywhere else.
+pub(crate) struct Mercurial<'a> {
+    repo: &'a Repo,
+}
+
+impl<'a> Mercurial<'a> {
+    pub(crate) fn new(repo: &'a Repo) -> Mercurial<'a> {
+        Mercurial { repo }
+    }
+
+    fn reference(&self) -> &str {
+        match (self.repo.rev.as_deref(), self.repo.branch.as_str()) {
+            (Some(rev), _) => rev,
+            (None, "") => "default",
+            (None, branch) => branch,
+        }
+    }
+
+    /// Runs `hg` on the checkout and returns what it printed. Failures of
+    /// commands that talk to the remote are reported as network errors.
+    fn hg(&self, args: &[&str], remote: bool) -> Result<String> {
+        let mut command = Command::new("hg");
+        command.arg("--noninteractive");
+        // A clone names its destination itself, which does not exist yet
+        if args[0] != "clone" {
+            command.args(["-R", &self.repo.path]);
+        }
+        let result = command
+            .args(args)
+            .env("HGPLAIN", "1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => Error::Config("hg is not installed".to_string()),
+                _ => Error::Io(e),
+            })?;
+        if result.status.success() {
+            return Ok(String::from_utf8_lossy(&result.stdout).into_owned());
+        }
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let message = format!(
+            "hg {} failed: {}",
+            args[0],
+            stderr.lines().last().unwrap_or_default().trim()
+        );
+        let error = match remote {
+            true if stderr.contains("authorization failed") => {
+                git2::Error::new(ErrorCode::Auth, ErrorClass::Http, message)
+            }
+            true => git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, message),
+            false => git2::Error::from_str(&message),
+        };
+        Err(error.into())
+    }
+}
+
+/// A node id as `hg` printed it.
+fn node(printed: &str) -> Result<Oid> {
+    Oid::from_str(printed.trim()).map_err(|_| {
+        Error::Git(git2::Error::from_str(&format!(
+            "hg printed an invalid node id '{}'",
+            printed.trim()
+        )))
+    })
+}
+
+impl Source for Mercurial<'_> {
+    fn describe(&self) -> String {
+        self.repo.url.clone()
+    }
+
+    fn current(&self) -> Result<Option<Oid>> {
+        if !Path::new(&self.repo.path).join(".hg").is_dir() {
+            return Ok(None);
+        }
+        match self.hg(&["log", "-r", ".", "-T", "{node}"], false)?.trim() {
+            NULL => Ok(None),
+            printed => node(printed).map(Some),
+        }
+    }
+
+    /// Clones without a working copy the first time and pulls afterwards.
+    #[instrument(skip_all, fields(reference = self.reference()))]
+    fn resolve(&self, output: &Output) -> Result<Revision> {
+        let path = Path::new(&self.repo.path);
+        if path.join(".hg").is_dir() {
+            self.hg(&["pull", &self.repo.url], true)?;
+        } else {
+            output.emit(Event::CloneStarted {
+                url: &self.repo.url,
+                path: &self.repo.path,
+            });
+            let existed = path.exists();
+            let cloned = self.hg(
+                &["clone", "--noupdate", &self.repo.url, &self.repo.path],
+                true,
+            );
+            if cloned.is_err() && !existed {
+                let _ = fs::remove_dir_all(path);
+            }
+            cloned?;
+        }
+
+        let printed = self.hg(
+            &["log", "-r", self.reference(), "-T", "{node}\\n{desc}"],
+            false,
+        )?;
+        let (id, message) = printed.split_once('\n').unwrap_or((&printed, ""));
+        let id = node(id)?;
+        debug!(%id, "resolved reference");
+        Ok(Revision {
+            id,
+            message: message.to_string(),
+        })
+    }
+
+    /// Local changes are discarded unless the dirty policy is to abort.
+    fn checkout(&self, commit: Oid, _output: &Output) -> Result<()> {
+        if self.repo.dirty == DirtyPolicy::Abort {
+            let changed = self.hg(&["status"], false)?.lines().count();
+            if changed > 0 {
+                return Err(Error::Refused(format!(
+                    "working tree of '{}' has {} modified or untracked file(s)",
+                    self.repo.path, changed
+                )));
+            }
+        }
+        self.hg(&["update", "--clean", "--rev", &commit.to_string()], false)?;
+        Ok(())
+    }
+}
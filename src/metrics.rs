@@ -0,0 +1,235 @@
+use crate::error::Result;
+use crate::updater::SyncOutcome;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name, type, help text and value of a metric reported for every repository.
+type Metric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&RepoMetrics) -> i64,
+);
+
+/// Counters of one repository, updated by its updater after every sync.
+#[derive(Default)]
+pub struct RepoMetrics {
+    attempted: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    received_bytes: AtomicU64,
+    last_sync: AtomicU64,
+    last_success: AtomicU64,
+    failing: AtomicBool,
+    /// Whether the last sync found an update it did not apply.
+    pending: AtomicBool,
+    /// Commit time of HEAD in seconds since the epoch, 0 before the first sync.
+    head_time: AtomicI64,
+    disk_usage: AtomicU64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+impl RepoMetrics {
+    pub fn record(&self, result: &Result<SyncOutcome>, received: u64, head_time: Option<i64>) {
+        let now = now();
+        self.attempted.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(received, Ordering::Relaxed);
+        self.last_sync.store(now, Ordering::Relaxed);
+        self.failing.store(result.is_err(), Ordering::Relaxed);
+        if let Ok(outcome) = result {
+            let pending = matches!(
+                outcome,
+                SyncOutcome::UpdateAvailable { .. } | SyncOutcome::Held { .. }
+            );
+            self.pending.store(pending, Ordering::Relaxed);
+        }
+        match result {
+            Ok(_) => {
+                self.succeeded.fetch_add(1, Ordering::Relaxed);
+                self.last_success.store(now, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if let Some(head_time) = head_time {
+            self.head_time.store(head_time, Ordering::Relaxed);
+        }
+    }
+
+    /// Time of the last sync in seconds since the epoch, if there was one.
+    pub fn last_sync(&self) -> Option<u64> {
+        Some(self.last_sync.load(Ordering::Relaxed)).filter(|time| *time > 0)
+    }
+
+    /// Time of the last successful sync in seconds since the epoch, if there was one.
+    pub fn last_success(&self) -> Option<u64> {
+        Some(self.last_success.load(Ordering::Relaxed)).filter(|time| *time > 0)
+    }
+
+    pub fn set_disk_usage(&self, bytes: u64) {
+        self.disk_usage.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes the checkout took up after the last sync, if it was measured.
+    pub fn disk_usage(&self) -> Option<u64> {
+        Some(self.disk_usage.load(Ordering::Relaxed)).filter(|bytes| *bytes > 0)
+    }
+
+    /// Whether the last sync failed.
+    pub fn failing(&self) -> bool {
+        self.failing.load(Ordering::Relaxed)
+    }
+}
+
+/// Metrics of every configured repository, rendered in the Prometheus text format.
+pub struct Registry {
+    repos: RwLock<Vec<(String, Arc<RepoMetrics>)>>,
+}
+
+impl Registry {
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a str>) -> Registry {
+        Registry {
+            repos: RwLock::new(
+                paths
+                    .into_iter()
+                    .map(|path| (path.to_string(), Arc::default()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn repos(&self) -> std::sync::RwLockReadGuard<'_, Vec<(String, Arc<RepoMetrics>)>> {
+        self.repos.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Metrics of the repository configured at `index`.
+    pub fn repo(&self, index: usize) -> Arc<RepoMetrics> {
+        self.repos()[index].1.clone()
+    }
+
+    /// Follows reloaded settings; repositories still configured keep their counters.
+    pub fn set_paths<'a>(&self, paths: impl IntoIterator<Item = &'a str>) {
+        let mut repos = self.repos.write().unwrap_or_else(|e| e.into_inner());
+        let kept = std::mem::take(&mut *repos);
+        *repos = paths
+            .into_iter()
+            .map(|path| {
+                let metrics = kept
+                    .iter()
+                    .find(|(kept, _)| kept == path)
+                    .map(|(_, metrics)| metrics.clone())
+                    .unwrap_or_default();
+                (path.to_string(), metrics)
+            })
+            .collect();
+    }
+
+    pub fn render(&self) -> String {
+        let now = now() as i64;
+        let metrics: [Metric; 9] = [
+            (
+                "autoupdate_syncs_attempted_total",
+                "counter",
+                "Syncs started.",
+                |m| m.attempted.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_syncs_succeeded_total",
+                "counter",
+                "Syncs that finished without an error.",
+                |m| m.succeeded.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_syncs_failed_total",
+                "counter",
+                "Syncs that failed.",
+                |m| m.failed.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_received_bytes_total",
+                "counter",
+                "Bytes received by clones and fetches.",
+                |m| m.received_bytes.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_last_sync_timestamp_seconds",
+                "gauge",
+                "Time the last sync finished, 0 if there was none yet.",
+                |m| m.last_sync.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_last_success_timestamp_seconds",
+                "gauge",
+                "Time the last successful sync finished, 0 if there was none yet.",
+                |m| m.last_success.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_head_commit_timestamp_seconds",
+                "gauge",
+                "Commit time of the checked out commit.",
+                |m| m.head_time.load(Ordering::Relaxed),
+            ),
+            (
+                "autoupdate_disk_usage_bytes",
+                "gauge",
+                "Space the checkout takes up, 0 if it was not measured yet.",
+                |m| m.disk_usage.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "autoupdate_update_pending",
+                "gauge",
+                "1 when the last sync found an update it did not apply.",
+                |m| m.pending.load(Ordering::Relaxed) as i64,
+            ),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for (path, repo) in self.repos().iter() {
+                let _ = writeln!(
+                    text,
+                    "{}{{repository=\"{}\"}} {}",
+                    name,
+                    escape(path),
+                    value(repo)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            text,
+            "# HELP autoupdate_commit_age_seconds Age of the checked out commit."
+        );
+        let _ = writeln!(text, "# TYPE autoupdate_commit_age_seconds gauge");
+        for (path, repo) in self.repos().iter() {
+            let head_time = repo.head_time.load(Ordering::Relaxed);
+            if head_time > 0 {
+                let _ = writeln!(
+                    text,
+                    "autoupdate_commit_age_seconds{{repository=\"{}\"}} {}",
+                    escape(path),
+                    (now - head_time).max(0)
+                );
+            }
+        }
+        text
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
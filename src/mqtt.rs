@@ -0,0 +1,186 @@
+use crate::control::{Control, Refusal};
+use crate::Command;
+use autoupdate::config;
+use autoupdate::output::{Event, Output, SummaryEntry};
+use autoupdate::Error;
+use rumqttc::{Client, Event as MqttEvent, MqttOptions, Packet, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Requests that may queue up for the broker while it cannot be reached.
+const CAPACITY: usize = 64;
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    #[default]
+    Sync,
+    Pause,
+    Resume,
+}
+
+/// A command: empty or `sync`, `pause` and `resume` for every repository,
+/// or a JSON object such as `{"action": "sync", "path": "/srv/app"}`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Message {
+    #[serde(default)]
+    action: Action,
+    path: Option<String>,
+}
+
+impl Message {
+    fn parse(payload: &[u8]) -> Result<Message, Refusal> {
+        let text = std::str::from_utf8(payload)
+            .map_err(|_| Refusal::new(400, "the command is not UTF-8"))?
+            .trim();
+        let action = match text {
+            "" | "sync" => Action::Sync,
+            "pause" => Action::Pause,
+            "resume" => Action::Resume,
+            _ => {
+                return serde_json::from_str(text)
+                    .map_err(|e| Refusal::new(400, &format!("invalid command: {}", e)))
+            }
+        };
+        Ok(Message { action, path: None })
+    }
+}
+
+fn handle(control: &Control, payload: &[u8]) -> Result<(), Refusal> {
+    let message = Message::parse(payload)?;
+    let indexes = control.selected(message.path.as_deref())?;
+    control.queue(indexes.into_iter().map(|index| match message.action {
+        Action::Sync => Command::Sync(index),
+        Action::Pause => Command::Pause(index, Some(true)),
+        Action::Resume => Command::Pause(index, Some(false)),
+    }))
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    host: &'a str,
+    timestamp: u64,
+    #[serde(flatten)]
+    entry: &'a SummaryEntry<'a>,
+}
+
+/// The connection to the broker, publishing how syncs went.
+pub(crate) struct Link {
+    client: Client,
+    topic: String,
+    host: String,
+}
+
+impl Link {
+    /// Publishes one message per repository to the status topic. While the
+    /// broker is unreachable they wait for it until the queue is full.
+    pub(crate) fn publish(&self, entries: &[SummaryEntry]) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        for entry in entries {
+            let status = Status {
+                host: &self.host,
+                timestamp,
+                entry,
+            };
+            let payload = serde_json::to_vec(&status).unwrap_or_default();
+            if let Err(e) = self
+                .client
+                .try_publish(&self.topic, QoS::AtLeastOnce, false, payload)
+            {
+                tracing::warn!(path = entry.path, error = %e, "could not publish the sync status");
+            }
+        }
+    }
+}
+
+/// Connects to the broker on its own thread, subscribing to the command
+/// topic again every time the connection comes back.
+pub(crate) fn spawn(
+    settings: &config::Mqtt,
+    control: Arc<Control>,
+    output: Output,
+) -> Result<Link, Error> {
+    let host = hostname::get()?.to_string_lossy().into_owned();
+    let topic = |topic: &str| topic.replace("{host}", &host);
+    let (address, port, tls) = settings.address()?;
+    let client_id = settings
+        .client_id
+        .clone()
+        .unwrap_or_else(|| format!("autoupdate-{}", host));
+
+    let mut options = MqttOptions::new(client_id, address, port);
+    options.set_keep_alive(KEEP_ALIVE);
+    if tls {
+        options.set_transport(match &settings.ca {
+            Some(ca) => Transport::tls(
+                fs::read(ca).map_err(|e| {
+                    Error::Config(format!("could not read mqtt ca '{}': {}", ca, e))
+                })?,
+                None,
+                None,
+            ),
+            None => Transport::tls_with_default_config(),
+        });
+    }
+    if let Some(username) = &settings.username {
+        // Brokers may take a username without a password
+        let password = settings.password()?;
+        options.set_credentials(username, password.as_deref().unwrap_or(""));
+    }
+
+    let (client, mut connection) = Client::new(options, CAPACITY);
+    let subscriber = client.clone();
+    let broker = settings.broker.clone();
+    let command_topic = topic(settings.command_topic());
+    thread::spawn(move || {
+        // Also reports when the first attempt fails
+        let mut connected = true;
+        for event in connection.iter() {
+            match event {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                    connected = true;
+                    output.emit(Event::MqttConnected {
+                        broker: &broker,
+                        topic: &command_topic,
+                    });
+                    if let Err(e) = subscriber.try_subscribe(&command_topic, QoS::AtLeastOnce) {
+                        tracing::warn!(error = %e, "could not subscribe to the command topic");
+                    }
+                }
+                Ok(MqttEvent::Incoming(Packet::Publish(publish))) => {
+                    if let Err(refusal) = handle(&control, &publish.payload) {
+                        output.emit(Event::MqttRefused {
+                            topic: &publish.topic,
+                            message: refusal.message,
+                        });
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if connected {
+                        output.emit(Event::MqttDisconnected {
+                            broker: &broker,
+                            message: e.to_string(),
+                        });
+                    }
+                    connected = false;
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+    });
+
+    Ok(Link {
+        client,
+        topic: topic(settings.status_topic()),
+        host,
+    })
+}
@@ -0,0 +1,193 @@
+use crate::config::{Notify, NotifyEmail, NotifyFormat, SmtpTls};
+use crate::error::{Error, Result};
+use crate::output::CommitInfo;
+use git2::Oid;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde_json::json;
+use std::sync::Arc;
+
+/// What happened to a repository, as reported to notifiers.
+pub enum Notification<'a> {
+    Updated {
+        path: &'a str,
+        old: Oid,
+        new: Oid,
+        commits: &'a [CommitInfo],
+    },
+    Failed {
+        path: &'a str,
+        error: &'a Error,
+    },
+}
+
+impl Notification<'_> {
+    pub fn subject(&self) -> String {
+        match self {
+            Notification::Updated { path, old, new, .. } => format!(
+                "{} updated from {:.7} to {:.7}",
+                path,
+                old.to_string(),
+                new.to_string()
+            ),
+            Notification::Failed { path, .. } => format!("{} failed to update", path),
+        }
+    }
+
+    /// Subject followed by the short log of new commits or the error.
+    pub fn text(&self) -> String {
+        let mut text = self.subject();
+        match self {
+            Notification::Updated { commits, .. } => {
+                for commit in commits.iter() {
+                    text.push_str(&format!(
+                        "\n- {:.7} {}: {}",
+                        commit.id, commit.author, commit.summary
+                    ));
+                }
+            }
+            Notification::Failed { error, .. } => text.push_str(&format!("\n{}", error)),
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Notification::Updated {
+                path,
+                old,
+                new,
+                commits,
+            } => json!({
+                "event": "updated",
+                "path": path,
+                "old": old.to_string(),
+                "new": new.to_string(),
+                "commits": commits,
+            }),
+            Notification::Failed { path, error } => json!({
+                "event": "failed",
+                "path": path,
+                "error": error.to_string(),
+            }),
+        }
+    }
+}
+
+/// Delivers notifications somewhere; implement this to add another backend.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Posts notifications to a Slack or Discord incoming webhook, or any URL accepting JSON.
+pub struct WebhookNotifier {
+    url: String,
+    format: NotifyFormat,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, format: Option<NotifyFormat>) -> WebhookNotifier {
+        let format = format.unwrap_or(if url.contains("hooks.slack.com") {
+            NotifyFormat::Slack
+        } else if url.contains("discord.com/api/webhooks") {
+            NotifyFormat::Discord
+        } else {
+            NotifyFormat::Json
+        });
+        WebhookNotifier {
+            url: url.to_string(),
+            format,
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let body = match self.format {
+            NotifyFormat::Slack => json!({ "text": notification.text() }),
+            NotifyFormat::Discord => json!({ "content": notification.text() }),
+            NotifyFormat::Json => notification.to_json(),
+        };
+        ureq::post(&self.url)
+            .send_json(&body)
+            .map_err(|e| Error::Notify(format!("webhook '{}': {}", self.url, e)))?;
+        Ok(())
+    }
+}
+
+/// Mails a summary to a list of recipients through an SMTP relay.
+pub struct EmailNotifier {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl EmailNotifier {
+    pub fn new(email: &NotifyEmail) -> Result<EmailNotifier> {
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| Error::Config(format!("invalid email address '{}': {}", address, e)))
+        };
+        let smtp = |e: lettre::transport::smtp::Error| {
+            Error::Config(format!("invalid smtp host '{}': {}", email.host, e))
+        };
+
+        let mut builder = match email.tls {
+            SmtpTls::Starttls => SmtpTransport::starttls_relay(&email.host).map_err(smtp)?,
+            SmtpTls::Tls => SmtpTransport::relay(&email.host).map_err(smtp)?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(&email.host),
+        };
+        if let Some(port) = email.port {
+            builder = builder.port(port);
+        }
+        if let Some(username) = &email.username {
+            let password = email.password()?.ok_or_else(|| {
+                Error::Config(format!("smtp username {} has no password", username))
+            })?;
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        Ok(EmailNotifier {
+            transport: builder.build(),
+            from: mailbox(&email.from)?,
+            to: email
+                .to
+                .iter()
+                .map(|address| mailbox(address))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(format!("[autoupdate] {}", notification.subject()));
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .body(notification.text())
+            .map_err(|e| Error::Notify(format!("could not build email: {}", e)))?;
+
+        self.transport.send(&message).map_err(|e| {
+            Error::Notify(format!("email to {} recipient(s): {}", self.to.len(), e))
+        })?;
+        Ok(())
+    }
+}
+
+/// Builds the notifiers configured in the settings.
+pub fn notifiers(notify: &Notify) -> Result<Vec<Arc<dyn Notifier>>> {
+    let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+    for webhook in &notify.webhooks {
+        notifiers.push(Arc::new(WebhookNotifier::new(&webhook.url, webhook.format)));
+    }
+    for email in &notify.emails {
+        notifiers.push(Arc::new(EmailNotifier::new(email)?));
+    }
+    Ok(notifiers)
+}
@@ -0,0 +1,1666 @@
+use crate::config::{ProgressBars, Strategy};
+use crate::error::{Error, Result};
+use crate::state::HistoryEntry;
+use clap::ValueEnum;
+use indicatif::{
+    HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::cmp::min;
+use std::fmt::Write;
+use std::io::{self, IsTerminal};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::Level;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    UpToDate,
+    Updated,
+    UpdateAvailable,
+    Held,
+    Failed,
+}
+
+impl Outcome {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Outcome::UpToDate => "up to date",
+            Outcome::Updated => "updated",
+            Outcome::UpdateAvailable => "update available",
+            Outcome::Held => "update held back",
+            Outcome::Failed => "failed",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Outcome::UpToDate | Outcome::Updated => Color::Green,
+            Outcome::UpdateAvailable | Outcome::Held => Color::Yellow,
+            Outcome::Failed => Color::Red,
+        }
+    }
+}
+
+/// Every output draws into the same progress bars, so log lines printed
+/// through `suspend` never land in the middle of one.
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Look of new progress bars, set by `configure_progress`.
+static STYLE: RwLock<Option<ProgressStyle>> = RwLock::new(None);
+
+const DEFAULT_TEMPLATE: &str =
+    "{spinner:.green} {prefix:>12} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})";
+
+/// The progress bar style the settings ask for.
+pub fn progress_style(settings: Option<&ProgressBars>) -> Result<ProgressStyle> {
+    let template = settings
+        .and_then(|settings| settings.template.as_deref())
+        .unwrap_or(DEFAULT_TEMPLATE);
+    let mut style = ProgressStyle::with_template(template)
+        .map_err(|e| Error::Config(format!("invalid progress template '{}': {}", template, e)))?
+        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+            write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
+        })
+        .progress_chars("#>-");
+    if let Some(spinner) = settings.and_then(|settings| settings.spinner.as_deref()) {
+        if spinner.chars().count() < 2 {
+            return Err(Error::Config(format!(
+                "progress spinner '{}' needs at least two characters",
+                spinner
+            )));
+        }
+        style = style.tick_chars(spinner);
+    }
+    Ok(style)
+}
+
+/// Applies the progress settings to every output; without `show` no bars are drawn.
+pub fn configure_progress(settings: Option<&ProgressBars>, show: bool) -> Result<()> {
+    let refresh = settings
+        .map(ProgressBars::refresh)
+        .transpose()?
+        .flatten()
+        .unwrap_or(Duration::from_millis(50));
+    BARS.set_draw_target(match show {
+        true => {
+            let hz = (1.0 / refresh.as_secs_f64().max(f64::EPSILON)).clamp(1.0, 100.0);
+            ProgressDrawTarget::stderr_with_hz(hz as u8)
+        }
+        false => ProgressDrawTarget::hidden(),
+    });
+    *STYLE.write().unwrap_or_else(|e| e.into_inner()) = Some(progress_style(settings)?);
+    Ok(())
+}
+
+/// Runs `f`, which prints to the terminal, with the progress bars cleared
+/// and draws them again afterwards.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    BARS.suspend(f)
+}
+
+thread_local! {
+    /// Color of the status line being logged on this thread.
+    static TONE: Cell<Option<Color>> = const { Cell::new(None) };
+}
+
+/// ANSI color code of the status line being logged, for the console formatter.
+pub fn tone() -> Option<u8> {
+    TONE.with(Cell::get).map(|color| color as u8)
+}
+
+/// Whether the terminal gets colors: stdout is one and `NO_COLOR` is not set.
+pub fn colors() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && io::stdout().is_terminal()
+}
+
+#[derive(Clone, Copy)]
+enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+#[derive(Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    pub author: String,
+    pub summary: String,
+}
+
+#[derive(Serialize)]
+pub struct SummaryEntry<'a> {
+    pub path: &'a str,
+    pub status: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    CloneStarted {
+        url: &'a str,
+        path: &'a str,
+    },
+    /// What a failed or interrupted clone left behind was removed.
+    CloneDiscarded {
+        path: &'a str,
+    },
+    CloneResumed {
+        path: &'a str,
+    },
+    /// The tree of `commit` is downloaded instead of fetched with git.
+    DownloadStarted {
+        path: &'a str,
+        source: String,
+        commit: String,
+    },
+    DownloadFinished {
+        path: &'a str,
+        commit: String,
+    },
+    ObjectsReceived {
+        path: &'a str,
+        received: u64,
+        total: u64,
+    },
+    DeltasResolved {
+        path: &'a str,
+        indexed: u64,
+        total: u64,
+    },
+    /// How long each phase of a clone or fetch took, telling a slow network
+    /// apart from a slow disk or CPU.
+    TransferFinished {
+        path: &'a str,
+        bytes: u64,
+        negotiation_ms: u64,
+        download_ms: u64,
+        resolve_ms: u64,
+    },
+    WorkTreeUpdated {
+        path: &'a str,
+        files: u64,
+        duration_ms: u64,
+    },
+    CheckoutProgress {
+        path: &'a str,
+        completed: u64,
+        total: u64,
+    },
+    LfsProgress {
+        path: &'a str,
+        downloaded: u64,
+        total: u64,
+    },
+    OwnershipProgress {
+        path: &'a str,
+        applied: u64,
+        total: u64,
+    },
+    OwnershipApplied {
+        path: &'a str,
+        files: usize,
+        failed: usize,
+    },
+    SubmoduleUpdate {
+        path: &'a str,
+        submodule: String,
+    },
+    BranchSwitched {
+        path: &'a str,
+        from: &'a str,
+        to: &'a str,
+    },
+    TlsUnverified {
+        path: &'a str,
+    },
+    HostKeyTrusted {
+        path: &'a str,
+        host: &'a str,
+        fingerprint: &'a str,
+    },
+    UrlDrift {
+        path: &'a str,
+        remote: &'a str,
+        actual: &'a str,
+        configured: &'a str,
+        rewritten: bool,
+    },
+    UpToDate {
+        path: &'a str,
+    },
+    MirrorUpdated {
+        path: &'a str,
+        created: usize,
+        updated: usize,
+        deleted: usize,
+    },
+    UpdateAvailable {
+        path: &'a str,
+        old: String,
+        new: String,
+        commits: Vec<CommitInfo>,
+    },
+    /// The update from `old` to `new` waits for `autoupdate approve`.
+    AwaitingApproval {
+        path: &'a str,
+        old: String,
+        new: String,
+        commits: Vec<CommitInfo>,
+    },
+    Approved {
+        path: &'a str,
+        commit: String,
+    },
+    NothingAwaitingApproval,
+    BackedUp {
+        path: &'a str,
+        directory: String,
+        files: usize,
+    },
+    Maintained {
+        path: &'a str,
+        /// Size of the object database before and after, in bytes.
+        before: u64,
+        after: u64,
+    },
+    MaintenanceFailed {
+        path: &'a str,
+        message: String,
+    },
+    /// The first sync in a row that could not reach the remote.
+    RemoteUnreachable {
+        path: &'a str,
+    },
+    RemoteReachable {
+        path: &'a str,
+        failures: u32,
+    },
+    QuotaExceeded {
+        path: &'a str,
+        usage: u64,
+        quota: u64,
+    },
+    /// A corrupt repository was moved to `backup` to be cloned again.
+    Repairing {
+        path: &'a str,
+        problem: String,
+        backup: String,
+    },
+    Archived {
+        path: &'a str,
+        commit: String,
+        destination: String,
+    },
+    /// `files` copied or deleted, unknown when rsync did the copying.
+    Propagated {
+        path: &'a str,
+        destination: &'a str,
+        files: Option<usize>,
+    },
+    Released {
+        path: &'a str,
+        commit: String,
+        release: String,
+        /// An earlier release of the commit was made current again.
+        reused: bool,
+    },
+    Swapped {
+        path: &'a str,
+        directory: String,
+    },
+    WorktreeAdded {
+        path: &'a str,
+        repository: &'a str,
+    },
+    Cleaned {
+        path: &'a str,
+        files: usize,
+    },
+    Stashed {
+        path: &'a str,
+        stash: String,
+    },
+    StashReapplied {
+        path: &'a str,
+    },
+    StashConflict {
+        path: &'a str,
+        message: String,
+    },
+    TagSelected {
+        path: &'a str,
+        tag: &'a str,
+        commit: String,
+    },
+    Verified {
+        path: &'a str,
+        commit: String,
+        signer: &'a str,
+    },
+    IntegrityVerified {
+        path: &'a str,
+        commit: String,
+        objects: usize,
+    },
+    CheckoutStarted {
+        path: &'a str,
+        target: &'a str,
+        old: String,
+        new: String,
+    },
+    CheckoutFinished {
+        path: &'a str,
+        target: &'a str,
+    },
+    UpdateStarted {
+        path: &'a str,
+        branch: &'a str,
+        upstream: String,
+        strategy: Strategy,
+        old: String,
+        new: String,
+    },
+    UpdateFinished {
+        path: &'a str,
+        branch: &'a str,
+        upstream: String,
+        strategy: Strategy,
+    },
+    Changelog {
+        path: &'a str,
+        commits: &'a [CommitInfo],
+    },
+    Head {
+        path: &'a str,
+        commit: String,
+        message: &'a str,
+    },
+    HookStarted {
+        stage: &'a str,
+        command: &'a str,
+    },
+    HookFinished {
+        stage: &'a str,
+        command: &'a str,
+        code: Option<i32>,
+        stdout: &'a str,
+        stderr: &'a str,
+    },
+    Error {
+        path: &'a str,
+        message: String,
+    },
+    Summary {
+        repositories: Vec<SummaryEntry<'a>>,
+    },
+    Listening {
+        address: &'a str,
+        secured: bool,
+    },
+    ServingMetrics {
+        address: &'a str,
+    },
+    ServingApi {
+        address: &'a str,
+        secured: bool,
+    },
+    ServingGrpc {
+        address: &'a str,
+        tls: bool,
+        secured: bool,
+    },
+    ServingSocket {
+        path: &'a str,
+    },
+    MqttConnected {
+        broker: &'a str,
+        topic: &'a str,
+    },
+    MqttDisconnected {
+        broker: &'a str,
+        message: String,
+    },
+    /// A message on the command topic that could not be carried out.
+    MqttRefused {
+        topic: &'a str,
+        message: String,
+    },
+    /// The running daemon took `action` on for this many repositories.
+    DaemonQueued {
+        action: &'a str,
+        repositories: usize,
+    },
+    /// A repository as the running daemon reports it.
+    DaemonStatus {
+        path: &'a str,
+        branch: &'a str,
+        commit: Option<&'a str>,
+        paused: bool,
+        healthy: bool,
+    },
+    WebhookTriggered {
+        path: &'a str,
+    },
+    NextCheck {
+        seconds: u64,
+    },
+    Splaying {
+        seconds: u64,
+    },
+    ScheduleMissed {
+        path: &'a str,
+        due: u64,
+    },
+    WaitingForLock {
+        path: &'a str,
+        pid: Option<u32>,
+    },
+    StaleLock {
+        path: &'a str,
+        pid: u32,
+    },
+    Retrying {
+        path: &'a str,
+        attempt: u32,
+        attempts: u32,
+        delay_ms: u64,
+        message: String,
+    },
+    Pruned {
+        path: &'a str,
+        reference: &'a str,
+    },
+    Deepened {
+        path: &'a str,
+        shallow: bool,
+    },
+    RolledBack {
+        path: &'a str,
+        from: String,
+        to: String,
+    },
+    RollbackHeld {
+        path: &'a str,
+        commit: String,
+    },
+    /// The update waits for the checks of `commit` to pass.
+    ChecksPending {
+        path: &'a str,
+        commit: String,
+    },
+    ChecksPassed {
+        path: &'a str,
+        commit: String,
+    },
+    /// The update to `commit` waits for a maintenance window to open, at `opens`.
+    UpdateDeferred {
+        path: &'a str,
+        commit: String,
+        opens: Option<u64>,
+    },
+    /// The update to `commit` waits for the canaries to soak until `until`.
+    RolloutWaiting {
+        path: &'a str,
+        commit: String,
+        until: u64,
+    },
+    /// Applying `commit` failed on `hosts`, so the other hosts hold it back.
+    RolloutHalted {
+        path: &'a str,
+        commit: String,
+        hosts: Vec<String>,
+    },
+    /// Another host, `leader` when known, was elected to run the leader hooks.
+    LeaderHooksSkipped {
+        path: &'a str,
+        leader: Option<String>,
+    },
+    /// None of the new commits passed the filter; they are held back.
+    UpdateFiltered {
+        path: &'a str,
+        commit: String,
+        commits: usize,
+    },
+    History {
+        path: &'a str,
+        entries: &'a [HistoryEntry],
+    },
+    NotifyFailed {
+        path: &'a str,
+        message: String,
+    },
+    InvalidSetting {
+        message: &'a str,
+    },
+    SettingsValid {
+        repositories: usize,
+    },
+    SettingsWritten {
+        file: &'a str,
+    },
+    SettingsReloaded {
+        repositories: usize,
+    },
+    ReloadFailed {
+        message: String,
+    },
+    RestartRequired {
+        setting: &'a str,
+    },
+    RepositoryAdded {
+        path: &'a str,
+    },
+    RepositoryChanged {
+        path: &'a str,
+    },
+    RepositoryRemoved {
+        path: &'a str,
+        deleted: bool,
+    },
+    SelfUpToDate {
+        version: String,
+    },
+    SelfUpdateAvailable {
+        current: String,
+        latest: String,
+    },
+    SelfUpdateVerified {
+        asset: &'a str,
+        signer: &'a str,
+    },
+    SelfUpdated {
+        from: String,
+        to: String,
+    },
+    Paused {
+        path: &'a str,
+        paused: bool,
+    },
+    Interrupted,
+    ShuttingDown,
+}
+
+/// Logs one human-readable line, prefixed with the label when there is one.
+macro_rules! say {
+    ($output:expr, $level:expr, $($arg:tt)*) => {
+        $output.line($level, &format!($($arg)*))
+    };
+}
+
+impl Event<'_> {
+    /// Log level of the human-readable rendering.
+    pub fn level(&self) -> Level {
+        match self {
+            Event::Error { .. } | Event::InvalidSetting { .. } | Event::ReloadFailed { .. } => {
+                Level::ERROR
+            }
+            Event::StashConflict { .. }
+            | Event::StaleLock { .. }
+            | Event::TlsUnverified { .. }
+            | Event::HostKeyTrusted { .. }
+            | Event::UrlDrift {
+                rewritten: false, ..
+            }
+            | Event::Retrying { .. }
+            | Event::CloneDiscarded { .. }
+            | Event::Repairing { .. }
+            | Event::MaintenanceFailed { .. }
+            | Event::QuotaExceeded { .. }
+            | Event::RemoteUnreachable { .. }
+            | Event::Interrupted
+            | Event::RestartRequired { .. }
+            | Event::NotifyFailed { .. }
+            | Event::MqttDisconnected { .. }
+            | Event::MqttRefused { .. } => Level::WARN,
+            Event::ObjectsReceived { .. }
+            | Event::DeltasResolved { .. }
+            | Event::CheckoutProgress { .. }
+            | Event::LfsProgress { .. }
+            | Event::OwnershipProgress { .. } => Level::TRACE,
+            // A fetch that found nothing new happens every cycle
+            Event::TransferFinished { bytes: 0, .. } => Level::DEBUG,
+            _ => Level::INFO,
+        }
+    }
+}
+
+/// Sees every event as it is emitted, e.g. to draw a dashboard.
+pub type Observer = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Progress bars of repositories updated on different threads draw below
+/// each other instead of over each other.
+#[derive(Clone)]
+pub struct Output {
+    format: Format,
+    bars: MultiProgress,
+    label: Option<String>,
+    observer: Option<Observer>,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+        Output {
+            format,
+            bars: BARS.clone(),
+            label: None,
+            observer: None,
+        }
+    }
+
+    /// A copy of this output that also hands every event to `observer`,
+    /// which then owns the terminal: progress is reported as events instead of bars.
+    pub fn observed(self, observer: Observer) -> Output {
+        Output {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    fn draws_bars(&self) -> bool {
+        self.format == Format::Human && self.observer.is_none()
+    }
+
+    /// A copy of this output that prefixes human-readable lines with `label`.
+    pub fn labelled(&self, label: &str) -> Output {
+        Output {
+            label: Some(label.to_string()),
+            ..self.clone()
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(observer) = &self.observer {
+            observer(&event);
+        }
+        match self.format {
+            Format::Json => println!("{}", serde_json::to_string(&event).unwrap()),
+            // The log writer keeps the lines clear of the progress bars
+            Format::Human => self.human(&event),
+        }
+    }
+
+    /// Logs the lines of `say` in `color` on a color terminal.
+    fn tinted(&self, color: Color, say: impl FnOnce()) {
+        TONE.with(|tone| tone.set(Some(color)));
+        say();
+        TONE.with(|tone| tone.set(None));
+    }
+
+    fn line(&self, level: Level, text: &str) {
+        let text = match &self.label {
+            Some(label) => format!("[{}] {}", label, text),
+            None => text.to_string(),
+        };
+        // The tracing macros need the level as a constant
+        match level {
+            Level::ERROR => tracing::error!("{}", text),
+            Level::WARN => tracing::warn!("{}", text),
+            Level::INFO => tracing::info!("{}", text),
+            Level::DEBUG => tracing::debug!("{}", text),
+            Level::TRACE => tracing::trace!("{}", text),
+        }
+    }
+
+    pub fn progress<'a>(&'a self, path: &'a str, kind: ProgressKind) -> Progress<'a> {
+        let pb = match self.draws_bars() {
+            true => self.bars.add(spawn_progress_bar()),
+            false => ProgressBar::hidden(),
+        };
+        pb.set_prefix(kind.label());
+        Progress {
+            output: self,
+            path,
+            kind,
+            pb,
+            last_percent: Cell::new(None),
+        }
+    }
+
+    pub(crate) fn transfer<'a>(&'a self, path: &'a str) -> Transfer<'a> {
+        Transfer {
+            output: self,
+            path,
+            receiving: self.progress(path, ProgressKind::Transfer),
+            resolving: RefCell::new(None),
+            started: Cell::new(Instant::now()),
+            received: Cell::new(None),
+            downloaded: Cell::new(None),
+            bytes: Cell::new(0),
+            finished: Cell::new(false),
+        }
+    }
+
+    pub(crate) fn checkout<'a>(&'a self, path: &'a str) -> Checkout<'a> {
+        Checkout {
+            output: self,
+            path,
+            progress: self.progress(path, ProgressKind::Checkout),
+            started: Cell::new(None),
+            files: Cell::new(0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ProgressKind {
+    Transfer,
+    Deltas,
+    Checkout,
+    Lfs,
+    Ownership,
+}
+
+impl ProgressKind {
+    fn label(&self) -> &'static str {
+        match self {
+            ProgressKind::Transfer => "receiving",
+            ProgressKind::Deltas => "resolving",
+            ProgressKind::Checkout => "checking out",
+            ProgressKind::Lfs => "lfs",
+            ProgressKind::Ownership => "ownership",
+        }
+    }
+}
+
+pub struct Progress<'a> {
+    output: &'a Output,
+    path: &'a str,
+    kind: ProgressKind,
+    pb: ProgressBar,
+    last_percent: Cell<Option<u64>>,
+}
+
+impl Progress<'_> {
+    pub fn set(&self, position: usize, total: usize) {
+        let position = min(position, total) as u64;
+        let total = total as u64;
+
+        if self.output.draws_bars() {
+            self.pb.set_length(total);
+            self.pb.set_position(position);
+            return;
+        }
+
+        // Only emit an event whenever another percent of the work is done
+        let percent = (position * 100).checked_div(total).unwrap_or(100);
+        if self.last_percent.replace(Some(percent)) == Some(percent) {
+            return;
+        }
+
+        let path = self.path;
+        self.output.emit(match self.kind {
+            ProgressKind::Transfer => Event::ObjectsReceived {
+                path,
+                received: position,
+                total,
+            },
+            ProgressKind::Deltas => Event::DeltasResolved {
+                path,
+                indexed: position,
+                total,
+            },
+            ProgressKind::Checkout => Event::CheckoutProgress {
+                path,
+                completed: position,
+                total,
+            },
+            ProgressKind::Lfs => Event::LfsProgress {
+                path,
+                downloaded: position,
+                total,
+            },
+            ProgressKind::Ownership => Event::OwnershipProgress {
+                path,
+                applied: position,
+                total,
+            },
+        });
+    }
+
+    pub fn finish(&self) {
+        self.pb.finish();
+    }
+}
+
+/// Follows a clone or fetch through its phases using libgit2's transfer
+/// statistics: negotiating until the first object arrives, downloading until
+/// every object is there and resolving deltas after that.
+pub(crate) struct Transfer<'a> {
+    output: &'a Output,
+    path: &'a str,
+    receiving: Progress<'a>,
+    /// Drawn once the download is complete.
+    resolving: RefCell<Option<Progress<'a>>>,
+    started: Cell<Instant>,
+    received: Cell<Option<Instant>>,
+    downloaded: Cell<Option<Instant>>,
+    bytes: Cell<usize>,
+    finished: Cell<bool>,
+}
+
+impl Transfer<'_> {
+    /// Starts timing over for another attempt.
+    pub(crate) fn restart(&self) {
+        self.started.set(Instant::now());
+        self.received.set(None);
+        self.downloaded.set(None);
+    }
+
+    pub(crate) fn update(&self, stats: &git2::Progress) {
+        let now = Instant::now();
+        self.bytes.set(stats.received_bytes());
+        if stats.received_objects() > 0 && self.received.get().is_none() {
+            self.received.set(Some(now));
+        }
+        if stats.received_objects() == stats.total_objects() && self.downloaded.get().is_none() {
+            if self.received.get().is_none() {
+                self.received.set(Some(now));
+            }
+            self.downloaded.set(Some(now));
+            self.receiving.finish();
+        }
+
+        match self.downloaded.get() {
+            None => self
+                .receiving
+                .set(stats.received_objects(), stats.total_objects()),
+            Some(_) if stats.total_deltas() > 0 => self
+                .resolving
+                .borrow_mut()
+                .get_or_insert_with(|| self.output.progress(self.path, ProgressKind::Deltas))
+                .set(stats.indexed_deltas(), stats.total_deltas()),
+            Some(_) => {}
+        }
+    }
+
+    /// Reports how long each phase took, once the objects are in place.
+    pub(crate) fn finish(&self) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.receiving.finish();
+        if let Some(resolving) = self.resolving.borrow().as_ref() {
+            resolving.finish();
+        }
+        let now = Instant::now();
+        let received = self.received.get().unwrap_or(now);
+        let downloaded = self.downloaded.get().unwrap_or(now);
+        let millis = |from: Instant, to: Instant| to.duration_since(from).as_millis() as u64;
+        self.output.emit(Event::TransferFinished {
+            path: self.path,
+            bytes: self.bytes.get() as u64,
+            negotiation_ms: millis(self.started.get(), received),
+            download_ms: millis(received, downloaded),
+            resolve_ms: millis(downloaded, now),
+        });
+    }
+}
+
+/// Times updating the work tree, from the first file libgit2 reports.
+pub(crate) struct Checkout<'a> {
+    output: &'a Output,
+    path: &'a str,
+    progress: Progress<'a>,
+    started: Cell<Option<Instant>>,
+    files: Cell<usize>,
+}
+
+impl Checkout<'_> {
+    pub(crate) fn set(&self, position: usize, total: usize) {
+        if self.started.get().is_none() {
+            self.started.set(Some(Instant::now()));
+        }
+        self.files.set(total);
+        self.progress.set(position, total);
+    }
+
+    pub(crate) fn finish(&self) {
+        self.progress.finish();
+        if let Some(started) = self.started.get() {
+            self.output.emit(Event::WorkTreeUpdated {
+                path: self.path,
+                files: self.files.get() as u64,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+}
+
+fn spawn_progress_bar() -> ProgressBar {
+    let configured = STYLE.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let style = configured.unwrap_or_else(|| progress_style(None).unwrap());
+    ProgressBar::new(0).with_style(style)
+}
+
+impl Output {
+    fn human(&self, event: &Event) {
+        let level = event.level();
+        match event {
+            Event::CloneStarted { url, path } => say!(
+                self,
+                level,
+                "Cloning repository from '{}' into directory '{}'. Please wait...",
+                url,
+                path
+            ),
+            Event::CloneDiscarded { path } => {
+                say!(self, level, "Removed the incomplete clone in '{}'", path)
+            }
+            Event::CloneResumed { path } => {
+                say!(self, level, "Resuming the interrupted clone in '{}'", path)
+            }
+            Event::DownloadStarted {
+                path,
+                source,
+                commit,
+            } => say!(
+                self,
+                level,
+                "Downloading the tree of {} from {} into directory '{}'. Please wait...",
+                &commit[..7],
+                source,
+                path
+            ),
+            Event::DownloadFinished { path, commit } => say!(
+                self,
+                level,
+                "Replaced the files in '{}' with the tree of {}",
+                path,
+                &commit[..7]
+            ),
+            Event::ObjectsReceived { .. }
+            | Event::DeltasResolved { .. }
+            | Event::CheckoutProgress { .. }
+            | Event::LfsProgress { .. }
+            | Event::OwnershipProgress { .. } => {}
+            Event::TransferFinished {
+                bytes,
+                negotiation_ms,
+                download_ms,
+                resolve_ms,
+                ..
+            } => say!(
+                self,
+                level,
+                "Received {}: negotiating {:.1}s, downloading {:.1}s, resolving deltas {:.1}s",
+                HumanBytes(*bytes),
+                *negotiation_ms as f64 / 1000.0,
+                *download_ms as f64 / 1000.0,
+                *resolve_ms as f64 / 1000.0
+            ),
+            Event::WorkTreeUpdated {
+                files, duration_ms, ..
+            } => say!(
+                self,
+                level,
+                "Checked out {} file(s) in {:.1}s",
+                files,
+                *duration_ms as f64 / 1000.0
+            ),
+            Event::SubmoduleUpdate { submodule, .. } => {
+                say!(self, level, "Updating submodule '{}'", submodule)
+            }
+            Event::BranchSwitched { from, to, .. } => {
+                say!(self, level, "Switched from '{}' to branch '{}'", from, to)
+            }
+            Event::TlsUnverified { .. } => say!(
+                self,
+                level,
+                "Not verifying the server certificate, the remote cannot be trusted"
+            ),
+            Event::HostKeyTrusted {
+                host, fingerprint, ..
+            } => say!(
+                self,
+                level,
+                "Trusting new host key {} of {} and adding it to known_hosts",
+                fingerprint,
+                host
+            ),
+            Event::UrlDrift {
+                remote,
+                actual,
+                configured,
+                rewritten,
+                ..
+            } => match rewritten {
+                true => say!(
+                    self,
+                    level,
+                    "Changed URL of remote '{}' from '{}' to '{}'",
+                    remote,
+                    actual,
+                    configured
+                ),
+                false => say!(
+                    self,
+                    level,
+                    "warning: remote '{}' points at '{}' instead of the configured '{}'",
+                    remote,
+                    actual,
+                    configured
+                ),
+            },
+            Event::UpToDate { .. } => {
+                self.tinted(Color::Green, || say!(self, level, "Already up to date"))
+            }
+            Event::MirrorUpdated {
+                created,
+                updated,
+                deleted,
+                ..
+            } => say!(
+                self,
+                level,
+                "Mirror updated: {} new, {} updated, {} deleted ref(s)",
+                created,
+                updated,
+                deleted
+            ),
+            Event::UpdateAvailable {
+                old, new, commits, ..
+            } => {
+                say!(
+                    self,
+                    level,
+                    "Update available: {} -> {} ({} new commit(s))",
+                    &old[..7],
+                    &new[..7],
+                    commits.len()
+                );
+                for commit in commits {
+                    say!(self, level, "  {} {}", &commit.id[..7], commit.summary);
+                }
+            }
+            Event::AwaitingApproval {
+                path,
+                old,
+                new,
+                commits,
+            } => {
+                say!(
+                    self,
+                    level,
+                    "Update of '{}' waiting for approval: {} -> {} ({} new commit(s))",
+                    path,
+                    &old[..7],
+                    &new[..7],
+                    commits.len()
+                );
+                for commit in commits {
+                    say!(self, level, "  {} {}", &commit.id[..7], commit.summary);
+                }
+            }
+            Event::Approved { path, commit } => say!(
+                self,
+                level,
+                "Approved the update of '{}' to {}, it is applied with the next sync",
+                path,
+                &commit[..7]
+            ),
+            Event::NothingAwaitingApproval => {
+                say!(self, level, "No update is waiting for approval")
+            }
+            Event::OwnershipApplied { files, .. } => {
+                say!(self, level, "Set owner and mode of {} path(s)", files)
+            }
+            Event::Archived {
+                commit,
+                destination,
+                ..
+            } => say!(
+                self,
+                level,
+                "Exported {} to '{}'",
+                &commit[..7],
+                destination
+            ),
+            Event::Propagated {
+                destination,
+                files: Some(files),
+                ..
+            } => say!(
+                self,
+                level,
+                "Propagated {} files to '{}'",
+                files,
+                destination
+            ),
+            Event::Propagated { destination, .. } => {
+                say!(self, level, "Propagated the checkout to '{}'", destination)
+            }
+            Event::Released {
+                commit,
+                release,
+                reused,
+                ..
+            } => match reused {
+                true => say!(
+                    self,
+                    level,
+                    "Made release '{}' of {} current again",
+                    release,
+                    &commit[..7]
+                ),
+                false => say!(self, level, "Released {} as '{}'", &commit[..7], release),
+            },
+            Event::Swapped { path, directory } => {
+                say!(self, level, "Switched '{}' over to '{}'", path, directory)
+            }
+            Event::WorktreeAdded { path, repository } => {
+                say!(
+                    self,
+                    level,
+                    "Added '{}' as a worktree of '{}'",
+                    path,
+                    repository
+                )
+            }
+            Event::Cleaned { files, .. } => {
+                say!(self, level, "Removed {} untracked file(s)", files)
+            }
+            Event::Maintained { before, after, .. } => say!(
+                self,
+                level,
+                "Repacked the object database from {} to {}",
+                HumanBytes(*before),
+                HumanBytes(*after)
+            ),
+            Event::MaintenanceFailed { message, .. } => {
+                say!(self, level, "warning: maintenance failed: {}", message)
+            }
+            Event::RemoteUnreachable { path } => say!(
+                self,
+                level,
+                "warning: the remote of '{}' is unreachable, checking less often until it is back",
+                path
+            ),
+            Event::RemoteReachable { path, failures } => say!(
+                self,
+                level,
+                "The remote of '{}' is reachable again after {} failed check(s)",
+                path,
+                failures
+            ),
+            Event::QuotaExceeded { path, usage, quota } => say!(
+                self,
+                level,
+                "warning: '{}' takes up {}, more than its quota of {}",
+                path,
+                HumanBytes(*usage),
+                HumanBytes(*quota)
+            ),
+            Event::Repairing {
+                path,
+                problem,
+                backup,
+            } => say!(
+                self,
+                level,
+                "Repository '{}' is corrupt ({}), moved it to '{}' to clone it again",
+                path,
+                problem,
+                backup
+            ),
+            Event::BackedUp {
+                directory, files, ..
+            } => say!(
+                self,
+                level,
+                "Backed up {} dirty file(s) to '{}'",
+                files,
+                directory
+            ),
+            Event::Stashed { .. } => say!(self, level, "Stashed local changes"),
+            Event::StashReapplied { .. } => say!(self, level, "Re-applied stashed local changes"),
+            Event::StashConflict { message, .. } => {
+                say!(
+                    self,
+                    level,
+                    "warning: could not re-apply stashed local changes, they are kept in the stash: {}",
+                    message
+                )
+            }
+            Event::TagSelected { tag, commit, .. } => {
+                say!(
+                    self,
+                    level,
+                    "Newest matching tag is '{}' at {}",
+                    tag,
+                    &commit[..7]
+                )
+            }
+            Event::Verified { commit, signer, .. } => say!(
+                self,
+                level,
+                "Commit {} has a good signature from '{}'",
+                &commit[..7],
+                signer
+            ),
+            Event::IntegrityVerified {
+                commit, objects, ..
+            } => say!(
+                self,
+                level,
+                "Verified {} objects of commit {}",
+                objects,
+                &commit[..7]
+            ),
+            Event::CheckoutStarted { target, .. } => {
+                say!(self, level, "Checking out '{}'...", target)
+            }
+            Event::CheckoutFinished { target, .. } => {
+                say!(self, level, "Checked out '{}' as detached HEAD", target)
+            }
+            Event::UpdateStarted {
+                branch,
+                upstream,
+                strategy,
+                ..
+            } => {
+                let text = match strategy {
+                    Strategy::HardReset => {
+                        format!("Resetting local '{}' to '{}'...", branch, upstream)
+                    }
+                    Strategy::FastForwardOnly => {
+                        format!("Fast-forwarding local '{}' to '{}'...", branch, upstream)
+                    }
+                    Strategy::Merge => format!("Merging '{}' into local '{}'...", upstream, branch),
+                };
+                self.tinted(Color::Yellow, || say!(self, level, "{}", text))
+            }
+            Event::UpdateFinished {
+                upstream, strategy, ..
+            } => {
+                let text = match strategy {
+                    Strategy::HardReset => format!("Local branch reset to '{}'", upstream),
+                    Strategy::FastForwardOnly | Strategy::Merge => {
+                        format!("Local branch updated to '{}'", upstream)
+                    }
+                };
+                self.tinted(Color::Green, || say!(self, level, "{}", text))
+            }
+            Event::Changelog { commits, .. } => {
+                say!(self, level, "Changes:");
+                for commit in commits.iter() {
+                    say!(
+                        self,
+                        level,
+                        "  {} {}: {}",
+                        &commit.id[..7],
+                        commit.author,
+                        commit.summary
+                    );
+                }
+            }
+            Event::Head {
+                commit, message, ..
+            } => say!(
+                self,
+                level,
+                "Current HEAD at commit {}: {}",
+                commit,
+                message
+            ),
+            Event::HookStarted { stage, command } => {
+                say!(self, level, "Running {} hook '{}'", stage, command)
+            }
+            Event::HookFinished { stdout, stderr, .. } => {
+                for line in stdout.lines() {
+                    say!(self, level, "{}", line);
+                }
+                for line in stderr.lines() {
+                    say!(self, Level::WARN, "{}", line);
+                }
+            }
+            Event::Error { message, .. } => say!(self, level, "error: {}", message),
+            Event::Summary { repositories } => {
+                say!(self, level, "Summary:");
+                for entry in repositories {
+                    let status = entry.status.describe();
+                    self.tinted(entry.status.color(), || match &entry.error {
+                        Some(error) => {
+                            say!(self, level, "  {}: {} ({})", entry.path, status, error)
+                        }
+                        None => say!(self, level, "  {}: {}", entry.path, status),
+                    });
+                }
+            }
+            Event::ServingMetrics { address } => {
+                say!(self, level, "Serving metrics on http://{}/metrics", address)
+            }
+            Event::ServingApi { address, secured } => {
+                say!(
+                    self,
+                    level,
+                    "Serving the control API on http://{}/api",
+                    address
+                );
+                if !secured {
+                    say!(
+                        self,
+                        Level::WARN,
+                        "warning: no API token configured, anyone who reaches it controls the updater"
+                    );
+                }
+            }
+            Event::ServingGrpc {
+                address,
+                tls,
+                secured,
+            } => {
+                let scheme = if *tls { "https" } else { "http" };
+                say!(
+                    self,
+                    level,
+                    "Serving the gRPC control service on {}://{}",
+                    scheme,
+                    address
+                );
+                if !secured {
+                    say!(
+                        self,
+                        Level::WARN,
+                        "warning: neither a gRPC token nor client certificates required, anyone who reaches it controls the updater"
+                    );
+                }
+            }
+            Event::ServingSocket { path } => {
+                say!(self, level, "Listening for ctl commands on '{}'", path)
+            }
+            Event::MqttConnected { broker, topic } => say!(
+                self,
+                level,
+                "Connected to {}, listening for commands on '{}'",
+                broker,
+                topic
+            ),
+            Event::MqttDisconnected { broker, message } => say!(
+                self,
+                level,
+                "warning: lost the connection to {}, reconnecting: {}",
+                broker,
+                message
+            ),
+            Event::MqttRefused { topic, message } => say!(
+                self,
+                level,
+                "warning: ignored a command on '{}': {}",
+                topic,
+                message
+            ),
+            Event::DaemonQueued {
+                action,
+                repositories,
+            } => say!(
+                self,
+                level,
+                "Asked the daemon to {} {} repository(ies)",
+                action,
+                repositories
+            ),
+            Event::DaemonStatus {
+                path,
+                branch,
+                commit,
+                paused,
+                healthy,
+            } => say!(
+                self,
+                level,
+                "  {} ({}): {}, {}{}",
+                path,
+                branch,
+                commit.map_or("not checked out", |commit| &commit[..7]),
+                if *healthy { "healthy" } else { "failing" },
+                if *paused { ", paused" } else { "" }
+            ),
+            Event::Listening { address, secured } => {
+                say!(self, level, "Listening for webhooks on {}", address);
+                if !secured {
+                    say!(
+                        self,
+                        Level::WARN,
+                        "warning: no webhook secret configured, requests are not verified"
+                    );
+                }
+            }
+            Event::WebhookTriggered { path } => {
+                say!(self, level, "Webhook received for '{}'", path)
+            }
+            Event::NextCheck { seconds } => say!(self, level, "Next check in {}s", seconds),
+            Event::Splaying { seconds } => {
+                say!(self, level, "Waiting {}s before the first check", seconds)
+            }
+            Event::ScheduleMissed { due, .. } => say!(
+                self,
+                level,
+                "Skipping the check missed at {}, waiting for the next one",
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(*due))
+            ),
+            Event::WaitingForLock { pid, .. } => match pid {
+                Some(pid) => say!(
+                    self,
+                    level,
+                    "Waiting for another run (pid {}) to finish...",
+                    pid
+                ),
+                None => say!(self, level, "Waiting for another run to finish..."),
+            },
+            Event::StaleLock { pid, .. } => say!(
+                self,
+                level,
+                "warning: previous run (pid {}) did not finish cleanly",
+                pid
+            ),
+            Event::Retrying {
+                attempt,
+                attempts,
+                delay_ms,
+                message,
+                ..
+            } => say!(
+                self,
+                level,
+                "warning: {} (attempt {}/{}), retrying in {:.1}s",
+                message,
+                attempt,
+                attempts,
+                *delay_ms as f64 / 1000.0
+            ),
+            Event::Pruned { reference, .. } => {
+                say!(
+                    self,
+                    level,
+                    "Pruned '{}', it is gone from the remote",
+                    reference
+                )
+            }
+            Event::Deepened { shallow, .. } => match shallow {
+                true => say!(self, level, "Fetched more history"),
+                false => say!(self, level, "Fetched the full history"),
+            },
+            Event::RolledBack { from, to, .. } => {
+                say!(
+                    self,
+                    level,
+                    "Rolled back from {} to {}",
+                    &from[..7],
+                    &to[..7]
+                )
+            }
+            Event::RollbackHeld { commit, .. } => say!(
+                self,
+                level,
+                "Not updating to {} again, it was rolled back",
+                &commit[..7]
+            ),
+            Event::ChecksPending { commit, .. } => say!(
+                self,
+                level,
+                "Waiting for the checks of {} to pass...",
+                &commit[..7]
+            ),
+            Event::ChecksPassed { commit, .. } => {
+                say!(self, level, "The checks of {} passed", &commit[..7])
+            }
+            Event::UpdateDeferred { commit, opens, .. } => match opens {
+                Some(opens) => say!(
+                    self,
+                    level,
+                    "Holding back the update to {} until the maintenance window opens at {}",
+                    &commit[..7],
+                    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(*opens))
+                ),
+                None => say!(
+                    self,
+                    level,
+                    "Holding back the update to {}, no maintenance window opens within a week",
+                    &commit[..7]
+                ),
+            },
+            Event::RolloutWaiting { commit, until, .. } => say!(
+                self,
+                level,
+                "Holding back the update to {} while it soaks on the canaries, until {}",
+                &commit[..7],
+                humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(*until))
+            ),
+            Event::RolloutHalted { commit, hosts, .. } => say!(
+                self,
+                level,
+                "Holding back the update to {}, it failed on {}",
+                &commit[..7],
+                hosts.join(", ")
+            ),
+            Event::LeaderHooksSkipped { leader, .. } => match leader {
+                Some(leader) => say!(
+                    self,
+                    level,
+                    "Not running the leader hooks, {} is the leader",
+                    leader
+                ),
+                None => say!(
+                    self,
+                    level,
+                    "Not running the leader hooks, another host is the leader"
+                ),
+            },
+            Event::UpdateFiltered {
+                commit, commits, ..
+            } => say!(
+                self,
+                level,
+                "Holding back {} new commit(s) up to {}, none of them passed the filter",
+                commits,
+                &commit[..7]
+            ),
+            Event::InvalidSetting { message } => say!(self, level, "error: {}", message),
+            Event::SettingsValid { repositories } => say!(
+                self,
+                level,
+                "Settings are valid ({} repositories)",
+                repositories
+            ),
+            Event::SettingsWritten { file } => say!(self, level, "Wrote settings to '{}'", file),
+            Event::SettingsReloaded { repositories } => say!(
+                self,
+                level,
+                "Reloaded settings ({} repositories)",
+                repositories
+            ),
+            Event::ReloadFailed { message } => say!(
+                self,
+                level,
+                "error: could not reload settings, keeping the current ones: {}",
+                message
+            ),
+            Event::RestartRequired { setting } => say!(
+                self,
+                level,
+                "warning: changes to {} take effect after a restart",
+                setting
+            ),
+            Event::RepositoryAdded { path } => say!(self, level, "Added repository '{}'", path),
+            Event::RepositoryChanged { path } => {
+                say!(self, level, "Settings of repository '{}' changed", path)
+            }
+            Event::RepositoryRemoved { path, deleted } => match deleted {
+                true => say!(
+                    self,
+                    level,
+                    "Removed repository '{}' and deleted its checkout",
+                    path
+                ),
+                false => say!(self, level, "Stopped syncing removed repository '{}'", path),
+            },
+            Event::SelfUpToDate { version } => {
+                say!(self, level, "autoupdate {} is the latest release", version)
+            }
+            Event::SelfUpdateAvailable { current, latest } => say!(
+                self,
+                level,
+                "autoupdate {} is available (running {})",
+                latest,
+                current
+            ),
+            Event::SelfUpdateVerified { asset, signer } => {
+                say!(self, level, "Signature of '{}' made by {}", asset, signer)
+            }
+            Event::SelfUpdated { from, to } => {
+                say!(self, level, "Updated autoupdate from {} to {}", from, to)
+            }
+            Event::History { path, entries } => {
+                say!(self, level, "History of '{}':", path);
+                for entry in entries.iter() {
+                    let time = UNIX_EPOCH + Duration::from_secs(entry.timestamp);
+                    let commits = match (&entry.old, &entry.new) {
+                        (Some(old), Some(new)) if old != new => {
+                            format!("{:.7} -> {:.7}", old, new)
+                        }
+                        (_, Some(new)) => format!("{:.7}", new),
+                        (Some(old), None) => format!("{:.7}", old),
+                        (None, None) => "-".to_string(),
+                    };
+                    let error = entry
+                        .error
+                        .as_ref()
+                        .map(|error| format!(" ({})", error))
+                        .unwrap_or_default();
+                    say!(
+                        self,
+                        level,
+                        "  {}  {:<16}  {}  {}ms{}",
+                        humantime::format_rfc3339_seconds(time),
+                        entry.outcome.describe(),
+                        commits,
+                        entry.duration_ms,
+                        error
+                    );
+                }
+            }
+            Event::NotifyFailed { message, .. } => say!(self, level, "warning: {}", message),
+            Event::Paused { path, paused } => match paused {
+                true => say!(self, level, "Paused scheduled checks of '{}'", path),
+                false => say!(self, level, "Resumed scheduled checks of '{}'", path),
+            },
+            Event::Interrupted => say!(
+                self,
+                level,
+                "Interrupted, stopping after cleaning up (press Ctrl-C again to quit now)"
+            ),
+            Event::ShuttingDown => say!(self, level, "Shutting down"),
+        }
+    }
+}
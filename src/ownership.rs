@@ -0,0 +1,102 @@
+use crate::config::{Ownership, Repo};
+use crate::error::Result;
+use crate::output::Output;
+use std::path::Path;
+
+/// Gives every file below `root` the configured owner, group and mode, going
+/// on past failures and reporting them together at the end.
+#[cfg(unix)]
+pub fn apply(repo: &Repo, root: &Path, output: &Output) -> Result<()> {
+    use crate::error::Error;
+    use crate::output::{Event, ProgressKind};
+    use std::fs;
+    use std::os::unix::fs::{lchown, PermissionsExt};
+
+    let Some(ownership) = &repo.ownership else {
+        return Ok(());
+    };
+    let (uid, gid) = ids(ownership)?;
+    let file_mode = ownership.file_mode()?;
+    let dir_mode = ownership.dir_mode()?;
+
+    // The git directory keeps the owner of whoever runs the updates
+    let mut paths = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if dir == root && entry.file_name() == ".git" {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                pending.push(path.clone());
+            }
+            paths.push(path);
+        }
+    }
+
+    let progress = output.progress(&repo.path, ProgressKind::Ownership);
+    let mut failures = Vec::new();
+    for (done, path) in paths.iter().enumerate() {
+        let result = (|| -> std::io::Result<()> {
+            if uid.is_some() || gid.is_some() {
+                lchown(path, uid, gid)?;
+            }
+            let metadata = fs::symlink_metadata(path)?;
+            let mode = match metadata.file_type() {
+                kind if kind.is_symlink() => None,
+                kind if kind.is_dir() => dir_mode,
+                // Executables stay executable for whoever may read them
+                _ => file_mode.map(|mode| match metadata.permissions().mode() & 0o111 {
+                    0 => mode,
+                    _ => mode | ((mode & 0o444) >> 2),
+                }),
+            };
+            if let Some(mode) = mode {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+        progress.set(done + 1, paths.len());
+    }
+    progress.finish();
+
+    output.emit(Event::OwnershipApplied {
+        path: &repo.path,
+        files: paths.len() - failures.len(),
+        failed: failures.len(),
+    });
+    match failures.as_slice() {
+        [] => Ok(()),
+        [first, ..] => Err(Error::Io(std::io::Error::other(format!(
+            "could not set ownership of {} path(s), first {}",
+            failures.len(),
+            first
+        )))),
+    }
+}
+
+/// The uid and gid of the configured user and group.
+#[cfg(unix)]
+pub(crate) fn ids(ownership: &Ownership) -> Result<(Option<u32>, Option<u32>)> {
+    use crate::users;
+
+    Ok((
+        ownership.user.as_deref().map(users::uid).transpose()?,
+        ownership.group.as_deref().map(users::gid).transpose()?,
+    ))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn ids(_ownership: &Ownership) -> Result<(Option<u32>, Option<u32>)> {
+    Ok((None, None))
+}
+
+#[cfg(not(unix))]
+pub fn apply(_repo: &Repo, _root: &Path, _output: &Output) -> Result<()> {
+    Ok(())
+}
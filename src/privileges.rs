@@ -0,0 +1,91 @@
+use crate::error::{Error, Result};
+#[cfg(unix)]
+use crate::users::{self, Account};
+
+/// The account and group named by `run_as`.
+#[cfg(unix)]
+fn resolve(run_as: &str) -> Result<(Account, u32)> {
+    let (user, group) = match run_as.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (run_as, None),
+    };
+    let account = users::account(user)?;
+    let gid = match group {
+        Some(group) => users::gid(group)?,
+        None => account.gid,
+    };
+    Ok((account, gid))
+}
+
+/// Fails when `run_as` names an unknown user or group.
+#[cfg(unix)]
+pub(crate) fn check(run_as: &str) -> Result<()> {
+    resolve(run_as).map(drop)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn check(run_as: &str) -> Result<()> {
+    drop_to(run_as)
+}
+
+/// Switches the process to the user, and optionally group, in `run_as`, given
+/// as `user` or `user:group`. Files created afterwards belong to that user.
+#[cfg(unix)]
+pub fn drop_to(run_as: &str) -> Result<()> {
+    use std::ffi::CString;
+    use std::{env, io};
+
+    let (account, gid) = resolve(run_as)?;
+
+    let euid = unsafe { libc::geteuid() };
+    if euid == account.uid && unsafe { libc::getegid() } == gid {
+        return Ok(());
+    }
+    if euid != 0 {
+        return Err(Error::Config(format!(
+            "run_as '{}' requires starting as root",
+            run_as
+        )));
+    }
+
+    let failed = |call: &str| {
+        Error::Io(io::Error::other(format!(
+            "could not switch to '{}', {} failed: {}",
+            run_as,
+            call,
+            io::Error::last_os_error()
+        )))
+    };
+    // Groups go first, changing them is not allowed anymore once the uid changed
+    let name = CString::new(account.name.as_str())
+        .map_err(|_| Error::Config(format!("invalid user name '{}'", account.name)))?;
+    if unsafe { libc::initgroups(name.as_ptr(), gid as _) } != 0 {
+        return Err(failed("initgroups"));
+    }
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(failed("setgid"));
+    }
+    if unsafe { libc::setuid(account.uid) } != 0 {
+        return Err(failed("setuid"));
+    }
+    if unsafe { libc::setuid(0) } == 0 {
+        return Err(Error::Io(io::Error::other(format!(
+            "could not switch to '{}', root privileges could be regained",
+            run_as
+        ))));
+    }
+
+    // Git reads the global configuration and credentials from the home directory
+    env::set_var("HOME", &account.home);
+    env::set_var("USER", &account.name);
+    env::set_var("LOGNAME", &account.name);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_to(run_as: &str) -> Result<()> {
+    Err(Error::Config(format!(
+        "run_as '{}' is only supported on Unix",
+        run_as
+    )))
+}
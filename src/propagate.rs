@@ -0,0 +1,268 @@
+use crate::changes::Changes;
+use crate::clean;
+use crate::config::{Propagate, Repo};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::state::State;
+use git2::{Oid, Repository};
+use glob::Pattern;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::instrument;
+
+/// Files and symlinks under `root`, relative to it, leaving out git
+/// directories and excluded paths.
+fn files(root: &Path, excludes: &[Pattern]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_name() == ".git" || clean::protected(excludes, &path) {
+                continue;
+            }
+            match entry.file_type()?.is_dir() {
+                true => dirs.push(path),
+                false => files.push(path),
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(unix)]
+fn copy_link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn copy_link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Copies `path` from `source` to `destination` unless it is there with the
+/// same size and modification time already. Returns whether it copied.
+fn copy(source: &Path, destination: &Path, path: &Path) -> Result<bool> {
+    let from = source.join(path);
+    let to = destination.join(path);
+    let metadata = fs::symlink_metadata(&from)?;
+    let link = metadata.file_type().is_symlink();
+    if let Ok(existing) = fs::symlink_metadata(&to) {
+        let same = match link {
+            true => {
+                existing.file_type().is_symlink() && fs::read_link(&to)? == fs::read_link(&from)?
+            }
+            false => {
+                existing.is_file()
+                    && existing.len() == metadata.len()
+                    && existing.modified()? == metadata.modified()?
+            }
+        };
+        if same {
+            return Ok(false);
+        }
+        if existing.is_dir() {
+            fs::remove_dir_all(&to)?;
+        }
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Renamed into place, so the destination never serves half a file
+    let mut staged = to.clone().into_os_string();
+    staged.push(".autoupdate-partial");
+    let staged = PathBuf::from(staged);
+    let _ = fs::remove_file(&staged);
+    if link {
+        copy_link(&fs::read_link(&from)?, &staged)?;
+    } else {
+        fs::copy(&from, &staged)?;
+        File::options()
+            .write(true)
+            .open(&staged)?
+            .set_modified(metadata.modified()?)?;
+    }
+    fs::rename(&staged, &to)?;
+    Ok(true)
+}
+
+/// Deletes `path` from `destination`, along with the directories that leaves empty.
+fn delete(destination: &Path, path: &Path) -> Result<bool> {
+    match fs::remove_file(destination.join(path)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+    for parent in path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() || fs::remove_dir(destination.join(parent)).is_err() {
+            break;
+        }
+    }
+    Ok(true)
+}
+
+/// Makes the local `destination` a copy of the working tree, or with
+/// `changes` applies just those. Returns the number of files copied or deleted.
+fn local(
+    source: &Path,
+    destination: &Path,
+    excludes: &[Pattern],
+    changes: Option<&Changes>,
+) -> Result<usize> {
+    let mut touched = 0;
+    match changes {
+        Some(changes) => {
+            for path in changes.added.iter().chain(&changes.modified).map(Path::new) {
+                // The sparse checkout keeps some paths off disk
+                let present = fs::symlink_metadata(source.join(path)).is_ok_and(|m| !m.is_dir());
+                if present && !clean::protected(excludes, path) && copy(source, destination, path)?
+                {
+                    touched += 1;
+                }
+            }
+            for path in changes.deleted.iter().map(Path::new) {
+                if !clean::protected(excludes, path) && delete(destination, path)? {
+                    touched += 1;
+                }
+            }
+        }
+        None => {
+            fs::create_dir_all(destination)?;
+            let wanted = files(source, excludes)?;
+            for path in &wanted {
+                if copy(source, destination, path)? {
+                    touched += 1;
+                }
+            }
+            let wanted: HashSet<PathBuf> = wanted.into_iter().collect();
+            for path in files(destination, excludes)? {
+                if !wanted.contains(&path) && delete(destination, &path)? {
+                    touched += 1;
+                }
+            }
+        }
+    }
+    Ok(touched)
+}
+
+/// Sends the working tree to `destination` by running rsync, or with
+/// `changes` just those paths, deleting the ones missing from the tree.
+fn rsync(
+    source: &Path,
+    destination: &str,
+    propagate: &Propagate,
+    changes: Option<&Changes>,
+) -> Result<()> {
+    let mut command = Command::new("rsync");
+    command.args(["-a", "--exclude=.git"]);
+    for pattern in &propagate.excludes {
+        // Anchored to the top of the tree like the glob patterns
+        command.arg(format!("--exclude=/{}", pattern));
+    }
+    let list = match changes {
+        Some(changes) => {
+            command.args(["--from0", "--files-from=-", "--delete-missing-args"]);
+            let excludes = propagate.excludes()?;
+            let mut list = Vec::new();
+            for path in changes.paths() {
+                if !clean::protected(&excludes, Path::new(&path)) {
+                    list.extend_from_slice(path.as_bytes());
+                    list.push(0);
+                }
+            }
+            Some(list)
+        }
+        None => {
+            command.arg("--delete");
+            None
+        }
+    };
+    // The trailing separator sends what is in the tree rather than the tree itself
+    let mut from = source.as_os_str().to_os_string();
+    from.push("/");
+    let mut child = command
+        .arg(from)
+        .arg(destination)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::Config("rsync is not installed".to_string()),
+            _ => Error::Io(e),
+        })?;
+    let mut stdin = child.stdin.take().unwrap();
+    if let Some(list) = list {
+        stdin.write_all(&list)?;
+    }
+    drop(stdin);
+
+    let result = child.wait_with_output()?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(Error::Io(io::Error::other(format!(
+            "rsync failed: {}",
+            stderr.lines().last().unwrap_or_default().trim()
+        ))));
+    }
+    Ok(())
+}
+
+/// Copies the working tree at `head` to the destination unless it was
+/// already propagated there at that commit. With `changed_only` only the
+/// paths changed since the last propagation are copied over; the first run,
+/// and any after the destination went missing, copies everything.
+#[instrument(skip_all, fields(path = %repo.path))]
+pub fn run(
+    repo: &Repo,
+    propagate: &Propagate,
+    repository: &Repository,
+    head: Oid,
+    output: &Output,
+) -> Result<()> {
+    let source = Path::new(&repo.path);
+    let destination = &propagate.destination;
+    let remote = propagate.remote();
+    let mut state = State::load(repository)?;
+    let last = state
+        .propagated
+        .as_deref()
+        .filter(|_| state.propagated_to.as_ref() == Some(destination))
+        .filter(|_| remote || Path::new(destination).exists())
+        .and_then(|commit| Oid::from_str(commit).ok());
+    if last == Some(head) {
+        return Ok(());
+    }
+
+    // The last commit may be gone from a shallow history, which takes a full copy then
+    let changes = last
+        .filter(|_| propagate.changed_only)
+        .and_then(|last| Changes::between(repository, last, head).ok());
+    let files = match remote {
+        true => {
+            rsync(source, destination, propagate, changes.as_ref())?;
+            None
+        }
+        false => Some(local(
+            source,
+            Path::new(destination),
+            &propagate.excludes()?,
+            changes.as_ref(),
+        )?),
+    };
+
+    state.propagated = Some(head.to_string());
+    state.propagated_to = Some(destination.clone());
+    state.save(repository)?;
+    output.emit(Event::Propagated {
+        path: &repo.path,
+        destination,
+        files,
+    });
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use crate::config::{Releases, Repo};
+use crate::error::Result;
+use crate::output::{Event, Output};
+use crate::{ownership, sparse, swap};
+use git2::build::CheckoutBuilder;
+use git2::{Oid, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// File in every release naming the commit it holds.
+const REVISION: &str = "REVISION";
+
+fn revision(release: &Path) -> Option<String> {
+    fs::read_to_string(release.join(REVISION))
+        .ok()
+        .map(|revision| revision.trim().to_string())
+}
+
+/// Release directories, oldest first; their timestamp names sort by age.
+fn list(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut releases = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    releases.sort();
+    Ok(releases)
+}
+
+/// Capistrano style name such as `20240102150405`.
+fn timestamp() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect()
+}
+
+/// Points `current` at a release of `commit`, exporting one when none of the
+/// kept releases holds it, then removes the oldest releases past `keep`.
+pub fn publish(
+    repo: &Repo,
+    releases: &Releases,
+    repository: &Repository,
+    commit: Oid,
+    output: &Output,
+) -> Result<()> {
+    let root = Path::new(&releases.path);
+    let directory = root.join("releases");
+    let current = root.join("current");
+    let commit_id = commit.to_string();
+    let live = fs::canonicalize(&current).ok();
+    if live
+        .as_deref()
+        .and_then(revision)
+        .is_some_and(|revision| revision == commit_id)
+    {
+        return Ok(());
+    }
+
+    let existing = list(&directory)?
+        .into_iter()
+        .rev()
+        .find(|release| revision(release).as_deref() == Some(commit_id.as_str()));
+    let reused = existing.is_some();
+    let release = match existing {
+        Some(release) => release,
+        None => {
+            let mut name = timestamp();
+            while directory.join(&name).exists() {
+                name.push('0');
+            }
+            let release = directory.join(name);
+            fs::create_dir_all(&release)?;
+
+            let mut cb = CheckoutBuilder::new();
+            sparse::restrict(repo, &mut cb);
+            cb.force()
+                .target_dir(&fs::canonicalize(&release)?)
+                .update_index(false);
+            let tree = repository.find_commit(commit)?.tree()?;
+            repository.checkout_tree(tree.as_object(), Some(&mut cb))?;
+            fs::write(release.join(REVISION), format!("{}\n", commit_id))?;
+            ownership::apply(repo, &release, output)?;
+            release
+        }
+    };
+
+    let name = release.file_name().unwrap_or_default();
+    swap::flip(&current, &Path::new("releases").join(name))?;
+    output.emit(Event::Released {
+        path: &repo.path,
+        commit: commit_id,
+        release: release.display().to_string(),
+        reused,
+    });
+
+    let kept = list(&directory)?;
+    let surplus = kept.len().saturating_sub(releases.keep() as usize);
+    for old in kept.into_iter().take(surplus) {
+        if old != release {
+            fs::remove_dir_all(&old)?;
+        }
+    }
+    Ok(())
+}
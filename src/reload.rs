@@ -0,0 +1,61 @@
+use crate::Command;
+use autoupdate::{Error, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Changes this close to each other are taken as one edit.
+const SETTLE: Duration = Duration::from_millis(300);
+
+fn failed(e: notify::Error) -> Error {
+    Error::Io(std::io::Error::other(e))
+}
+
+/// Whether a changed path is a source file or a file in a source directory.
+fn concerns(sources: &[PathBuf], path: &Path) -> bool {
+    sources
+        .iter()
+        .any(|source| path == source || path.parent() == Some(source))
+}
+
+/// Sends a reload whenever the settings file or anything it includes changes,
+/// until the returned watcher is dropped.
+pub fn watch(sources: &[PathBuf], commands: Sender<Command>) -> Result<RecommendedWatcher> {
+    let (tx, rx) = mpsc::channel();
+    let relevant = sources.to_vec();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if !event.kind.is_access() && event.paths.iter().any(|path| concerns(&relevant, path)) {
+                let _ = tx.send(());
+            }
+        }
+    })
+    .map_err(failed)?;
+
+    // Editors save by renaming a new file over the old one, which only the directory sees
+    let mut directories: Vec<&Path> = Vec::new();
+    for source in sources {
+        let directory = match source.is_dir() {
+            true => source.as_path(),
+            false => source.parent().unwrap_or(Path::new("/")),
+        };
+        if !directories.contains(&directory) {
+            watcher
+                .watch(directory, RecursiveMode::NonRecursive)
+                .map_err(failed)?;
+            directories.push(directory);
+        }
+    }
+
+    thread::spawn(move || {
+        while rx.recv().is_ok() {
+            while rx.recv_timeout(SETTLE).is_ok() {}
+            if commands.send(Command::Reload).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(watcher)
+}
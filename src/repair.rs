@@ -0,0 +1,115 @@
+use crate::config::Repo;
+use crate::error::{Error, Result};
+use git2::{ErrorClass, ErrorCode, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, instrument};
+
+/// Errors libgit2 raises on damaged objects, packs, refs or index files,
+/// as opposed to network and configuration problems.
+fn suspicious(e: &Error) -> bool {
+    let Error::Git(e) = e else {
+        return false;
+    };
+    matches!(
+        e.class(),
+        ErrorClass::Odb
+            | ErrorClass::Zlib
+            | ErrorClass::Object
+            | ErrorClass::Index
+            | ErrorClass::Tree
+            | ErrorClass::Indexer
+            | ErrorClass::Reference
+            | ErrorClass::Repository
+    )
+}
+
+/// Reads every object, which makes libgit2 inflate it and check its hash,
+/// then looks for the objects the tree of HEAD needs and reads the index,
+/// much like `git fsck` does.
+#[instrument(skip_all)]
+fn verify(repository: &Repository) -> Result<()> {
+    let odb = repository.odb()?;
+    let mut failed = None;
+    let walked = odb.foreach(|id| match odb.read(*id) {
+        Ok(_) => true,
+        Err(e) => {
+            failed = Some(e);
+            false
+        }
+    });
+    if let Some(e) = failed {
+        return Err(e.into());
+    }
+    walked?;
+
+    let tree = repository.head()?.peel_to_tree()?;
+    let mut missing = None;
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        let present = match entry.kind() {
+            Some(ObjectType::Blob) | Some(ObjectType::Tree) => odb.exists(entry.id()),
+            _ => true,
+        };
+        if present {
+            return TreeWalkResult::Ok;
+        }
+        missing = Some(format!("{}{}", dir, entry.name().unwrap_or_default()));
+        TreeWalkResult::Abort
+    })?;
+    if let Some(path) = missing {
+        return Err(Error::Git(git2::Error::new(
+            git2::ErrorCode::NotFound,
+            ErrorClass::Odb,
+            format!("object of '{}' is missing", path),
+        )));
+    }
+
+    if !repository.is_bare() {
+        repository.index()?;
+    }
+    Ok(())
+}
+
+/// Whether `e` comes from a damaged repository at the configured path: one
+/// that no longer opens, or one that does but fails verification.
+pub(crate) fn corrupt(repo: &Repo, e: &Error) -> bool {
+    let path = Path::new(&repo.path);
+    match Repository::open(path) {
+        // A directory that never was a repository is left alone, and so is
+        // one that cannot be opened for its ownership, permissions or the like
+        Err(open) => {
+            (path.join(".git").exists() || path.join("objects").is_dir())
+                && open.code() != ErrorCode::Owner
+                && matches!(
+                    open.class(),
+                    ErrorClass::Odb
+                        | ErrorClass::Zlib
+                        | ErrorClass::Repository
+                        | ErrorClass::Reference
+                )
+        }
+        Ok(_) if !suspicious(e) => false,
+        Ok(repository) => match verify(&repository) {
+            Ok(()) => false,
+            Err(problem) => {
+                debug!(%problem, "repository failed verification");
+                true
+            }
+        },
+    }
+}
+
+/// Moves the repository at the configured path to
+/// `<path>.corrupt/<timestamp>`, out of the way of a fresh clone.
+pub(crate) fn set_aside(repo: &Repo) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let directory = PathBuf::from(format!("{}.corrupt", repo.path));
+    fs::create_dir_all(&directory)?;
+    let backup = directory.join(timestamp.to_string());
+    fs::rename(&repo.path, &backup)?;
+    Ok(backup)
+}
@@ -0,0 +1,163 @@
+use autoupdate::config::{self, Repo};
+use autoupdate::{Error, Result, SyncOutcome, Updater};
+use sentry::protocol::{Context, Event, Exception, Level, Map, Value};
+use sentry::Hub;
+use std::cell::Cell;
+use std::sync::Arc;
+use tracing::span;
+use tracing::Subscriber;
+use tracing_subscriber::layer::{self, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+thread_local! {
+    static PHASE: Cell<&'static str> = const { Cell::new("sync") };
+}
+
+/// Remembers the last step of a sync each thread went into, which is the
+/// step a failure of the sync happened in.
+pub(crate) struct Phases;
+
+impl<S> Layer<S> for Phases
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        if let Some(metadata) = ctx.metadata(id) {
+            PHASE.with(|phase| phase.set(metadata.name()));
+        }
+    }
+}
+
+pub(crate) fn init(settings: &config::Sentry) -> Result<sentry::ClientInitGuard> {
+    let dsn = match settings.dsn()? {
+        Some(dsn) => Some(
+            dsn.parse()
+                .map_err(|e| Error::Config(format!("invalid sentry dsn: {}", e)))?,
+        ),
+        None => None,
+    };
+    let mut options = sentry::ClientOptions::new();
+    options.dsn = dsn;
+    options.release = sentry::release_name!();
+    options.environment = settings.environment.clone().map(Into::into);
+    options.before_breadcrumb = Some(Arc::new(|mut breadcrumb| {
+        breadcrumb.message = breadcrumb.message.as_deref().map(scrub);
+        for value in breadcrumb.data.values_mut() {
+            if let Value::String(text) = value {
+                *text = scrub(text);
+            }
+        }
+        Some(breadcrumb)
+    }));
+    options.server_name = hostname::get()
+        .ok()
+        .map(|host| host.to_string_lossy().into_owned().into());
+    Ok(sentry::init(options))
+}
+
+fn class(e: &Error) -> &'static str {
+    match e {
+        Error::Config(_) => "Config",
+        Error::Git(_) => "Git",
+        Error::Io(_) => "Io",
+        Error::Auth(_) => "Auth",
+        Error::Hook(_) => "Hook",
+        Error::Refused(_) => "Refused",
+        Error::Notify(_) => "Notify",
+        Error::Locked(_) => "Locked",
+        Error::Timeout(_) => "Timeout",
+        Error::DiskSpace(_) => "DiskSpace",
+        Error::Interrupted(_) => "Interrupted",
+    }
+}
+
+/// `text` without the credentials the URLs in it may carry.
+fn scrub(text: &str) -> String {
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("://") {
+        let (head, tail) = rest.split_at(start + 3);
+        scrubbed.push_str(head);
+        let authority = tail
+            .find(|c: char| c == '/' || c == '\'' || c == '"' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        rest = match tail[..authority].rfind('@') {
+            Some(at) => &tail[at + 1..],
+            None => tail,
+        };
+    }
+    scrubbed.push_str(rest);
+    scrubbed
+}
+
+/// The settings of `repo` that tell how it is updated, leaving out anything
+/// secret.
+fn settings(repo: &Repo) -> Map<String, Value> {
+    let mut settings = Map::new();
+    settings.insert("url".into(), scrub(&repo.url).into());
+    settings.insert("path".into(), repo.path.clone().into());
+    settings.insert("branch".into(), repo.branch.clone().into());
+    settings.insert("strategy".into(), format!("{:?}", repo.strategy).into());
+    if let Some(depth) = repo.depth {
+        settings.insert("depth".into(), depth.into());
+    }
+    for (name, set) in [
+        ("submodules", repo.submodules),
+        ("lfs", repo.lfs),
+        ("atomic", repo.atomic),
+        ("mirror", repo.mirror),
+        ("offline", repo.offline),
+        ("dry_run", repo.dry_run),
+    ] {
+        settings.insert(name.into(), set.into());
+    }
+    settings
+}
+
+fn capture(repo: &Repo, e: &Error) {
+    let class = class(e);
+    let mut event = Event {
+        level: Level::Error,
+        message: Some(format!("{} failed to update", repo.path)),
+        exception: vec![Exception {
+            ty: class.to_string(),
+            value: Some(scrub(&e.to_string())),
+            ..Default::default()
+        }]
+        .into(),
+        ..Default::default()
+    };
+    event.tags.insert("repository".into(), repo.path.clone());
+    event
+        .tags
+        .insert("phase".into(), PHASE.with(Cell::get).to_string());
+    event.tags.insert("error.class".into(), class.to_string());
+    if let Error::Git(e) = e {
+        event
+            .tags
+            .insert("git.class".into(), format!("{:?}", e.class()));
+        event
+            .tags
+            .insert("git.code".into(), format!("{:?}", e.code()));
+    }
+    event
+        .contexts
+        .insert("repository".into(), Context::Other(settings(repo)));
+    sentry::capture_event(event);
+}
+
+/// Syncs with breadcrumbs of its own, reporting a failure along with the
+/// step it happened in.
+pub(crate) fn sync(updater: &mut Updater) -> Result<SyncOutcome> {
+    Hub::run(Arc::new(Hub::new_from_top(Hub::main())), || {
+        PHASE.with(|phase| phase.set("sync"));
+        let result = updater.sync();
+        if let Err(e) = &result {
+            // An unreachable remote is reported once until it is back
+            if !(e.is_network() && updater.unreachable() > 1) {
+                capture(updater.repo(), e);
+            }
+        }
+        result
+    })
+}
@@ -0,0 +1,256 @@
+use crate::config::{IpFamily, Network, Repo};
+use crate::error::Result;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Used when the repository sets no connect timeout of its own.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest request head the proxy reads before giving up on a client.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Host and port an https URL connects to.
+pub(crate) fn endpoint(url: &str) -> Option<(String, u16)> {
+    let rest = url.strip_prefix("https://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, rest)| rest);
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => 443,
+    };
+    match host.is_empty() {
+        true => None,
+        false => Some((host.to_ascii_lowercase(), port)),
+    }
+}
+
+/// What a proxy lets its clients connect to, and how.
+#[derive(Clone, PartialEq)]
+struct Target {
+    network: Network,
+    host: String,
+    port: u16,
+    connect_timeout: Duration,
+}
+
+impl Target {
+    fn of(repo: &Repo) -> Option<Target> {
+        let network = repo.network.clone()?;
+        let (host, port) = endpoint(&repo.url)?;
+        Some(Target {
+            network,
+            host,
+            port,
+            connect_timeout: repo
+                .timeouts
+                .connect()
+                .ok()
+                .flatten()
+                .unwrap_or(CONNECT_TIMEOUT),
+        })
+    }
+
+    /// The remote host, and the hosts given addresses for submodules on other hosts.
+    fn allows(&self, host: &str) -> bool {
+        host.eq_ignore_ascii_case(&self.host)
+            || self
+                .network
+                .hosts
+                .iter()
+                .any(|entry| entry.name.eq_ignore_ascii_case(host))
+    }
+
+    /// The configured addresses of `host` or the ones it resolves to, of the
+    /// wanted family only.
+    fn addresses(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let configured = self
+            .network
+            .hosts
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(host));
+        let addresses: Vec<SocketAddr> = match configured {
+            Some(entry) => entry
+                .addresses()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect(),
+            None => (host, port).to_socket_addrs()?.collect(),
+        };
+        let addresses: Vec<SocketAddr> = addresses
+            .into_iter()
+            .filter(|address| match self.network.ip {
+                IpFamily::Any => true,
+                IpFamily::V4 => address.is_ipv4(),
+                IpFamily::V6 => address.is_ipv6(),
+            })
+            .collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no {} address", host, self.network.ip.describe()),
+            ));
+        }
+        Ok(addresses)
+    }
+
+    fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut failure = None;
+        for address in self.addresses(host, port)? {
+            debug!(host, %address, "connecting");
+            match TcpStream::connect_timeout(&address, self.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => failure = Some(e),
+            }
+        }
+        Err(failure.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+    }
+}
+
+static PROXIES: Mutex<Vec<(Target, SocketAddr)>> = Mutex::new(Vec::new());
+
+/// Starts the loopback proxy that connects to the remote of `repo` the way
+/// its network settings say, unless one is running already. libgit2 leaves
+/// resolving and connecting to the operating system, but goes through a
+/// proxy for https, with TLS and authentication still end to end.
+pub fn configure(repo: &Repo) -> Result<()> {
+    let Some(target) = Target::of(repo) else {
+        return Ok(());
+    };
+    let mut proxies = PROXIES.lock().unwrap_or_else(|e| e.into_inner());
+    if proxies.iter().any(|(running, _)| *running == target) {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let address = listener.local_addr()?;
+    debug!(%address, host = %target.host, "started resolving proxy");
+    proxies.push((target.clone(), address));
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            let target = target.clone();
+            thread::spawn(move || serve(client, &target));
+        }
+    });
+    Ok(())
+}
+
+/// URL of the proxy `configure` started for `repo`.
+pub fn proxy(repo: &Repo) -> Option<String> {
+    let target = Target::of(repo)?;
+    let proxies = PROXIES.lock().unwrap_or_else(|e| e.into_inner());
+    proxies
+        .iter()
+        .find(|(running, _)| *running == target)
+        .map(|(_, address)| format!("http://{}", address))
+}
+
+fn reply(client: &mut TcpStream, status: &str, message: &str) {
+    let _ = write!(
+        client,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    );
+}
+
+/// Reads up to the end of the request head, returning it with whatever
+/// followed it in the same reads.
+fn read_head(client: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 4096];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let read = client.read(&mut buffer)?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Ok(head)
+}
+
+/// Tunnels the `CONNECT` requests libgit2 sends for https remotes. It
+/// connects to plain http servers directly even with a proxy set, which is
+/// why the settings only allow https.
+fn serve(mut client: TcpStream, target: &Target) {
+    let Ok(head) = read_head(&mut client) else {
+        return;
+    };
+    let line = String::from_utf8_lossy(&head);
+    let mut words = line.split_whitespace();
+    let (method, authority) = (
+        words.next().unwrap_or_default(),
+        words.next().unwrap_or_default(),
+    );
+    let requested = match method {
+        "CONNECT" if authority.contains(':') => endpoint(&format!("https://{}", authority)),
+        _ => None,
+    };
+    let Some((host, port)) = requested.filter(|(host, _)| target.allows(host)) else {
+        warn!(
+            method,
+            authority, "refusing to proxy a request for another host"
+        );
+        reply(
+            &mut client,
+            "403 Forbidden",
+            "only the hosts of the repository are proxied",
+        );
+        return;
+    };
+
+    let mut upstream = match target.connect(&host, port) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            let message = format!("could not connect to {}:{}: {}", host, port, e);
+            warn!("{}", message);
+            reply(&mut client, "502 Bad Gateway", &message);
+            return;
+        }
+    };
+    // Whatever the client sent past the request head is already for the server
+    let end = head
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map_or(head.len(), |position| position + 4);
+    let tunnelled = client
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .and_then(|_| upstream.write_all(&head[end..]));
+    if tunnelled.is_ok() {
+        relay(client, upstream);
+    }
+}
+
+/// Copies both ways until either side closes the connection.
+fn relay(mut client: TcpStream, mut upstream: TcpStream) {
+    let (Ok(mut requests), Ok(mut sent)) = (client.try_clone(), upstream.try_clone()) else {
+        return;
+    };
+    let uploads = thread::spawn(move || {
+        let _ = io::copy(&mut requests, &mut sent);
+        let _ = sent.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut upstream, &mut client);
+    // Also ends the upload of a client that is still waiting for more
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = uploads.join();
+}
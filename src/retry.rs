@@ -0,0 +1,207 @@
+use crate::cancel;
+use crate::config::Repo;
+use crate::output::{Event, Output};
+use git2::{ErrorClass, ErrorCode};
+use std::time::{Duration, SystemTime};
+
+/// How long to back off when a server rate limits without saying for how long.
+const RATE_LIMIT_DELAY: Duration = Duration::from_secs(30);
+/// The longest `Retry-After` that is honoured.
+const RATE_LIMIT_MAX: Duration = Duration::from_secs(15 * 60);
+
+/// Connection, DNS and TLS failures and server errors are worth another try;
+/// anything else, including rejected credentials, untrusted certificates and
+/// a missing repository, fails straight away.
+pub(crate) fn is_transient(e: &git2::Error) -> bool {
+    if matches!(e.code(), ErrorCode::Auth | ErrorCode::Certificate) {
+        return false;
+    }
+    match e.class() {
+        ErrorClass::Http => matches!(status(e), Some(429 | 500..=599)),
+        class => matches!(
+            class,
+            ErrorClass::Net | ErrorClass::Ssl | ErrorClass::Os | ErrorClass::Ssh
+        ),
+    }
+}
+
+/// libgit2 reports the status of a failed smart-HTTP request at the end of
+/// its message, but not its headers.
+fn status(e: &git2::Error) -> Option<u16> {
+    let message = e.message().trim_end();
+    let (_, code) = message.rsplit_once(' ')?;
+    code.parse().ok()
+}
+
+fn is_rate_limited(e: &git2::Error) -> bool {
+    e.class() == ErrorClass::Http && status(e) == Some(429)
+}
+
+/// How long a `Retry-After` value, in seconds or as an HTTP date, asks to
+/// wait from `now`. A date that has passed asks for no wait at all.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let until = httpdate::parse_http_date(value).ok()?;
+            Some(until.duration_since(now).unwrap_or_default())
+        }
+    }
+}
+
+/// Asks the server again how long it wants clients to wait, from the
+/// `Retry-After` header of the reference advertisement.
+fn retry_after(url: &str) -> Option<Duration> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+    let response = ureq::get(&format!(
+        "{}/info/refs?service=git-upload-pack",
+        url.trim_end_matches('/')
+    ))
+    .config()
+    .http_status_as_error(false)
+    .timeout_global(Some(Duration::from_secs(10)))
+    .build()
+    .call()
+    .ok()?;
+    if response.status() != 429 {
+        return None;
+    }
+    let value = response.headers().get("retry-after")?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Runs a network operation, retrying transient failures with exponential backoff.
+pub fn run<T>(
+    repo: &Repo,
+    output: &Output,
+    mut operation: impl FnMut() -> Result<T, git2::Error>,
+) -> Result<T, git2::Error> {
+    // Durations were checked by `Settings::validate`
+    let attempts = repo.retry.attempts();
+    let base = repo.retry.delay().unwrap_or_default();
+    let max_delay = repo.retry.max_delay().unwrap_or_default();
+
+    let mut attempt = 1;
+    loop {
+        match operation() {
+            Err(e) if attempt < attempts && is_transient(&e) && !cancel::requested() => {
+                let mut delay = base
+                    .saturating_mul(1 << (attempt - 1).min(16))
+                    .min(max_delay);
+                if repo.retry.jitter() {
+                    delay = delay.mul_f64(0.5 + fastrand::f64() / 2.0);
+                }
+                if is_rate_limited(&e) {
+                    let wanted = retry_after(&repo.url).unwrap_or(RATE_LIMIT_DELAY);
+                    delay = delay.max(wanted.min(RATE_LIMIT_MAX));
+                }
+                output.emit(Event::Retrying {
+                    path: &repo.path,
+                    attempt,
+                    attempts,
+                    delay_ms: delay.as_millis() as u64,
+                    message: e.message().to_string(),
+                });
+                cancel::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(message: &str) -> git2::Error {
+        git2::Error::new(ErrorCode::GenericError, ErrorClass::Http, message)
+    }
+
+    #[test]
+    fn reads_the_http_status() {
+        assert_eq!(status(&http("unexpected http status code: 503")), Some(503));
+        assert_eq!(
+            status(&http("unexpected http status code: 429 ")),
+            Some(429)
+        );
+        assert_eq!(
+            status(&http("too many redirects or authentication replays")),
+            None
+        );
+    }
+
+    #[test]
+    fn retries_rate_limits_and_server_errors() {
+        for code in [429, 500, 502, 503, 504, 599] {
+            let e = http(&format!("unexpected http status code: {}", code));
+            assert!(is_transient(&e), "{}", code);
+        }
+    }
+
+    #[test]
+    fn fails_on_client_errors_right_away() {
+        for code in [400, 403, 404, 410, 422] {
+            let e = http(&format!("unexpected http status code: {}", code));
+            assert!(!is_transient(&e), "{}", code);
+        }
+        assert!(!is_transient(&http(
+            "too many redirects or authentication replays"
+        )));
+        let refused = git2::Error::new(ErrorCode::Auth, ErrorClass::Http, "status code: 401");
+        assert!(!is_transient(&refused));
+    }
+
+    #[test]
+    fn tells_rate_limits_apart() {
+        assert!(is_rate_limited(&http("unexpected http status code: 429")));
+        assert!(!is_rate_limited(&http("unexpected http status code: 503")));
+        assert!(!is_rate_limited(&http("unexpected http status code: 1429")));
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+
+    #[test]
+    fn parses_retry_after_dates() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 32 Oct 2015 07:29:30 GMT", now),
+            None
+        );
+    }
+
+    #[test]
+    fn retries_network_failures() {
+        let e = git2::Error::new(
+            ErrorCode::GenericError,
+            ErrorClass::Net,
+            "failed to connect",
+        );
+        assert!(is_transient(&e));
+        let e = git2::Error::new(ErrorCode::GenericError, ErrorClass::Os, "connection reset");
+        assert!(is_transient(&e));
+        let e = git2::Error::new(ErrorCode::NotFound, ErrorClass::Reference, "no such branch");
+        assert!(!is_transient(&e));
+    }
+}
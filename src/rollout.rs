@@ -0,0 +1,129 @@
+use crate::config::{Repo, Rollout};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::store::{self, Document};
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use tracing::{debug, instrument};
+
+/// The rollout of one commit to the hosts sharing a key.
+#[derive(Serialize, Deserialize)]
+struct State {
+    commit: String,
+    /// Seconds since the unix epoch when the first host found the commit.
+    started: u64,
+    /// Hosts that applied the commit, and when.
+    #[serde(default)]
+    applied: BTreeMap<String, u64>,
+    /// Hosts that failed to apply it, and why. Any of them stops the rollout.
+    #[serde(default)]
+    failed: BTreeMap<String, String>,
+}
+
+/// Whether `host` is among the canaries of `commit`. Every host decides the
+/// same for itself, without knowing the others, and the canaries change
+/// from commit to commit.
+fn canary(host: &str, commit: Oid, fraction: f64) -> bool {
+    let digest = Sha256::new()
+        .chain_update(host.as_bytes())
+        .chain_update(commit.as_bytes())
+        .finalize();
+    let draw = u64::from_be_bytes(digest[..8].try_into().unwrap_or_default());
+    (draw as f64 / u64::MAX as f64) < fraction
+}
+
+/// The document of the rollout of `commit`. Each commit has its own, so a
+/// host that is behind never touches the state of a newer rollout.
+fn document(repo: &Repo, rollout: &Rollout, commit: Oid) -> Result<Document> {
+    let key = match &rollout.key {
+        Some(key) => key.clone(),
+        None => {
+            let digest = Sha256::digest(format!("{}#{}", repo.url, repo.branch));
+            hex::encode(&digest[..8])
+        }
+    };
+    let url = format!("{}/{}/{}", rollout.url.trim_end_matches('/'), key, commit);
+    Ok(Document::new(url, rollout.token()?))
+}
+
+/// Changes the state of the rollout of `commit` with `change`, starting the
+/// rollout when nothing is stored for it yet.
+fn update(document: &Document, commit: Oid, change: impl Fn(&mut State) -> bool) -> Result<State> {
+    document.update(|stored: Option<State>| match stored {
+        Some(mut state) => {
+            let changed = change(&mut state);
+            (state, changed)
+        }
+        None => {
+            let mut state = State {
+                commit: commit.to_string(),
+                started: store::now(),
+                applied: BTreeMap::new(),
+                failed: BTreeMap::new(),
+            };
+            change(&mut state);
+            (state, true)
+        }
+    })
+}
+
+/// Whether this host may apply the update to `commit` now: right away as a
+/// canary, otherwise once the canaries ran it for the soak period without
+/// any host failing to apply it.
+#[instrument(skip_all, fields(%commit))]
+pub(crate) fn admit(repo: &Repo, rollout: &Rollout, commit: Oid, output: &Output) -> Result<bool> {
+    let host = store::host(rollout.host.as_deref())?;
+    let state = update(&document(repo, rollout, commit)?, commit, |_| false)?;
+    if !state.failed.is_empty() {
+        output.emit(Event::RolloutHalted {
+            path: &repo.path,
+            commit: commit.to_string(),
+            hosts: state.failed.keys().cloned().collect(),
+        });
+        return Ok(false);
+    }
+    if canary(&host, commit, rollout.canary()) {
+        debug!(%host, "updating as a canary");
+        return Ok(true);
+    }
+
+    // Without any canary yet the rollout soaks from when it started
+    let soaked_from = state
+        .applied
+        .values()
+        .min()
+        .copied()
+        .unwrap_or(state.started);
+    let until = soaked_from.saturating_add(rollout.soak()?.as_secs());
+    if store::now() < until {
+        output.emit(Event::RolloutWaiting {
+            path: &repo.path,
+            commit: commit.to_string(),
+            until,
+        });
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Records how applying `commit` went on this host; a failure stops the
+/// rollout for the hosts that did not update yet.
+pub(crate) fn report(
+    repo: &Repo,
+    rollout: &Rollout,
+    commit: Oid,
+    failure: Option<&Error>,
+) -> Result<()> {
+    let host = store::host(rollout.host.as_deref())?;
+    update(
+        &document(repo, rollout, commit)?,
+        commit,
+        |state| match failure {
+            Some(e) => state.failed.insert(host.clone(), e.to_string()).is_none(),
+            None => state.applied.insert(host.clone(), store::now()).is_none(),
+        },
+    )?;
+    Ok(())
+}
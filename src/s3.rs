@@ -0,0 +1,147 @@
+use crate::error::{Error, Result};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::SystemTime;
+
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// An `s3://bucket/key` location together with where and how to reach it.
+pub struct Object<'a> {
+    pub bucket: &'a str,
+    pub key: String,
+    pub region: Option<&'a str>,
+    /// S3 compatible endpoint such as `https://minio.local:9000`, addressed path-style.
+    pub endpoint: Option<&'a str>,
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn credential(name: &str) -> Result<String> {
+    env::var(name).map_err(|_| Error::Config(format!("uploading to S3 needs {} to be set", name)))
+}
+
+/// Percent-encodes a key the way SigV4 expects, leaving the `/` separators.
+fn encode(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn digest(path: &Path) -> Result<impl AsRef<[u8]>> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.finalize()),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+}
+
+/// Uploads the file at `body` with a single SigV4 signed PUT, using the usual
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` variables.
+/// The file is read twice, once for the payload hash and once for the upload.
+pub fn put(object: &Object, body: &Path) -> Result<()> {
+    let access_key = credential("AWS_ACCESS_KEY_ID")?;
+    let secret_key = credential("AWS_SECRET_ACCESS_KEY")?;
+    let token = env::var("AWS_SESSION_TOKEN").ok();
+    let region = object
+        .region
+        .map(str::to_string)
+        .or_else(|| env::var("AWS_REGION").ok())
+        .unwrap_or_else(|| DEFAULT_REGION.to_string());
+
+    let (base, host, path) = match object.endpoint {
+        Some(endpoint) => {
+            let endpoint = endpoint.trim_end_matches('/');
+            let host = endpoint
+                .split_once("://")
+                .map_or(endpoint, |(_, host)| host);
+            (
+                endpoint.to_string(),
+                host.to_string(),
+                format!("/{}/{}", object.bucket, encode(&object.key)),
+            )
+        }
+        None => {
+            let host = format!("{}.s3.{}.amazonaws.com", object.bucket, region);
+            (
+                format!("https://{}", host),
+                host,
+                format!("/{}", encode(&object.key)),
+            )
+        }
+    };
+
+    // 2024-01-02T15:04:05Z becomes 20240102T150405Z
+    let timestamp: String = humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    let date = &timestamp[..8];
+    let payload = hex::encode(digest(body)?);
+
+    let mut headers = vec![
+        ("host", host.clone()),
+        ("x-amz-content-sha256", payload.clone()),
+        ("x-amz-date", timestamp.clone()),
+    ];
+    if let Some(token) = &token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let signed: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+    let signed = signed.join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let canonical = format!(
+        "PUT\n{}\n\n{}\n{}\n{}",
+        path, canonical_headers, signed, payload
+    );
+
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex::encode(Sha256::digest(canonical.as_bytes()))
+    );
+    let key = [date, region.as_str(), "s3", "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| {
+            hmac(&key, part)
+        });
+    let signature = hex::encode(hmac(&key, &to_sign));
+
+    let url = format!("{}{}", base, path);
+    let mut request = ureq::put(&url).header(
+        "Authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            access_key, scope, signed, signature
+        ),
+    );
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.header(*name, value);
+    }
+    request
+        .send(File::open(body)?)
+        .map_err(|e| Error::Io(std::io::Error::other(format!("{}: {}", url, e))))?;
+    Ok(())
+}
@@ -0,0 +1,344 @@
+use crate::error::{Error, Result};
+
+/// How far ahead `Cron::next_after` looks before giving up on an expression
+/// that never fires, like one for the 30th of February.
+const SEARCH_DAYS: i64 = 5 * 366;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A five field cron expression, `minute hour day-of-month month day-of-week`,
+/// evaluated in UTC like every other time the tool handles.
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron fires on either day field when both are restricted, otherwise on both.
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Cron> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let invalid = |reason: String| {
+            Error::Config(format!(
+                "invalid schedule '{}': {}",
+                expression.trim(),
+                reason
+            ))
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(invalid(format!(
+                "expected 5 fields, found {}",
+                fields.len()
+            )));
+        };
+
+        let weekdays = weekdays(weekday).map_err(&invalid)?;
+        Ok(Cron {
+            minutes: field(minute, 0, 59, &[], 0).map_err(&invalid)?,
+            hours: field(hour, 0, 23, &[], 0).map_err(&invalid)?,
+            days: field(day, 1, 31, &[], 0).map_err(&invalid)?,
+            months: field(month, 1, 12, &MONTHS, 1).map_err(&invalid)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    /// The first time the expression fires after `time`, both in seconds since
+    /// the epoch, or `None` when it never does.
+    pub fn next_after(&self, time: u64) -> Option<u64> {
+        let start = time / 60 + 1;
+        let first_day = (start / (24 * 60)) as i64;
+        for day in first_day..first_day + SEARCH_DAYS {
+            let (_, month, day_of_month) = civil(day);
+            let weekday = (day + 4).rem_euclid(7) as u32;
+            let on_day = self.days & (1 << day_of_month) != 0;
+            let on_weekday = self.weekdays & (1 << weekday) != 0;
+            let matches_day = match self.either_day {
+                true => on_day || on_weekday,
+                false => on_day && on_weekday,
+            };
+            if self.months & (1 << month) == 0 || !matches_day {
+                continue;
+            }
+
+            let midnight = day as u64 * 24 * 60;
+            for hour in (0..24).filter(|hour| self.hours & (1 << hour) != 0) {
+                for minute in (0..60).filter(|minute| self.minutes & (1 << minute) != 0) {
+                    let candidate = midnight + hour * 60 + minute;
+                    if candidate >= start {
+                        return Some(candidate * 60);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses a day-of-week field such as `mon-fri` into a bit set with Sunday
+/// as bit 0, which both 0 and 7 stand for.
+pub(crate) fn weekdays(text: &str) -> std::result::Result<u64, String> {
+    let weekdays = field(text, 0, 7, &WEEKDAYS, 0)?;
+    Ok(match weekdays & (1 << 7) {
+        0 => weekdays,
+        _ => (weekdays & !(1 << 7)) | 1,
+    })
+}
+
+/// Parses one field into a bit set of the values it allows: a list of `*`,
+/// single values and ranges, each optionally with a `/step`.
+fn field(
+    text: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    offset: u32,
+) -> std::result::Result<u64, String> {
+    let value = |text: &str| -> std::result::Result<u32, String> {
+        let value = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            Some(index) => index as u32 + offset,
+            None => text
+                .parse()
+                .map_err(|_| format!("'{}' is not a number", text))?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(format!("{} is not between {} and {}", value, min, max)),
+        }
+    };
+
+    let mut allowed = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("invalid step '{}'", step)),
+            },
+            None => (part, None),
+        };
+        let (start, end) = match (range, range.split_once('-')) {
+            ("*", _) => (min, max),
+            (_, Some((start, end))) => (value(start)?, value(end)?),
+            // A single value with a step runs to the end of the range, like `5/15`
+            (start, None) => {
+                let start = value(start)?;
+                (start, if step.is_some() { max } else { start })
+            }
+        };
+        if start > end {
+            return Err(format!("range '{}' is reversed", range));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            allowed |= 1 << value;
+        }
+    }
+    Ok(allowed)
+}
+
+/// Year, month and day of the day `days` after the epoch.
+fn civil(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(time: &str) -> u64 {
+        humantime::parse_rfc3339(time)
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn next(expression: &str, after: &str) -> Option<u64> {
+        Cron::parse(expression).unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn steps() {
+        assert_eq!(
+            next("*/15 * * * *", "2024-05-01T10:07:00Z"),
+            Some(at("2024-05-01T10:15:00Z"))
+        );
+        assert_eq!(
+            next("*/15 * * * *", "2024-05-01T10:45:00Z"),
+            Some(at("2024-05-01T11:00:00Z"))
+        );
+        // A single value with a step runs to the end of the field
+        assert_eq!(
+            next("5/20 * * * *", "2024-05-01T10:26:00Z"),
+            Some(at("2024-05-01T10:45:00Z"))
+        );
+        assert_eq!(
+            next("0 */6 * * *", "2024-05-01T13:00:00Z"),
+            Some(at("2024-05-01T18:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn ranges_and_lists() {
+        assert_eq!(
+            next("0 9-17 * * *", "2024-05-01T17:30:00Z"),
+            Some(at("2024-05-02T09:00:00Z"))
+        );
+        assert_eq!(
+            next("5,35 * * * *", "2024-05-01T10:05:00Z"),
+            Some(at("2024-05-01T10:35:00Z"))
+        );
+        assert_eq!(
+            next("0 8-10/2,20 * * *", "2024-05-01T10:00:00Z"),
+            Some(at("2024-05-01T20:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 * jun-aug *", "2024-01-15T00:00:00Z"),
+            Some(at("2024-06-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn fires_strictly_after_the_time() {
+        assert_eq!(
+            next("30 12 * * *", "2024-05-01T12:30:00Z"),
+            Some(at("2024-05-02T12:30:00Z"))
+        );
+        assert_eq!(
+            next("30 12 * * *", "2024-05-01T12:29:59Z"),
+            Some(at("2024-05-01T12:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn day_fields_or_when_both_are_restricted() {
+        // Friday 2024-09-06, then Tuesday the 10th before the next Friday
+        assert_eq!(
+            next("0 0 10 * fri", "2024-09-01T00:00:00Z"),
+            Some(at("2024-09-06T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 10 * fri", "2024-09-06T00:00:00Z"),
+            Some(at("2024-09-10T00:00:00Z"))
+        );
+        // With one of them unrestricted only the other one counts
+        assert_eq!(
+            next("0 0 * * mon", "2024-09-06T00:00:00Z"),
+            Some(at("2024-09-09T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 10 * *", "2024-09-06T00:00:00Z"),
+            Some(at("2024-09-10T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        let sunday = Some(at("2024-09-08T00:00:00Z"));
+        assert_eq!(next("0 0 * * 0", "2024-09-06T00:00:00Z"), sunday);
+        assert_eq!(next("0 0 * * 7", "2024-09-06T00:00:00Z"), sunday);
+        assert_eq!(next("0 0 * * sun", "2024-09-06T00:00:00Z"), sunday);
+        assert_eq!(next("@weekly", "2024-09-06T00:00:00Z"), sunday);
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(
+            next("0 0 29 2 *", "2024-03-01T00:00:00Z"),
+            Some(at("2028-02-29T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", "2024-02-28T12:00:00Z"),
+            Some(at("2024-02-29T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 31 * *", "2024-04-01T00:00:00Z"),
+            Some(at("2024-05-31T00:00:00Z"))
+        );
+        assert_eq!(Cron::parse("0 0 30 2 *").unwrap().next_after(0), None);
+    }
+
+    #[test]
+    fn rolls_over_at_year_end() {
+        assert_eq!(
+            next("0 0 1 1 *", "2024-12-31T23:59:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            next("@yearly", "2024-06-01T00:00:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            next("59 23 31 12 *", "2024-12-31T23:59:00Z"),
+            Some(at("2025-12-31T23:59:00Z"))
+        );
+        assert_eq!(
+            next("*/10 * * * *", "2024-12-31T23:55:00Z"),
+            Some(at("2025-01-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil(0), (1970, 1, 1));
+        assert_eq!(civil(-1), (1969, 12, 31));
+        for (time, date) in [
+            ("2000-02-29T00:00:00Z", (2000, 2, 29)),
+            ("2024-12-31T00:00:00Z", (2024, 12, 31)),
+            ("2100-03-01T00:00:00Z", (2100, 3, 1)),
+        ] {
+            assert_eq!(civil((at(time) / 86_400) as i64), date, "{}", time);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "x * * * *",
+            "@reboot",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use autoupdate::config::{CatchUp, Settings};
+use autoupdate::output::{Event, Output};
+use autoupdate::window::Windows;
+use autoupdate::{Result, Updater};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest wait between looks at the clock, so that checks missed while the
+/// machine slept are noticed soon after it wakes up.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Longest interval between checks of a repository whose remote is unreachable.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// A random number of seconds up to `limit`.
+fn random(limit: Option<Duration>) -> u64 {
+    limit.map_or(0, |limit| limit.mul_f64(fastrand::f64()).as_secs())
+}
+
+/// When a repository is first checked after starting. Repositories without a
+/// schedule are checked right away, scheduled ones when their schedule fires
+/// after their last sync, which may have passed while the tool was not running.
+fn initial(updater: &Updater, now: u64, output: &Output) -> u64 {
+    let repo = updater.repo();
+    let Ok(Some(cron)) = repo.schedule() else {
+        return now;
+    };
+    let last = updater
+        .history()
+        .ok()
+        .and_then(|entries| entries.last().map(|entry| entry.timestamp));
+    let Some(due) = last.map(|last| cron.next_after(last).unwrap_or(u64::MAX)) else {
+        return now;
+    };
+    if due > now {
+        return due;
+    }
+    match repo.catch_up {
+        CatchUp::RunOnce => now,
+        CatchUp::Skip => {
+            output.emit(Event::ScheduleMissed {
+                path: &repo.path,
+                due,
+            });
+            cron.next_after(now).unwrap_or(u64::MAX)
+        }
+    }
+}
+
+/// When every repository is checked next, in seconds since the epoch and in
+/// the order of the updaters. Random splay and jitter keep hosts started at
+/// the same time from checking at the same time.
+pub struct Scheduler {
+    due: Vec<(String, u64)>,
+    interval: Duration,
+    jitter: Option<Duration>,
+}
+
+impl Scheduler {
+    pub fn new(settings: &Settings, updaters: &[Updater], output: &Output) -> Result<Scheduler> {
+        let now = now();
+        let splay = settings.splay()?;
+        Ok(Scheduler {
+            due: updaters
+                .iter()
+                .map(|updater| {
+                    let due = initial(updater, now, output) + random(splay);
+                    (updater.repo().path.clone(), due)
+                })
+                .collect(),
+            interval: settings.interval()?,
+            jitter: settings.jitter()?,
+        })
+    }
+
+    /// Follows the settings after a reload; repositories still configured keep their time.
+    pub fn reconcile(
+        &mut self,
+        settings: &Settings,
+        updaters: &[Updater],
+        output: &Output,
+    ) -> Result<()> {
+        let now = now();
+        self.interval = settings.interval()?;
+        self.jitter = settings.jitter()?;
+        let previous = std::mem::take(&mut self.due);
+        self.due = updaters
+            .iter()
+            .map(|updater| {
+                let path = &updater.repo().path;
+                let due = previous
+                    .iter()
+                    .find(|(kept, _)| kept == path)
+                    .map(|(_, due)| *due)
+                    .unwrap_or_else(|| initial(updater, now, output));
+                (path.clone(), due)
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// The time after `now` a repository is checked again: the next time its
+    /// schedule fires, or one interval later without a schedule, plus jitter.
+    /// The interval doubles with every check in a row that found the remote
+    /// unreachable, up to an hour.
+    fn after(&self, updater: &Updater, now: u64) -> u64 {
+        let interval = match updater.unreachable() {
+            0 => self.interval,
+            failures => self
+                .interval
+                .saturating_mul(1 << (failures - 1).min(16))
+                .min(MAX_BACKOFF.max(self.interval)),
+        };
+        let next = match updater.repo().schedule() {
+            Ok(Some(cron)) => cron.next_after(now).unwrap_or(u64::MAX),
+            _ => now + interval.as_secs(),
+        };
+        // An update held back by a maintenance window is applied once it opens
+        let opens = match Windows::of(updater.repo()) {
+            Ok(Some(windows)) if !windows.allow(now) => windows.opens_after(now),
+            _ => None,
+        };
+        next.min(opens.unwrap_or(u64::MAX))
+            .saturating_add(random(self.jitter))
+    }
+
+    /// Seconds until the next check of any repository.
+    pub fn next(&self) -> u64 {
+        let earliest = self.due.iter().map(|(_, due)| *due).min();
+        earliest.unwrap_or(u64::MAX).saturating_sub(now())
+    }
+
+    /// How long to wait before looking for due repositories again.
+    pub fn wait(&self) -> Duration {
+        Duration::from_secs(self.next()).min(MAX_WAIT)
+    }
+
+    /// Indexes of the repositories due now. A scheduled check found later
+    /// than the longest wait was missed, and is skipped when the repository
+    /// does not catch up.
+    pub fn due(&mut self, updaters: &[Updater], output: &Output) -> Vec<usize> {
+        let now = now();
+        let mut due = Vec::new();
+        for (index, updater) in updaters.iter().enumerate() {
+            let time = self.due[index].1;
+            if time > now {
+                continue;
+            }
+            let repo = updater.repo();
+            if let (Ok(Some(cron)), CatchUp::Skip) = (repo.schedule(), repo.catch_up) {
+                if now > time + MAX_WAIT.as_secs() {
+                    output.emit(Event::ScheduleMissed {
+                        path: &repo.path,
+                        due: time,
+                    });
+                    self.due[index].1 = cron.next_after(now).unwrap_or(u64::MAX);
+                    continue;
+                }
+            }
+            due.push(index);
+        }
+        due
+    }
+
+    /// Schedules the next checks of the repositories that just synced.
+    pub fn synced(&mut self, selected: &[usize], updaters: &[Updater]) {
+        let now = now();
+        for index in selected {
+            self.due[*index].1 = self.after(&updaters[*index], now);
+        }
+    }
+}
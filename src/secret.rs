@@ -0,0 +1,56 @@
+use crate::error::{Error, Result};
+use std::fs;
+
+const KEYRING_SCHEME: &str = "keyring://";
+
+/// Splits a `keyring://service/account` reference, `None` for a plain value.
+pub fn keyring_entry(value: &str) -> Option<Result<(&str, &str)>> {
+    let reference = value.strip_prefix(KEYRING_SCHEME)?;
+    Some(
+        reference
+            .split_once('/')
+            .filter(|(service, account)| !service.is_empty() && !account.is_empty())
+            .ok_or_else(|| {
+                Error::Config(format!(
+                    "keyring reference '{}' is not of the form keyring://service/account",
+                    value
+                ))
+            }),
+    )
+}
+
+/// The secret in `value`: the value itself, or what the OS keyring stores for
+/// it when it is a `keyring://service/account` reference.
+pub fn resolve(field: &str, value: &str) -> Result<String> {
+    let Some(entry) = keyring_entry(value) else {
+        return Ok(value.to_string());
+    };
+    let (service, account) = entry?;
+    keyring::Entry::new(service, account)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| Error::Auth(format!("could not read {} from the keyring: {}", field, e)))
+}
+
+/// The secret set as `value`, resolved like any other, or else the one in the
+/// variable `env` names. An unset or empty variable is an error rather than
+/// no secret, so that a missing secret never turns authentication off.
+pub fn lookup(field: &str, value: Option<&str>, env: Option<&str>) -> Result<Option<String>> {
+    match (value, env) {
+        (Some(value), _) => resolve(field, value).map(Some),
+        (None, Some(name)) => match std::env::var(name) {
+            Ok(value) if !value.is_empty() => Ok(Some(value)),
+            _ => Err(Error::Config(format!(
+                "{} is taken from {}, which is not set",
+                field, name
+            ))),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+/// The secret stored in a file, without the line break editors leave at its end.
+pub fn read_file(field: &str, path: &str) -> Result<String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::Auth(format!("could not read {} '{}': {}", field, path, e)))?;
+    Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+}
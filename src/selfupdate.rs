@@ -0,0 +1,227 @@
+use autoupdate::config::Verify;
+use autoupdate::output::{Event, Output};
+use autoupdate::{verify, Error, Result};
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_REPOSITORY: &str = "momozahara/gitbase-auto-update";
+const MAX_DOWNLOAD: u64 = 256 * 1024 * 1024;
+const CHECKSUM_FILES: [&str; 3] = ["SHA256SUMS", "sha256sums.txt", "checksums.txt"];
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+pub struct Options<'a> {
+    pub repository: &'a str,
+    pub check: bool,
+    pub force: bool,
+    /// Signature verification is required when any trusted key is given.
+    pub verify: Option<Verify>,
+}
+
+fn http_error(url: &str, e: ureq::Error) -> Error {
+    Error::Io(std::io::Error::other(format!("{}: {}", url, e)))
+}
+
+fn get(url: &str) -> Result<ureq::http::Response<ureq::Body>> {
+    let mut request = ureq::get(url);
+    if let Ok(token) = env::var("GITHUB_TOKEN") {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    request.call().map_err(|e| http_error(url, e))
+}
+
+fn download(asset: &Asset) -> Result<Vec<u8>> {
+    get(&asset.browser_download_url)?
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD)
+        .read_to_vec()
+        .map_err(|e| http_error(&asset.browser_download_url, e))
+}
+
+/// Names this platform goes by in release asset names.
+fn platform() -> (Vec<&'static str>, Vec<&'static str>) {
+    let os = match env::consts::OS {
+        "macos" => vec!["macos", "darwin", "apple"],
+        "windows" => vec!["windows", "win64", "msvc"],
+        os => vec![os],
+    };
+    let arch = match env::consts::ARCH {
+        "x86_64" => vec!["x86_64", "amd64", "x64"],
+        "aarch64" => vec!["aarch64", "arm64"],
+        arch => vec![arch],
+    };
+    (os, arch)
+}
+
+/// Picks the plain executable built for this platform, skipping archives,
+/// checksums and signatures.
+fn pick(assets: &[Asset]) -> Option<&Asset> {
+    let (os, arch) = platform();
+    assets.iter().find(|asset| {
+        let name = asset.name.to_ascii_lowercase();
+        let extra = [".sha256", ".sig", ".asc", ".tar.gz", ".tgz", ".zip", ".txt"];
+        !extra.iter().any(|ext| name.ends_with(ext))
+            && os.iter().any(|os| name.contains(os))
+            && arch.iter().any(|arch| name.contains(arch))
+    })
+}
+
+/// Looks up the published sha256 of `asset`, either from `<asset>.sha256` or
+/// from a checksum list covering every asset of the release.
+fn checksum(release: &Release, asset: &Asset) -> Result<String> {
+    let own = format!("{}.sha256", asset.name);
+    let source = release
+        .assets
+        .iter()
+        .find(|candidate| candidate.name == own)
+        .or_else(|| {
+            release
+                .assets
+                .iter()
+                .find(|candidate| CHECKSUM_FILES.contains(&candidate.name.as_str()))
+        })
+        .ok_or_else(|| {
+            Error::Refused(format!(
+                "release {} publishes no checksum for {}",
+                release.tag_name, asset.name
+            ))
+        })?;
+
+    let content = String::from_utf8_lossy(&download(source)?).into_owned();
+    content
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let hash = fields.next()?;
+            match fields.next().map(|name| name.trim_start_matches('*')) {
+                Some(name) if name == asset.name => Some(hash),
+                None if source.name == own => Some(hash),
+                _ => None,
+            }
+        })
+        .map(str::to_ascii_lowercase)
+        .ok_or_else(|| {
+            Error::Refused(format!(
+                "{} has no checksum for {}",
+                source.name, asset.name
+            ))
+        })
+}
+
+fn signature(release: &Release, asset: &Asset) -> Result<Vec<u8>> {
+    let names = [format!("{}.sig", asset.name), format!("{}.asc", asset.name)];
+    let source = release
+        .assets
+        .iter()
+        .find(|candidate| names.contains(&candidate.name))
+        .ok_or_else(|| {
+            Error::Refused(format!(
+                "release {} publishes no signature for {}",
+                release.tag_name, asset.name
+            ))
+        })?;
+    download(source)
+}
+
+/// Moves the new executable into place; the running one keeps using the old
+/// file until it exits.
+fn replace(current: &Path, binary: &[u8]) -> Result<()> {
+    let staged = PathBuf::from(format!("{}.new", current.display()));
+    fs::write(&staged, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+
+    // Windows refuses to overwrite a running executable but lets it be renamed
+    #[cfg(windows)]
+    {
+        let old = PathBuf::from(format!("{}.old", current.display()));
+        let _ = fs::remove_file(&old);
+        fs::rename(current, &old)?;
+    }
+    fs::rename(&staged, current)?;
+    Ok(())
+}
+
+/// Checks the latest release of the tool and installs it when it is newer.
+pub fn run(options: Options, output: &Output) -> Result<()> {
+    let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("valid package version");
+    let url = format!(
+        "https://api.github.com/repos/{}/releases/latest",
+        options.repository
+    );
+    let release: Release = get(&url)?
+        .body_mut()
+        .read_json()
+        .map_err(|e| http_error(&url, e))?;
+    let latest = Version::parse(release.tag_name.trim_start_matches('v')).map_err(|_| {
+        Error::Config(format!(
+            "latest release tag '{}' is not a version",
+            release.tag_name
+        ))
+    })?;
+
+    if latest <= current && !options.force {
+        output.emit(Event::SelfUpToDate {
+            version: current.to_string(),
+        });
+        return Ok(());
+    }
+    output.emit(Event::SelfUpdateAvailable {
+        current: current.to_string(),
+        latest: latest.to_string(),
+    });
+    if options.check {
+        return Ok(());
+    }
+
+    let asset = pick(&release.assets).ok_or_else(|| {
+        Error::Config(format!(
+            "release {} has no executable for {}-{}",
+            release.tag_name,
+            env::consts::OS,
+            env::consts::ARCH
+        ))
+    })?;
+    let expected = checksum(&release, asset)?;
+    let binary = download(asset)?;
+    let actual = hex::encode(Sha256::digest(&binary));
+    if actual != expected {
+        return Err(Error::Refused(format!(
+            "checksum of {} is {} but the release says {}",
+            asset.name, actual, expected
+        )));
+    }
+    if let Some(trusted) = &options.verify {
+        let signer =
+            verify::check_file(trusted, &asset.name, &binary, &signature(&release, asset)?)?;
+        output.emit(Event::SelfUpdateVerified {
+            asset: &asset.name,
+            signer: &signer,
+        });
+    }
+
+    replace(&env::current_exe()?, &binary)?;
+    output.emit(Event::SelfUpdated {
+        from: current.to_string(),
+        to: latest.to_string(),
+    });
+    Ok(())
+}
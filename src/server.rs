@@ -0,0 +1,250 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_BODY: usize = 10 * 1024 * 1024;
+/// Limit of the request line and headers together.
+const MAX_HEAD: u64 = 64 * 1024;
+/// Connections served at once; more are closed right away.
+const MAX_CONNECTIONS: usize = 64;
+/// How long a client may take to send its request or to read the response.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn json(status: u16, body: &impl serde::Serialize) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(body).unwrap_or_default(),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn read_request(stream: impl Read) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut head = reader.by_ref().take(MAX_HEAD);
+
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    if head.limit() == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "request headers too large",
+        ));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    };
+
+    let length: usize = request
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "request body too large",
+        ));
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body)?;
+
+    Ok(request)
+}
+
+fn write_response(mut stream: impl Write, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+fn answer<S>(stream: S, handler: &impl Fn(&Request) -> Response)
+where
+    for<'a> &'a S: Read + Write,
+{
+    let response = match read_request(&stream) {
+        Ok(request) => handler(&request),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Response::text(413, &e.to_string()),
+        Err(_) => Response::text(400, "malformed request"),
+    };
+    let _ = write_response(&stream, &response);
+}
+
+/// Gives its place among the open connections back when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answers every connection on its own thread, closing those that come in
+/// while `MAX_CONNECTIONS` are open already.
+fn serve<S, F>(connections: impl Iterator<Item = S>, handler: F)
+where
+    S: Send + 'static,
+    for<'a> &'a S: Read + Write,
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in connections {
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let slot = Slot(open.clone());
+        let handler = Arc::clone(&handler);
+        thread::spawn(move || {
+            let _slot = slot;
+            answer(stream, &*handler)
+        });
+    }
+}
+
+/// Serves every connection on its own thread, answering one request per connection.
+pub fn spawn<F>(listen: &str, handler: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(listen)?;
+
+    thread::spawn(move || {
+        let connections = listener.incoming().flatten().filter(|stream| {
+            stream.set_read_timeout(Some(TIMEOUT)).is_ok()
+                && stream.set_write_timeout(Some(TIMEOUT)).is_ok()
+        });
+        serve(connections, handler);
+    });
+    Ok(())
+}
+
+/// Like [`spawn`], on a unix socket only its owner may connect to. A socket
+/// left behind by a daemon that is gone is replaced.
+#[cfg(unix)]
+pub fn spawn_unix<F>(path: &str, handler: F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    use std::fs::{self, DirBuilder};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AddrInUse,
+            format!("another daemon is listening on '{}'", path),
+        ));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    // Bound inside a directory only the owner can enter and moved into place
+    // once restricted, so other users never get to connect in between
+    let staging = PathBuf::from(format!("{}.partial", path));
+    let _ = fs::remove_dir_all(&staging);
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&staging);
+    let listener = bound?;
+
+    thread::spawn(move || {
+        let connections = listener.incoming().flatten().filter(|stream| {
+            stream.set_read_timeout(Some(TIMEOUT)).is_ok()
+                && stream.set_write_timeout(Some(TIMEOUT)).is_ok()
+        });
+        serve(connections, handler);
+    });
+    Ok(())
+}
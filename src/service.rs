@@ -0,0 +1,183 @@
+use crate::cli::{Cli, ServiceCommand, StartType};
+use crate::Command;
+use autoupdate::{config, Error, Result};
+use clap::Parser;
+use std::ffi::OsString;
+use std::fs;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept,
+    ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+/// Name the dispatcher was started with, needed again inside `service_main`.
+static NAME: OnceLock<String> = OnceLock::new();
+
+fn service_error(e: windows_service::Error) -> Error {
+    Error::Io(std::io::Error::other(e.to_string()))
+}
+
+pub fn run(command: &ServiceCommand, cli: &Cli) -> Result<()> {
+    match command {
+        ServiceCommand::Install {
+            name,
+            display_name,
+            start,
+            restart_delay,
+            no_restart,
+        } => install(
+            cli,
+            name,
+            display_name.as_deref(),
+            *start,
+            restart_delay,
+            *no_restart,
+        ),
+        ServiceCommand::Uninstall { name } => uninstall(name),
+        ServiceCommand::Run { name } => {
+            let _ = NAME.set(name.clone());
+            service_dispatcher::start(name, ffi_service_main).map_err(service_error)
+        }
+    }
+}
+
+/// Registers the daemon mode as a service that restarts after failures.
+fn install(
+    cli: &Cli,
+    name: &str,
+    display_name: Option<&str>,
+    start: StartType,
+    restart_delay: &str,
+    no_restart: bool,
+) -> Result<()> {
+    let delay = config::parse_duration(restart_delay)
+        .ok_or_else(|| Error::Config(format!("invalid restart delay '{}'", restart_delay)))?;
+    // Services start in the system directory, so the settings file needs an absolute path
+    let settings = fs::canonicalize(config::locate_file(cli.config.as_deref())?)?;
+
+    let access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
+    let manager = ServiceManager::local_computer(None::<&str>, access).map_err(service_error)?;
+    let info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from(display_name.unwrap_or(name)),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: match start {
+            StartType::Auto | StartType::Delayed => ServiceStartType::AutoStart,
+            StartType::Manual => ServiceStartType::OnDemand,
+        },
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            settings.into_os_string(),
+            OsString::from("service"),
+            OsString::from("run"),
+            OsString::from("--name"),
+            OsString::from(name),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .map_err(service_error)?;
+    service
+        .set_description("Keeps local git checkouts in sync with their remotes")
+        .map_err(service_error)?;
+    if start == StartType::Delayed {
+        service
+            .set_delayed_auto_start(true)
+            .map_err(service_error)?;
+    }
+
+    if !no_restart {
+        let restart = ServiceAction {
+            action_type: ServiceActionType::Restart,
+            delay,
+        };
+        service
+            .update_failure_actions(ServiceFailureActions {
+                reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+                reboot_msg: None,
+                command: None,
+                actions: Some(vec![restart.clone(), restart.clone(), restart]),
+            })
+            .map_err(service_error)?;
+        service
+            .set_failure_actions_on_non_crash_failures(true)
+            .map_err(service_error)?;
+    }
+    Ok(())
+}
+
+fn uninstall(name: &str) -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(service_error)?;
+    let access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE;
+    let service = manager.open_service(name, access).map_err(service_error)?;
+
+    // The service is removed once it has stopped and every handle to it is closed
+    service.delete().map_err(service_error)?;
+    if service.query_status().map_err(service_error)?.current_state != ServiceState::Stopped {
+        service.stop().map_err(service_error)?;
+    }
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    let name = NAME.get().cloned().unwrap_or_default();
+    let (tx, rx) = mpsc::channel();
+
+    let stop = tx.clone();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop.send(Command::Stop);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let Ok(status) = service_control_handler::register(&name, handler) else {
+        return;
+    };
+    let report = |state, exit_code| {
+        let _ = status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                }
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        });
+    };
+    report(ServiceState::Running, 0);
+
+    // The service keeps watching until it is told to stop
+    let mut cli = Cli::parse();
+    cli.action = None;
+    cli.watch = true;
+    cli.once = false;
+    let exit_code = match crate::start(cli, (tx, rx)) {
+        Ok(_) => 0,
+        Err(e) => {
+            tracing::error!("error: {}", e);
+            e.exit_code() as u32
+        }
+    };
+    report(ServiceState::Stopped, exit_code);
+}
@@ -0,0 +1,141 @@
+use crate::config::{Repo, Vcs};
+use crate::error::{Error, Result};
+use crate::hooks::{self, Stage};
+use crate::leader;
+use crate::mercurial::Mercurial;
+use crate::output::{Event, Output};
+use crate::ownership;
+use crate::retry;
+use crate::tarball;
+use crate::updater::SyncOutcome;
+use crate::window;
+use git2::Oid;
+use std::path::Path;
+use tracing::instrument;
+
+/// A commit the tracked ref points to, as its source reports it.
+pub(crate) struct Revision {
+    pub id: Oid,
+    pub message: String,
+}
+
+/// Where the checkout of a repository that is not fetched with git comes
+/// from. Git itself stays on the updater's own path, which the options
+/// around history, branches and work trees are built on.
+pub(crate) trait Source {
+    /// Where the checkout is updated from, for messages.
+    fn describe(&self) -> String;
+    /// The commit the checkout holds, `None` before it was first updated.
+    fn current(&self) -> Result<Option<Oid>>;
+    /// The commit the tracked ref points to now.
+    fn resolve(&self, output: &Output) -> Result<Revision>;
+    /// Brings the checkout to `commit`, which `resolve` returned.
+    fn checkout(&self, commit: Oid, output: &Output) -> Result<()>;
+}
+
+/// The source configured for `repo`, `None` when it is fetched with git.
+pub(crate) fn of(repo: &Repo) -> Result<Option<Box<dyn Source + '_>>> {
+    match (repo.vcs, &repo.tarball) {
+        (Vcs::Mercurial, _) => Ok(Some(Box::new(Mercurial::new(repo)))),
+        (Vcs::Git, Some(tarball)) => Ok(Some(Box::new(tarball::Download::new(repo, tarball)?))),
+        (Vcs::Git, None) => Ok(None),
+    }
+}
+
+/// Retries the transient network failures of `operation`, which reports them
+/// as git errors.
+fn retried<T>(repo: &Repo, output: &Output, mut operation: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut other = None;
+    let result = retry::run(repo, output, || match operation() {
+        Ok(value) => Ok(value),
+        Err(Error::Git(e)) => Err(e),
+        Err(e) => {
+            other = Some(e);
+            Err(git2::Error::from_str("not retried"))
+        }
+    });
+    match (result, other) {
+        (_, Some(e)) => Err(e),
+        (result, None) => result.map_err(Error::from),
+    }
+}
+
+/// Brings the checkout to the commit the tracked ref points to when it
+/// holds another one.
+#[instrument(skip_all, fields(source = source.describe()))]
+pub(crate) fn update(repo: &Repo, source: &dyn Source, output: &Output) -> Result<SyncOutcome> {
+    let path = Path::new(&repo.path);
+    let current = source.current()?;
+    if repo.offline {
+        return match current {
+            Some(commit) => {
+                output.emit(Event::UpToDate { path: &repo.path });
+                Ok(SyncOutcome::UpToDate { commit })
+            }
+            None => Err(Error::Refused(format!(
+                "'{}' has not been checked out yet and could not be while offline",
+                repo.path
+            ))),
+        };
+    }
+
+    let revision = retried(repo, output, || source.resolve(output))?;
+    let new = revision.id;
+    if current == Some(new) {
+        output.emit(Event::UpToDate { path: &repo.path });
+        output.emit(Event::Head {
+            path: &repo.path,
+            commit: new.to_string(),
+            message: revision.message.trim(),
+        });
+        return Ok(SyncOutcome::UpToDate { commit: new });
+    }
+    if let (Some(old), true) = (current, repo.dry_run) {
+        output.emit(Event::UpdateAvailable {
+            path: &repo.path,
+            old: old.to_string(),
+            new: new.to_string(),
+            commits: Vec::new(),
+        });
+        return Ok(SyncOutcome::UpdateAvailable {
+            old,
+            new,
+            commits: Vec::new(),
+        });
+    }
+
+    if let (Some(old), true) = (current, window::hold(repo, new, output)?) {
+        return Ok(SyncOutcome::Held { old, new });
+    }
+
+    // Like after a clone, the hooks only run for updates of an existing checkout
+    let context = current.map(|old| hooks::Context {
+        dir: path,
+        branch: &repo.branch,
+        old_commit: old,
+        new_commit: new,
+        changed: Vec::new(),
+    });
+    if let Some(context) = &context {
+        hooks::run(Stage::PreUpdate, &repo.hooks, context, output).map_err(|e| match e {
+            Error::Hook(message) => Error::Hook(format!("update aborted: {}", message)),
+            e => e,
+        })?;
+    }
+    retried(repo, output, || source.checkout(new, output))?;
+    ownership::apply(repo, path, output)?;
+
+    if let Some(context) = &context {
+        hooks::run(Stage::PostUpdate, &repo.hooks, context, output)?;
+        leader::run_hooks(repo, context, output)?;
+    }
+    output.emit(Event::Head {
+        path: &repo.path,
+        commit: new.to_string(),
+        message: revision.message.trim(),
+    });
+    Ok(match current {
+        Some(old) => SyncOutcome::Updated { old, new },
+        None => SyncOutcome::UpToDate { commit: new },
+    })
+}
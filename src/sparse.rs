@@ -0,0 +1,55 @@
+use crate::config::Repo;
+use crate::error::Result;
+use git2::build::CheckoutBuilder;
+use git2::{Index, IndexEntryExtendedFlag, Pathspec, PathspecFlags, Repository};
+use std::fs;
+use std::path::Path;
+
+/// Limits a checkout to the configured sparse paths, if any.
+pub fn restrict(repo: &Repo, cb: &mut CheckoutBuilder) {
+    for path in &repo.sparse {
+        cb.path(path);
+    }
+}
+
+/// Marks everything outside the sparse paths as skip-worktree, so the
+/// missing files do not count as deleted and `git` sees what we see.
+pub fn mark(repo: &Repo, repository: &Repository) -> Result<()> {
+    if repo.sparse.is_empty() {
+        return Ok(());
+    }
+
+    let info = repository.path().join("info");
+    fs::create_dir_all(&info)?;
+    fs::write(info.join("sparse-checkout"), repo.sparse.join("\n") + "\n")?;
+    repository.config()?.set_bool("core.sparseCheckout", true)?;
+
+    // A checkout limited to paths leaves the rest out of the index, put it back
+    let pathspec = Pathspec::new(repo.sparse.iter())?;
+    let mut index = repository.index()?;
+    index.read_tree(&repository.head()?.peel_to_tree()?)?;
+    let entries: Vec<_> = index.iter().collect();
+    for mut entry in entries {
+        let path = String::from_utf8_lossy(&entry.path).into_owned();
+        let flag = IndexEntryExtendedFlag::SKIP_WORKTREE.bits();
+        let skip = !pathspec.matches_path(Path::new(&path), PathspecFlags::DEFAULT);
+        let flags = match skip {
+            true => entry.flags_extended | flag,
+            false => entry.flags_extended & !flag,
+        };
+        if flags != entry.flags_extended {
+            entry.flags_extended = flags;
+            index.add(&entry)?;
+        }
+    }
+    index.write()?;
+    Ok(())
+}
+
+/// Whether `path` is kept off disk by the sparse checkout; libgit2's status
+/// reports such files as deleted.
+pub fn skipped(index: &Index, path: &str) -> bool {
+    index.get_path(Path::new(path), 0).is_some_and(|entry| {
+        entry.flags_extended & IndexEntryExtendedFlag::SKIP_WORKTREE.bits() != 0
+    })
+}
@@ -0,0 +1,118 @@
+use crate::error::{Error, Result};
+use crate::output::Outcome;
+use git2::{Oid, Repository};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+const FILE_NAME: &str = "autoupdate-state.json";
+const HISTORY_FILE_NAME: &str = "autoupdate-history.jsonl";
+const MAX_PREVIOUS: usize = 20;
+
+/// Per-repository state kept in the git directory, out of reach of resets.
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+    /// Commits HEAD pointed at before each applied update, oldest first.
+    #[serde(default)]
+    pub previous: Vec<String>,
+    /// Commit that was rolled back from; syncs leave it alone until the remote moves on.
+    #[serde(default)]
+    pub skip: Option<String>,
+    /// Updates applied since the object database was last maintained.
+    #[serde(default)]
+    pub updates_since_maintenance: u32,
+    /// Commit the update found last waits for approval to.
+    #[serde(default)]
+    pub pending: Option<String>,
+    /// Commit `autoupdate approve` let the update through to.
+    #[serde(default)]
+    pub approved: Option<String>,
+    /// Commit the working tree was last propagated at, and where to.
+    #[serde(default)]
+    pub propagated: Option<String>,
+    #[serde(default)]
+    pub propagated_to: Option<String>,
+}
+
+fn path(repository: &Repository) -> PathBuf {
+    repository.path().join(FILE_NAME)
+}
+
+impl State {
+    pub fn load(repository: &Repository) -> Result<State> {
+        let path = path(repository);
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| Error::Config(format!("could not parse '{}': {}", path.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, repository: &Repository) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        // Written next to it first, so a crash never leaves half a state behind
+        let path = path(repository);
+        let staged = path.with_extension("partial");
+        fs::write(&staged, content)?;
+        fs::rename(&staged, &path)?;
+        Ok(())
+    }
+
+    pub fn push_previous(&mut self, oid: Oid) {
+        self.previous.push(oid.to_string());
+        if self.previous.len() > MAX_PREVIOUS {
+            self.previous.remove(0);
+        }
+    }
+
+    pub fn skips(&self, oid: Oid) -> bool {
+        self.skip.as_deref() == Some(oid.to_string().as_str())
+    }
+
+    pub fn approves(&self, oid: Oid) -> bool {
+        self.approved.as_deref() == Some(oid.to_string().as_str())
+    }
+}
+
+/// One sync of a repository, as appended to its history.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    /// Seconds since the unix epoch when the sync started.
+    pub timestamp: u64,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Appends `entry` to the history kept as JSON lines in the git directory.
+pub fn record(repository: &Repository, entry: &HistoryEntry) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(repository.path().join(HISTORY_FILE_NAME))?;
+    writeln!(file, "{}", serde_json::to_string(entry).unwrap())?;
+    Ok(())
+}
+
+/// Every recorded sync, oldest first.
+pub fn history(repository: &Repository) -> Result<Vec<HistoryEntry>> {
+    let path = repository.path().join(HISTORY_FILE_NAME);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| Error::Config(format!("could not parse '{}': {}", path.display(), e)))
+        })
+        .collect()
+}
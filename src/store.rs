@@ -0,0 +1,116 @@
+use crate::error::{Error, Result};
+use crate::forge;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Times a change of a document is tried again after another host changed it.
+const CONFLICT_RETRIES: u32 = 5;
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The name this host goes by among the others sharing a document.
+pub(crate) fn host(configured: Option<&str>) -> Result<String> {
+    match configured {
+        Some(host) => Ok(host.to_string()),
+        None => Ok(hostname::get()?.to_string_lossy().into_owned()),
+    }
+}
+
+/// A JSON document several hosts read with GET and write with PUT. Writes
+/// are conditional on the ETag read before, so two hosts changing it at once
+/// do not lose either change.
+pub(crate) struct Document {
+    url: String,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Document {
+    pub(crate) fn new(url: String, token: Option<String>) -> Document {
+        let agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(TIMEOUT))
+            .build()
+            .into();
+        Document { url, token, agent }
+    }
+
+    /// The document and its ETag, `None` before any host stored one.
+    fn load<T: DeserializeOwned>(&self) -> Result<Option<(T, Option<String>)>> {
+        let mut request = self.agent.get(&self.url);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let mut response = request.call().map_err(|e| forge::failure(&self.url, e))?;
+        match response.status().as_u16() {
+            404 => return Ok(None),
+            200 => {}
+            status => return Err(forge::failure(&self.url, ureq::Error::StatusCode(status))),
+        }
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let value = response
+            .body_mut()
+            .read_json()
+            .map_err(|e| Error::Config(format!("could not parse {}: {}", self.url, e)))?;
+        Ok(Some((value, etag)))
+    }
+
+    /// Stores `value` unless the document changed since it was read with
+    /// `etag`, or was created when there was none. Returns whether it was
+    /// stored.
+    fn store<T: Serialize>(&self, value: &T, etag: Option<&str>, created: bool) -> Result<bool> {
+        let mut request = self.agent.put(&self.url);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request = match (etag, created) {
+            (Some(etag), _) => request.header("If-Match", etag),
+            (None, true) => request.header("If-None-Match", "*"),
+            // The server does not tag its documents; the last write wins
+            (None, false) => request,
+        };
+        let response = request
+            .send_json(value)
+            .map_err(|e| forge::failure(&self.url, e))?;
+        match response.status().as_u16() {
+            200..=299 => Ok(true),
+            409 | 412 => Ok(false),
+            status => Err(forge::failure(&self.url, ureq::Error::StatusCode(status))),
+        }
+    }
+
+    /// Hands the stored document to `change`, which returns what it should
+    /// be and whether that has to be written, and returns what was stored.
+    pub(crate) fn update<T: Serialize + DeserializeOwned>(
+        &self,
+        mut change: impl FnMut(Option<T>) -> (T, bool),
+    ) -> Result<T> {
+        for _ in 0..CONFLICT_RETRIES {
+            let (value, etag, created) = match self.load()? {
+                Some((value, etag)) => (change(Some(value)), etag, false),
+                None => (change(None), None, true),
+            };
+            let (value, changed) = value;
+            if !changed || self.store(&value, etag.as_deref(), created)? {
+                return Ok(value);
+            }
+            debug!(url = %self.url, "the document changed in between, trying again");
+        }
+        Err(Error::Refused(format!(
+            "{} kept changing while it was updated",
+            self.url
+        )))
+    }
+}
@@ -0,0 +1,112 @@
+use crate::cancel;
+use crate::config::Repo;
+use crate::error::Result;
+use crate::output::{Event, Output};
+use crate::timeout::Deadline;
+use git2::build::CheckoutBuilder;
+use git2::{Oid, Repository};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory holding the trees `path` links to.
+fn trees(repo: &Repo) -> PathBuf {
+    PathBuf::from(format!("{}.trees", repo.path.trim_end_matches(['/', '\\'])))
+}
+
+/// Target of the symlink at the configured path, relative to the directory it is in.
+fn relative(trees: &Path, tree: &Path) -> PathBuf {
+    Path::new(trees.file_name().unwrap_or_default()).join(tree.file_name().unwrap_or_default())
+}
+
+#[cfg(unix)]
+pub(crate) fn link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub(crate) fn link(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+/// Points the symlink at `path` to `target`. Replacing a symlink by renaming
+/// another one over it is atomic, so readers see either the old or the new tree.
+pub(crate) fn flip(path: &Path, target: &Path) -> io::Result<()> {
+    let staged = PathBuf::from(format!("{}.link", path.display()));
+    let _ = fs::remove_file(&staged);
+    link(target, &staged)?;
+    // Windows does not rename over an existing directory link
+    #[cfg(windows)]
+    let _ = fs::remove_dir(path);
+    fs::rename(&staged, path)
+}
+
+/// Checks `target` out into a new directory next to the checkout, moves the
+/// git directory over and then swaps the directories by flipping the symlink
+/// at the configured path. Reopens `repository` at its new place.
+pub fn apply(
+    repo: &Repo,
+    repository: &mut Repository,
+    target: Oid,
+    cb: &mut CheckoutBuilder,
+    deadline: &Deadline,
+    detached: bool,
+    output: &Output,
+) -> Result<()> {
+    let path = Path::new(&repo.path);
+    let trees = trees(repo);
+    // Named by time as well, a commit may come back after a rollback while its tree is live
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    let next = trees.join(format!("{}-{:.7}", millis, target.to_string()));
+    fs::create_dir_all(&next)?;
+
+    let tree = repository.find_commit(target)?.tree()?;
+    // libgit2 only creates the directories of files in an absolute target
+    cb.force()
+        .target_dir(&fs::canonicalize(&next)?)
+        .update_index(false);
+    repository.checkout_tree(tree.as_object(), Some(cb))?;
+    let what = format!("checking out {}", target);
+    deadline.ensure(&what)?;
+    cancel::ensure(&what)?;
+    let head = repository.head()?.peel_to_commit()?.id();
+    drop(tree);
+
+    // A checkout made before atomic updates were enabled moves into the
+    // trees first, leaving the configured path free for the symlink
+    let current = match fs::symlink_metadata(path)?.file_type().is_symlink() {
+        true => fs::canonicalize(path)?,
+        false => {
+            let moved = trees.join(format!("0-{:.7}", head.to_string()));
+            fs::rename(path, &moved)?;
+            link(&relative(&trees, &moved), path)?;
+            moved
+        }
+    };
+    fs::rename(current.join(".git"), next.join(".git"))?;
+    flip(path, &relative(&trees, &next))?;
+    fs::remove_dir_all(&current)?;
+
+    *repository = Repository::open(path)?;
+    let mut index = repository.index()?;
+    index.read_tree(&repository.find_commit(target)?.tree()?)?;
+    index.write()?;
+    match detached {
+        true => repository.set_head_detached(target)?,
+        false => {
+            repository
+                .find_reference(&format!("refs/heads/{}", repo.branch))?
+                .set_target(target, "autoupdate: atomic update")?;
+        }
+    }
+
+    output.emit(Event::Swapped {
+        path: &repo.path,
+        directory: next.display().to_string(),
+    });
+    Ok(())
+}
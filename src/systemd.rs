@@ -0,0 +1,104 @@
+use autoupdate::{config, Result};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Sends a state change like `READY=1` to the service manager. Does nothing
+/// when not started by systemd with `Type=notify`.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+
+    // A leading '@' names a socket in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.to_str().and_then(|path| path.strip_prefix('@')) {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        if let Ok(address) = SocketAddr::from_abstract_name(name) {
+            let _ = socket.send_to_addr(state.as_bytes(), &address);
+        }
+        return;
+    }
+    let _ = socket.send_to(state.as_bytes(), path);
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Keeps pinging the watchdog at half its timeout when systemd asked for it,
+/// so long fetches and hooks do not get the service restarted.
+pub fn spawn_watchdog() {
+    let Some(timeout) = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+    else {
+        return;
+    };
+    // The watchdog is meant for another process when the pid does not match
+    if let Some(pid) = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return;
+        }
+    }
+
+    let period = Duration::from_micros(timeout / 2);
+    thread::spawn(move || loop {
+        notify("WATCHDOG=1");
+        thread::sleep(period);
+    });
+}
+
+/// A unit file that runs the watch mode with the given settings file.
+pub fn unit(settings: Option<&Path>) -> Result<String> {
+    let settings = fs::canonicalize(config::locate_file(settings)?)?;
+    let directory = settings.parent().unwrap_or(Path::new("/"));
+    let executable = env::current_exe()?;
+
+    Ok(format!(
+        "[Unit]
+Description=Keep git checkouts in sync with their remotes
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={} --config {} --watch
+WorkingDirectory={}
+Restart=on-failure
+RestartSec=10
+WatchdogSec=60
+
+[Install]
+WantedBy=multi-user.target
+",
+        quote(&executable.to_string_lossy()),
+        quote(&settings.to_string_lossy()),
+        directory.display()
+    ))
+}
+
+/// Quotes a command line argument for systemd when it contains spaces.
+fn quote(argument: &str) -> String {
+    match argument.contains(char::is_whitespace) {
+        true => format!(
+            "\"{}\"",
+            argument.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        false => argument.to_string(),
+    }
+}
@@ -0,0 +1,51 @@
+use crate::error::Result;
+use git2::{Oid, Repository};
+use glob::Pattern;
+use semver::{Version, VersionReq};
+
+fn version(name: &str) -> Option<Version> {
+    Version::parse(name.strip_prefix('v').unwrap_or(name)).ok()
+}
+
+/// Whether the tag `name` matches `pattern`, either as a glob (`v1.*`) or as
+/// a semver range (`^1.2`, `>=2, <3`).
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    let range = VersionReq::parse(pattern.strip_prefix('v').unwrap_or(pattern)).ok();
+    Pattern::new(pattern).is_ok_and(|glob| glob.matches(name))
+        || matches!((&range, &version(name)), (Some(range), Some(version)) if range.matches(version))
+}
+
+/// Finds the newest local tag whose name matches `pattern`, either as a glob
+/// (`v1.*`) or as a semver range (`^1.2`, `>=2, <3`).
+///
+/// Tags with a semver name are ordered by version and always win over tags
+/// without one, which are ordered by commit time.
+pub fn newest(repository: &Repository, pattern: &str) -> Result<Option<(String, Oid)>> {
+    let mut best: Option<(Option<Version>, i64, String, Oid)> = None;
+    for name in repository.tag_names(None)?.iter().flatten() {
+        if !matches(pattern, name) {
+            continue;
+        }
+        let version = version(name);
+
+        let commit = repository
+            .revparse_single(&format!("refs/tags/{}", name))?
+            .peel_to_commit()?;
+        let candidate = (
+            version,
+            commit.time().seconds(),
+            name.to_string(),
+            commit.id(),
+        );
+
+        let newer = match &best {
+            Some(best) => (&candidate.0, candidate.1) > (&best.0, best.1),
+            None => true,
+        };
+        if newer {
+            best = Some(candidate);
+        }
+    }
+
+    Ok(best.map(|(_, _, name, oid)| (name, oid)))
+}
@@ -0,0 +1,171 @@
+use crate::cancel;
+use crate::config::{Provider, Repo, Tarball};
+use crate::error::{Error, Result};
+use crate::forge::Api;
+use crate::output::{Event, Output};
+use crate::source::{Revision, Source};
+use crate::throttle::Throttle;
+use flate2::read::GzDecoder;
+use git2::{ErrorClass, ErrorCode, Oid};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::instrument;
+
+/// A file next to the checkout, like the lock, named `.<name>.autoupdate.<suffix>`.
+fn sibling(repo: &Repo, suffix: &str) -> PathBuf {
+    let path = Path::new(&repo.path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.autoupdate.{}", name, suffix))
+}
+
+/// The tree of the tracked ref, downloaded as an archive from the forge's API.
+pub(crate) struct Download<'a> {
+    repo: &'a Repo,
+    api: Api<'a>,
+}
+
+impl<'a> Download<'a> {
+    pub(crate) fn new(repo: &'a Repo, tarball: &Tarball) -> Result<Download<'a>> {
+        Ok(Download {
+            repo,
+            api: Api::new(repo, tarball.provider, tarball.api_url.as_deref())?,
+        })
+    }
+
+    fn archive_url(&self, commit: Oid) -> String {
+        match self.api.provider() {
+            Provider::Github => format!("{}/tarball/{}", self.api.project_url(), commit),
+            Provider::Gitlab => format!(
+                "{}/repository/archive.tar.gz?sha={}",
+                self.api.project_url(),
+                commit
+            ),
+        }
+    }
+
+    /// Unpacks the archive next to `into` first; it holds the tree in a
+    /// single directory named after the project and commit.
+    #[instrument(skip_all, fields(%commit))]
+    fn download(&self, commit: Oid, into: &Path) -> Result<()> {
+        let url = self.archive_url(commit);
+        let body = self.api.get(&url)?.into_body().into_reader();
+        let reader = Paced {
+            inner: body,
+            throttle: Throttle::new(self.repo.max_bandwidth()?),
+            received: 0,
+        };
+
+        let unpacked = into.with_extension("unpack");
+        if unpacked.exists() {
+            fs::remove_dir_all(&unpacked)?;
+        }
+        let result = tar::Archive::new(GzDecoder::new(reader)).unpack(&unpacked);
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&unpacked);
+            return Err(match cancel::requested() {
+                true => Error::Interrupted(format!("downloading {}", url)),
+                false => git2::Error::new(
+                    ErrorCode::GenericError,
+                    ErrorClass::Net,
+                    format!("{}: {}", url, e),
+                )
+                .into(),
+            });
+        }
+
+        let mut directories = fs::read_dir(&unpacked)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()));
+        let tree = match (directories.next(), directories.next()) {
+            (Some(tree), None) => tree.path(),
+            _ => {
+                let _ = fs::remove_dir_all(&unpacked);
+                return Err(Error::Io(io::Error::other(format!(
+                    "the archive of {} does not hold a single directory",
+                    commit
+                ))));
+            }
+        };
+        fs::rename(tree, into)?;
+        fs::remove_dir_all(&unpacked)?;
+        Ok(())
+    }
+}
+
+impl Source for Download<'_> {
+    fn describe(&self) -> String {
+        self.api.describe()
+    }
+
+    /// Recorded outside of the checkout, which only holds the tree.
+    fn current(&self) -> Result<Option<Oid>> {
+        if !Path::new(&self.repo.path).is_dir() {
+            return Ok(None);
+        }
+        let revision = fs::read_to_string(sibling(self.repo, "revision")).ok();
+        Ok(revision.and_then(|revision| Oid::from_str(revision.trim()).ok()))
+    }
+
+    #[instrument(skip_all)]
+    fn resolve(&self, _output: &Output) -> Result<Revision> {
+        self.api
+            .commit(self.repo.rev.as_deref().unwrap_or(&self.repo.branch))
+    }
+
+    /// Replaces the checkout with the downloaded tree once it is complete.
+    /// Local changes to the checkout are lost.
+    fn checkout(&self, commit: Oid, output: &Output) -> Result<()> {
+        let path = Path::new(&self.repo.path);
+        output.emit(Event::DownloadStarted {
+            path: &self.repo.path,
+            source: self.describe(),
+            commit: commit.to_string(),
+        });
+        let staged = sibling(self.repo, "download");
+        if staged.exists() {
+            fs::remove_dir_all(&staged)?;
+        }
+        self.download(commit, &staged)?;
+
+        let replaced = sibling(self.repo, "replaced");
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)?;
+        }
+        if path.exists() {
+            fs::rename(path, &replaced)?;
+        }
+        fs::rename(&staged, path)?;
+        fs::write(sibling(self.repo, "revision"), format!("{}\n", commit))?;
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)?;
+        }
+        output.emit(Event::DownloadFinished {
+            path: &self.repo.path,
+            commit: commit.to_string(),
+        });
+        Ok(())
+    }
+}
+
+/// Reads the download at the configured rate and stops on Ctrl-C.
+struct Paced<R> {
+    inner: R,
+    throttle: Throttle,
+    received: usize,
+}
+
+impl<R: Read> Read for Paced<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if cancel::requested() {
+            return Err(io::Error::other("interrupted"));
+        }
+        let read = self.inner.read(buffer)?;
+        self.received += read;
+        self.throttle.pace(self.received);
+        Ok(read)
+    }
+}
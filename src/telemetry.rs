@@ -0,0 +1,92 @@
+use autoupdate::config::Telemetry;
+use autoupdate::{Error, Result};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Exports over the same HTTP client as everything else.
+#[derive(Debug)]
+struct Client(ureq::Agent);
+
+#[async_trait::async_trait]
+impl HttpClient for Client {
+    async fn send_bytes(
+        &self,
+        request: Request<Bytes>,
+    ) -> std::result::Result<Response<Bytes>, HttpError> {
+        let (parts, body) = request.into_parts();
+        let response = self.0.run(Request::from_parts(parts, body.to_vec()))?;
+        let (parts, mut body) = response.into_parts();
+        Ok(Response::from_parts(
+            parts,
+            Bytes::from(body.read_to_vec()?),
+        ))
+    }
+}
+
+/// The tracer whose spans are exported in batches to the collector.
+pub(crate) fn tracer(settings: &Telemetry) -> Result<SdkTracer> {
+    let mut headers = HashMap::new();
+    for header in &settings.headers {
+        headers.insert(header.name.clone(), header.value()?);
+    }
+    let agent = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(EXPORT_TIMEOUT))
+        .build()
+        .new_agent();
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_http_client(Client(agent))
+        .with_endpoint(settings.traces_url())
+        .with_headers(headers)
+        .build()
+        .map_err(|e| Error::Config(format!("invalid telemetry settings: {}", e)))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(settings.service_name().to_string())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    let _ = PROVIDER.set(provider);
+    Ok(tracer)
+}
+
+/// A span around the whole run that continues the trace of whatever started
+/// it, as passed on in the W3C `TRACEPARENT` and `TRACESTATE` variables.
+pub(crate) fn root() -> Option<tracing::Span> {
+    PROVIDER.get()?;
+    let mut carrier = HashMap::new();
+    carrier.insert(
+        "traceparent".to_string(),
+        std::env::var("TRACEPARENT").ok()?,
+    );
+    if let Ok(state) = std::env::var("TRACESTATE") {
+        carrier.insert("tracestate".to_string(), state);
+    }
+    let span = tracing::info_span!("autoupdate");
+    let _ = span.set_parent(TraceContextPropagator::new().extract(&carrier));
+    Some(span)
+}
+
+/// Exports the spans still waiting for their batch.
+pub(crate) fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        let _ = provider.shutdown();
+    }
+}
@@ -0,0 +1,29 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Keeps a transfer under a rate limit by pausing in its progress callback,
+/// which stops libgit2 from reading the socket until the transfer is back on pace.
+pub struct Throttle {
+    rate: Option<u64>,
+    started: Instant,
+}
+
+impl Throttle {
+    pub fn new(rate: Option<u64>) -> Throttle {
+        Throttle {
+            rate,
+            started: Instant::now(),
+        }
+    }
+
+    /// Sleeps until `received` bytes are no more than the rate allows so far.
+    pub fn pace(&self, received: usize) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let due = Duration::from_secs_f64(received as f64 / rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::Repo;
+use crate::error::{Error, Result};
+use std::cell::Cell;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+/// Time limit of an operation, counted from the first libgit2 callback it
+/// makes, after which the callbacks ask libgit2 to stop.
+pub struct Deadline {
+    limit: Option<Duration>,
+    started: Cell<Option<Instant>>,
+    expired: Cell<bool>,
+}
+
+impl Deadline {
+    pub fn new(limit: Option<Duration>) -> Deadline {
+        Deadline {
+            limit,
+            started: Cell::new(None),
+            expired: Cell::new(false),
+        }
+    }
+
+    /// Whether the operation may go on; the callbacks return this to libgit2.
+    pub fn check(&self) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let started = self.started.get().unwrap_or_else(Instant::now);
+        self.started.set(Some(started));
+        if started.elapsed() >= limit {
+            self.expired.set(true);
+        }
+        !self.expired.get()
+    }
+
+    pub fn expired(&self) -> bool {
+        self.expired.get()
+    }
+
+    /// Fails when the deadline passed; libgit2 reports an aborted checkout as
+    /// a success, so callers check this before moving any reference.
+    pub fn ensure(&self, what: &str) -> Result<()> {
+        match self.expired() {
+            true => Err(self.error(what)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn error(&self, what: &str) -> Error {
+        Error::Timeout(format!(
+            "{} took longer than {}",
+            what,
+            humantime::format_duration(self.limit.unwrap_or_default())
+        ))
+    }
+}
+
+/// Sets the libgit2 connect timeout the first time a repository configures one,
+/// and makes reads give up after the transfer timeout so a silent remote cannot
+/// stall a sync past its deadline.
+pub fn configure(repo: &Repo) -> Result<()> {
+    static CONFIGURED: Once = Once::new();
+    let connect = repo.timeouts.connect()?;
+    let transfer = repo.timeouts.transfer()?;
+    if connect.is_none() && transfer.is_none() {
+        return Ok(());
+    }
+
+    let mut result = Ok(());
+    CONFIGURED.call_once(|| result = set_timeouts(connect, transfer));
+    result.map_err(Error::from)
+}
+
+fn set_timeouts(
+    connect: Option<Duration>,
+    transfer: Option<Duration>,
+) -> std::result::Result<(), git2::Error> {
+    let millis = |limit: Duration| limit.as_millis().min(i32::MAX as u128) as i32;
+    // libgit2 only reads these values when it opens a connection
+    unsafe {
+        if let Some(connect) = connect {
+            git2::opts::set_server_connect_timeout_in_milliseconds(millis(connect))?;
+        }
+        if let Some(transfer) = transfer {
+            git2::opts::set_server_timeout_in_milliseconds(millis(transfer))?;
+        }
+    }
+    Ok(())
+}
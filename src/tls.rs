@@ -0,0 +1,77 @@
+use crate::config::Repo;
+use crate::error::Result;
+use crate::known_hosts;
+use crate::output::{Event, Output};
+use git2::{CertificateCheckStatus, ErrorClass, ErrorCode, RemoteCallbacks};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Adds the configured CA bundle to the certificates libgit2 trusts. The store
+/// is shared by the whole process, so every bundle is loaded once and trusted
+/// for all repositories. Warns before connecting without any checks.
+pub fn configure(repo: &Repo, output: &Output) -> Result<()> {
+    static LOADED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+    if repo.tls.as_ref().is_some_and(|tls| tls.skip_verify) {
+        output.emit(Event::TlsUnverified { path: &repo.path });
+    }
+    let Some(ca_file) = repo.tls.as_ref().and_then(|tls| tls.ca_file.as_ref()) else {
+        return Ok(());
+    };
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    let loaded = loaded.get_or_insert_with(HashSet::new);
+    if !loaded.contains(ca_file) {
+        unsafe { git2::opts::set_ssl_cert_file(ca_file.as_str())? };
+        loaded.insert(ca_file.clone());
+    }
+    Ok(())
+}
+
+/// Checks SSH host keys against known_hosts, and accepts server certificates
+/// whose SHA-256 fingerprint is pinned, or any certificate when verification
+/// is turned off. Other certificates are left to libgit2's own verification.
+pub fn attach<'a>(cb: &mut RemoteCallbacks<'a>, repo: &'a Repo, output: &'a Output) {
+    // libgit2 reports failures of its own checks more precisely without a callback
+    let checked = repo
+        .tls
+        .as_ref()
+        .is_some_and(|tls| !tls.pins.is_empty() || tls.skip_verify);
+    let ssh = match repo.url.split_once("://") {
+        Some((scheme, _)) => scheme.contains("ssh"),
+        None => true,
+    };
+    if !checked && !ssh {
+        return;
+    }
+    cb.certificate_check(move |cert, host| {
+        if let Some(hostkey) = cert.as_hostkey() {
+            return known_hosts::verify(repo, hostkey, host, output);
+        }
+        let (Some(x509), Some(tls)) = (cert.as_x509(), &repo.tls) else {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        };
+        if tls.pins.is_empty() && !tls.skip_verify {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        }
+        if tls.skip_verify {
+            return Ok(CertificateCheckStatus::CertificateOk);
+        }
+        let fingerprint = hex::encode(Sha256::digest(x509.data()));
+        match tls.pins.iter().any(|pin| fingerprint == normalize(pin)) {
+            true => Ok(CertificateCheckStatus::CertificateOk),
+            false => Err(git2::Error::new(
+                ErrorCode::Certificate,
+                ErrorClass::Callback,
+                format!(
+                    "certificate of {} with sha256 fingerprint {} is not pinned",
+                    host, fingerprint
+                ),
+            )),
+        }
+    });
+}
+
+/// Fingerprints may be written with colons and in either case, as tools print them.
+pub fn normalize(pin: &str) -> String {
+    pin.replace(':', "").to_ascii_lowercase()
+}
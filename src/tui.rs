@@ -0,0 +1,252 @@
+use crate::Command;
+use autoupdate::config::Repo;
+use autoupdate::output::{Event, Observer, Outcome};
+use ratatui::crossterm::event::{self, Event as Input, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the dashboard redraws and looks for key presses.
+const TICK: Duration = Duration::from_millis(250);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// What the dashboard knows about one repository, gathered from its events.
+#[derive(Default)]
+struct RepoState {
+    commit: Option<String>,
+    last_sync: Option<u64>,
+    status: Option<String>,
+    failed: bool,
+    /// Step, done and total work of the transfer or checkout running now.
+    progress: Option<(&'static str, u64, u64)>,
+    paused: bool,
+}
+
+#[derive(Default)]
+struct State {
+    repos: HashMap<String, RepoState>,
+    next_check: Option<u64>,
+}
+
+impl State {
+    fn repo(&mut self, path: &str) -> &mut RepoState {
+        self.repos.entry(path.to_string()).or_default()
+    }
+
+    fn observe(&mut self, event: &Event) {
+        match event {
+            Event::CloneStarted { path, .. } | Event::CloneResumed { path } => {
+                self.repo(path).status = Some("cloning".into())
+            }
+            Event::ObjectsReceived {
+                path,
+                received,
+                total,
+            } => self.repo(path).progress = Some(("receiving", *received, *total)),
+            Event::DeltasResolved {
+                path,
+                indexed,
+                total,
+            } => self.repo(path).progress = Some(("resolving deltas", *indexed, *total)),
+            Event::CheckoutProgress {
+                path,
+                completed,
+                total,
+            } => self.repo(path).progress = Some(("checking out", *completed, *total)),
+            Event::Head { path, commit, .. } => self.repo(path).commit = Some(commit.clone()),
+            Event::Summary { repositories } => {
+                let now = now();
+                for entry in repositories {
+                    let repo = self.repo(entry.path);
+                    repo.last_sync = Some(now);
+                    repo.progress = None;
+                    repo.failed = matches!(entry.status, Outcome::Failed);
+                    repo.status = Some(match &entry.error {
+                        Some(error) => format!("failed: {}", error),
+                        None => entry.status.describe().to_string(),
+                    });
+                }
+            }
+            Event::NextCheck { seconds } => self.next_check = Some(now() + seconds),
+            Event::Paused { path, paused } => self.repo(path).paused = *paused,
+            _ => {}
+        }
+    }
+}
+
+/// Terminal dashboard of the configured repositories for watch mode. It is
+/// drawn on its own thread from the events of the output it observes, and
+/// gives the terminal back when dropped.
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Dashboard {
+    /// Takes over the terminal; keys sync or pause the selected repository
+    /// through `tx` like webhooks do.
+    pub fn start(repos: Arc<RwLock<Vec<Repo>>>, tx: Sender<Command>) -> Dashboard {
+        let state = Arc::<Mutex<State>>::default();
+        let stop = Arc::<AtomicBool>::default();
+        let terminal = ratatui::init();
+        let thread = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || run(terminal, &state, &repos, &tx, &stop))
+        };
+        Dashboard {
+            state,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn observer(&self) -> Observer {
+        let state = self.state.clone();
+        Arc::new(move |event| lock(&state).observe(event))
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        ratatui::restore();
+    }
+}
+
+fn run(
+    mut terminal: DefaultTerminal,
+    state: &Mutex<State>,
+    repos: &RwLock<Vec<Repo>>,
+    tx: &Sender<Command>,
+    stop: &AtomicBool,
+) {
+    let mut table = TableState::default().with_selected(Some(0));
+    while !stop.load(Ordering::Relaxed) {
+        // Reloads replace the repositories, their order matches the updaters
+        let rows: Vec<(String, String)> = repos
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|repo| (repo.path.clone(), repo.branch.clone()))
+            .collect();
+        let _ = terminal.draw(|frame| draw(frame, &lock(state), &rows, &mut table));
+
+        if !event::poll(TICK).unwrap_or(false) {
+            continue;
+        }
+        let Ok(Input::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = table.selected().filter(|index| *index < rows.len());
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => {
+                let _ = tx.send(Command::Stop);
+            }
+            // Raw mode turns Ctrl-C into a key press instead of a signal
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let _ = tx.send(Command::Stop);
+            }
+            KeyCode::Up | KeyCode::Char('k') => table.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => table.select_next(),
+            KeyCode::Char('s') => {
+                if let Some(index) = selected {
+                    lock(state).repo(&rows[index].0).status = Some("syncing".into());
+                    let _ = tx.send(Command::Sync(index));
+                }
+            }
+            KeyCode::Char('p') => {
+                if let Some(index) = selected {
+                    let _ = tx.send(Command::Pause(index, None));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, state: &State, rows: &[(String, String)], table: &mut TableState) {
+    let [body, footer] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+    let now = now();
+
+    let rows = rows.iter().map(|(path, branch)| {
+        let repo = state.repos.get(path);
+        let commit = repo
+            .and_then(|repo| repo.commit.as_deref())
+            .map(|commit| commit.chars().take(7).collect())
+            .unwrap_or_else(|| "-".to_string());
+        let last_sync = repo
+            .and_then(|repo| repo.last_sync)
+            .map(|time| {
+                let ago = Duration::from_secs(now.saturating_sub(time));
+                format!("{} ago", humantime::format_duration(ago))
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let mut status = match repo.and_then(|repo| repo.progress) {
+            Some((step, done, total)) => {
+                let percent = (done * 100).checked_div(total).unwrap_or(100);
+                format!("{} {}%", step, percent)
+            }
+            None => repo
+                .and_then(|repo| repo.status.clone())
+                .unwrap_or_else(|| "waiting".to_string()),
+        };
+        let paused = repo.is_some_and(|repo| repo.paused);
+        if paused {
+            status = format!("paused, {}", status);
+        }
+        let style = match (repo.is_some_and(|repo| repo.failed), paused) {
+            (true, _) => Style::new().fg(Color::Red),
+            (false, true) => Style::new().fg(Color::DarkGray),
+            (false, false) => Style::new(),
+        };
+        Row::new([path.clone(), branch.clone(), commit, last_sync, status]).style(style)
+    });
+    let widths = [
+        Constraint::Fill(2),
+        Constraint::Length(16),
+        Constraint::Length(8),
+        Constraint::Length(14),
+        Constraint::Fill(3),
+    ];
+    let repositories = Table::new(rows, widths)
+        .header(Row::new(["Repository", "Branch", "Commit", "Last sync", "Status"]).bold())
+        .block(Block::bordered().title(" autoupdate "))
+        .row_highlight_style(Style::new().reversed());
+    frame.render_stateful_widget(repositories, body, table);
+
+    let next = state
+        .next_check
+        .map(|time| format!("next check in {}s   ", time.saturating_sub(now)))
+        .unwrap_or_default();
+    let help = format!(
+        " {}↑/↓ select   s sync now   p pause schedule   q quit",
+        next
+    );
+    frame.render_widget(Paragraph::new(help).dim(), footer);
+}
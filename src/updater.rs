@@ -0,0 +1,1876 @@
+use crate::archive;
+use crate::auth;
+use crate::cancel;
+use crate::changes::{self, Changes};
+use crate::ci;
+use crate::clean;
+use crate::config::{CorruptionPolicy, DirtyPolicy, DriftPolicy, Header, Repo, Strategy, Vcs};
+use crate::disk::{self, Space};
+use crate::error::{Error, Result};
+use crate::filter;
+use crate::hooks::{self, Stage};
+use crate::integrity;
+use crate::leader;
+use crate::lfs;
+use crate::lock;
+use crate::maintenance;
+use crate::metrics::RepoMetrics;
+use crate::notify::{Notification, Notifier};
+use crate::output::{CommitInfo, Event, Format, Outcome, Output};
+use crate::ownership;
+use crate::propagate;
+use crate::releases;
+use crate::repair;
+use crate::resolve;
+use crate::retry;
+use crate::rollout;
+use crate::source;
+use crate::sparse;
+use crate::state::{self, HistoryEntry, State};
+use crate::swap;
+use crate::tags;
+use crate::throttle::Throttle;
+use crate::timeout::{self, Deadline};
+use crate::tls;
+use crate::verify;
+use crate::window;
+use crate::worktree;
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    CheckoutNotificationType, Commit, Direction, ErrorCode, FetchOptions, FetchPrune, Oid,
+    ProxyOptions, Remote, RemoteCallbacks, Repository, Signature, StashFlags, StatusOptions,
+    SubmoduleUpdateOptions,
+};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, instrument, warn};
+
+thread_local! {
+    /// Bytes received by clones and fetches of the sync running on this thread.
+    static RECEIVED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Fetches every ref of the remote into the same name, like `git clone --mirror`.
+const MIRROR_REFSPEC: &str = "+refs/*:refs/*";
+
+fn add_received(bytes: usize) {
+    RECEIVED.with(|received| received.set(received.get() + bytes as u64));
+}
+
+/// Result of a successful [`Updater::sync`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// The local branch already pointed at the remote commit.
+    UpToDate { commit: Oid },
+    /// Dry run only: the remote has `commits` (newest first) that were not applied.
+    UpdateAvailable {
+        old: Oid,
+        new: Oid,
+        commits: Vec<Oid>,
+    },
+    /// The local branch was reset from `old` to `new`.
+    Updated { old: Oid, new: Oid },
+    /// The remote moved on to `new`, but a maintenance window, the path
+    /// filter, approval or the rollout holds the update back for now.
+    Held { old: Oid, new: Oid },
+}
+
+/// Keeps the checkout of a single configured repository in sync with its remote.
+pub struct Updater {
+    repo: Repo,
+    output: Output,
+    notifiers: Vec<Arc<dyn Notifier>>,
+    wait_for_lock: bool,
+    metrics: Option<Arc<RepoMetrics>>,
+    repository: Option<Repository>,
+    /// Whether the last measured disk usage was over the quota.
+    over_quota: bool,
+    /// Syncs in a row that could not reach the remote.
+    unreachable: u32,
+}
+
+impl Updater {
+    pub fn new(repo: Repo) -> Updater {
+        Updater {
+            repo,
+            output: Output::new(Format::Human),
+            notifiers: Vec::new(),
+            wait_for_lock: false,
+            metrics: None,
+            repository: None,
+            over_quota: false,
+            unreachable: 0,
+        }
+    }
+
+    /// Replaces the default human-readable output.
+    pub fn with_output(mut self, output: Output) -> Updater {
+        self.output = output;
+        self
+    }
+
+    /// Adds a notifier that is told about applied updates and failures.
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Updater {
+        self.notifiers.push(notifier);
+        self
+    }
+
+    /// Waits for another run holding the repository lock instead of failing.
+    pub fn with_lock_wait(mut self, wait: bool) -> Updater {
+        self.wait_for_lock = wait;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<RepoMetrics>) -> Updater {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn repo(&self) -> &Repo {
+        &self.repo
+    }
+
+    /// Number of syncs in a row that failed to reach the remote, 0 when the
+    /// last one did.
+    pub fn unreachable(&self) -> u32 {
+        self.unreachable
+    }
+
+    /// Opens the local repository, cloning it first if it does not exist yet.
+    pub fn ensure_cloned(&mut self) -> Result<&Repository> {
+        if self.repository.is_none() {
+            self.repository = Some(clone(&self.repo, &self.output)?);
+        }
+        Ok(self.repository.as_ref().unwrap())
+    }
+
+    /// Repacks the object database of the existing checkout and prunes its
+    /// unreachable objects right away, whatever the configured interval.
+    #[instrument(skip_all, fields(path = %self.repo.path))]
+    pub fn maintain(&mut self) -> Result<()> {
+        let _lock = lock::acquire(&self.repo.path, self.wait_for_lock, &self.output)?;
+        self.refuse_source("maintained")?;
+        let repository = Repository::open(&self.repo.path)?;
+        maintenance::run(&self.repo, &repository, &self.output)?;
+        let mut state = State::load(&repository)?;
+        state.updates_since_maintenance = 0;
+        state.save(&repository)
+    }
+
+    /// Resets the working tree back to the commit it was at `steps` updates
+    /// ago. Returns the commit it was at and the one it is at now.
+    #[instrument(skip_all, fields(path = %self.repo.path, steps))]
+    pub fn rollback(&mut self, steps: usize) -> Result<(Oid, Oid)> {
+        let _lock = lock::acquire(&self.repo.path, self.wait_for_lock, &self.output)?;
+        // Worktrees of one repository fetch into the same object database
+        let _shared = self
+            .repo
+            .worktree_of
+            .as_deref()
+            .map(|shared| lock::acquire(shared, true, &self.output))
+            .transpose()?;
+        self.refuse_mirror("rolled back")?;
+        self.refuse_source("rolled back")?;
+        let repository = Repository::open(&self.repo.path)?;
+        let mut state = State::load(&repository)?;
+
+        if steps == 0 || steps > state.previous.len() {
+            return Err(Error::Refused(format!(
+                "'{}' has {} recorded update(s), could not roll back {}",
+                self.repo.path,
+                state.previous.len(),
+                steps
+            )));
+        }
+        let target = state.previous[state.previous.len() - steps].as_str();
+        let target = repository.find_commit(Oid::from_str(target)?)?;
+        let current = repository.head()?.peel_to_commit()?.id();
+
+        guard_dirty(&self.repo, &repository, &self.output)?;
+        let mut cb = CheckoutBuilder::new();
+        clean::protect(&self.repo, &mut cb);
+        sparse::restrict(&self.repo, &mut cb);
+        repository.reset(target.as_object(), git2::ResetType::Hard, Some(&mut cb))?;
+        sparse::mark(&self.repo, &repository)?;
+        if let Some(releases) = &self.repo.releases {
+            releases::publish(&self.repo, releases, &repository, target.id(), &self.output)?;
+        }
+
+        state.previous.truncate(state.previous.len() - steps);
+        state.skip = Some(current.to_string());
+        state.save(&repository)?;
+
+        self.output.emit(Event::RolledBack {
+            path: &self.repo.path,
+            from: current.to_string(),
+            to: target.id().to_string(),
+        });
+        Ok((current, target.id()))
+    }
+
+    /// Lets the update waiting for approval through to the next sync. When
+    /// `commit` is given, it has to be the one the update goes to.
+    #[instrument(skip_all, fields(path = %self.repo.path))]
+    pub fn approve(&mut self, commit: Option<&str>) -> Result<Oid> {
+        let _lock = lock::acquire(&self.repo.path, self.wait_for_lock, &self.output)?;
+        self.refuse_mirror("approved")?;
+        self.refuse_source("approved")?;
+        let repository = Repository::open(&self.repo.path)?;
+        let mut state = State::load(&repository)?;
+        let Some(pending) = state.pending.clone() else {
+            return Err(Error::Refused(format!(
+                "'{}' has no update waiting for approval",
+                self.repo.path
+            )));
+        };
+        if let Some(commit) = commit.filter(|commit| !pending.starts_with(*commit)) {
+            return Err(Error::Refused(format!(
+                "the update of '{}' waiting for approval goes to {}, not {}",
+                self.repo.path, pending, commit
+            )));
+        }
+        state.approved = Some(pending.clone());
+        state.save(&repository)?;
+        self.output.emit(Event::Approved {
+            path: &self.repo.path,
+            commit: pending.clone(),
+        });
+        Ok(Oid::from_str(&pending)?)
+    }
+
+    /// Tells about the update waiting for approval and returns whether there is one.
+    pub fn awaiting_approval(&self) -> Result<bool> {
+        let repository = Repository::open(&self.repo.path)?;
+        let state = State::load(&repository)?;
+        let Some(pending) = state
+            .pending
+            .filter(|pending| state.approved.as_ref() != Some(pending))
+        else {
+            return Ok(false);
+        };
+        let new = Oid::from_str(&pending)?;
+        let old = repository.head()?.peel_to_commit()?.id();
+        self.output.emit(Event::AwaitingApproval {
+            path: &self.repo.path,
+            old: old.to_string(),
+            new: pending,
+            commits: commit_infos(&repository, old, new)?,
+        });
+        Ok(true)
+    }
+
+    /// Fetches `by` more commits of history, or all of it when `by` is `None`.
+    #[instrument(skip_all, fields(path = %self.repo.path, by))]
+    pub fn deepen(&mut self, by: Option<u32>) -> Result<()> {
+        let _lock = lock::acquire(&self.repo.path, self.wait_for_lock, &self.output)?;
+        self.refuse_mirror("deepened")?;
+        self.refuse_source("deepened")?;
+        self.ensure_cloned()?;
+        let repository = self.repository.as_ref().unwrap();
+        if !repository.is_shallow() {
+            return Ok(());
+        }
+
+        // libgit2 only knows absolute depths, counted from the fetched tip
+        let depth = match by {
+            Some(by) => {
+                let mut walk = repository.revwalk()?;
+                walk.push_head()?;
+                (walk.count() as u32)
+                    .saturating_add(by)
+                    .min(i32::MAX as u32) as i32
+            }
+            None => i32::MAX,
+        };
+
+        let refspecs = [format!(
+            "+refs/heads/{}:refs/remotes/{}/{}",
+            self.repo.branch,
+            self.repo.remote(),
+            self.repo.branch
+        )];
+        fetch_with_depth(&self.repo, repository, &refspecs, Some(depth), &self.output)?;
+        self.output.emit(Event::Deepened {
+            path: &self.repo.path,
+            shallow: repository.is_shallow(),
+        });
+        Ok(())
+    }
+
+    fn refuse_mirror(&self, action: &str) -> Result<()> {
+        match self.repo.mirror {
+            true => Err(Error::Refused(format!(
+                "'{}' is a mirror and could not be {}",
+                self.repo.path, action
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// The other sources have no git repository to work on.
+    fn refuse_source(&self, action: &str) -> Result<()> {
+        let source = match (self.repo.vcs, &self.repo.tarball) {
+            (Vcs::Mercurial, _) => "is a Mercurial repository",
+            (Vcs::Git, Some(_)) => "is downloaded as tarballs, without history,",
+            (Vcs::Git, None) => return Ok(()),
+        };
+        Err(Error::Refused(format!(
+            "'{}' {} and could not be {}",
+            self.repo.path, source, action
+        )))
+    }
+
+    /// Fetches the remote branch and resets the local branch to it when they differ.
+    #[instrument(name = "sync", skip_all, fields(path = %self.repo.path))]
+    pub fn sync(&mut self) -> Result<SyncOutcome> {
+        // Repositories still waiting their turn when Ctrl-C came are left alone
+        cancel::ensure(&format!("syncing '{}'", self.repo.path))?;
+        // A run that finds the lock taken is not worth a notification
+        let _lock = lock::acquire(&self.repo.path, self.wait_for_lock, &self.output)?;
+        let started = SystemTime::now();
+        RECEIVED.with(|received| received.set(0));
+        let result = match self.update() {
+            Err(e)
+                if self.repo.corruption == CorruptionPolicy::Repair
+                    && repair::corrupt(&self.repo, &e) =>
+            {
+                self.repair(&e)
+            }
+            result => result,
+        };
+        if let (Ok(SyncOutcome::Updated { .. }), Some(repository)) = (&result, &self.repository) {
+            if let Err(e) = maintenance::after_update(&self.repo, repository, &self.output) {
+                self.output.emit(Event::MaintenanceFailed {
+                    path: &self.repo.path,
+                    message: e.to_string(),
+                });
+            }
+        }
+        // Walking the checkout is only worth it when its size may have changed
+        let updated = matches!(result, Ok(SyncOutcome::Updated { .. }));
+        let stale = self
+            .metrics
+            .as_ref()
+            .is_some_and(|metrics| updated || metrics.disk_usage().is_none());
+        if self.repo.quota.is_some() || stale {
+            self.measure_disk(updated);
+        }
+        self.track_network(&result);
+        self.record(started, &result);
+        self.measure(&result);
+        self.notify(&result);
+        result
+    }
+
+    fn update(&mut self) -> Result<SyncOutcome> {
+        if let Some(source) = source::of(&self.repo)? {
+            return source::update(&self.repo, source.as_ref(), &self.output);
+        }
+        self.ensure_cloned()?;
+        update(&self.repo, self.repository.as_mut().unwrap(), &self.output)
+    }
+
+    /// Moves the corrupt repository aside and syncs a fresh clone instead.
+    fn repair(&mut self, problem: &Error) -> Result<SyncOutcome> {
+        self.repository = None;
+        let backup = repair::set_aside(&self.repo)?;
+        self.output.emit(Event::Repairing {
+            path: &self.repo.path,
+            problem: problem.to_string(),
+            backup: backup.display().to_string(),
+        });
+        self.update()
+    }
+
+    /// Tells once when the remote becomes unreachable and once when it is back.
+    fn track_network(&mut self, result: &Result<SyncOutcome>) {
+        let path = &self.repo.path;
+        match result {
+            Err(e) if e.is_network() => {
+                self.unreachable += 1;
+                if self.unreachable == 1 {
+                    self.output.emit(Event::RemoteUnreachable { path });
+                }
+            }
+            _ if self.unreachable > 0 => {
+                self.output.emit(Event::RemoteReachable {
+                    path,
+                    failures: self.unreachable,
+                });
+                self.unreachable = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Measures how much space the checkout takes up and holds it against
+    /// the quota. An update that pushed it over first gets a maintenance run
+    /// to win space back; the warning is given when it crosses the quota.
+    fn measure_disk(&mut self, updated: bool) {
+        let path = Path::new(&self.repo.path);
+        let mut usage = disk::usage(path);
+        // Checked by `Settings::validate`
+        let quota = self.repo.quota().ok().flatten();
+        if let (Some(quota), Some(repository), true) = (quota, &self.repository, updated) {
+            if usage > quota && maintenance::run(&self.repo, repository, &self.output).is_ok() {
+                usage = disk::usage(path);
+            }
+        }
+        debug!(usage, "measured disk usage");
+        if let Some(metrics) = &self.metrics {
+            metrics.set_disk_usage(usage);
+        }
+
+        let over = quota.is_some_and(|quota| usage > quota);
+        if over && !self.over_quota {
+            self.output.emit(Event::QuotaExceeded {
+                path: &self.repo.path,
+                usage,
+                quota: quota.unwrap_or_default(),
+            });
+        }
+        self.over_quota = over;
+    }
+
+    fn measure(&self, result: &Result<SyncOutcome>) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        let head_time = self
+            .repository
+            .as_ref()
+            .and_then(|repository| repository.head().ok()?.peel_to_commit().ok())
+            .map(|commit| commit.time().seconds());
+        metrics.record(result, RECEIVED.with(Cell::get), head_time);
+    }
+
+    /// Every recorded sync of the repository, oldest first.
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        state::history(&Repository::open(&self.repo.path)?)
+    }
+
+    fn record(&self, started: SystemTime, result: &Result<SyncOutcome>) {
+        // Nothing to record into when the clone itself failed
+        let Some(repository) = &self.repository else {
+            return;
+        };
+        let head = || {
+            repository
+                .head()
+                .ok()
+                .and_then(|head| head.target())
+                .map(|oid| oid.to_string())
+        };
+        let (old, new, outcome) = match result {
+            Ok(SyncOutcome::UpToDate { commit }) => (
+                Some(commit.to_string()),
+                Some(commit.to_string()),
+                Outcome::UpToDate,
+            ),
+            Ok(SyncOutcome::Updated { old, new }) => (
+                Some(old.to_string()),
+                Some(new.to_string()),
+                Outcome::Updated,
+            ),
+            Ok(SyncOutcome::UpdateAvailable { old, new, .. }) => (
+                Some(old.to_string()),
+                Some(new.to_string()),
+                Outcome::UpdateAvailable,
+            ),
+            Ok(SyncOutcome::Held { old, new }) => {
+                (Some(old.to_string()), Some(new.to_string()), Outcome::Held)
+            }
+            Err(_) => (head(), None, Outcome::Failed),
+        };
+
+        let entry = HistoryEntry {
+            timestamp: started
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            old,
+            new,
+            duration_ms: started
+                .elapsed()
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            outcome,
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        if let Err(e) = state::record(repository, &entry) {
+            tracing::warn!("could not record history of '{}': {}", self.repo.path, e);
+        }
+    }
+
+    fn notify(&self, result: &Result<SyncOutcome>) {
+        if self.notifiers.is_empty() || self.repo.offline {
+            return;
+        }
+
+        let path = self.repo.path.as_str();
+        let commits;
+        let notification = match result {
+            Ok(SyncOutcome::Updated { old, new }) => {
+                commits = self
+                    .repository
+                    .as_ref()
+                    .and_then(|repository| commit_infos(repository, *old, *new).ok())
+                    .unwrap_or_default();
+                Notification::Updated {
+                    path,
+                    old: *old,
+                    new: *new,
+                    commits: &commits,
+                }
+            }
+            Err(error) => Notification::Failed { path, error },
+            Ok(_) => return,
+        };
+
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(&notification) {
+                self.output.emit(Event::NotifyFailed {
+                    path,
+                    message: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+#[instrument(skip_all, fields(url = %repo.url))]
+fn clone(repo: &Repo, output: &Output) -> Result<Repository> {
+    let path = Path::new(&repo.path);
+    match Repository::open(path) {
+        Ok(repository) if !repo.mirror && repo.worktree_of.is_none() && unfinished(&repository) => {
+            check_url(repo, &repository, output)?;
+            output.emit(Event::CloneResumed { path: &repo.path });
+            resume(repo, &repository, output)?;
+            return set_up(repo, repository, output);
+        }
+        Ok(repository) => {
+            debug!("opened existing repository");
+            check_url(repo, &repository, output)?;
+            return Ok(repository);
+        }
+        // A process killed while cloning leaves a git directory without a HEAD
+        Err(_) if partial(path) => discard(path, output),
+        Err(_) => {}
+    }
+    if repo.offline {
+        return Err(Error::Refused(format!(
+            "'{}' is not cloned yet and could not be cloned offline",
+            repo.path
+        )));
+    }
+
+    output.emit(Event::CloneStarted {
+        url: &repo.url,
+        path: &repo.path,
+    });
+    // A directory with files in it is not ours to clean up
+    let fresh = fs::read_dir(path).map_or(true, |mut entries| entries.next().is_none());
+    let cloned = clone_into(repo, output);
+    if cloned.is_err() && fresh {
+        discard(path, output);
+    }
+    set_up(repo, cloned?, output)
+}
+
+/// Removes what a failed clone left at `path`, so the next sync starts over
+/// instead of opening a repository without a work tree.
+fn discard(path: &Path, output: &Output) {
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let removed = match entry.file_type() {
+            Ok(kind) if kind.is_dir() => fs::remove_dir_all(entry.path()),
+            _ => fs::remove_file(entry.path()),
+        };
+        if let Err(e) = removed {
+            warn!(path = %entry.path().display(), error = %e, "could not remove");
+        }
+    }
+    output.emit(Event::CloneDiscarded {
+        path: &path.display().to_string(),
+    });
+}
+
+fn clone_into(repo: &Repo, output: &Output) -> Result<Repository> {
+    // The first sync fetches into the empty mirror
+    if repo.mirror {
+        let repository = Repository::init_bare(&repo.path)?;
+        repository.remote_with_fetch(repo.remote(), &repo.url, MIRROR_REFSPEC)?;
+        repository
+            .config()?
+            .set_bool(&format!("remote.{}.mirror", repo.remote()), true)?;
+        repository.set_head(&format!("refs/heads/{}", repo.branch))?;
+        return Ok(repository);
+    }
+    if let Some(shared) = &repo.worktree_of {
+        return worktree::add(repo, shared, output);
+    }
+
+    let phases = output.transfer(&repo.path);
+
+    timeout::configure(repo)?;
+    resolve::configure(repo)?;
+    tls::configure(repo, output)?;
+    let rate = repo.max_bandwidth()?;
+    let transfer = Deadline::new(repo.timeouts.transfer()?);
+    let space = Space::new(repo, Path::new(&repo.path))?;
+    let headers = headers(repo)?;
+    let received = Cell::new(0);
+    let cloned = retry::run(repo, output, || {
+        let throttle = Throttle::new(rate);
+        let mut cb = RemoteCallbacks::new();
+        phases.restart();
+        space.restart();
+        cb.transfer_progress(|stats| {
+            phases.update(&stats);
+            received.set(stats.received_bytes());
+            throttle.pace(stats.received_bytes());
+            transfer.check() && space.check(&stats) && !cancel::requested()
+        });
+        cb.sideband_progress(|_| transfer.check() && !cancel::requested());
+        let supplied = auth::attach(&mut cb, repo);
+        tls::attach(&mut cb, repo, output);
+
+        let mut fo = fetch_options(repo, cb, &headers);
+        if repo.depth() > 0 {
+            fo.depth(repo.depth() as i32);
+        }
+        // The work tree is written below, once there is room for it
+        let mut checkout = CheckoutBuilder::new();
+        checkout.dry_run();
+        let mut builder = RepoBuilder::new();
+        builder
+            .branch(&repo.branch)
+            .remote_create(|repository, _, url| repository.remote(repo.remote(), url))
+            .fetch_options(fo)
+            .with_checkout(checkout);
+        let cloned = builder.clone(&repo.url, Path::new(&repo.path));
+        if cloned.is_ok() {
+            supplied.approve();
+        }
+        cloned
+    });
+    let repository = match cloned {
+        Ok(repository) => repository,
+        Err(_) if transfer.expired() => {
+            return Err(transfer.error(&format!("cloning '{}'", repo.url)))
+        }
+        Err(_) if space.exhausted() => return Err(space.error(&format!("cloning '{}'", repo.url))),
+        Err(_) if cancel::requested() => {
+            return Err(Error::Interrupted(format!("cloning '{}'", repo.url)))
+        }
+        Err(ref e) if e.code() == ErrorCode::NotFound => {
+            return Err(Error::Config(format!(
+                "Could not clone repository from '{}' branch '{}' does not existed.",
+                repo.url, repo.branch
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    phases.finish();
+    add_received(received.get());
+    check_out(repo, &repository, output)?;
+    Ok(repository)
+}
+
+/// Writes the work tree of a new clone from its HEAD commit.
+fn check_out(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    let head = repository.head()?.peel_to_commit()?.id();
+    disk::ensure_checkout(repo, repository, None, head)?;
+    let progress = output.checkout(&repo.path);
+    let deadline = Deadline::new(repo.timeouts.checkout()?);
+    let mut checkout = CheckoutBuilder::new();
+    checkout.progress(|_, cur, total| progress.set(cur, total));
+    sparse::restrict(repo, &mut checkout);
+    limit_checkout(&mut checkout, &deadline);
+    repository.checkout_head(Some(checkout.force()))?;
+    let what = format!("checking out {}", head);
+    deadline.ensure(&what)?;
+    cancel::ensure(&what)?;
+    progress.finish();
+    Ok(())
+}
+
+/// Whether `path` holds nothing but a git directory, as a clone leaves it
+/// when the process dies before libgit2 can clean up.
+fn partial(path: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(path) else {
+        return false;
+    };
+    let names: Vec<_> = entries.flatten().map(|entry| entry.file_name()).collect();
+    names == [".git"] && path.join(".git").is_dir()
+}
+
+/// Whether a clone of this repository stopped before its first checkout:
+/// the branch was never created, or nothing is in the index yet.
+fn unfinished(repository: &Repository) -> bool {
+    match repository.head() {
+        Err(e) => e.code() == ErrorCode::UnbornBranch,
+        Ok(head) => {
+            let empty_index = repository.index().is_ok_and(|index| index.is_empty());
+            let empty_tree = head.peel_to_tree().is_ok_and(|tree| tree.is_empty());
+            empty_index && !empty_tree
+        }
+    }
+}
+
+/// Finishes an unfinished clone. The objects it already fetched stay in its
+/// object database, so only what is missing is downloaded again.
+fn resume(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    if repository.head().is_err() {
+        let tracking = format!("refs/remotes/{}", repo.upstream());
+        let refspec = format!("+refs/heads/{}:{}", repo.branch, tracking);
+        let depth = (repo.depth() > 0).then_some(repo.depth() as i32);
+        fetch_with_depth(repo, repository, &[refspec], depth, output)?;
+        let target = repository
+            .find_reference(&tracking)
+            .and_then(|reference| reference.peel_to_commit())
+            .map_err(|_| {
+                Error::Config(format!(
+                    "Could not clone repository from '{}' branch '{}' does not existed.",
+                    repo.url, repo.branch
+                ))
+            })?;
+        let mut branch = repository.branch(&repo.branch, &target, true)?;
+        branch.set_upstream(Some(&repo.upstream()))?;
+        repository.set_head(&format!("refs/heads/{}", repo.branch))?;
+    }
+    check_out(repo, repository, output)
+}
+
+/// Prepares a new checkout for its first update.
+fn set_up(repo: &Repo, repository: Repository, output: &Output) -> Result<Repository> {
+    sparse::mark(repo, &repository)?;
+    ownership::apply(repo, Path::new(&repo.path), output)?;
+
+    if repo.submodules && !repo.offline {
+        update_submodules(repo, &repository, output)?;
+    }
+    if repo.lfs && !repo.offline {
+        lfs::pull(repo, output)?;
+    }
+
+    Ok(repository)
+}
+
+/// Compares the URL of the existing remote with the configured one and
+/// applies the drift policy when they differ.
+fn check_url(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    let Ok(remote) = repository.find_remote(repo.remote()) else {
+        return Ok(());
+    };
+    let actual = remote.url().unwrap_or_default().to_string();
+    if actual.trim_end_matches('/') == repo.url.trim_end_matches('/') {
+        return Ok(());
+    }
+
+    match repo.url_drift {
+        DriftPolicy::Fail => {
+            return Err(Error::Config(format!(
+                "remote '{}' of '{}' points at '{}' instead of '{}'",
+                repo.remote(),
+                repo.path,
+                actual,
+                repo.url
+            )))
+        }
+        DriftPolicy::Rewrite => repository.remote_set_url(repo.remote(), &repo.url)?,
+        DriftPolicy::Warn => {}
+    }
+    output.emit(Event::UrlDrift {
+        path: &repo.path,
+        remote: repo.remote(),
+        actual: &actual,
+        configured: &repo.url,
+        rewritten: repo.url_drift == DriftPolicy::Rewrite,
+    });
+    Ok(())
+}
+
+fn update_submodules(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    for mut submodule in repository.submodules()? {
+        output.emit(Event::SubmoduleUpdate {
+            path: &repo.path,
+            submodule: submodule.path().display().to_string(),
+        });
+
+        let mut cb = RemoteCallbacks::new();
+        let supplied = auth::attach(&mut cb, repo);
+        tls::attach(&mut cb, repo, output);
+
+        // The headers are meant for the remote, submodules may live elsewhere
+        let fo = fetch_options(repo, cb, &[]);
+
+        let mut opts = SubmoduleUpdateOptions::new();
+        opts.fetch(fo);
+        submodule.update(true, Some(&mut opts))?;
+        supplied.approve();
+
+        update_submodules(repo, &submodule.open()?, output)?;
+    }
+    Ok(())
+}
+
+/// Stops the checkout once `deadline` has passed or on Ctrl-C, before any file is written.
+fn limit_checkout<'a>(cb: &mut CheckoutBuilder<'a>, deadline: &'a Deadline) {
+    cb.notify_on(CheckoutNotificationType::UPDATED);
+    cb.notify(move |_, _, _, _, _| deadline.check() && !cancel::requested());
+}
+
+/// Fetch options carrying the callbacks, the configured proxy and `headers`.
+fn fetch_options<'a>(repo: &Repo, cb: RemoteCallbacks<'a>, headers: &[String]) -> FetchOptions<'a> {
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(cb);
+    if repo.mirror {
+        fo.prune(FetchPrune::On);
+    }
+    if let Some(po) = proxy_options(repo) {
+        fo.proxy_options(po);
+    }
+    if !headers.is_empty() {
+        let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+        fo.custom_headers(&headers);
+    }
+    fo
+}
+
+/// The configured extra headers, resolved from the keyring or environment.
+fn headers(repo: &Repo) -> Result<Vec<String>> {
+    repo.headers.iter().map(Header::line).collect()
+}
+
+fn proxy_options(repo: &Repo) -> Option<ProxyOptions<'static>> {
+    let mut po = ProxyOptions::new();
+    // The network settings are applied by a proxy of our own
+    if let Some(url) = resolve::proxy(repo) {
+        po.url(&url);
+        return Some(po);
+    }
+    match repo.proxy.as_ref()?.as_str() {
+        "auto" => po.auto(),
+        url => po.url(url),
+    };
+    Some(po)
+}
+
+/// The configured remote; it is added when an existing checkout does not know it yet.
+fn find_remote<'r>(repo: &Repo, repository: &'r Repository) -> Result<Remote<'r>> {
+    match repository.find_remote(repo.remote()) {
+        Ok(remote) => Ok(remote),
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            Ok(repository.remote(repo.remote(), &repo.url)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Asks the remote which commit its branch points to, like `git ls-remote`,
+/// without negotiating a fetch. Returns the connected remote along with it so
+/// pruning can use the refs it advertised.
+#[instrument(name = "ls_remote", skip_all)]
+fn remote_tip<'r>(
+    repo: &Repo,
+    repository: &'r Repository,
+    output: &Output,
+) -> Result<(Remote<'r>, Option<Oid>)> {
+    let mut remote = find_remote(repo, repository)?;
+    timeout::configure(repo)?;
+    resolve::configure(repo)?;
+    tls::configure(repo, output)?;
+    retry::run(repo, output, || {
+        let mut cb = RemoteCallbacks::new();
+        let supplied = auth::attach(&mut cb, repo);
+        tls::attach(&mut cb, repo, output);
+        remote
+            .connect_auth(Direction::Fetch, Some(cb), proxy_options(repo))
+            .map(|_| supplied.approve())
+    })?;
+    let branch = format!("refs/heads/{}", repo.branch);
+    let tip = remote
+        .list()?
+        .iter()
+        .find(|head| head.name() == branch)
+        .map(|head| head.oid());
+    Ok((remote, tip))
+}
+
+/// Fetches `refspecs` from the configured remote, retrying transient network failures.
+#[instrument(name = "fetch", skip_all)]
+fn fetch_refspecs<S: AsRef<str> + git2::IntoCString + Clone>(
+    repo: &Repo,
+    repository: &Repository,
+    refspecs: &[S],
+    output: &Output,
+) -> Result<()> {
+    // Once a checkout has its full history, keep it that way
+    let depth = match repo.depth() {
+        0 => None,
+        _ if !repository.is_shallow() => None,
+        depth => Some(depth as i32),
+    };
+    fetch_with_depth(repo, repository, refspecs, depth, output)
+}
+
+pub(crate) fn fetch_with_depth<S: AsRef<str> + git2::IntoCString + Clone>(
+    repo: &Repo,
+    repository: &Repository,
+    refspecs: &[S],
+    depth: Option<i32>,
+    output: &Output,
+) -> Result<()> {
+    if repo.offline {
+        debug!("offline, not fetching");
+        return Ok(());
+    }
+    let mut remote = find_remote(repo, repository)?;
+    for refspec in refspecs {
+        debug!(refspec = refspec.as_ref(), ?depth, "fetching");
+    }
+    timeout::configure(repo)?;
+    resolve::configure(repo)?;
+    tls::configure(repo, output)?;
+    let rate = repo.max_bandwidth()?;
+    let transfer = Deadline::new(repo.timeouts.transfer()?);
+    let space = Space::new(repo, repository.path())?;
+    let headers = headers(repo)?;
+    // libgit2 forgets the shallow boundaries when a shallow fetch brings nothing
+    let shallow = repository.path().join("shallow");
+    let boundaries = depth.and_then(|_| fs::read_to_string(&shallow).ok());
+    let received = Cell::new(0);
+    let phases = output.transfer(&repo.path);
+    let fetched = retry::run(repo, output, || {
+        let throttle = Throttle::new(rate);
+        let mut cb = RemoteCallbacks::new();
+        phases.restart();
+        space.restart();
+        cb.transfer_progress(|stats| {
+            phases.update(&stats);
+            received.set(stats.received_bytes());
+            throttle.pace(stats.received_bytes());
+            transfer.check() && space.check(&stats) && !cancel::requested()
+        });
+        cb.sideband_progress(|_| transfer.check() && !cancel::requested());
+        let supplied = auth::attach(&mut cb, repo);
+        tls::attach(&mut cb, repo, output);
+
+        let mut fo = fetch_options(repo, cb, &headers);
+        if let Some(depth) = depth {
+            fo.depth(depth);
+        }
+        remote
+            .fetch(refspecs, Some(&mut fo), None)
+            .map(|()| supplied.approve())
+    });
+    match fetched {
+        Err(_) if transfer.expired() => {
+            return Err(transfer.error(&format!("fetching from '{}'", repo.url)))
+        }
+        Err(_) if space.exhausted() => {
+            return Err(space.error(&format!("fetching from '{}'", repo.url)))
+        }
+        Err(_) if cancel::requested() => {
+            return Err(Error::Interrupted(format!("fetching from '{}'", repo.url)))
+        }
+        result => result?,
+    }
+    phases.finish();
+    add_received(received.get());
+    if let Some(boundaries) = boundaries.filter(|_| !shallow.exists()) {
+        restore_shallow(repository, &shallow, &boundaries)?;
+    }
+    if repo.prune && !repo.mirror {
+        prune(repo, repository, &remote, output)?;
+    }
+    Ok(())
+}
+
+/// Writes back the shallow boundaries libgit2 dropped that still lack their
+/// parents, which git would otherwise go looking for.
+fn restore_shallow(repository: &Repository, shallow: &Path, boundaries: &str) -> Result<()> {
+    let odb = repository.odb()?;
+    let kept: Vec<&str> = boundaries
+        .lines()
+        .filter(|line| {
+            // The parents libgit2 reports are still cut off at the boundary
+            let Ok(object) = Oid::from_str(line).and_then(|id| odb.read(id)) else {
+                return false;
+            };
+            String::from_utf8_lossy(object.data())
+                .lines()
+                .take_while(|header| !header.is_empty())
+                .filter_map(|header| header.strip_prefix("parent "))
+                .any(|parent| Oid::from_str(parent).is_ok_and(|parent| !odb.exists(parent)))
+        })
+        .collect();
+    if !kept.is_empty() {
+        debug!(boundaries = kept.len(), "restoring shallow boundaries");
+        fs::write(shallow, kept.join("\n") + "\n")?;
+    }
+    Ok(())
+}
+
+/// Deletes remote-tracking branches and tags the remote no longer has, using
+/// the refs it advertised during the fetch, and with `prune_local` the local
+/// branches that tracked a deleted branch.
+fn prune(repo: &Repo, repository: &Repository, remote: &Remote, output: &Output) -> Result<()> {
+    let advertised: HashSet<String> = remote
+        .list()?
+        .iter()
+        .map(|head| head.name().to_string())
+        .collect();
+    let tracking = format!("refs/remotes/{}/", repo.remote());
+    let gone = |name: &str| match name.strip_prefix(&tracking) {
+        Some("HEAD") => false,
+        Some(branch) => !advertised.contains(&format!("refs/heads/{}", branch)),
+        None => name.starts_with("refs/tags/") && !advertised.contains(name),
+    };
+
+    let mut stale = Vec::new();
+    for reference in repository.references()? {
+        let reference = reference?;
+        if let Some(name) = reference.name().filter(|name| gone(name)) {
+            stale.push(name.to_string());
+        }
+    }
+
+    if repo.prune_local {
+        let current = repository
+            .head()
+            .ok()
+            .and_then(|head| head.name().map(str::to_string));
+        let config = repository.config()?.snapshot()?;
+        for branch in repository.branches(Some(git2::BranchType::Local))? {
+            let (branch, _) = branch?;
+            let Some(name) = branch.get().name().map(str::to_string) else {
+                continue;
+            };
+            let short = name.trim_start_matches("refs/heads/");
+            let tracks = |key: &str| config.get_string(&format!("branch.{}.{}", short, key)).ok();
+            let upstream_gone = tracks("remote").as_deref() == Some(repo.remote())
+                && tracks("merge").is_some_and(|merge| !advertised.contains(&merge));
+            if upstream_gone && short != repo.branch && current.as_ref() != Some(&name) {
+                stale.push(name);
+            }
+        }
+    }
+
+    for name in stale {
+        // Deleting a branch also drops its section from the config
+        match name.strip_prefix("refs/heads/") {
+            Some(branch) => repository
+                .find_branch(branch, git2::BranchType::Local)?
+                .delete()?,
+            None => repository.find_reference(&name)?.delete()?,
+        }
+        output.emit(Event::Pruned {
+            path: &repo.path,
+            reference: &name,
+        });
+    }
+    Ok(())
+}
+
+/// Fetches the remote branch, along with any extra configured refspecs, and
+/// returns the local and remote commit ids.
+fn fetch(repo: &Repo, repository: &Repository, output: &Output) -> Result<(Oid, Oid)> {
+    let tracking = format!("refs/remotes/{}", repo.upstream());
+    let mut refspecs = vec![format!("+refs/heads/{}:{}", repo.branch, tracking)];
+    refspecs.extend(repo.refspecs.iter().cloned());
+
+    // Extra refspecs may have moved even when the branch did not
+    let tracked = repository.refname_to_id(&tracking).ok();
+    let unchanged = match tracked.filter(|_| repo.refspecs.is_empty()) {
+        _ if repo.offline => true,
+        // Listing the refs cannot send the headers, a gateway would turn it away
+        Some(_) if !repo.headers.is_empty() => false,
+        Some(tracked) => {
+            let (remote, tip) = remote_tip(repo, repository, output)?;
+            let unchanged = tip == Some(tracked);
+            if unchanged && repo.prune {
+                prune(repo, repository, &remote, output)?;
+            }
+            unchanged
+        }
+        None => false,
+    };
+    match unchanged {
+        true if repo.offline => debug!("offline, using the remote branch as last fetched"),
+        true => debug!(branch = %repo.branch, "remote branch unchanged, not fetching"),
+        false => fetch_refspecs(repo, repository, &refspecs, output)?,
+    }
+
+    let origin_head = repository.find_reference(&tracking)?;
+    let origin_commit = repository.reference_to_annotated_commit(&origin_head)?;
+    switch_branch(repo, repository, origin_commit.id(), output)?;
+
+    // Find the local branch
+    let local_branch = repository.find_branch(&repo.branch, git2::BranchType::Local)?;
+    let local_oid = local_branch
+        .get()
+        .target()
+        .ok_or_else(|| Error::Git(git2::Error::from_str("local branch has no target")))?;
+
+    debug!(local = %local_oid, origin = %origin_commit.id(), "fetched branch");
+    Ok((local_oid, origin_commit.id()))
+}
+
+/// Checks out the configured branch when HEAD is on another branch, creating
+/// it at `origin_oid` and tracking the upstream when it does not exist yet.
+fn switch_branch(
+    repo: &Repo,
+    repository: &Repository,
+    origin_oid: Oid,
+    output: &Output,
+) -> Result<()> {
+    let branch_ref = format!("refs/heads/{}", repo.branch);
+    let head = repository.head()?;
+    if head.name() == Some(branch_ref.as_str()) {
+        return Ok(());
+    }
+    let from = match head.shorthand() {
+        Some(name) if head.is_branch() => name.to_string(),
+        _ => head.peel_to_commit()?.id().to_string(),
+    };
+
+    let mut branch = match repository.find_branch(&repo.branch, git2::BranchType::Local) {
+        Ok(branch) => branch,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            repository.branch(&repo.branch, &repository.find_commit(origin_oid)?, false)?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if branch.upstream().is_err() {
+        branch.set_upstream(Some(&repo.upstream()))?;
+    }
+
+    guard_dirty(repo, repository, output)?;
+    let target = branch.get().peel_to_commit()?;
+    let mut cb = CheckoutBuilder::new();
+    if repo.strategy == Strategy::HardReset {
+        cb.force();
+    } else {
+        cb.safe();
+    }
+    clean::protect(repo, &mut cb);
+    sparse::restrict(repo, &mut cb);
+    repository.checkout_tree(target.as_object(), Some(&mut cb))?;
+    repository.set_head(&branch_ref)?;
+    sparse::mark(repo, repository)?;
+
+    output.emit(Event::BranchSwitched {
+        path: &repo.path,
+        from: &from,
+        to: &repo.branch,
+    });
+    Ok(())
+}
+
+/// Fetches the remote tags and returns the current HEAD commit id together
+/// with the newest tag matching the configured pattern.
+fn fetch_tag(
+    repo: &Repo,
+    repository: &Repository,
+    pattern: &str,
+    output: &Output,
+) -> Result<(Oid, Oid, String)> {
+    fetch_refspecs(repo, repository, &["+refs/tags/*:refs/tags/*"], output)?;
+
+    let (name, oid) = tags::newest(repository, pattern)?
+        .ok_or_else(|| Error::Config(format!("no tag matches '{}'", pattern)))?;
+    output.emit(Event::TagSelected {
+        path: &repo.path,
+        tag: &name,
+        commit: oid.to_string(),
+    });
+
+    let head_oid = repository.head()?.peel_to_commit()?.id();
+    Ok((head_oid, oid, name))
+}
+
+/// Makes sure the pinned `rev` exists locally, fetching it when needed, and
+/// returns the current HEAD commit id together with the commit it names.
+fn fetch_rev(
+    repo: &Repo,
+    repository: &Repository,
+    rev: &str,
+    output: &Output,
+) -> Result<(Oid, Oid)> {
+    let local = |rev: &str| {
+        repository
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .map(|commit| commit.id())
+    };
+
+    let is_sha = rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit());
+    if !(is_sha && local(rev).is_ok()) {
+        let refspecs = if is_sha {
+            vec![rev.to_string()]
+        } else if rev.starts_with("refs/") {
+            vec![format!("+{0}:{0}", rev)]
+        } else {
+            vec![
+                format!("+refs/tags/{0}:refs/tags/{0}", rev),
+                format!("+refs/heads/{0}:refs/remotes/{1}/{0}", rev, repo.remote()),
+            ]
+        };
+
+        fetch_refspecs(repo, repository, &refspecs, output)?;
+    }
+
+    let oid = local(rev)
+        .or_else(|_| local(&format!("{}/{}", repo.remote(), rev)))
+        .map_err(|_| Error::Config(format!("rev '{}' does not exist on the remote", rev)))?;
+    debug!(rev, commit = %oid, "resolved rev");
+
+    let head_oid = repository.head()?.peel_to_commit()?.id();
+    Ok((head_oid, oid))
+}
+
+fn update(repo: &Repo, repository: &mut Repository, output: &Output) -> Result<SyncOutcome> {
+    if repo.mirror {
+        return update_mirror(repo, repository, output);
+    }
+
+    // Tags and pinned revs are checked out as a detached HEAD labelled by `detached`
+    let (local_oid, origin_oid, detached) = match (&repo.tag, &repo.rev) {
+        (_, Some(rev)) => {
+            let (local_oid, rev_oid) = fetch_rev(repo, repository, rev, output)?;
+            (local_oid, rev_oid, Some(rev.clone()))
+        }
+        (Some(pattern), None) => {
+            let (local_oid, tag_oid, name) = fetch_tag(repo, repository, pattern, output)?;
+            (local_oid, tag_oid, Some(name))
+        }
+        (None, None) => {
+            let (local_oid, origin_oid) = fetch(repo, repository, output)?;
+            (local_oid, origin_oid, None)
+        }
+    };
+    // After a merge the local branch has the remote commit in its history already
+    let origin_oid = match repo.strategy == Strategy::Merge
+        && detached.is_none()
+        && local_oid != origin_oid
+        && repository.graph_descendant_of(local_oid, origin_oid)?
+    {
+        true => local_oid,
+        false => origin_oid,
+    };
+
+    // A commit that was rolled back is not applied again
+    let mut state = State::load(repository)?;
+    if local_oid != origin_oid && state.skips(origin_oid) {
+        output.emit(Event::RollbackHeld {
+            path: &repo.path,
+            commit: origin_oid.to_string(),
+        });
+        return Ok(SyncOutcome::UpToDate { commit: local_oid });
+    }
+    if let (Some(filter), true) = (&repo.filter, local_oid != origin_oid) {
+        let commits = pending_commits(repository, local_oid, origin_oid)?;
+        if !filter::applies(filter, repository, &commits)? {
+            output.emit(Event::UpdateFiltered {
+                path: &repo.path,
+                commit: origin_oid.to_string(),
+                commits: commits.len(),
+            });
+            return Ok(SyncOutcome::Held {
+                old: local_oid,
+                new: origin_oid,
+            });
+        }
+    }
+
+    if repo.dry_run && local_oid != origin_oid {
+        let commits = pending_commits(repository, local_oid, origin_oid)?;
+        let pending = commit_infos(repository, local_oid, origin_oid)?;
+        output.emit(Event::UpdateAvailable {
+            path: &repo.path,
+            old: local_oid.to_string(),
+            new: origin_oid.to_string(),
+            commits: pending,
+        });
+        return Ok(SyncOutcome::UpdateAvailable {
+            old: local_oid,
+            new: origin_oid,
+            commits,
+        });
+    }
+
+    if repo.approval && local_oid != origin_oid && !state.approves(origin_oid) {
+        // Told once per commit, syncs in between find the same one waiting
+        let pending = Some(origin_oid.to_string());
+        if state.pending != pending {
+            state.pending = pending;
+            state.save(repository)?;
+            output.emit(Event::AwaitingApproval {
+                path: &repo.path,
+                old: local_oid.to_string(),
+                new: origin_oid.to_string(),
+                commits: commit_infos(repository, local_oid, origin_oid)?,
+            });
+        }
+        return Ok(SyncOutcome::Held {
+            old: local_oid,
+            new: origin_oid,
+        });
+    }
+    if local_oid != origin_oid && window::hold(repo, origin_oid, output)? {
+        return Ok(SyncOutcome::Held {
+            old: local_oid,
+            new: origin_oid,
+        });
+    }
+    if let (Some(ci), true) = (&repo.ci, local_oid != origin_oid) {
+        ci::wait(repo, ci, origin_oid, output)?;
+    }
+    if let (Some(rollout), true) = (&repo.rollout, local_oid != origin_oid) {
+        if !rollout::admit(repo, rollout, origin_oid, output)? {
+            return Ok(SyncOutcome::Held {
+                old: local_oid,
+                new: origin_oid,
+            });
+        }
+    }
+
+    let mut head_oid = origin_oid;
+    let outcome = if local_oid == origin_oid {
+        output.emit(Event::UpToDate { path: &repo.path });
+        SyncOutcome::UpToDate { commit: origin_oid }
+    } else {
+        let stashed = repo.stash && stash(repo, repository, output)?;
+        let applied = apply_update(
+            repo,
+            repository,
+            local_oid,
+            origin_oid,
+            detached.as_deref(),
+            output,
+        );
+        if stashed {
+            unstash(repo, repository, output);
+        }
+        if let Some(rollout) = &repo.rollout {
+            if let Err(e) = rollout::report(repo, rollout, origin_oid, applied.as_ref().err()) {
+                warn!(error = %e, "could not report the update to the rollout");
+            }
+        }
+        head_oid = applied?;
+
+        state.push_previous(local_oid);
+        state.skip = None;
+        state.pending = None;
+        state.approved = None;
+        state.save(repository)?;
+
+        let commits = commit_infos(repository, local_oid, head_oid)?;
+        if !commits.is_empty() {
+            output.emit(Event::Changelog {
+                path: &repo.path,
+                commits: &commits,
+            });
+        }
+        if let Some(file) = &repo.changelog {
+            write_changelog(Path::new(file), repo, local_oid, head_oid, &commits)?;
+        }
+        if let Some(file) = &repo.manifest {
+            let changes = Changes::between(repository, local_oid, head_oid)?;
+            changes::write_manifest(
+                Path::new(file),
+                &repo.path,
+                &repo.branch,
+                local_oid,
+                head_oid,
+                &changes,
+            )?;
+        }
+
+        SyncOutcome::Updated {
+            old: local_oid,
+            new: head_oid,
+        }
+    };
+
+    if let Some(releases) = &repo.releases {
+        releases::publish(repo, releases, repository, head_oid, output)?;
+    }
+    if let Some(destination) = &repo.archive {
+        let updated = matches!(outcome, SyncOutcome::Updated { .. });
+        archive::export(repo, destination, repository, head_oid, updated, output)?;
+    }
+    if let Some(propagate) = &repo.propagate {
+        propagate::run(repo, propagate, repository, head_oid, output)?;
+    }
+
+    let head_commit = repository.find_commit(head_oid)?;
+    output.emit(Event::Head {
+        path: &repo.path,
+        commit: head_oid.to_string(),
+        message: head_commit.message().unwrap_or("No commit message").trim(),
+    });
+
+    Ok(outcome)
+}
+
+/// Fetches every ref into the mirror, pruning the ones the remote deleted.
+fn update_mirror(repo: &Repo, repository: &Repository, output: &Output) -> Result<SyncOutcome> {
+    let targets = || -> Result<HashMap<String, Oid>> {
+        let mut targets = HashMap::new();
+        for reference in repository.references()? {
+            let reference = reference?;
+            if let (Some(name), Some(oid)) = (reference.name(), reference.target()) {
+                targets.insert(name.to_string(), oid);
+            }
+        }
+        Ok(targets)
+    };
+    // HEAD names a branch that may not have been fetched yet
+    let head = || {
+        repository
+            .head()
+            .ok()
+            .and_then(|head| head.target())
+            .unwrap_or_else(Oid::zero)
+    };
+
+    let before = targets()?;
+    let old = head();
+    fetch_refspecs(repo, repository, &[MIRROR_REFSPEC], output)?;
+    let after = targets()?;
+    let new = head();
+
+    let created = after
+        .keys()
+        .filter(|name| !before.contains_key(*name))
+        .count();
+    let deleted = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .count();
+    let updated = after
+        .iter()
+        .filter(|(name, oid)| before.get(*name).is_some_and(|old| old != *oid))
+        .count();
+    if created + updated + deleted == 0 {
+        output.emit(Event::UpToDate { path: &repo.path });
+        return Ok(SyncOutcome::UpToDate { commit: new });
+    }
+    output.emit(Event::MirrorUpdated {
+        path: &repo.path,
+        created,
+        updated,
+        deleted,
+    });
+    Ok(SyncOutcome::Updated { old, new })
+}
+
+/// Runs the hooks around applying the update and returns the new head commit.
+///
+/// With a `detached` label (a tag or pinned rev) the commit is checked out as
+/// a detached HEAD instead of moving the local branch.
+fn apply_update(
+    repo: &Repo,
+    repository: &mut Repository,
+    local_oid: Oid,
+    origin_oid: Oid,
+    detached: Option<&str>,
+    output: &Output,
+) -> Result<Oid> {
+    let context = hooks::Context {
+        dir: Path::new(&repo.path),
+        branch: &repo.branch,
+        old_commit: local_oid,
+        new_commit: origin_oid,
+        changed: changes::between(repository, local_oid, origin_oid)?,
+    };
+
+    guard_dirty(repo, repository, output)?;
+    if detached.is_none() {
+        preflight(repo, repository, local_oid, origin_oid)?;
+    }
+    // An atomic update writes the whole tree next to the live one
+    let from = (!repo.atomic).then_some(local_oid);
+    disk::ensure_checkout(repo, repository, from, origin_oid)?;
+    if let Some(trusted) = &repo.verify {
+        let signer = verify::check(trusted, repository, origin_oid, detached)?;
+        output.emit(Event::Verified {
+            path: &repo.path,
+            commit: origin_oid.to_string(),
+            signer: &signer,
+        });
+    }
+    if let Some(expected) = &repo.integrity {
+        let objects = integrity::check(expected, repository, local_oid, origin_oid)?;
+        output.emit(Event::IntegrityVerified {
+            path: &repo.path,
+            commit: origin_oid.to_string(),
+            objects,
+        });
+    }
+
+    hooks::run(Stage::PreUpdate, &repo.hooks, &context, output).map_err(|e| match e {
+        Error::Hook(message) => Error::Hook(format!("update aborted: {}", message)),
+        e => e,
+    })?;
+
+    match detached {
+        Some(target) => output.emit(Event::CheckoutStarted {
+            path: &repo.path,
+            target,
+            old: local_oid.to_string(),
+            new: origin_oid.to_string(),
+        }),
+        None => output.emit(Event::UpdateStarted {
+            path: &repo.path,
+            branch: &repo.branch,
+            upstream: repo.upstream(),
+            strategy: repo.strategy,
+            old: local_oid.to_string(),
+            new: origin_oid.to_string(),
+        }),
+    }
+
+    let progress = output.checkout(&repo.path);
+
+    let deadline = Deadline::new(repo.timeouts.checkout()?);
+    let mut cb = CheckoutBuilder::new();
+    cb.progress(|_, cur, total| progress.set(cur, total));
+    clean::protect(repo, &mut cb);
+    sparse::restrict(repo, &mut cb);
+    limit_checkout(&mut cb, &deadline);
+
+    if repo.atomic {
+        swap::apply(
+            repo,
+            repository,
+            origin_oid,
+            &mut cb,
+            &deadline,
+            detached.is_some(),
+            output,
+        )?;
+    }
+    let origin_commit = repository.find_commit(origin_oid)?;
+    let head_oid = match detached {
+        _ if repo.atomic => origin_oid,
+        Some(_) => {
+            repository.checkout_tree(origin_commit.as_object(), Some(cb.force()))?;
+            let what = format!("checking out {}", origin_oid);
+            deadline.ensure(&what)?;
+            cancel::ensure(&what)?;
+            repository.set_head_detached(origin_oid)?;
+            origin_oid
+        }
+        None => apply(
+            repo,
+            repository,
+            local_oid,
+            &origin_commit,
+            &mut cb,
+            &deadline,
+        )?,
+    };
+
+    progress.finish();
+    sparse::mark(repo, repository)?;
+    clean::run(repo, repository, output)?;
+    ownership::apply(repo, Path::new(&repo.path), output)?;
+
+    match detached {
+        Some(target) => output.emit(Event::CheckoutFinished {
+            path: &repo.path,
+            target,
+        }),
+        None => output.emit(Event::UpdateFinished {
+            path: &repo.path,
+            branch: &repo.branch,
+            upstream: repo.upstream(),
+            strategy: repo.strategy,
+        }),
+    }
+
+    if repo.submodules && !repo.offline {
+        update_submodules(repo, repository, output)?;
+    }
+    if repo.lfs && !repo.offline {
+        lfs::pull(repo, output)?;
+    }
+
+    hooks::run(Stage::PostUpdate, &repo.hooks, &context, output)?;
+    leader::run_hooks(repo, &context, output)?;
+    Ok(head_oid)
+}
+
+fn signature(repository: &Repository) -> Result<Signature<'static>> {
+    Ok(repository
+        .signature()
+        .or_else(|_| Signature::now("autoupdate", "autoupdate@localhost"))?)
+}
+
+/// Stashes local modifications, returning whether there was anything to stash.
+fn stash(repo: &Repo, repository: &mut Repository, output: &Output) -> Result<bool> {
+    if changed_paths(repository, true)?.is_empty() {
+        return Ok(false);
+    }
+
+    let signature = signature(repository)?;
+    let oid = repository.stash_save(
+        &signature,
+        "autoupdate: local changes",
+        Some(StashFlags::INCLUDE_UNTRACKED),
+    )?;
+    output.emit(Event::Stashed {
+        path: &repo.path,
+        stash: oid.to_string(),
+    });
+    Ok(true)
+}
+
+/// Re-applies the stash created by [`stash`]; on conflicts the stash is kept
+/// so nothing is lost and the failure is reported.
+fn unstash(repo: &Repo, repository: &mut Repository, output: &Output) {
+    match repository.stash_pop(0, None) {
+        Ok(()) => output.emit(Event::StashReapplied { path: &repo.path }),
+        Err(e) => output.emit(Event::StashConflict {
+            path: &repo.path,
+            message: e.message().to_string(),
+        }),
+    }
+}
+
+fn commit_infos(repository: &Repository, old: Oid, new: Oid) -> Result<Vec<CommitInfo>> {
+    pending_commits(repository, old, new)?
+        .iter()
+        .map(|oid| {
+            let commit = repository.find_commit(*oid)?;
+            let author = commit.author().name().unwrap_or_default().to_string();
+            Ok(CommitInfo {
+                id: oid.to_string(),
+                author,
+                summary: commit.summary().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Appends the applied commits to the changelog file, one block per update.
+fn write_changelog(
+    file: &Path,
+    repo: &Repo,
+    old: Oid,
+    new: Oid,
+    commits: &[CommitInfo],
+) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let mut text = format!(
+        "{} {:.7}..{:.7} at {}\n",
+        repo.path,
+        old.to_string(),
+        new.to_string(),
+        timestamp
+    );
+    for commit in commits {
+        text.push_str(&format!(
+            "  {:.7} {}: {}\n",
+            commit.id, commit.author, commit.summary
+        ));
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?;
+    file.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// Commits reachable from `new` but not from `old`, newest first.
+fn pending_commits(repository: &Repository, old: Oid, new: Oid) -> Result<Vec<Oid>> {
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push(new)?;
+    // The old commit may be missing from a shallow history; walk everything then
+    if repository.find_commit(old).is_ok() {
+        revwalk.hide(old)?;
+    }
+    Ok(revwalk.collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Modified, deleted and untracked paths of the working tree, relative to its
+/// root, leaving out those the sparse checkout keeps off disk.
+fn changed_paths(repository: &Repository, untracked: bool) -> Result<Vec<PathBuf>> {
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(untracked)
+        .recurse_untracked_dirs(untracked)
+        .include_ignored(false);
+    let index = repository.index()?;
+    Ok(repository
+        .statuses(Some(&mut opts))?
+        .iter()
+        .filter_map(|entry| entry.path().map(str::to_string))
+        .filter(|path| !sparse::skipped(&index, path))
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn dirty_files(repository: &Repository) -> Result<Vec<PathBuf>> {
+    changed_paths(repository, true)
+}
+
+/// Applies the dirty working tree policy before anything is overwritten.
+fn guard_dirty(repo: &Repo, repository: &Repository, output: &Output) -> Result<()> {
+    if repo.dirty == DirtyPolicy::Force {
+        return Ok(());
+    }
+
+    let files = dirty_files(repository)?;
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    if repo.dirty == DirtyPolicy::Abort {
+        return Err(Error::Refused(format!(
+            "working tree of '{}' has {} modified or untracked file(s)",
+            repo.path,
+            files.len()
+        )));
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let directory = PathBuf::from(format!("{}.backup", repo.path)).join(timestamp.to_string());
+
+    let mut copied = 0;
+    for file in &files {
+        let source = Path::new(&repo.path).join(file);
+        // Deleted files have nothing left to back up
+        if !source.is_file() {
+            continue;
+        }
+        let target = directory.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&source, &target)?;
+        copied += 1;
+    }
+
+    output.emit(Event::BackedUp {
+        path: &repo.path,
+        directory: directory.display().to_string(),
+        files: copied,
+    });
+    Ok(())
+}
+
+pub(crate) fn is_dirty(repository: &Repository) -> Result<bool> {
+    Ok(!changed_paths(repository, false)?.is_empty())
+}
+
+/// Refuses updates the configured strategy cannot apply without losing work.
+fn preflight(repo: &Repo, repository: &Repository, local_oid: Oid, target: Oid) -> Result<()> {
+    if repo.strategy == Strategy::HardReset {
+        return Ok(());
+    }
+
+    if is_dirty(repository)? {
+        return Err(Error::Refused(format!(
+            "working tree of '{}' has local modifications",
+            repo.path
+        )));
+    }
+
+    if repo.strategy == Strategy::FastForwardOnly
+        && !repository.graph_descendant_of(target, local_oid)?
+    {
+        return Err(Error::Refused(format!(
+            "local branch '{}' has diverged from '{}'",
+            repo.branch,
+            repo.upstream()
+        )));
+    }
+    Ok(())
+}
+
+/// Moves the local branch to `target` using the configured strategy and
+/// returns the commit the branch ends up at.
+#[instrument(name = "checkout", skip_all, fields(strategy = ?repo.strategy, target = %target.id()))]
+fn apply(
+    repo: &Repo,
+    repository: &Repository,
+    local_oid: Oid,
+    target: &Commit,
+    cb: &mut CheckoutBuilder,
+    deadline: &Deadline,
+) -> Result<Oid> {
+    let what = format!("checking out {}", target.id());
+    if repo.strategy == Strategy::HardReset {
+        // A reset moves the branch even when its checkout was aborted
+        repository.checkout_tree(target.as_object(), Some(cb.force()))?;
+        deadline.ensure(&what)?;
+        cancel::ensure(&what)?;
+        repository.reset(target.as_object(), git2::ResetType::Hard, Some(cb))?;
+        return Ok(target.id());
+    }
+
+    let branch_ref = format!("refs/heads/{}", repo.branch);
+    cb.safe();
+
+    if local_oid == target.id() || repository.graph_descendant_of(local_oid, target.id())? {
+        return Ok(local_oid);
+    }
+
+    if repository.graph_descendant_of(target.id(), local_oid)? {
+        repository.checkout_tree(target.as_object(), Some(cb))?;
+        deadline.ensure(&what)?;
+        cancel::ensure(&what)?;
+        repository
+            .find_reference(&branch_ref)?
+            .set_target(target.id(), "autoupdate: fast-forward")?;
+        return Ok(target.id());
+    }
+
+    let local_commit = repository.find_commit(local_oid)?;
+    let mut index = repository.merge_commits(&local_commit, target, None)?;
+    if index.has_conflicts() {
+        return Err(Error::Refused(format!(
+            "merging '{}' into '{}' has conflicts",
+            repo.upstream(),
+            repo.branch
+        )));
+    }
+
+    let tree = repository.find_tree(index.write_tree_to(repository)?)?;
+    repository.checkout_tree(tree.as_object(), Some(cb))?;
+    deadline.ensure(&what)?;
+    cancel::ensure(&what)?;
+
+    let signature = signature(repository)?;
+    let message = format!("Merge '{}' into '{}'", repo.upstream(), repo.branch);
+    let merge_oid = repository.commit(
+        Some(&branch_ref),
+        &signature,
+        &signature,
+        &message,
+        &tree,
+        &[&local_commit, target],
+    )?;
+    Ok(merge_oid)
+}
@@ -0,0 +1,96 @@
+use crate::error::{Error, Result};
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// A user from the password database.
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+fn name(kind: &str, name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| Error::Config(format!("invalid {} name '{}'", kind, name)))
+}
+
+fn string(value: *const libc::c_char) -> String {
+    match value.is_null() {
+        true => String::new(),
+        false => unsafe { CStr::from_ptr(value) }
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Looks a user up by name or numeric id through NSS.
+pub fn account(user: &str) -> Result<Account> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = ptr::null_mut();
+    match user.parse::<u32>() {
+        Ok(uid) => unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+        },
+        Err(_) => {
+            let name = name("user", user)?;
+            unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                );
+            }
+        }
+    }
+    match found.is_null() {
+        true => Err(Error::Config(format!("unknown user '{}'", user))),
+        false => Ok(Account {
+            name: string(entry.pw_name),
+            uid: entry.pw_uid,
+            gid: entry.pw_gid,
+            home: string(entry.pw_dir),
+        }),
+    }
+}
+
+/// Numeric ids are taken as they are, names are looked up through NSS.
+pub fn uid(user: &str) -> Result<u32> {
+    match user.parse() {
+        Ok(uid) => Ok(uid),
+        Err(_) => Ok(account(user)?.uid),
+    }
+}
+
+pub fn gid(group: &str) -> Result<u32> {
+    if let Ok(id) = group.parse() {
+        return Ok(id);
+    }
+    let name = name("group", group)?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = ptr::null_mut();
+    unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+    }
+    match found.is_null() {
+        true => Err(Error::Config(format!("unknown group '{}'", group))),
+        false => Ok(entry.gr_gid),
+    }
+}
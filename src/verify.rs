@@ -0,0 +1,213 @@
+use crate::config::Verify;
+use crate::error::{Error, Result};
+use git2::{ObjectType, Oid, Repository};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+
+/// Signature file handed to gpg or ssh-keygen, removed once dropped.
+struct SignatureFile(PathBuf);
+
+impl SignatureFile {
+    fn create(name: &str, signature: &[u8]) -> Result<SignatureFile> {
+        let path =
+            std::env::temp_dir().join(format!("autoupdate-{}-{}.sig", std::process::id(), name));
+        fs::write(&path, signature)?;
+        Ok(SignatureFile(path))
+    }
+}
+
+impl Drop for SignatureFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Runs `command` with `data` on stdin and returns its exit status and stdout.
+fn run(mut command: Command, data: &[u8]) -> Result<(bool, String)> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::Config(format!("{} is not installed", program)),
+            _ => Error::Io(e),
+        })?;
+    child.stdin.take().unwrap().write_all(data)?;
+    let output = child.wait_with_output()?;
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
+
+/// Fingerprint of the key that made a good gpg signature, if any.
+fn verify_gpg(verify: &Verify, signature: &SignatureFile, data: &[u8]) -> Result<Option<String>> {
+    let mut command = Command::new("gpg");
+    command.args(["--batch", "--status-fd", "1"]);
+    if let Some(keyring) = &verify.keyring {
+        command
+            .args(["--no-default-keyring", "--keyring"])
+            .arg(keyring);
+    }
+    command.arg("--verify").arg(&signature.0).arg("-");
+
+    let (success, status) = run(command, data)?;
+    if !success {
+        return Ok(None);
+    }
+
+    // [GNUPG:] VALIDSIG <fingerprint> <date> ... <primary key fingerprint>
+    let Some(fields) = status
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+    else {
+        return Ok(None);
+    };
+    let fingerprints: Vec<&str> = fields.split(' ').collect();
+    let (signer, primary) = (fingerprints[0], fingerprints[fingerprints.len() - 1]);
+    if verify.keys.is_empty() {
+        return Ok(Some(signer.to_string()));
+    }
+
+    // Keys may be given as full fingerprints or long key ids, of the subkey or the primary key
+    Ok(verify
+        .keys
+        .iter()
+        .find(|key| {
+            let key = key.replace(' ', "").to_ascii_uppercase();
+            !key.is_empty() && (signer.ends_with(&key) || primary.ends_with(&key))
+        })
+        .cloned())
+}
+
+/// Principal from the allowed signers file that made a good ssh signature, if any.
+fn verify_ssh(
+    verify: &Verify,
+    namespace: &str,
+    signature: &SignatureFile,
+    data: &[u8],
+) -> Result<Option<String>> {
+    let allowed = verify.allowed_signers.as_deref().ok_or_else(|| {
+        Error::Config("ssh signatures need an allowed_signers file to verify".to_string())
+    })?;
+
+    let mut find = Command::new("ssh-keygen");
+    find.args(["-Y", "find-principals", "-f", allowed, "-s"])
+        .arg(&signature.0);
+    let (found, principals) = run(find, &[])?;
+    let Some(principal) = principals.lines().next().filter(|_| found) else {
+        return Ok(None);
+    };
+
+    let mut check = Command::new("ssh-keygen");
+    check
+        .args([
+            "-Y", "verify", "-n", namespace, "-f", allowed, "-I", principal, "-s",
+        ])
+        .arg(&signature.0);
+    let (success, _) = run(check, data)?;
+    Ok(success.then(|| principal.to_string()))
+}
+
+/// A signed object together with the payload its signature covers.
+struct Signed {
+    oid: Oid,
+    signature: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Splits a signed annotated tag into the signed payload and its signature.
+fn tag_signature(repository: &Repository, tag: &str) -> Result<Option<Signed>> {
+    let Ok(reference) = repository.find_reference(&format!("refs/tags/{}", tag)) else {
+        return Ok(None);
+    };
+    let Some(oid) = reference.target() else {
+        return Ok(None);
+    };
+    let odb = repository.odb()?;
+    let object = odb.read(oid)?;
+    if object.kind() != ObjectType::Tag {
+        return Ok(None);
+    }
+
+    let content = object.data();
+    let text = String::from_utf8_lossy(content);
+    let start = match text
+        .find(PGP_SIGNATURE)
+        .or_else(|| text.find(SSH_SIGNATURE))
+    {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+    Ok(Some(Signed {
+        oid,
+        signature: content[start..].to_vec(),
+        data: content[..start].to_vec(),
+    }))
+}
+
+/// Refuses `commit` unless it, or the annotated `tag` pointing at it, carries
+/// a good signature from one of the trusted keys. Returns who signed it.
+pub fn check(
+    verify: &Verify,
+    repository: &Repository,
+    commit: Oid,
+    tag: Option<&str>,
+) -> Result<String> {
+    let signed = match tag
+        .map(|tag| tag_signature(repository, tag))
+        .transpose()?
+        .flatten()
+    {
+        Some(signed) => Some(signed),
+        None => match repository.extract_signature(&commit, None) {
+            Ok((signature, data)) => Some(Signed {
+                oid: commit,
+                signature: signature.to_vec(),
+                data: data.to_vec(),
+            }),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+            Err(e) => return Err(e.into()),
+        },
+    };
+    let Some(Signed {
+        oid,
+        signature,
+        data,
+    }) = signed
+    else {
+        return Err(Error::Refused(format!("commit {} is not signed", commit)));
+    };
+
+    signer(verify, "git", &oid.to_string(), &signature, &data)?
+        .ok_or_else(|| Error::Refused(format!("commit {} is not signed by a trusted key", commit)))
+}
+
+/// Refuses the downloaded file `name` unless `signature`, a detached gpg or
+/// ssh signature, is a good one from a trusted key. Returns who signed it.
+pub fn check_file(verify: &Verify, name: &str, data: &[u8], signature: &[u8]) -> Result<String> {
+    signer(verify, "file", name, signature, data)?
+        .ok_or_else(|| Error::Refused(format!("{} is not signed by a trusted key", name)))
+}
+
+fn signer(
+    verify: &Verify,
+    namespace: &str,
+    name: &str,
+    signature: &[u8],
+    data: &[u8],
+) -> Result<Option<String>> {
+    let file = SignatureFile::create(name, signature)?;
+    if signature.starts_with(SSH_SIGNATURE.as_bytes()) {
+        verify_ssh(verify, namespace, &file, data)
+    } else {
+        verify_gpg(verify, &file, data)
+    }
+}
@@ -0,0 +1,320 @@
+use crate::config::{Repo, Window};
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::schedule;
+use git2::Oid;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+/// How far ahead `Windows::opens_after` looks, a week and a day so that a
+/// window open once a week is always found.
+const SEARCH_MINUTES: u64 = 8 * MINUTES_PER_DAY;
+
+/// What the times of day of the windows are given in.
+#[derive(Clone, Copy)]
+enum Zone {
+    /// Ahead of UTC by this many seconds, behind when negative.
+    Offset(i64),
+    /// The time zone of the machine, daylight saving time included.
+    Local,
+}
+
+impl Zone {
+    fn parse(value: &str) -> Result<Zone> {
+        let invalid = || {
+            Error::Config(format!(
+                "invalid timezone '{}', expected UTC, local or an offset such as +02:00",
+                value
+            ))
+        };
+        match value.trim() {
+            value if value.eq_ignore_ascii_case("utc") => Ok(Zone::Offset(0)),
+            value if value.eq_ignore_ascii_case("local") => Ok(Zone::Local),
+            value => {
+                let (sign, rest) = match value.split_at_checked(1) {
+                    Some(("+", rest)) => (1, rest),
+                    Some(("-", rest)) => (-1, rest),
+                    _ => return Err(invalid()),
+                };
+                let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+                match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+                    (Ok(hours), Ok(minutes)) if hours <= 14 && minutes < 60 => {
+                        Ok(Zone::Offset(sign * (hours * 3600 + minutes * 60)))
+                    }
+                    _ => Err(invalid()),
+                }
+            }
+        }
+    }
+
+    /// Seconds the zone is ahead of UTC at `time`.
+    fn offset(self, time: u64) -> i64 {
+        match self {
+            Zone::Offset(offset) => offset,
+            Zone::Local => local_offset(time),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local_offset(time: u64) -> i64 {
+    let time = time as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+    match unsafe { libc::localtime_r(&time, &mut local) }.is_null() {
+        true => 0,
+        // The field type differs between platforms
+        #[allow(clippy::unnecessary_cast)]
+        false => local.tm_gmtoff as i64,
+    }
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: u64) -> i64 {
+    0
+}
+
+/// Minutes after midnight of a time of day such as `22:00`.
+fn time_of_day(value: &str) -> Result<u64> {
+    let parsed = value.trim().split_once(':').and_then(|(hours, minutes)| {
+        Some((hours.parse::<u64>().ok()?, minutes.parse::<u64>().ok()?))
+    });
+    match parsed {
+        Some((hours, minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        Some((24, 0)) => Ok(MINUTES_PER_DAY),
+        _ => Err(Error::Config(format!(
+            "invalid time of day '{}', expected HH:MM",
+            value
+        ))),
+    }
+}
+
+/// A window as minutes of the day on the weekdays it starts on. One that
+/// ends before it starts runs past midnight; one that ends when it starts
+/// lasts the whole day.
+struct Span {
+    weekdays: u64,
+    start: u64,
+    end: u64,
+}
+
+impl Span {
+    fn parse(window: &Window) -> Result<Span> {
+        let weekdays = match &window.days {
+            Some(days) => schedule::weekdays(days)
+                .map_err(|e| Error::Config(format!("invalid days '{}': {}", days, e)))?,
+            None => 0x7f,
+        };
+        Ok(Span {
+            weekdays,
+            start: time_of_day(&window.start)?,
+            end: time_of_day(&window.end)?,
+        })
+    }
+
+    /// Whether the span covers `minute`, counted in minutes since the epoch
+    /// in the zone of the windows.
+    fn contains(&self, minute: u64) -> bool {
+        let day = minute / MINUTES_PER_DAY;
+        let of_day = minute % MINUTES_PER_DAY;
+        let starts_on = |day: u64| self.weekdays & (1 << ((day + 4) % 7)) != 0;
+        match self.start < self.end {
+            true => starts_on(day) && (self.start..self.end).contains(&of_day),
+            false => {
+                (starts_on(day) && of_day >= self.start)
+                    || (day > 0 && starts_on(day - 1) && of_day < self.end)
+            }
+        }
+    }
+}
+
+/// When a repository may apply updates: inside one of its windows if it has
+/// any, and never during a blackout.
+pub struct Windows {
+    zone: Zone,
+    open: Vec<Span>,
+    blackouts: Vec<Span>,
+}
+
+impl Windows {
+    /// `None` when the repository restricts updates to no particular times.
+    pub fn of(repo: &Repo) -> Result<Option<Windows>> {
+        if repo.windows.is_empty() && repo.blackouts.is_empty() {
+            return Ok(None);
+        }
+        let zone = match &repo.timezone {
+            Some(timezone) => Zone::parse(timezone)?,
+            None => Zone::Offset(0),
+        };
+        let spans = |windows: &[Window]| windows.iter().map(Span::parse).collect::<Result<_>>();
+        Ok(Some(Windows {
+            zone,
+            open: spans(&repo.windows)?,
+            blackouts: spans(&repo.blackouts)?,
+        }))
+    }
+
+    /// Whether updates may be applied at `time`, in seconds since the epoch.
+    pub fn allow(&self, time: u64) -> bool {
+        let local = time.saturating_add_signed(self.zone.offset(time)) / 60;
+        let open = self.open.is_empty() || self.open.iter().any(|span| span.contains(local));
+        open && !self.blackouts.iter().any(|span| span.contains(local))
+    }
+
+    /// The first minute after `time` at which updates may be applied, or
+    /// `None` when the windows never allow them.
+    pub fn opens_after(&self, time: u64) -> Option<u64> {
+        let first = time / 60 + 1;
+        (first..first + SEARCH_MINUTES)
+            .map(|minute| minute * 60)
+            .find(|time| self.allow(*time))
+    }
+}
+
+/// Whether the update of `repo` to `commit` has to wait, telling when it
+/// does.
+pub(crate) fn hold(repo: &Repo, commit: Oid, output: &Output) -> Result<bool> {
+    let Some(windows) = Windows::of(repo)? else {
+        return Ok(false);
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    if windows.allow(now) {
+        return Ok(false);
+    }
+    output.emit(Event::UpdateDeferred {
+        path: &repo.path,
+        commit: commit.to_string(),
+        opens: windows.opens_after(now),
+    });
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minutes since the epoch, which started on a Thursday, at `hours:minutes` of `day`.
+    fn at(day: u64, hours: u64, minutes: u64) -> u64 {
+        day * MINUTES_PER_DAY + hours * 60 + minutes
+    }
+
+    fn span(days: Option<&str>, start: &str, end: &str) -> Span {
+        Span::parse(&Window {
+            days: days.map(str::to_string),
+            start: start.to_string(),
+            end: end.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn offset(value: &str) -> Option<i64> {
+        match Zone::parse(value) {
+            Ok(Zone::Offset(offset)) => Some(offset),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(offset("UTC"), Some(0));
+        assert_eq!(offset("+02:00"), Some(7200));
+        assert_eq!(offset("-05:30"), Some(-19800));
+        assert_eq!(offset("+9"), Some(9 * 3600));
+        assert_eq!(offset(" +14:00 "), Some(14 * 3600));
+        assert!(matches!(Zone::parse("local"), Ok(Zone::Local)));
+        for invalid in ["02:00", "+15:00", "+02:60", "+", "-aa:00", "CET"] {
+            assert!(Zone::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!(time_of_day("00:00").unwrap(), 0);
+        assert_eq!(time_of_day("22:30").unwrap(), 22 * 60 + 30);
+        assert_eq!(time_of_day("24:00").unwrap(), MINUTES_PER_DAY);
+        for invalid in ["24:01", "12:60", "12", "noon"] {
+            assert!(time_of_day(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn span_within_a_day() {
+        let span = span(None, "09:00", "17:00");
+        assert!(!span.contains(at(3, 8, 59)));
+        assert!(span.contains(at(3, 9, 0)));
+        assert!(span.contains(at(3, 16, 59)));
+        assert!(!span.contains(at(3, 17, 0)));
+    }
+
+    #[test]
+    fn span_past_midnight() {
+        let span = span(None, "22:00", "06:00");
+        assert!(span.contains(at(3, 23, 0)));
+        assert!(span.contains(at(4, 5, 59)));
+        assert!(!span.contains(at(4, 6, 0)));
+        assert!(!span.contains(at(4, 12, 0)));
+        assert!(span.contains(at(4, 22, 0)));
+    }
+
+    #[test]
+    fn span_past_midnight_belongs_to_the_day_it_starts() {
+        // Day 1 is a Friday, day 2 a Saturday
+        let span = span(Some("fri"), "22:00", "02:00");
+        assert!(span.contains(at(1, 23, 0)));
+        assert!(span.contains(at(2, 1, 0)));
+        assert!(!span.contains(at(1, 1, 0)));
+        assert!(!span.contains(at(2, 23, 0)));
+    }
+
+    #[test]
+    fn span_ending_when_it_starts_lasts_all_day() {
+        let span = span(Some("sat"), "00:00", "00:00");
+        assert!(span.contains(at(2, 0, 0)));
+        assert!(span.contains(at(2, 23, 59)));
+        assert!(!span.contains(at(3, 0, 0)));
+    }
+
+    #[test]
+    fn windows_apply_the_offset() {
+        let windows = Windows {
+            zone: Zone::Offset(2 * 3600),
+            open: vec![span(None, "09:00", "17:00")],
+            blackouts: Vec::new(),
+        };
+        // 07:30 UTC is 09:30 two hours ahead
+        assert!(windows.allow(at(3, 7, 30) * 60));
+        assert!(!windows.allow(at(3, 6, 30) * 60));
+        assert_eq!(
+            windows.opens_after(at(3, 6, 30) * 60),
+            Some(at(3, 7, 0) * 60)
+        );
+    }
+
+    #[test]
+    fn blackouts_win_over_windows() {
+        let windows = Windows {
+            zone: Zone::Offset(0),
+            open: Vec::new(),
+            blackouts: vec![span(None, "23:00", "01:00")],
+        };
+        assert!(!windows.allow(at(3, 23, 30) * 60));
+        assert!(!windows.allow(at(4, 0, 30) * 60));
+        assert!(windows.allow(at(4, 1, 0) * 60));
+        assert_eq!(
+            windows.opens_after(at(3, 23, 0) * 60),
+            Some(at(4, 1, 0) * 60)
+        );
+    }
+
+    #[test]
+    fn closed_windows_never_open() {
+        let windows = Windows {
+            zone: Zone::Offset(0),
+            open: vec![span(None, "09:00", "17:00")],
+            blackouts: vec![span(None, "00:00", "00:00")],
+        };
+        assert_eq!(windows.opens_after(0), None);
+    }
+}
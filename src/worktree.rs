@@ -0,0 +1,89 @@
+use crate::config::Repo;
+use crate::disk;
+use crate::error::{Error, Result};
+use crate::output::{Event, Output};
+use crate::updater;
+use git2::{BranchType, Repository, WorktreeAddOptions};
+use std::path::Path;
+
+/// Name of the worktree at `path` in the shared repository: its directory
+/// name, numbered when another worktree already has it.
+fn name(bare: &Repository, path: &Path) -> String {
+    let base: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect();
+    let mut name = base.clone();
+    let mut number = 1;
+    while bare.find_worktree(&name).is_ok() {
+        number += 1;
+        name = format!("{}-{}", base, number);
+    }
+    name
+}
+
+/// Forgets worktrees whose directory is gone, like `git worktree prune`, so
+/// their branches can be checked out again.
+fn prune(bare: &Repository) -> Result<()> {
+    for name in bare.worktrees()?.iter().flatten() {
+        let worktree = bare.find_worktree(name)?;
+        if worktree.is_prunable(None)? {
+            worktree.prune(None)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the checkout at `path` as a worktree of the bare repository
+/// `shared`, which is initialized first when it does not exist yet. Only the
+/// configured branch is fetched, with its full history, and the objects of
+/// the other checkouts are reused.
+pub(crate) fn add(repo: &Repo, shared: &str, output: &Output) -> Result<Repository> {
+    let bare = match Repository::open_bare(shared) {
+        Ok(bare) => bare,
+        Err(_) => Repository::init_bare(shared)?,
+    };
+    prune(&bare)?;
+
+    let tracking = format!("refs/remotes/{}", repo.upstream());
+    let refspec = format!("+refs/heads/{}:{}", repo.branch, tracking);
+    updater::fetch_with_depth(repo, &bare, &[refspec], None, output)?;
+    let target = bare
+        .find_reference(&tracking)
+        .and_then(|reference| reference.peel_to_commit())
+        .map_err(|_| {
+            Error::Config(format!(
+                "Could not clone repository from '{}' branch '{}' does not existed.",
+                repo.url, repo.branch
+            ))
+        })?;
+
+    let branch = match bare.find_branch(&repo.branch, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(_) => {
+            let mut branch = bare.branch(&repo.branch, &target, false)?;
+            branch.set_upstream(Some(&repo.upstream()))?;
+            branch
+        }
+    };
+    let reference = branch.into_reference();
+    disk::ensure_checkout(repo, &bare, None, reference.peel_to_commit()?.id())?;
+    let mut opts = WorktreeAddOptions::new();
+    opts.reference(Some(&reference));
+    let path = Path::new(&repo.path);
+    bare.worktree(&name(&bare, path), path, Some(&opts))?;
+
+    output.emit(Event::WorktreeAdded {
+        path: &repo.path,
+        repository: shared,
+    });
+    Ok(Repository::open(path)?)
+}